@@ -469,6 +469,7 @@ fn build_entities() -> anyhow::Result<TokenStream> {
                 pub animations: super::EntityAnimations,
                 pub object_data: super::ObjectData,
                 pub tracked_data: super::tracked_data::TrackedData,
+                pub movement: super::movement::TrackedMovement,
             }]);
 
             bundle_init_fields.extend([quote! {
@@ -487,6 +488,7 @@ fn build_entities() -> anyhow::Result<TokenStream> {
                 animations: Default::default(),
                 object_data: Default::default(),
                 tracked_data: Default::default(),
+                movement: Default::default(),
             }]);
 
             let bundle_name_ident = ident(format!("{entity_name}Bundle"));
@@ -546,6 +548,7 @@ fn build_entities() -> anyhow::Result<TokenStream> {
                 #[allow(clippy::suspicious_else_formatting)]
                 #[allow(clippy::needless_borrows_for_generic_args)]
                 fn #system_name_ident(
+                    _: Receiver<UpdateTrackedDataSet>,
                     mut fetcher: Fetcher<(&#component_path, &mut tracked_data::TrackedData)>,
                 ) {
                     for (value, tracked_data) in fetcher.iter_mut() {
@@ -611,6 +614,7 @@ fn build_entities() -> anyhow::Result<TokenStream> {
         /// Special case for `living::Absorption`.
         /// Updates the `AbsorptionAmount` component of the player entity.
         fn update_living_and_player_absorption(
+            _: Receiver<UpdateTrackedDataSet>,
             mut fetcher: Fetcher<(&living::Absorption, &mut player::AbsorptionAmount)>,
         ) {
             for (living_absorption, player_absorption) in fetcher.iter_mut() {
@@ -620,6 +624,7 @@ fn build_entities() -> anyhow::Result<TokenStream> {
 
         /// Special case for `living::Attributes`.
         fn update_living_attributes(
+            _: Receiver<UpdateTrackedDataSet>,
             mut fetcher: Fetcher<(
                 &mut attributes::TrackedEntityAttributes,
                 &mut attributes::EntityAttributes,
@@ -40,7 +40,9 @@ impl Default for EntityHitboxSettings {
 
 impl Plugin for HitboxPlugin {
     fn build(&self, world: &mut World) {
-        // HitboxShapeUpdateSet
+        world.add_handler(send_hitbox_sets);
+
+        // HitboxShapeUpdateEvent
         world.add_handler(update_constant_hitbox);
         world.add_handler(update_warden_hitbox);
         world.add_handler(update_area_effect_cloud_hitbox);
@@ -129,8 +131,18 @@ fn add_hitbox_component2(
     }
 }
 
-fn update_hitbox(
+/// Sends [`HitboxShapeUpdateEvent`] followed by [`HitboxUpdateSet`] at the
+/// start of every tick.
+fn send_hitbox_sets(
     _: Receiver<Tick>,
+    mut sender: Sender<(HitboxShapeUpdateEvent, HitboxUpdateSet)>,
+) {
+    sender.send(HitboxShapeUpdateEvent);
+    sender.send(HitboxUpdateSet);
+}
+
+fn update_hitbox(
+    _: Receiver<HitboxUpdateSet>,
     mut hitbox_fetcher: Fetcher<(&mut Hitbox, &HitboxShape, &Position)>,
 ) {
     for (in_world, hitbox, pos) in hitbox_fetcher.iter_mut() {
@@ -139,6 +151,7 @@ fn update_hitbox(
 }
 
 fn update_constant_hitbox(
+    _: Receiver<HitboxShapeUpdateEvent>,
     mut hitbox_fetcher: Fetcher<(&mut HitboxShape, &EntityKind)>,
 ) {
     for (hitbox, entity_kind) in hitbox_fetcher.iter_mut() {
@@ -223,6 +236,7 @@ fn update_constant_hitbox(
 }
 
 fn update_warden_hitbox(
+    _: Receiver<HitboxShapeUpdateEvent>,
     mut fetcher: Fetcher<(&mut HitboxShape, &entity::Pose, With<&warden::WardenEntity>)>,
 ) {
     for (hitbox, entity_pose, _) in fetcher.iter_mut() {
@@ -237,6 +251,7 @@ fn update_warden_hitbox(
 }
 
 fn update_area_effect_cloud_hitbox(
+    _: Receiver<HitboxShapeUpdateEvent>,
     mut fetcher: Fetcher<(&mut HitboxShape, &area_effect_cloud::Radius)>,
 ) {
     for (hitbox, cloud_radius) in fetcher.iter_mut() {
@@ -246,6 +261,7 @@ fn update_area_effect_cloud_hitbox(
 }
 
 fn update_armor_stand_hitbox(
+    _: Receiver<HitboxShapeUpdateEvent>,
     mut fetcher: Fetcher<(&mut HitboxShape, &armor_stand::ArmorStandFlags)>,
 ) {
     for (hitbox, stand_flags) in fetcher.iter_mut() {
@@ -273,6 +289,7 @@ fn child_hitbox(child: bool, v: DVec3) -> DVec3 {
 }
 
 fn update_passive_child_hitbox(
+    _: Receiver<HitboxShapeUpdateEvent>,
     mut fetcher: Fetcher<(EntityId, &mut HitboxShape, &EntityKind, &passive::Child, With<&Entity>)>,
     pose_fetcher: Fetcher<(&entity::Pose, With<&Entity>)>,
 ) {
@@ -328,6 +345,7 @@ fn update_passive_child_hitbox(
 }
 
 fn update_zombie_hitbox(
+    _: Receiver<HitboxShapeUpdateEvent>,
     mut fetcher: Fetcher<(&mut HitboxShape, &zombie::Baby)>,
 ) {
     for (hitbox, baby) in fetcher.iter_mut() {
@@ -336,6 +354,7 @@ fn update_zombie_hitbox(
 }
 
 fn update_piglin_hitbox(
+    _: Receiver<HitboxShapeUpdateEvent>,
     mut fetcher: Fetcher<(&mut HitboxShape, &piglin::Baby)>,
 ) {
     for (hitbox, baby) in fetcher.iter_mut() {
@@ -344,6 +363,7 @@ fn update_piglin_hitbox(
 }
 
 fn update_zoglin_hitbox(
+    _: Receiver<HitboxShapeUpdateEvent>,
     mut fetcher: Fetcher<(&mut HitboxShape, &zoglin::Baby)>,
 ) {
     for (hitbox, baby) in fetcher.iter_mut() {
@@ -352,7 +372,8 @@ fn update_zoglin_hitbox(
 }
 
 fn update_player_hitbox(
-    mut fetcher: Fetcher<(&mut HitboxShape, &entity::Pose, With<&Entity>)>,
+    _: Receiver<HitboxShapeUpdateEvent>,
+    mut fetcher: Fetcher<(&mut HitboxShape, &entity::Pose, With<&player::PlayerEntity>)>,
 ) {
     for (hitbox, pose, _) in fetcher.iter_mut() {
         hitbox.centered(
@@ -368,6 +389,7 @@ fn update_player_hitbox(
 }
 
 fn update_item_frame_hitbox(
+    _: Receiver<HitboxShapeUpdateEvent>,
     mut fetcher: Fetcher<(&mut HitboxShape, &item_frame::Rotation)>,
 ) {
     for (hitbox, rotation) in fetcher.iter_mut() {
@@ -398,6 +420,7 @@ fn update_item_frame_hitbox(
 }
 
 fn update_slime_hitbox(
+    _: Receiver<HitboxShapeUpdateEvent>,
     mut fetcher: Fetcher<(&mut HitboxShape, &slime::SlimeSize)>,
 ) {
     for (hitbox, slime_size) in fetcher.iter_mut() {
@@ -407,6 +430,7 @@ fn update_slime_hitbox(
 }
 
 fn update_painting_hitbox(
+    _: Receiver<HitboxShapeUpdateEvent>,
     mut fetcher: Fetcher<(&mut HitboxShape, &painting::Variant, &Look)>,
 ) {
     for (hitbox, painting_variant, look) in fetcher.iter_mut() {
@@ -471,6 +495,7 @@ fn update_painting_hitbox(
 }
 
 fn update_shulker_hitbox(
+    _: Receiver<HitboxShapeUpdateEvent>,
     mut fetcher: Fetcher<(&mut HitboxShape, &shulker::PeekAmount, &shulker::AttachedFace)>,
 ) {
     use std::f64::consts::PI;
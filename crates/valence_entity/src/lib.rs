@@ -22,6 +22,7 @@ pub mod active_status_effects;
 pub mod attributes;
 mod flags;
 pub mod hitbox;
+pub mod movement;
 pub mod query;
 pub mod tracked_data;
 
@@ -33,7 +34,7 @@ use tracing::warn;
 use tracked_data::TrackedData;
 use valence_math::{DVec3, Vec3};
 use valence_protocol::{Decode, Encode, VarInt};
-use valence_server_common::UniqueId;
+use valence_server_common::{PostUpdate, UniqueId};
 
 use crate::attributes::TrackedEntityAttributes;
 
@@ -44,7 +45,7 @@ pub struct EntityPlugin;
 /// When tracked data is written to the entity's [`TrackedData`] component.
 /// Systems that modify tracked data should run _before_ this.
 ///
-/// This set lives in [`PostUpdate`].
+/// This set is sent after every [`PostUpdate`] handler has run.
 #[derive(Event, Copy, Clone, PartialEq, Eq, Hash, Debug)]
 pub struct UpdateTrackedDataSet;
 
@@ -53,12 +54,14 @@ pub struct UpdateTrackedDataSet;
 /// between [`Position`] and [`OldPosition`]) should run _before_ this set (and
 /// probably after [`InitEntitiesSet`]).
 ///
-/// This set lives in [`PostUpdate`].
+/// This set is sent after [`UpdateTrackedDataSet`].
 #[derive(Event, Copy, Clone, PartialEq, Eq, Hash, Debug)]
 pub struct ClearEntityChangesSet;
 
 impl Plugin for EntityPlugin {
     fn build(&self, world: &mut World) {
+        world.add_handler(send_post_update_sets);
+
         // ClearEntityChangesSet
         world.add_handler(clear_status_changes);
         world.add_handler(clear_animation_changes);
@@ -71,25 +74,45 @@ impl Plugin for EntityPlugin {
     }
 }
 
-fn update_old_position(mut fetcher: Fetcher<(&Position, &mut OldPosition)>) {
+/// Runs [`UpdateTrackedDataSet`] followed by [`ClearEntityChangesSet`] once
+/// every other [`PostUpdate`] handler has run.
+fn send_post_update_sets(
+    _: Receiver<PostUpdate>,
+    mut sender: Sender<(UpdateTrackedDataSet, ClearEntityChangesSet)>,
+) {
+    sender.send(UpdateTrackedDataSet);
+    sender.send(ClearEntityChangesSet);
+}
+
+fn update_old_position(
+    _: Receiver<ClearEntityChangesSet>,
+    mut fetcher: Fetcher<(&Position, &mut OldPosition)>,
+) {
     for (pos, old_pos) in fetcher.iter_mut() {
         old_pos.0 = pos.0;
     }
 }
 
-fn update_old_layer_id(mut fetcher: Fetcher<(&EntityLayerId, &mut OldEntityLayerId)>) {
+fn update_old_layer_id(
+    _: Receiver<ClearEntityChangesSet>,
+    mut fetcher: Fetcher<(&EntityLayerId, &mut OldEntityLayerId)>,
+) {
     for (loc, old_loc) in fetcher.iter_mut() {
         old_loc.0 = loc.0;
     }
 }
 
-fn clear_status_changes(mut statuses: Fetcher<&mut EntityStatuses>) {
+fn clear_status_changes(
+    _: Receiver<ClearEntityChangesSet>,
+    mut statuses: Fetcher<&mut EntityStatuses>,
+) {
     for statuses in &mut statuses {
         statuses.0 = 0;
     }
 }
 
 fn clear_animation_changes(
+    _: Receiver<ClearEntityChangesSet>,
     mut animations: Fetcher<&mut EntityAnimations>,
 ) {
     for animations in &mut animations {
@@ -97,13 +120,17 @@ fn clear_animation_changes(
     }
 }
 
-fn clear_tracked_data_changes(mut tracked_data: Fetcher<&mut TrackedData>) {
+fn clear_tracked_data_changes(
+    _: Receiver<ClearEntityChangesSet>,
+    mut tracked_data: Fetcher<&mut TrackedData>,
+) {
     for tracked_data in &mut tracked_data {
         tracked_data.clear_update_values();
     }
 }
 
 fn clear_tracked_attributes_changes(
+    _: Receiver<ClearEntityChangesSet>,
     mut attributes: Fetcher<&mut TrackedEntityAttributes>,
 ) {
     for attributes in &mut attributes {
@@ -111,6 +138,12 @@ fn clear_tracked_attributes_changes(
     }
 }
 
+/// The ID of `entity` in packets. Every client is told its own ID is 0 when
+/// it joins, so IDs are offset by one to keep 0 free.
+pub fn protocol_id(entity: EntityId) -> VarInt {
+    VarInt(entity.index().0 as i32 + 1)
+}
+
 /// Contains the entity layer an entity is on.
 #[derive(Component, Copy, Clone, PartialEq, Eq, Debug, Deref)]
 pub struct EntityLayerId(pub EntityId);
@@ -0,0 +1,297 @@
+//! Chooses the packets that keep clients in sync with the position and
+//! rotation of an entity.
+//!
+//! Clients only learn where an entity is from the packets they are sent, so
+//! [`TrackedMovement`] remembers what they were told. Movement is sent as a
+//! delta from that position rather than from [`OldPosition`], so rounding
+//! deltas to 1/4096 of a block never adds up over time.
+//!
+//! Position and rotation are always sent together. The client handles
+//! [`MoveRelativeS2c`] and [`RotateS2c`] by resetting whichever half the
+//! packet doesn't include to the entity's currently interpolated value,
+//! which makes entities stutter when both change in quick succession
+//! (<https://bugs.mojang.com/browse/MC-255263>).
+//!
+//! [`OldPosition`]: crate::OldPosition
+//! [`MoveRelativeS2c`]: valence_protocol::packets::play::MoveRelativeS2c
+//! [`RotateS2c`]: valence_protocol::packets::play::RotateS2c
+
+use evenio::prelude::*;
+use valence_math::DVec3;
+use valence_protocol::ByteAngle;
+
+use crate::{Look, Velocity};
+
+/// How many ticks a moving entity goes without its absolute position being
+/// sent, like the game.
+pub const RESYNC_INTERVAL: u32 = 400;
+
+/// What the clients viewing an entity were last told about its movement.
+#[derive(Component, Clone, Default, Debug)]
+pub struct TrackedMovement {
+    /// `None` until the first update.
+    sent: Option<SentMovement>,
+    head_yaw: Option<ByteAngle>,
+    velocity: Option<[i16; 3]>,
+    ticks_since_resync: u32,
+}
+
+#[derive(Copy, Clone, Debug)]
+struct SentMovement {
+    position: DVec3,
+    yaw: ByteAngle,
+    pitch: ByteAngle,
+    on_ground: bool,
+}
+
+/// The packet bringing clients up to date with the movement of an entity.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum MovementPacket {
+    /// Nothing clients can see changed.
+    None,
+    /// A [`RotateAndMoveRelativeS2c`], with `delta` in 1/4096 of a block.
+    ///
+    /// [`RotateAndMoveRelativeS2c`]: valence_protocol::packets::play::RotateAndMoveRelativeS2c
+    Relative {
+        delta: [i16; 3],
+        yaw: ByteAngle,
+        pitch: ByteAngle,
+        on_ground: bool,
+    },
+    /// An [`EntityPositionS2c`] with the absolute position.
+    ///
+    /// [`EntityPositionS2c`]: valence_protocol::packets::play::EntityPositionS2c
+    Teleport {
+        position: DVec3,
+        yaw: ByteAngle,
+        pitch: ByteAngle,
+        on_ground: bool,
+    },
+}
+
+impl TrackedMovement {
+    /// The position clients were last told, or `None` before the first
+    /// update. Clients that start viewing the entity should be spawned here
+    /// so that later deltas apply to them too.
+    pub fn position(&self) -> Option<DVec3> {
+        self.sent.map(|sent| sent.position)
+    }
+
+    /// Chooses the packet that moves the entity to `position` with `look`
+    /// for clients and records it as sent. The first update only records the
+    /// movement, since new viewers are sent the whole entity.
+    pub fn update(&mut self, position: DVec3, look: Look, on_ground: bool) -> MovementPacket {
+        let yaw = ByteAngle::from_degrees(look.yaw);
+        let pitch = ByteAngle::from_degrees(look.pitch);
+
+        let Some(sent) = &mut self.sent else {
+            self.sent = Some(SentMovement {
+                position,
+                yaw,
+                pitch,
+                on_ground,
+            });
+            self.ticks_since_resync = 0;
+            return MovementPacket::None;
+        };
+
+        self.ticks_since_resync = self.ticks_since_resync.saturating_add(1);
+
+        let delta = ((position - sent.position) * 4096.0).round();
+        let delta_fits = delta.abs().max_element() <= i16::MAX as f64;
+        let delta = delta.to_array().map(|d| d as i16);
+
+        if delta == [0; 3] && yaw == sent.yaw && pitch == sent.pitch && on_ground == sent.on_ground
+        {
+            return MovementPacket::None;
+        }
+
+        if !delta_fits || self.ticks_since_resync >= RESYNC_INTERVAL {
+            *sent = SentMovement {
+                position,
+                yaw,
+                pitch,
+                on_ground,
+            };
+            self.ticks_since_resync = 0;
+
+            return MovementPacket::Teleport {
+                position,
+                yaw,
+                pitch,
+                on_ground,
+            };
+        }
+
+        sent.position += DVec3::from_array(delta.map(f64::from)) / 4096.0;
+        sent.yaw = yaw;
+        sent.pitch = pitch;
+        sent.on_ground = on_ground;
+
+        MovementPacket::Relative {
+            delta,
+            yaw,
+            pitch,
+            on_ground,
+        }
+    }
+
+    /// Returns the head yaw to send if it changed since the last call.
+    pub fn update_head_yaw(&mut self, head_yaw: f32) -> Option<ByteAngle> {
+        let head_yaw = ByteAngle::from_degrees(head_yaw);
+        (self.head_yaw.replace(head_yaw) != Some(head_yaw)).then_some(head_yaw)
+    }
+
+    /// Returns the velocity to send if it changed since the last call.
+    pub fn update_velocity(&mut self, velocity: Velocity) -> Option<valence_protocol::Velocity> {
+        let velocity = velocity.to_packet_units();
+        (self.velocity.replace(velocity.0) != Some(velocity.0)).then_some(velocity)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn look(yaw: f32, pitch: f32) -> Look {
+        Look::new(yaw, pitch)
+    }
+
+    #[test]
+    fn small_moves_are_relative_and_combined_with_rotation() {
+        let mut movement = TrackedMovement::default();
+        let start = DVec3::new(10.0, 64.0, -3.0);
+
+        assert_eq!(
+            movement.update(start, look(0.0, 0.0), true),
+            MovementPacket::None
+        );
+        assert_eq!(movement.position(), Some(start));
+
+        // Standing still sends nothing.
+        assert_eq!(
+            movement.update(start, look(0.0, 0.0), true),
+            MovementPacket::None
+        );
+
+        // Only moving still sends the rotation along.
+        assert_eq!(
+            movement.update(start + DVec3::new(0.5, 0.0, -0.25), look(0.0, 0.0), true),
+            MovementPacket::Relative {
+                delta: [2048, 0, -1024],
+                yaw: ByteAngle(0),
+                pitch: ByteAngle(0),
+                on_ground: true,
+            }
+        );
+
+        // Only turning still sends a zero delta.
+        assert_eq!(
+            movement.update(start + DVec3::new(0.5, 0.0, -0.25), look(90.0, -45.0), true),
+            MovementPacket::Relative {
+                delta: [0; 3],
+                yaw: ByteAngle(64),
+                pitch: ByteAngle(224),
+                on_ground: true,
+            }
+        );
+
+        // Landing is sent too.
+        assert!(matches!(
+            movement.update(start + DVec3::new(0.5, 0.0, -0.25), look(90.0, -45.0), false),
+            MovementPacket::Relative {
+                delta: [0, 0, 0],
+                on_ground: false,
+                ..
+            }
+        ));
+
+        // Turning less than a step of the protocol isn't visible.
+        assert_eq!(
+            movement.update(start + DVec3::new(0.5, 0.0, -0.25), look(90.3, -45.0), false),
+            MovementPacket::None
+        );
+    }
+
+    #[test]
+    fn big_moves_teleport() {
+        let mut movement = TrackedMovement::default();
+        movement.update(DVec3::ZERO, look(0.0, 0.0), true);
+
+        // The largest delta is just under 8 blocks.
+        assert!(matches!(
+            movement.update(DVec3::new(7.99, 0.0, 0.0), look(0.0, 0.0), true),
+            MovementPacket::Relative { .. }
+        ));
+
+        let far = DVec3::new(7.99, 0.0, -8.5);
+        assert_eq!(
+            movement.update(far, look(180.0, 0.0), true),
+            MovementPacket::Teleport {
+                position: far,
+                yaw: ByteAngle(128),
+                pitch: ByteAngle(0),
+                on_ground: true,
+            }
+        );
+        assert_eq!(movement.position(), Some(far));
+    }
+
+    #[test]
+    fn rounding_does_not_drift() {
+        let mut movement = TrackedMovement::default();
+        movement.update(DVec3::ZERO, look(0.0, 0.0), true);
+
+        // Each step rounds down to zero on its own, but the moves add up to
+        // whole protocol units relative to what was sent.
+        let step = 0.4 / 4096.0;
+        let mut client_position = DVec3::ZERO;
+
+        for i in 1..=100 {
+            let position = DVec3::new(step * i as f64, 0.0, 0.0);
+
+            if let MovementPacket::Relative { delta, .. } =
+                movement.update(position, look(0.0, 0.0), true)
+            {
+                client_position += DVec3::from_array(delta.map(f64::from)) / 4096.0;
+            }
+
+            assert!((client_position - position).abs().max_element() <= 0.5 / 4096.0);
+        }
+
+        assert_eq!(movement.position(), Some(client_position));
+    }
+
+    #[test]
+    fn absolute_position_is_resent_periodically() {
+        let mut movement = TrackedMovement::default();
+        movement.update(DVec3::ZERO, look(0.0, 0.0), true);
+
+        let mut teleports = vec![];
+        for tick in 1..=RESYNC_INTERVAL * 2 {
+            let position = DVec3::new(tick as f64 * 0.01, 0.0, 0.0);
+
+            if let MovementPacket::Teleport { .. } =
+                movement.update(position, look(0.0, 0.0), true)
+            {
+                teleports.push(tick);
+            }
+        }
+
+        assert_eq!(teleports, [RESYNC_INTERVAL, RESYNC_INTERVAL * 2]);
+    }
+
+    #[test]
+    fn head_yaw_and_velocity_are_sent_when_changed() {
+        let mut movement = TrackedMovement::default();
+
+        assert_eq!(movement.update_head_yaw(45.0), Some(ByteAngle(32)));
+        assert_eq!(movement.update_head_yaw(45.0), None);
+        assert_eq!(movement.update_head_yaw(-90.0), Some(ByteAngle(192)));
+
+        let velocity = Velocity([0.0, -1.0, 0.0].into());
+        assert!(movement.update_velocity(velocity).is_some());
+        assert!(movement.update_velocity(velocity).is_none());
+        assert!(movement.update_velocity(Velocity::default()).is_some());
+    }
+}
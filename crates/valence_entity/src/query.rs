@@ -6,33 +6,33 @@ use valence_protocol::encode::WritePacket;
 use valence_protocol::packets::play::{
     EntityAnimationS2c, EntityAttributesS2c, EntityPositionS2c, EntitySetHeadYawS2c,
     EntitySpawnS2c, EntityStatusS2c, EntityTrackerUpdateS2c, EntityVelocityUpdateS2c,
-    ExperienceOrbSpawnS2c, MoveRelativeS2c, PlayerSpawnS2c, RotateAndMoveRelativeS2c, RotateS2c,
+    ExperienceOrbSpawnS2c, PlayerSpawnS2c, RotateAndMoveRelativeS2c,
 };
-use valence_protocol::var_int::VarInt;
 use valence_protocol::ByteAngle;
 use valence_server_common::UniqueId;
 
 use crate::attributes::TrackedEntityAttributes;
+use crate::movement::{MovementPacket, TrackedMovement};
 use crate::tracked_data::TrackedData;
 use crate::{
-    EntityAnimations, EntityId, EntityKind, EntityLayerId, EntityStatuses, HeadYaw, Look,
-    ObjectData, OldEntityLayerId, OldPosition, OnGround, Position, Velocity,
+    protocol_id, EntityAnimations, EntityId, EntityKind, EntityLayerId, EntityStatuses, HeadYaw,
+    Look, ObjectData, OldEntityLayerId, OldPosition, OnGround, Position, Velocity,
 };
 
 #[derive(Query)]
-pub struct EntityInitQuery {
-    pub entity_id: &'static EntityId,
-    pub uuid: &'static UniqueId,
-    pub kind: &'static EntityKind,
-    pub look: &'static Look,
-    pub head_yaw: &'static HeadYaw,
-    pub on_ground: &'static OnGround,
-    pub object_data: &'static ObjectData,
-    pub velocity: &'static Velocity,
-    pub tracked_data: &'static TrackedData,
+pub struct EntityInitQuery<'a> {
+    pub entity_id: EntityId,
+    pub uuid: &'a UniqueId,
+    pub kind: &'a EntityKind,
+    pub look: &'a Look,
+    pub head_yaw: &'a HeadYaw,
+    pub on_ground: &'a OnGround,
+    pub object_data: &'a ObjectData,
+    pub velocity: &'a Velocity,
+    pub tracked_data: &'a TrackedData,
 }
 
-impl EntityInitQuery {
+impl EntityInitQuery<'_> {
     /// Writes the appropriate packets to initialize an entity. This will spawn
     /// the entity and initialize tracked data. `pos` is the initial position of
     /// the entity.
@@ -41,14 +41,14 @@ impl EntityInitQuery {
             EntityKind::MARKER => {}
             EntityKind::EXPERIENCE_ORB => {
                 writer.write_packet(&ExperienceOrbSpawnS2c {
-                    entity_id: (self.entity_id.index().0 as i32).into(),
+                    entity_id: protocol_id(self.entity_id),
                     position: pos,
                     count: self.object_data.0 as i16,
                 });
             }
             EntityKind::PLAYER => {
                 writer.write_packet(&PlayerSpawnS2c {
-                    entity_id: (self.entity_id.index().0 as i32).into(),
+                    entity_id: protocol_id(self.entity_id),
                     player_uuid: self.uuid.0,
                     position: pos,
                     yaw: ByteAngle::from_degrees(self.look.yaw),
@@ -57,12 +57,12 @@ impl EntityInitQuery {
 
                 // Player spawn packet doesn't include head yaw for some reason.
                 writer.write_packet(&EntitySetHeadYawS2c {
-                    entity_id: (self.entity_id.index().0 as i32).into(),
+                    entity_id: protocol_id(self.entity_id),
                     head_yaw: ByteAngle::from_degrees(self.head_yaw.0),
                 });
             }
             _ => writer.write_packet(&EntitySpawnS2c {
-                entity_id: (self.entity_id.index().0 as i32).into(),
+                entity_id: protocol_id(self.entity_id),
                 object_uuid: self.uuid.0,
                 kind: self.kind.get().into(),
                 position: pos,
@@ -76,89 +76,79 @@ impl EntityInitQuery {
 
         if let Some(init_data) = self.tracked_data.init_data() {
             writer.write_packet(&EntityTrackerUpdateS2c {
-                entity_id: (self.entity_id.index().0 as i32).into(),
+                entity_id: protocol_id(self.entity_id),
                 tracked_values: init_data.into(),
             });
         }
     }
 }
 
-// #[derive(Query)]
-pub struct UpdateEntityQuery {
-    pub id: &'static EntityId,
-    pub pos: &'static Position,
-    pub old_pos: &'static OldPosition,
-    pub loc: &'static EntityLayerId,
-    pub old_loc: &'static OldEntityLayerId,
-    pub look: &'static Look,
-    pub head_yaw: &'static HeadYaw,
-    pub on_ground: &'static OnGround,
-    pub velocity: &'static Velocity,
-    pub tracked_data: &'static TrackedData,
-    pub statuses: &'static EntityStatuses,
-    pub animations: &'static EntityAnimations,
+#[derive(Query)]
+pub struct UpdateEntityQuery<'a> {
+    pub id: EntityId,
+    pub pos: &'a Position,
+    pub old_pos: &'a OldPosition,
+    pub loc: &'a EntityLayerId,
+    pub old_loc: &'a OldEntityLayerId,
+    pub look: &'a Look,
+    pub head_yaw: &'a HeadYaw,
+    pub on_ground: &'a OnGround,
+    pub velocity: &'a Velocity,
+    pub tracked_data: &'a TrackedData,
+    pub statuses: &'a EntityStatuses,
+    pub animations: &'a EntityAnimations,
+    pub movement: &'a mut TrackedMovement,
     // Option because not all entities have attributes, only LivingEntity.
-    pub tracked_attributes: Option<&'static TrackedEntityAttributes>,
+    pub tracked_attributes: Option<&'a TrackedEntityAttributes>,
 }
 
-impl UpdateEntityQuery {
-    pub fn write_update_packets(&self, mut writer: impl WritePacket) {
-        // TODO: @RJ I saw you're using UpdateEntityPosition and UpdateEntityRotation sometimes. These two packets are actually broken on the client and will erase previous position/rotation https://bugs.mojang.com/browse/MC-255263 -Moulberry
-
-        let entity_id: VarInt = (self.id.index().0 as i32).into();
-
-        let position_delta = self.pos.0 - self.old_pos.get();
-        let needs_teleport = position_delta.abs().max_element() >= 8.0;
-        let changed_position = self.pos.0 != self.old_pos.get();
-
-        if changed_position && !needs_teleport /* && self.look.is_changed() */ {
-            writer.write_packet(&RotateAndMoveRelativeS2c {
+impl UpdateEntityQuery<'_> {
+    /// Writes the packets bringing clients that already see the entity up to
+    /// date with the changes made to it since the last call. Must be called
+    /// every tick, whether or not anyone sees the entity, so that movement is
+    /// sent relative to what clients were last told.
+    pub fn write_update_packets(&mut self, mut writer: impl WritePacket) {
+        let entity_id = protocol_id(self.id);
+
+        match self.movement.update(self.pos.0, *self.look, self.on_ground.0) {
+            MovementPacket::None => {}
+            MovementPacket::Relative {
+                delta,
+                yaw,
+                pitch,
+                on_ground,
+            } => writer.write_packet(&RotateAndMoveRelativeS2c {
                 entity_id,
-                delta: (position_delta * 4096.0).to_array().map(|v| v as i16),
-                yaw: ByteAngle::from_degrees(self.look.yaw),
-                pitch: ByteAngle::from_degrees(self.look.pitch),
-                on_ground: self.on_ground.0,
-            });
-        } else {
-            if changed_position && !needs_teleport {
-                writer.write_packet(&MoveRelativeS2c {
-                    entity_id,
-                    delta: (position_delta * 4096.0).to_array().map(|v| v as i16),
-                    on_ground: self.on_ground.0,
-                });
-            }
-
-            if true /* self.look.is_changed() */ {
-                writer.write_packet(&RotateS2c {
-                    entity_id,
-                    yaw: ByteAngle::from_degrees(self.look.yaw),
-                    pitch: ByteAngle::from_degrees(self.look.pitch),
-                    on_ground: self.on_ground.0,
-                });
-            }
-        }
-
-        if needs_teleport {
-            writer.write_packet(&EntityPositionS2c {
+                delta,
+                yaw,
+                pitch,
+                on_ground,
+            }),
+            MovementPacket::Teleport {
+                position,
+                yaw,
+                pitch,
+                on_ground,
+            } => writer.write_packet(&EntityPositionS2c {
                 entity_id,
-                position: self.pos.0,
-                yaw: ByteAngle::from_degrees(self.look.yaw),
-                pitch: ByteAngle::from_degrees(self.look.pitch),
-                on_ground: self.on_ground.0,
-            });
+                position,
+                yaw,
+                pitch,
+                on_ground,
+            }),
         }
 
-        if true /* self.velocity.is_changed() */ {
+        if let Some(velocity) = self.movement.update_velocity(*self.velocity) {
             writer.write_packet(&EntityVelocityUpdateS2c {
                 entity_id,
-                velocity: self.velocity.to_packet_units(),
+                velocity,
             });
         }
 
-        if true /* self.head_yaw.is_changed() */ {
+        if let Some(head_yaw) = self.movement.update_head_yaw(self.head_yaw.0) {
             writer.write_packet(&EntitySetHeadYawS2c {
                 entity_id,
-                head_yaw: ByteAngle::from_degrees(self.head_yaw.0),
+                head_yaw,
             });
         }
 
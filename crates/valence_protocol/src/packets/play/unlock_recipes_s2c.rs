@@ -57,8 +57,29 @@ impl<'a> Decode<'a> for UnlockRecipesS2c<'a> {
 }
 
 impl Encode for UnlockRecipesS2c<'_> {
-    fn encode(&self, _w: impl Write) -> anyhow::Result<()> {
-        todo!()
+    fn encode(&self, mut w: impl Write) -> anyhow::Result<()> {
+        VarInt(match self.action {
+            UpdateRecipeBookAction::Init { .. } => 0,
+            UpdateRecipeBookAction::Add => 1,
+            UpdateRecipeBookAction::Remove => 2,
+        })
+        .encode(&mut w)?;
+
+        self.crafting_recipe_book_open.encode(&mut w)?;
+        self.crafting_recipe_book_filter_active.encode(&mut w)?;
+        self.smelting_recipe_book_open.encode(&mut w)?;
+        self.smelting_recipe_book_filter_active.encode(&mut w)?;
+        self.blast_furnace_recipe_book_open.encode(&mut w)?;
+        self.blast_furnace_recipe_book_filter_active.encode(&mut w)?;
+        self.smoker_recipe_book_open.encode(&mut w)?;
+        self.smoker_recipe_book_filter_active.encode(&mut w)?;
+        self.recipe_ids.encode(&mut w)?;
+
+        if let UpdateRecipeBookAction::Init { recipe_ids } = &self.action {
+            recipe_ids.encode(&mut w)?;
+        }
+
+        Ok(())
     }
 }
 
@@ -21,8 +21,10 @@
 mod uuid;
 
 use std::num::NonZeroU32;
+use std::time::Duration;
 
 use evenio::prelude::*;
+use evenio_plugin::Plugin;
 use valence_protocol::CompressionThreshold;
 
 pub use crate::uuid::*;
@@ -73,38 +75,75 @@ impl Default for ServerSettings {
     }
 }
 
+/// Sent at the start of every tick, before [`Tick`]. Work that should see the
+/// world as it was at the end of the previous tick (like reading incoming
+/// packets) happens here.
 #[derive(Debug, Event)]
-pub struct Tick; // TODO: add tick to everything
+pub struct PreUpdate;
 
-pub struct ServerPlugin;
+/// Sent once per tick after [`PreUpdate`]. Most game logic lives here.
+#[derive(Debug, Event)]
+pub struct Tick;
+
+/// Sent after [`Tick`]. Work that observes the changes made during the tick
+/// (like broadcasting them to clients) happens here.
+#[derive(Debug, Event)]
+pub struct PostUpdate;
+
+/// Sent at the very end of every tick, after [`PostUpdate`]. The tick counter
+/// is incremented and packets are flushed here.
+#[derive(Debug, Event)]
+pub struct Last;
+
+/// Runs a single game update by sending [`PreUpdate`], [`Tick`],
+/// [`PostUpdate`] and [`Last`] in that order.
+///
+/// Every event sent by the handlers of one stage is handled before the next
+/// stage begins.
+pub fn run_tick(world: &mut World) {
+    world.send(PreUpdate);
+    world.send(Tick);
+    world.send(PostUpdate);
+    world.send(Last);
+}
 
-// impl Plugin for ServerPlugin {
-//     fn build(&self, app: &mut App) {
-//         let settings = app
-//             .world
-//             .get_resource_or_insert_with(ServerSettings::default)
-//             .clone();
+/// Adds the [`Server`] and [`ServerSettings`] to the world and keeps the tick
+/// counter up to date. The game loop itself is driven by calling [`run_tick`]
+/// at [`ServerSettings::tick_rate`].
+#[derive(Clone, Default)]
+pub struct ServerPlugin {
+    pub settings: ServerSettings,
+}
 
-//         app.insert_resource(Server {
-//             current_tick: 0,
-//             threshold: settings.compression_threshold,
-//             tick_rate: settings.tick_rate,
-//         });
+impl Plugin for ServerPlugin {
+    fn build(&self, world: &mut World) {
+        let settings = self.settings.clone();
 
-//         let tick_period = Duration::from_secs_f64((settings.tick_rate.get() as f64).recip());
+        let server = world.spawn();
+        world.insert(server, Server {
+            current_tick: 0,
+            threshold: settings.compression_threshold,
+            tick_rate: settings.tick_rate,
+        });
+        world.insert(server, settings);
 
-//         // Make the app loop forever at the configured TPS.
-//         app.add_plugins(ScheduleRunnerPlugin::run_loop(tick_period));
+        world.add_handler(increment_tick_counter.low());
+    }
+}
 
-//         fn increment_tick_counter(mut server: ResMut<Server>) {
-//             server.current_tick += 1;
-//         }
+fn increment_tick_counter(_: Receiver<Last>, server: Single<&mut Server>) {
+    server.0.current_tick += 1;
+}
 
-//         app.add_systems(Last, (increment_tick_counter, despawn_marked_entities));
-//     }
-// }
+impl ServerSettings {
+    /// The duration of a single tick at the configured tick rate.
+    pub fn tick_period(&self) -> Duration {
+        Duration::from_secs_f64((self.tick_rate.get() as f64).recip())
+    }
+}
 
-/// Contains global server state accessible as a [`Resource`].
+/// Contains global server state accessible as a [`Component`] on a single
+/// entity.
 #[derive(Component)]
 pub struct Server {
     /// Incremented on every tick.
@@ -125,7 +164,7 @@ impl Server {
         self.threshold
     }
 
-    // Returns the server's [tick rate](ServerPlugin::tick_rate).
+    /// Returns the server's [tick rate](ServerSettings::tick_rate).
     pub fn tick_rate(&self) -> NonZeroU32 {
         self.tick_rate
     }
@@ -0,0 +1,977 @@
+//! Loads chunks from vanilla worlds saved in the Anvil format.
+//!
+//! An [`AnvilLevel`] on a [`ChunkLayer`] entity reads the chunks clients can
+//! see from the world's region files on a worker thread. Loaded chunks are
+//! inserted into the layer at the start of the next tick and announced with a
+//! [`ChunkLoadEvent`].
+//!
+//! Modified chunks are saved back to the region files on the same thread
+//! every [`AnvilLevel::autosave_interval`] ticks, or when
+//! [`AnvilLevel::save`] is called.
+
+mod parse;
+mod serialize;
+
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use evenio::prelude::*;
+use flate2::bufread::{GzDecoder, ZlibDecoder};
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+use thiserror::Error;
+use tracing::warn;
+use valence_protocol::nbt::{self, Compound};
+use valence_protocol::ChunkPos;
+use valence_server_common::{PreUpdate, Tick};
+
+pub use self::parse::{parse_chunk, ParseChunkError};
+pub use self::serialize::chunk_to_nbt;
+use crate::chunk::view::{spiral, ChunkView, VisibleChunkLayer};
+use crate::chunk::{BiomeId, Chunk, ChunkLayer};
+use crate::registry::{RegistryCodec, BIOME};
+
+const SECTOR_SIZE: usize = 4096;
+/// Chunks longer than this are stored in their own `.mcc` file.
+const MAX_SECTOR_COUNT: usize = 255;
+
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum RegionError {
+    #[error("an I/O error occurred: {0}")]
+    Io(#[from] io::Error),
+    #[error("invalid chunk sector offset")]
+    InvalidChunkSectorOffset,
+    #[error("invalid chunk size")]
+    InvalidChunkSize,
+    #[error("invalid compression scheme number of {0}")]
+    InvalidCompressionScheme(u8),
+    #[error("invalid chunk NBT: {0}")]
+    InvalidNbt(#[from] nbt::Error),
+}
+
+/// A region file that [`RegionFolder::set_chunks`] couldn't write.
+#[derive(Debug, Error)]
+#[error("failed to write region ({region_x}, {region_z}): {source}")]
+pub struct RegionWriteError {
+    pub region_x: i32,
+    pub region_z: i32,
+    /// The chunks in the region that weren't saved.
+    pub chunks: Vec<ChunkPos>,
+    #[source]
+    pub source: RegionError,
+}
+
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum AnvilError {
+    #[error(transparent)]
+    Region(#[from] RegionError),
+    #[error(transparent)]
+    Parse(#[from] ParseChunkError),
+}
+
+/// The NBT of a chunk as stored in a region file.
+#[derive(Clone, Debug)]
+pub struct RawChunk {
+    pub data: Compound,
+    /// When the chunk was last saved, in seconds since the Unix epoch.
+    pub timestamp: u32,
+}
+
+/// The region files of one dimension, e.g. `<world>/region`. Region files are
+/// opened on first use and kept open.
+#[derive(Debug)]
+pub struct RegionFolder {
+    region_root: PathBuf,
+    regions: HashMap<(i32, i32), Option<Region>>,
+}
+
+impl RegionFolder {
+    pub fn new(region_root: impl Into<PathBuf>) -> Self {
+        Self {
+            region_root: region_root.into(),
+            regions: HashMap::new(),
+        }
+    }
+
+    pub fn region_root(&self) -> &Path {
+        &self.region_root
+    }
+
+    /// Reads the chunk at `pos`. Returns `Ok(None)` if the chunk or its region
+    /// file doesn't exist.
+    pub fn get_chunk(&mut self, pos: ChunkPos) -> Result<Option<RawChunk>, RegionError> {
+        let region = open_region(
+            &mut self.regions,
+            &self.region_root,
+            pos.x.div_euclid(32),
+            pos.z.div_euclid(32),
+        )?;
+
+        match region {
+            Some(region) => region.get_chunk(pos, &self.region_root),
+            None => Ok(None),
+        }
+    }
+
+    /// Writes `chunks` to their region files, replacing the chunks already
+    /// there.
+    ///
+    /// Every region file is rewritten to a temporary file which is then
+    /// renamed over the original, so a crash while saving leaves either the
+    /// old or the new region behind and never a mix of both.
+    ///
+    /// A region that fails to write doesn't stop the others from being
+    /// written. Every failed region is returned.
+    pub fn set_chunks(
+        &mut self,
+        chunks: impl IntoIterator<Item = (ChunkPos, Compound)>,
+    ) -> Result<(), Vec<RegionWriteError>> {
+        let mut regions: HashMap<_, Vec<_>> = HashMap::new();
+
+        for (pos, nbt) in chunks {
+            regions
+                .entry((pos.x.div_euclid(32), pos.z.div_euclid(32)))
+                .or_default()
+                .push((pos, nbt));
+        }
+
+        let mut errors = vec![];
+
+        for ((region_x, region_z), chunks) in regions {
+            let positions = chunks.iter().map(|(pos, _)| *pos).collect();
+
+            if let Err(source) = self.write_region(region_x, region_z, chunks) {
+                errors.push(RegionWriteError {
+                    region_x,
+                    region_z,
+                    chunks: positions,
+                    source,
+                });
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    fn write_region(
+        &mut self,
+        region_x: i32,
+        region_z: i32,
+        chunks: Vec<(ChunkPos, Compound)>,
+    ) -> Result<(), RegionError> {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_secs() as u32);
+
+        let mut entries: Vec<Option<RegionEntry>> = (0..1024).map(|_| None).collect();
+        let mut replaced = [false; 1024];
+
+        for (pos, nbt) in chunks {
+            let mut data = vec![];
+            nbt::to_binary(&nbt, &mut data, "")?;
+
+            let mut encoder = ZlibEncoder::new(vec![], Compression::default());
+            encoder.write_all(&data)?;
+
+            let idx = chunk_idx(pos);
+            entries[idx] = Some(RegionEntry {
+                timestamp,
+                compression: 2,
+                data: encoder.finish()?,
+            });
+            replaced[idx] = true;
+        }
+
+        // Keep the chunks that weren't replaced. Failing to read one of them
+        // aborts the save instead of silently dropping it.
+        let region = open_region(&mut self.regions, &self.region_root, region_x, region_z)?;
+
+        if let Some(region) = region {
+            for (idx, entry) in entries.iter_mut().enumerate() {
+                if !replaced[idx] {
+                    let pos = region_pos(region_x, region_z, idx);
+                    *entry = region.read_entry(pos, &self.region_root)?;
+                }
+            }
+        }
+
+        // The region is reopened the next time it's read.
+        self.regions.remove(&(region_x, region_z));
+
+        fs::create_dir_all(&self.region_root)?;
+
+        let mut file = vec![0; SECTOR_SIZE * 2];
+        let mut external_paths = vec![];
+
+        for (idx, entry) in entries.iter().enumerate() {
+            let Some(entry) = entry else {
+                continue;
+            };
+
+            let pos = region_pos(region_x, region_z, idx);
+            let external_path = self.region_root.join(format!("c.{}.{}.mcc", pos.x, pos.z));
+            let start = file.len();
+
+            if entry.data.len() + 5 > MAX_SECTOR_COUNT * SECTOR_SIZE {
+                write_atomically(&external_path, &entry.data)?;
+
+                file.extend(1_u32.to_be_bytes());
+                file.push(entry.compression | 0x80);
+            } else {
+                if replaced[idx] {
+                    external_paths.push(external_path);
+                }
+
+                file.extend((entry.data.len() as u32 + 1).to_be_bytes());
+                file.push(entry.compression);
+                file.extend(&entry.data);
+            }
+
+            let sector_offset = start / SECTOR_SIZE;
+            let sector_count = (file.len() - start).div_ceil(SECTOR_SIZE);
+            file.resize(start + sector_count * SECTOR_SIZE, 0);
+
+            let location = (sector_offset as u32) << 8 | sector_count as u32;
+            file[idx * 4..idx * 4 + 4].copy_from_slice(&location.to_be_bytes());
+            file[SECTOR_SIZE + idx * 4..SECTOR_SIZE + idx * 4 + 4]
+                .copy_from_slice(&entry.timestamp.to_be_bytes());
+        }
+
+        write_atomically(
+            &self
+                .region_root
+                .join(format!("r.{region_x}.{region_z}.mca")),
+            &file,
+        )?;
+
+        // Chunks that used to be too big for the region file may have left an
+        // external file behind.
+        for path in external_paths {
+            match fs::remove_file(path) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e.into()),
+                _ => {}
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn open_region<'a>(
+    regions: &'a mut HashMap<(i32, i32), Option<Region>>,
+    region_root: &Path,
+    region_x: i32,
+    region_z: i32,
+) -> Result<Option<&'a mut Region>, RegionError> {
+    let region = match regions.entry((region_x, region_z)) {
+        Entry::Occupied(entry) => entry.into_mut(),
+        Entry::Vacant(entry) => {
+            let path = region_root.join(format!("r.{region_x}.{region_z}.mca"));
+
+            let region = match File::open(path) {
+                Ok(file) => Some(Region::open(file)?),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+                Err(e) => return Err(e.into()),
+            };
+
+            entry.insert(region)
+        }
+    };
+
+    Ok(region.as_mut())
+}
+
+/// Writes `data` to a temporary file next to `path` and renames it to `path`.
+fn write_atomically(path: &Path, data: &[u8]) -> io::Result<()> {
+    let mut tmp_path = path.as_os_str().to_owned();
+    tmp_path.push(".tmp");
+
+    let mut file = File::create(&tmp_path)?;
+    file.write_all(data)?;
+    file.sync_all()?;
+    drop(file);
+
+    fs::rename(&tmp_path, path)
+}
+
+/// A chunk in a region file, still compressed.
+#[derive(Debug)]
+struct RegionEntry {
+    timestamp: u32,
+    compression: u8,
+    data: Vec<u8>,
+}
+
+#[derive(Debug)]
+struct Region {
+    file: File,
+    /// The sector offset and sector count of each chunk, packed as in the
+    /// file header.
+    locations: [u32; 1024],
+    timestamps: [u32; 1024],
+}
+
+impl Region {
+    fn open(mut file: File) -> Result<Self, RegionError> {
+        let mut header = [0; SECTOR_SIZE * 2];
+
+        match file.read_exact(&mut header) {
+            Ok(()) => {}
+            // A file cut short before its header was written, such as an empty
+            // file left behind by a crash, holds no chunks.
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => header.fill(0),
+            Err(e) => return Err(e.into()),
+        }
+
+        let read = |i: usize| u32::from_be_bytes(header[i * 4..i * 4 + 4].try_into().unwrap());
+
+        Ok(Self {
+            file,
+            locations: std::array::from_fn(read),
+            timestamps: std::array::from_fn(|i| read(i + 1024)),
+        })
+    }
+
+    fn get_chunk(
+        &mut self,
+        pos: ChunkPos,
+        region_root: &Path,
+    ) -> Result<Option<RawChunk>, RegionError> {
+        let Some(entry) = self.read_entry(pos, region_root)? else {
+            return Ok(None);
+        };
+
+        let data = decompress(entry.compression, entry.data)?;
+        let (data, _) = nbt::from_binary::<String>(&mut data.as_slice())?;
+
+        Ok(Some(RawChunk {
+            data,
+            timestamp: entry.timestamp,
+        }))
+    }
+
+    fn read_entry(
+        &mut self,
+        pos: ChunkPos,
+        region_root: &Path,
+    ) -> Result<Option<RegionEntry>, RegionError> {
+        let idx = chunk_idx(pos);
+        let location = self.locations[idx];
+        let timestamp = self.timestamps[idx];
+
+        if location == 0 {
+            return Ok(None);
+        }
+
+        let sector_offset = (location >> 8) as u64;
+        let sector_count = (location & 0xff) as usize;
+
+        // The first two sectors are the header.
+        if sector_offset < 2 {
+            return Err(RegionError::InvalidChunkSectorOffset);
+        }
+
+        self.file
+            .seek(SeekFrom::Start(sector_offset * SECTOR_SIZE as u64))?;
+
+        let mut header = [0; 5];
+        self.file.read_exact(&mut header)?;
+
+        let len = u32::from_be_bytes(header[..4].try_into().unwrap()) as usize;
+        let compression = header[4];
+
+        if len == 0 || len + 4 > sector_count * SECTOR_SIZE {
+            return Err(RegionError::InvalidChunkSize);
+        }
+
+        let data = if compression & 0x80 != 0 {
+            // The chunk is too big for the region file and is stored in its own
+            // file.
+            let path = region_root.join(format!("c.{}.{}.mcc", pos.x, pos.z));
+            fs::read(path)?
+        } else {
+            let mut data = vec![0; len - 1];
+            self.file.read_exact(&mut data)?;
+            data
+        };
+
+        Ok(Some(RegionEntry {
+            timestamp,
+            compression: compression & 0x7f,
+            data,
+        }))
+    }
+}
+
+fn decompress(compression: u8, data: Vec<u8>) -> Result<Vec<u8>, RegionError> {
+    let mut out = vec![];
+
+    match compression {
+        1 => {
+            GzDecoder::new(data.as_slice()).read_to_end(&mut out)?;
+        }
+        2 => {
+            ZlibDecoder::new(data.as_slice()).read_to_end(&mut out)?;
+        }
+        3 => return Ok(data),
+        n => return Err(RegionError::InvalidCompressionScheme(n)),
+    }
+
+    Ok(out)
+}
+
+/// The index of a chunk in the header of its region file.
+fn chunk_idx(pos: ChunkPos) -> usize {
+    (pos.x.rem_euclid(32) + pos.z.rem_euclid(32) * 32) as usize
+}
+
+/// The inverse of [`chunk_idx`].
+fn region_pos(region_x: i32, region_z: i32, idx: usize) -> ChunkPos {
+    ChunkPos::new(region_x * 32 + (idx % 32) as i32, region_z * 32 + (idx / 32) as i32)
+}
+
+/// Loads the chunks of its [`ChunkLayer`] from an Anvil world and saves them
+/// back.
+#[derive(Component, Debug)]
+pub struct AnvilLevel {
+    requests: flume::Sender<WorkerRequest>,
+    results: flume::Receiver<(ChunkPos, Result<Option<Chunk>, AnvilError>)>,
+    /// The chunks of the saves that failed.
+    failed_saves: flume::Receiver<Vec<ChunkPos>>,
+    /// Chunks that were requested but haven't been received yet.
+    pending: HashSet<ChunkPos>,
+    /// Chunks that don't exist in the world or couldn't be read, and whether
+    /// a client saw them missing during the current tick. They aren't read
+    /// again until no client sees them missing anymore.
+    unavailable: HashMap<ChunkPos, bool>,
+    /// The number of ticks between saves of the modified chunks, or `None` to
+    /// only save when [`AnvilLevel::save`] is called. Defaults to 6000 ticks,
+    /// five minutes at 20 ticks per second.
+    pub autosave_interval: Option<u32>,
+    ticks_since_save: u32,
+}
+
+#[derive(Debug)]
+enum WorkerRequest {
+    Load(ChunkPos),
+    Save(Vec<(ChunkPos, Chunk)>),
+}
+
+impl AnvilLevel {
+    /// Starts loading chunks for `layer` from `region_root`, the directory
+    /// with the `.mca` files of the dimension.
+    pub fn new(region_root: impl Into<PathBuf>, codec: &RegistryCodec, layer: &ChunkLayer) -> Self {
+        let mut folder = RegionFolder::new(region_root);
+
+        let biome_names: Vec<_> = codec
+            .registry(BIOME)
+            .iter()
+            .map(|value| value.name.to_string())
+            .collect();
+
+        let biomes: HashMap<_, _> = biome_names
+            .iter()
+            .enumerate()
+            .map(|(id, name)| (name.clone(), BiomeId(id as u16)))
+            .collect();
+
+        let min_y = layer.min_y();
+        let height = layer.height();
+
+        let (request_sender, request_receiver) = flume::unbounded();
+        let (result_sender, result_receiver) = flume::unbounded();
+        let (failed_sender, failed_receiver) = flume::unbounded();
+
+        // Loads and saves share a thread so a chunk is never read while it's
+        // being written.
+        thread::spawn(move || {
+            // Stops once the level is dropped and every save is written.
+            for request in request_receiver {
+                match request {
+                    WorkerRequest::Load(pos) => {
+                        let result = folder
+                            .get_chunk(pos)
+                            .map_err(AnvilError::from)
+                            .and_then(|raw| match raw {
+                                Some(raw) => {
+                                    Ok(Some(parse_chunk(raw.data, &biomes, min_y, height)?))
+                                }
+                                None => Ok(None),
+                            });
+
+                        // The level is gone, but queued saves still need to be
+                        // written.
+                        let _ = result_sender.send((pos, result));
+                    }
+                    WorkerRequest::Save(chunks) => {
+                        let chunks = chunks.into_iter().map(|(pos, chunk)| {
+                            (pos, chunk_to_nbt(&chunk, pos, &biome_names, min_y))
+                        });
+
+                        if let Err(errors) = folder.set_chunks(chunks) {
+                            let mut positions = vec![];
+
+                            for e in errors {
+                                warn!("failed to save {} chunks: {e}", e.chunks.len());
+                                positions.extend(e.chunks);
+                            }
+
+                            let _ = failed_sender.send(positions);
+                        }
+                    }
+                }
+            }
+        });
+
+        Self {
+            requests: request_sender,
+            results: result_receiver,
+            failed_saves: failed_receiver,
+            pending: HashSet::new(),
+            unavailable: HashMap::new(),
+            autosave_interval: Some(6000),
+            ticks_since_save: 0,
+        }
+    }
+
+    /// Queues the chunk at `pos` to be loaded. Does nothing if it's already
+    /// queued.
+    pub fn load(&mut self, pos: impl Into<ChunkPos>) {
+        let pos = pos.into();
+
+        if self.pending.insert(pos) {
+            let _ = self.requests.send(WorkerRequest::Load(pos));
+        }
+    }
+
+    /// Queues every modified chunk of `layer` to be saved and marks them as
+    /// saved. The chunks are copied, so they can keep changing while they're
+    /// written. Chunks whose previous save failed are saved again.
+    pub fn save(&mut self, layer: &mut ChunkLayer) {
+        self.ticks_since_save = 0;
+        self.mark_failed_saves(layer);
+
+        let chunks: Vec<_> = layer
+            .chunks_mut()
+            .filter(|(_, chunk)| chunk.is_modified())
+            .map(|(pos, chunk)| {
+                chunk.clear_modified();
+                (pos, chunk.clone())
+            })
+            .collect();
+
+        if !chunks.is_empty() {
+            let _ = self.requests.send(WorkerRequest::Save(chunks));
+        }
+    }
+
+    /// Marks the chunks whose save failed as modified, so the next save
+    /// retries them.
+    fn mark_failed_saves(&mut self, layer: &mut ChunkLayer) {
+        for pos in self.failed_saves.try_iter().flatten() {
+            // Unloaded chunks can't be saved anymore.
+            if let Some(chunk) = layer.chunk_mut(pos) {
+                chunk.mark_modified();
+            }
+        }
+    }
+
+    /// Whether the chunk at `pos` is being loaded.
+    pub fn is_pending(&self, pos: impl Into<ChunkPos>) -> bool {
+        self.pending.contains(&pos.into())
+    }
+
+    /// Loads the chunk at `pos`, which a client sees missing from the layer,
+    /// unless it's already known to be missing from the world.
+    fn request(&mut self, pos: ChunkPos) {
+        match self.unavailable.get_mut(&pos) {
+            Some(seen) => *seen = true,
+            None => self.load(pos),
+        }
+    }
+
+    /// Forgets the unavailable chunks no client saw missing since the previous
+    /// call, so they're read again the next time they're needed.
+    fn forget_unseen(&mut self) {
+        self.unavailable.retain(|_, seen| mem::take(seen));
+    }
+}
+
+/// Sent after a chunk requested by an [`AnvilLevel`] was read.
+#[derive(Event, Debug)]
+pub struct ChunkLoadEvent {
+    #[event(target)]
+    pub layer: EntityId,
+    pub pos: ChunkPos,
+    pub status: ChunkLoadStatus,
+}
+
+#[derive(Debug)]
+pub enum ChunkLoadStatus {
+    /// The chunk was loaded and inserted into the layer.
+    Success,
+    /// The chunk doesn't exist in the world.
+    Empty,
+    /// The chunk couldn't be read.
+    Failed(AnvilError),
+}
+
+/// Requests the chunks in view of every client that aren't loaded yet.
+pub fn request_chunks(
+    _: Receiver<Tick>,
+    clients: Fetcher<(&ChunkView, &VisibleChunkLayer)>,
+    mut layers: Fetcher<(&ChunkLayer, &mut AnvilLevel)>,
+) {
+    for (view, visible_layer) in clients.iter() {
+        let Some(center) = view.center() else {
+            continue;
+        };
+
+        let Ok((layer, level)) = layers.get_mut(visible_layer.0) else {
+            continue;
+        };
+
+        for pos in spiral(center, view.view_distance()) {
+            if layer.chunk(pos).is_none() {
+                level.request(pos);
+            }
+        }
+    }
+
+    // Unavailable chunks that were inserted by something else or left the
+    // view of every client are read again when they're needed.
+    for (_, level) in layers.iter_mut() {
+        level.forget_unseen();
+    }
+}
+
+/// Saves the modified chunks of every layer whose autosave interval elapsed.
+pub fn autosave(_: Receiver<Tick>, mut layers: Fetcher<(&mut ChunkLayer, &mut AnvilLevel)>) {
+    for (layer, level) in layers.iter_mut() {
+        level.mark_failed_saves(layer);
+
+        let Some(interval) = level.autosave_interval else {
+            continue;
+        };
+
+        level.ticks_since_save += 1;
+
+        if level.ticks_since_save >= interval {
+            level.save(layer);
+        }
+    }
+}
+
+/// Inserts the chunks loaded since the previous tick into their layers.
+pub fn insert_loaded_chunks(
+    _: Receiver<PreUpdate>,
+    mut layers: Fetcher<(EntityId, &mut ChunkLayer, &mut AnvilLevel)>,
+    mut sender: Sender<ChunkLoadEvent>,
+) {
+    for (entity, layer, level) in layers.iter_mut() {
+        for (pos, result) in level.results.try_iter() {
+            level.pending.remove(&pos);
+
+            let status = match result {
+                Ok(Some(chunk)) => {
+                    layer.insert_chunk(pos, chunk);
+                    ChunkLoadStatus::Success
+                }
+                Ok(None) => {
+                    level.unavailable.insert(pos, false);
+                    ChunkLoadStatus::Empty
+                }
+                Err(e) => {
+                    warn!("failed to load chunk at ({}, {}): {e}", pos.x, pos.z);
+                    level.unavailable.insert(pos, false);
+                    ChunkLoadStatus::Failed(e)
+                }
+            };
+
+            sender.send(ChunkLoadEvent {
+                layer: entity,
+                pos,
+                status,
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use valence_protocol::block::{PropName, PropValue};
+    use valence_protocol::BlockState;
+
+    use super::*;
+    use crate::testing::layer;
+
+    /// `r.0.0.mca` holds three chunks built by hand: (0, 0) compressed with
+    /// zlib, (1, 0) with gzip and (2, 0) stored uncompressed.
+    fn fixture_folder() -> RegionFolder {
+        RegionFolder::new(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/region"))
+    }
+
+    /// An empty directory unique to the test.
+    fn temp_region_root(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("anvil-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+        path
+    }
+
+    fn parse(raw: RawChunk) -> Chunk {
+        let biomes = HashMap::from([("minecraft:plains".to_owned(), BiomeId(0))]);
+        parse_chunk(raw.data, &biomes, -64, 384).unwrap()
+    }
+
+    #[test]
+    fn missing_chunks_and_regions() {
+        let mut folder = fixture_folder();
+
+        assert!(folder.get_chunk(ChunkPos::new(3, 0)).unwrap().is_none());
+        assert!(folder.get_chunk(ChunkPos::new(-1, 0)).unwrap().is_none());
+    }
+
+    #[test]
+    fn read_every_compression_scheme() {
+        let mut folder = fixture_folder();
+
+        for x in 0..3 {
+            let raw = folder.get_chunk(ChunkPos::new(x, 0)).unwrap().unwrap();
+            assert_eq!(raw.timestamp, 1_700_000_000 + x as u32);
+
+            let chunk = parse(raw);
+            assert_eq!(chunk.block_state(0, 0, 0), BlockState::BEDROCK);
+            assert_eq!(chunk.block_state(15, 63, 15), BlockState::STONE);
+            assert_eq!(chunk.block_state(7, 64, 7), BlockState::AIR);
+        }
+    }
+
+    #[test]
+    fn parse_palettes_and_block_entities() {
+        let raw = fixture_folder().get_chunk(ChunkPos::new(0, 0)).unwrap().unwrap();
+        let chunk = parse(raw);
+
+        // The section at Y=0 has a packed palette with properties.
+        assert_eq!(chunk.block_state(1, 64, 0), BlockState::GRASS_BLOCK);
+        assert_eq!(
+            chunk.block_state(2, 64, 0),
+            BlockState::OAK_STAIRS.set(PropName::Facing, PropValue::East)
+        );
+        assert_eq!(chunk.block_state(3, 64, 0), BlockState::CHEST);
+
+        // Unknown biomes fall back to the first biome.
+        assert_eq!(chunk.biome(0, 16, 0), BiomeId(0));
+
+        let chest = chunk.block_entity(3, 64, 0).unwrap();
+        assert!(chest.get("Items").is_some());
+        assert!(chest.get("id").is_none());
+        assert!(chunk.block_entity(2, 64, 0).is_none());
+
+        assert!(!chunk.is_modified());
+    }
+
+    #[test]
+    fn set_chunks_keeps_other_chunks() {
+        let region_root = temp_region_root("set-chunks");
+        fs::copy(
+            fixture_folder().region_root().join("r.0.0.mca"),
+            region_root.join("r.0.0.mca"),
+        )
+        .unwrap();
+
+        let mut chunk = Chunk::with_height(384);
+        chunk.set_block_state(4, 100, 4, BlockState::GLASS);
+        let names = ["minecraft:plains".to_owned()];
+
+        let mut folder = RegionFolder::new(region_root.clone());
+        // Opens the region before it's replaced.
+        assert!(folder.get_chunk(ChunkPos::new(0, 0)).unwrap().is_some());
+
+        folder
+            .set_chunks([ChunkPos::new(0, 0), ChunkPos::new(-33, 2)].map(|pos| {
+                (pos, chunk_to_nbt(&chunk, pos, &names, -64))
+            }))
+            .unwrap();
+
+        assert!(region_root.join("r.-2.0.mca").exists());
+        assert!(!region_root.join("r.0.0.mca.tmp").exists());
+
+        for folder in [&mut folder, &mut RegionFolder::new(region_root.clone())] {
+            for pos in [ChunkPos::new(0, 0), ChunkPos::new(-33, 2)] {
+                let raw = folder.get_chunk(pos).unwrap().unwrap();
+                assert!(raw.timestamp > 1_700_000_002);
+                assert_eq!(parse(raw).block_state(4, 100, 4), BlockState::GLASS);
+            }
+
+            for x in 1..3 {
+                let raw = folder.get_chunk(ChunkPos::new(x, 0)).unwrap().unwrap();
+                assert_eq!(raw.timestamp, 1_700_000_000 + x as u32);
+                assert_eq!(parse(raw).block_state(0, 0, 0), BlockState::BEDROCK);
+            }
+        }
+
+        // Sectors are a multiple of 4 KiB.
+        let len = fs::metadata(region_root.join("r.0.0.mca")).unwrap().len();
+        assert_eq!(len % SECTOR_SIZE as u64, 0);
+
+        fs::remove_dir_all(region_root).unwrap();
+    }
+
+    #[test]
+    fn empty_region_files_have_no_chunks() {
+        let region_root = temp_region_root("empty-region");
+        fs::write(region_root.join("r.0.0.mca"), []).unwrap();
+        fs::write(region_root.join("r.1.0.mca"), [0; 100]).unwrap();
+
+        let mut folder = RegionFolder::new(region_root.clone());
+        assert!(folder.get_chunk(ChunkPos::new(0, 0)).unwrap().is_none());
+        assert!(folder.get_chunk(ChunkPos::new(32, 0)).unwrap().is_none());
+
+        let pos = ChunkPos::new(0, 0);
+        let names = ["minecraft:plains".to_owned()];
+        let nbt = chunk_to_nbt(&Chunk::with_height(384), pos, &names, -64);
+        folder.set_chunks([(pos, nbt)]).unwrap();
+
+        let mut folder = RegionFolder::new(region_root.clone());
+        assert!(folder.get_chunk(pos).unwrap().is_some());
+
+        fs::remove_dir_all(region_root).unwrap();
+    }
+
+    #[test]
+    fn set_chunks_writes_every_region_it_can() {
+        let region_root = temp_region_root("partial-save");
+        // A directory where the temporary region file goes can't be written to.
+        fs::create_dir(region_root.join("r.1.0.mca.tmp")).unwrap();
+
+        let chunk = Chunk::with_height(384);
+        let names = ["minecraft:plains".to_owned()];
+        let positions = [ChunkPos::new(-1, 0), ChunkPos::new(32, 0), ChunkPos::new(33, 0)];
+
+        let mut folder = RegionFolder::new(region_root.clone());
+        let errors = folder
+            .set_chunks(positions.map(|pos| (pos, chunk_to_nbt(&chunk, pos, &names, -64))))
+            .unwrap_err();
+
+        assert_eq!(errors.len(), 1);
+        assert_eq!((errors[0].region_x, errors[0].region_z), (1, 0));
+        let mut failed = errors[0].chunks.clone();
+        failed.sort_by_key(|pos| pos.x);
+        assert_eq!(failed, [ChunkPos::new(32, 0), ChunkPos::new(33, 0)]);
+
+        let mut folder = RegionFolder::new(region_root.clone());
+        assert!(folder.get_chunk(ChunkPos::new(-1, 0)).unwrap().is_some());
+
+        fs::remove_dir_all(region_root).unwrap();
+    }
+
+    #[test]
+    fn save_then_load_on_worker_thread() {
+        let region_root = temp_region_root("save");
+        let mut layer = layer();
+        let mut level = AnvilLevel::new(region_root.clone(), &RegistryCodec::default(), &layer);
+
+        let mut chunk = Chunk::with_height(layer.height());
+        chunk.set_block_state(1, 2, 3, BlockState::DIRT);
+        layer.insert_chunk([7, -9], chunk);
+        assert!(layer.chunk([7, -9]).unwrap().is_modified());
+
+        level.save(&mut layer);
+        assert!(!layer.chunk([7, -9]).unwrap().is_modified());
+
+        // Saves and loads are handled in order.
+        level.load([7, -9]);
+        let (pos, result) = level.results.recv().unwrap();
+        assert_eq!(pos, ChunkPos::new(7, -9));
+        assert_eq!(result.unwrap().unwrap().block_state(1, 2, 3), BlockState::DIRT);
+
+        fs::remove_dir_all(region_root).unwrap();
+    }
+
+    #[test]
+    fn failed_saves_are_retried() {
+        // A file where the region directory should be can't be written to.
+        let region_root = temp_region_root("failed-save").join("region");
+        fs::write(&region_root, []).unwrap();
+
+        let mut layer = layer();
+        let mut level = AnvilLevel::new(region_root.clone(), &RegistryCodec::default(), &layer);
+
+        let mut chunk = Chunk::with_height(layer.height());
+        chunk.set_block_state(1, 2, 3, BlockState::DIRT);
+        layer.insert_chunk([0, 0], chunk);
+
+        level.save(&mut layer);
+        assert!(!layer.chunk([0, 0]).unwrap().is_modified());
+
+        while level.failed_saves.is_empty() {
+            thread::sleep(std::time::Duration::from_millis(1));
+        }
+
+        level.mark_failed_saves(&mut layer);
+        assert!(layer.chunk([0, 0]).unwrap().is_modified());
+
+        fs::remove_dir_all(region_root.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn load_on_worker_thread() {
+        let layer = layer();
+        let mut level = AnvilLevel::new(
+            concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/region"),
+            &RegistryCodec::default(),
+            &layer,
+        );
+
+        level.load([1, 0]);
+        level.load([5, 5]);
+        assert!(level.is_pending([1, 0]));
+
+        let mut results: Vec<_> = (0..2).map(|_| level.results.recv().unwrap()).collect();
+        results.sort_by_key(|(pos, _)| pos.x);
+
+        assert!(matches!(results[0], (ChunkPos { x: 1, z: 0 }, Ok(Some(_)))));
+        assert!(matches!(results[1], (ChunkPos { x: 5, z: 5 }, Ok(None))));
+    }
+
+    #[test]
+    fn unavailable_chunks_are_read_once_while_in_view() {
+        let layer = layer();
+        let mut level = AnvilLevel::new(
+            concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/region"),
+            &RegistryCodec::default(),
+            &layer,
+        );
+        let pos = ChunkPos::new(5, 5);
+
+        level.request(pos);
+        assert!(level.is_pending(pos));
+        level.pending.remove(&pos);
+        level.unavailable.insert(pos, false);
+
+        // Seen missing on every tick, so it isn't read again.
+        for _ in 0..3 {
+            level.request(pos);
+            level.forget_unseen();
+            assert!(!level.is_pending(pos));
+        }
+
+        // Out of view for a tick, so it's read again when it comes back.
+        level.forget_unseen();
+        level.request(pos);
+        assert!(level.is_pending(pos));
+    }
+}
@@ -0,0 +1,250 @@
+//! Converts chunks in the Anvil NBT format into [`Chunk`]s.
+
+use std::collections::HashMap;
+
+use thiserror::Error;
+use valence_protocol::block::{BlockKind, PropName, PropValue};
+use valence_protocol::nbt::{Compound, List, Value};
+use valence_protocol::BlockState;
+
+use crate::chunk::{bit_width, BiomeId, Chunk};
+
+#[derive(Clone, Debug, Error)]
+#[non_exhaustive]
+pub enum ParseChunkError {
+    #[error("missing chunk sections")]
+    MissingSections,
+    #[error("missing chunk section Y")]
+    MissingSectionY,
+    #[error("missing block states")]
+    MissingBlockStates,
+    #[error("missing block state palette")]
+    MissingBlockPalette,
+    #[error("invalid block state palette")]
+    BadBlockPalette,
+    #[error("unknown block kind '{0}'")]
+    UnknownBlockKind(String),
+    #[error("invalid block state property '{0}'")]
+    BadBlockProperty(String),
+    #[error("missing biomes")]
+    MissingBiomes,
+    #[error("missing biome palette")]
+    MissingBiomePalette,
+    #[error("invalid biome palette")]
+    BadBiomePalette,
+    #[error("invalid packed long array of length {0}")]
+    BadPackedArray(usize),
+    #[error("palette index {0} is out of bounds")]
+    BadPaletteIndex(u64),
+    #[error("invalid block entity")]
+    BadBlockEntity,
+}
+
+/// Converts the NBT of an Anvil chunk into a [`Chunk`] `height` blocks tall
+/// starting at `min_y`. Sections outside that range are ignored, and so are
+/// biomes missing from `biomes`.
+pub fn parse_chunk(
+    mut nbt: Compound,
+    biomes: &HashMap<String, BiomeId>,
+    min_y: i32,
+    height: u32,
+) -> Result<Chunk, ParseChunkError> {
+    let mut chunk = Chunk::with_height(height);
+    let min_sect_y = min_y.div_euclid(16);
+    let section_count = height / 16;
+
+    let Some(Value::List(List::Compound(sections))) = nbt.remove("sections") else {
+        return Err(ParseChunkError::MissingSections);
+    };
+
+    for mut section in sections {
+        let Some(Value::Byte(sect_y)) = section.remove("Y") else {
+            return Err(ParseChunkError::MissingSectionY);
+        };
+
+        // Vanilla keeps light-only sections above and below the world.
+        let Ok(sect_y) = u32::try_from(sect_y as i32 - min_sect_y) else {
+            continue;
+        };
+        if sect_y >= section_count {
+            continue;
+        }
+
+        let Some(Value::Compound(mut block_states)) = section.remove("block_states") else {
+            return Err(ParseChunkError::MissingBlockStates);
+        };
+
+        let Some(Value::List(List::Compound(palette))) = block_states.remove("palette") else {
+            return Err(ParseChunkError::MissingBlockPalette);
+        };
+
+        if palette.is_empty() || palette.len() > 4096 {
+            return Err(ParseChunkError::BadBlockPalette);
+        }
+
+        let palette = palette
+            .into_iter()
+            .map(parse_block_state)
+            .collect::<Result<Vec<_>, _>>()?;
+
+        if palette.len() == 1 {
+            chunk.fill_block_state_section(sect_y, palette[0]);
+        } else {
+            let Some(Value::LongArray(data)) = block_states.remove("data") else {
+                return Err(ParseChunkError::BadBlockPalette);
+            };
+
+            let bits = bit_width(palette.len() - 1).max(4);
+
+            for_each_packed(&data, bits, 4096, |i, idx| {
+                let block = *palette
+                    .get(idx as usize)
+                    .ok_or(ParseChunkError::BadPaletteIndex(idx))?;
+
+                let (x, y, z) = (i % 16, i / 256, i / 16 % 16);
+                chunk.set_block_state(x, sect_y * 16 + y, z, block);
+                Ok(())
+            })?;
+        }
+
+        let Some(Value::Compound(mut section_biomes)) = section.remove("biomes") else {
+            return Err(ParseChunkError::MissingBiomes);
+        };
+
+        let Some(Value::List(palette)) = section_biomes.remove("palette") else {
+            return Err(ParseChunkError::MissingBiomePalette);
+        };
+
+        let List::String(palette) = palette else {
+            return Err(ParseChunkError::BadBiomePalette);
+        };
+
+        if palette.is_empty() || palette.len() > 64 {
+            return Err(ParseChunkError::BadBiomePalette);
+        }
+
+        let palette: Vec<_> = palette
+            .iter()
+            .map(|name| biomes.get(name).copied().unwrap_or_default())
+            .collect();
+
+        if palette.len() == 1 {
+            for i in 0..64 {
+                let (x, y, z) = (i % 4, i / 16, i / 4 % 4);
+                chunk.set_biome(x, sect_y * 4 + y, z, palette[0]);
+            }
+        } else {
+            let Some(Value::LongArray(data)) = section_biomes.remove("data") else {
+                return Err(ParseChunkError::BadBiomePalette);
+            };
+
+            let bits = bit_width(palette.len() - 1);
+
+            for_each_packed(&data, bits, 64, |i, idx| {
+                let biome = *palette
+                    .get(idx as usize)
+                    .ok_or(ParseChunkError::BadPaletteIndex(idx))?;
+
+                let (x, y, z) = (i % 4, i / 16, i / 4 % 4);
+                chunk.set_biome(x, sect_y * 4 + y, z, biome);
+                Ok(())
+            })?;
+        }
+    }
+
+    if let Some(Value::List(block_entities)) = nbt.remove("block_entities") {
+        let block_entities = match block_entities {
+            List::Compound(block_entities) => block_entities,
+            // An empty list is saved with the element type `End`.
+            List::End => vec![],
+            _ => return Err(ParseChunkError::BadBlockEntity),
+        };
+
+        for mut block_entity in block_entities {
+            let (Some(Value::Int(x)), Some(Value::Int(y)), Some(Value::Int(z))) = (
+                block_entity.remove("x"),
+                block_entity.remove("y"),
+                block_entity.remove("z"),
+            ) else {
+                return Err(ParseChunkError::BadBlockEntity);
+            };
+
+            block_entity.remove("id");
+            block_entity.remove("keepPacked");
+
+            let Ok(y) = u32::try_from(y - min_y) else {
+                continue;
+            };
+            if y >= height {
+                continue;
+            }
+
+            chunk.set_block_entity(
+                x.rem_euclid(16) as u32,
+                y,
+                z.rem_euclid(16) as u32,
+                Some(block_entity),
+            );
+        }
+    }
+
+    chunk.optimize();
+    // The chunk matches what's saved.
+    chunk.clear_modified();
+
+    Ok(chunk)
+}
+
+fn parse_block_state(mut entry: Compound) -> Result<BlockState, ParseChunkError> {
+    let Some(Value::String(name)) = entry.remove("Name") else {
+        return Err(ParseChunkError::BadBlockPalette);
+    };
+
+    let kind = BlockKind::from_str(name.strip_prefix("minecraft:").unwrap_or(&name))
+        .ok_or_else(|| ParseChunkError::UnknownBlockKind(name.clone()))?;
+
+    let mut state = kind.to_state();
+
+    if let Some(Value::Compound(properties)) = entry.remove("Properties") {
+        for (key, value) in properties {
+            let Value::String(value) = value else {
+                return Err(ParseChunkError::BadBlockProperty(key));
+            };
+
+            let (Some(prop_name), Some(prop_value)) =
+                (PropName::from_str(&key), PropValue::from_str(&value))
+            else {
+                return Err(ParseChunkError::BadBlockProperty(key));
+            };
+
+            state = state.set(prop_name, prop_value);
+        }
+    }
+
+    Ok(state)
+}
+
+/// Calls `f` with the index and value of each of the `count` values packed
+/// `bits` at a time into `data`. Values never span two longs.
+fn for_each_packed(
+    data: &[i64],
+    bits: usize,
+    count: u32,
+    mut f: impl FnMut(u32, u64) -> Result<(), ParseChunkError>,
+) -> Result<(), ParseChunkError> {
+    let per_long = 64 / bits as u32;
+
+    if data.len() != count.div_ceil(per_long) as usize {
+        return Err(ParseChunkError::BadPackedArray(data.len()));
+    }
+
+    let mask = u64::MAX >> (64 - bits);
+
+    for i in 0..count {
+        let long = data[(i / per_long) as usize] as u64;
+        let idx = (long >> ((i % per_long) as usize * bits)) & mask;
+        f(i, idx)?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,219 @@
+//! Converts [`Chunk`]s into the Anvil NBT format.
+
+use std::collections::HashMap;
+use std::hash::Hash;
+
+use valence_protocol::nbt::{compound, Compound, List, Value};
+use valence_protocol::{BlockState, ChunkPos};
+
+use crate::chunk::{bit_width, BiomeId, Chunk};
+
+/// The data version of Minecraft 1.20.1.
+const DATA_VERSION: i32 = 3465;
+
+/// Converts `chunk` at `pos` into the NBT of an Anvil chunk whose bottom is
+/// at `min_y`. `biomes` holds the name of every biome, indexed by
+/// [`BiomeId`].
+///
+/// Heightmaps and light aren't saved. The game recomputes them when the chunk
+/// is loaded.
+pub fn chunk_to_nbt(chunk: &Chunk, pos: ChunkPos, biomes: &[String], min_y: i32) -> Compound {
+    let min_sect_y = min_y.div_euclid(16);
+
+    let sections: Vec<_> = (0..chunk.height() / 16)
+        .map(|sect_y| {
+            let (palette, data) = palettize(
+                (0..4096).map(|i| chunk.block_state(i % 16, sect_y * 16 + i / 256, i / 16 % 16)),
+                4,
+            );
+
+            let mut block_states = compound! {
+                "palette" => List::Compound(palette.into_iter().map(block_state_nbt).collect()),
+            };
+            if let Some(data) = data {
+                block_states.insert("data", data);
+            }
+
+            let (palette, data) = palettize(
+                (0..64).map(|i| chunk.biome(i % 4, sect_y * 4 + i / 16, i / 4 % 4)),
+                0,
+            );
+
+            let mut section_biomes = compound! {
+                "palette" => List::String(
+                    palette.into_iter().map(|biome| biome_name(biomes, biome)).collect(),
+                ),
+            };
+            if let Some(data) = data {
+                section_biomes.insert("data", data);
+            }
+
+            compound! {
+                "Y" => (min_sect_y + sect_y as i32) as i8,
+                "block_states" => block_states,
+                "biomes" => section_biomes,
+            }
+        })
+        .collect();
+
+    let block_entities: Vec<_> = chunk
+        .block_entities()
+        .filter_map(|(x, y, z, nbt)| {
+            // Block entity data without a matching block is dropped by the game
+            // anyway.
+            let kind = chunk.block_state(x, y, z).block_entity_kind()?;
+
+            let mut nbt = nbt.clone();
+            nbt.insert("id", kind.ident().to_string());
+            nbt.insert("x", pos.x * 16 + x as i32);
+            nbt.insert("y", min_y + y as i32);
+            nbt.insert("z", pos.z * 16 + z as i32);
+            nbt.insert("keepPacked", false);
+            Some(nbt)
+        })
+        .collect();
+
+    compound! {
+        "DataVersion" => DATA_VERSION,
+        "xPos" => pos.x,
+        "zPos" => pos.z,
+        "yPos" => min_sect_y,
+        "Status" => "minecraft:full",
+        "LastUpdate" => 0_i64,
+        "sections" => List::Compound(sections),
+        "block_entities" => List::Compound(block_entities),
+    }
+}
+
+fn block_state_nbt(block: BlockState) -> Compound {
+    let kind = block.to_kind();
+
+    let mut nbt = compound! {
+        "Name" => format!("minecraft:{}", kind.to_str()),
+    };
+
+    if !kind.props().is_empty() {
+        let properties: Compound = kind
+            .props()
+            .iter()
+            .filter_map(|&name| {
+                let value = block.get(name)?;
+                Some((name.to_str().to_owned(), Value::String(value.to_str().to_owned())))
+            })
+            .collect();
+
+        nbt.insert("Properties", properties);
+    }
+
+    nbt
+}
+
+fn biome_name(biomes: &[String], biome: BiomeId) -> String {
+    biomes
+        .get(biome.0 as usize)
+        .or(biomes.first())
+        .cloned()
+        .unwrap_or_else(|| "minecraft:plains".into())
+}
+
+/// Splits `vals` into a palette and the packed indices into it. The indices
+/// are `None` when the palette has a single entry.
+fn palettize<T: Copy + Eq + Hash>(
+    vals: impl Iterator<Item = T>,
+    min_bits: usize,
+) -> (Vec<T>, Option<Vec<i64>>) {
+    let mut palette = vec![];
+    let mut palette_idxs = HashMap::new();
+
+    let idxs: Vec<_> = vals
+        .map(|val| {
+            *palette_idxs.entry(val).or_insert_with(|| {
+                palette.push(val);
+                palette.len() as u64 - 1
+            })
+        })
+        .collect();
+
+    if palette.len() == 1 {
+        return (palette, None);
+    }
+
+    let bits = bit_width(palette.len() - 1).max(min_bits);
+    (palette, Some(pack(&idxs, bits)))
+}
+
+/// Packs `vals` into longs `bits` at a time. Values never span two longs.
+fn pack(vals: &[u64], bits: usize) -> Vec<i64> {
+    let per_long = 64 / bits;
+
+    vals.chunks(per_long)
+        .map(|vals| {
+            vals.iter()
+                .enumerate()
+                .fold(0_u64, |long, (i, &val)| long | val << (i * bits)) as i64
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use valence_protocol::block::{PropName, PropValue};
+
+    use super::*;
+    use crate::anvil::parse_chunk;
+
+    #[test]
+    fn parse_what_was_serialized() {
+        let mut chunk = Chunk::with_height(64);
+        chunk.fill_block_state_section(0, BlockState::STONE);
+        chunk.set_block_state(3, 20, 4, BlockState::CHEST);
+        chunk.set_block_state(
+            5,
+            20,
+            6,
+            BlockState::OAK_STAIRS.set(PropName::Facing, PropValue::West),
+        );
+        chunk.set_block_entity(3, 20, 4, Some(compound! { "Items" => List::End }));
+        // No chest is placed here, so this is dropped.
+        chunk.set_block_entity(0, 40, 0, Some(Compound::new()));
+        for x in 0..4 {
+            chunk.set_biome(x, 9, 2, BiomeId(1));
+        }
+
+        let names = vec!["minecraft:plains".to_owned(), "minecraft:forest".to_owned()];
+        let nbt = chunk_to_nbt(&chunk, ChunkPos::new(-2, 7), &names, -32);
+
+        let Some(Value::List(List::Compound(block_entities))) = nbt.get("block_entities") else {
+            panic!("missing block entities");
+        };
+        assert_eq!(block_entities.len(), 1);
+        assert_eq!(block_entities[0].get("x"), Some(&Value::Int(-2 * 16 + 3)));
+        assert_eq!(block_entities[0].get("y"), Some(&Value::Int(-32 + 20)));
+        assert_eq!(block_entities[0].get("z"), Some(&Value::Int(7 * 16 + 4)));
+
+        let biomes = HashMap::from([
+            (names[0].clone(), BiomeId(0)),
+            (names[1].clone(), BiomeId(1)),
+        ]);
+        let parsed = parse_chunk(nbt, &biomes, -32, 64).unwrap();
+
+        for y in 0..64 {
+            for z in 0..16 {
+                for x in 0..16 {
+                    assert_eq!(parsed.block_state(x, y, z), chunk.block_state(x, y, z));
+                }
+            }
+        }
+
+        for y in 0..16 {
+            for z in 0..4 {
+                for x in 0..4 {
+                    assert_eq!(parsed.biome(x, y, z), chunk.biome(x, y, z));
+                }
+            }
+        }
+
+        assert!(parsed.block_entity(3, 20, 4).unwrap().get("Items").is_some());
+        assert!(parsed.block_entity(0, 40, 0).is_none());
+    }
+}
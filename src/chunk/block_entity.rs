@@ -0,0 +1,39 @@
+//! Answers the block NBT queries clients send for F3+I.
+
+use evenio::prelude::*;
+use valence_protocol::nbt::Compound;
+use valence_protocol::packets::play::{NbtQueryResponseS2c, QueryBlockNbtC2s};
+use valence_protocol::{GameMode, WritePacket};
+
+use super::view::VisibleChunkLayer;
+use super::ChunkLayer;
+use crate::client::Client;
+use crate::event_loop::PacketEvent;
+
+/// Sends the block entity data at the queried position to clients in creative
+/// mode. The response is empty if there's no block entity there.
+pub fn handle_query_block_nbt(
+    r: Receiver<PacketEvent<QueryBlockNbtC2s>, (&mut Client, &GameMode, &VisibleChunkLayer)>,
+    layers: Fetcher<&ChunkLayer>,
+) {
+    let (client, game_mode, visible_layer) = r.query;
+
+    // The game only lets operators copy block data.
+    if *game_mode != GameMode::Creative {
+        return;
+    }
+
+    let packet = &r.event.packet;
+
+    let nbt = layers
+        .get(visible_layer.0)
+        .ok()
+        .and_then(|layer| layer.block_entity(packet.position))
+        .cloned()
+        .unwrap_or_else(Compound::new);
+
+    client.write_packet(&NbtQueryResponseS2c {
+        transaction_id: packet.transaction_id,
+        nbt,
+    });
+}
@@ -0,0 +1,972 @@
+//! Sky and block light.
+//!
+//! Every chunk stores a sky light and a block light level for each of its
+//! blocks, packed into one nibble array per section like the game does. Sky
+//! light shines down from above the layer and block light comes from
+//! emissive blocks. Both lose a level per block travelled and more when
+//! passing through translucent blocks, except sky light at full strength,
+//! which goes straight down through air without dimming.
+//!
+//! Chunks are lit from scratch when they are inserted into a layer, and
+//! together with the chunks around them when one of their sections is filled.
+//! Otherwise, only the light around changed blocks is recomputed, with the usual
+//! two flood fills: one darkening everything lit by the old light and one
+//! spreading light back in from the edges of the darkened area. Light spreads
+//! across loaded chunks. Clients viewing a chunk are sent a
+//! [`LightUpdateS2c`] for the sections whose light changed.
+//!
+//! Layers can be made full bright instead, for maps where lighting doesn't
+//! matter. Then no light is computed and every block is sent as fully sky lit.
+
+use std::borrow::Cow;
+use std::collections::{BTreeSet, HashMap, VecDeque};
+
+use evenio::prelude::*;
+use valence_protocol::packets::play::LightUpdateS2c;
+use valence_protocol::{BlockState, ChunkPos, FixedArray, VarInt, WritePacket};
+use valence_server_common::PostUpdate;
+
+use super::{Chunk, ChunkLayer, SECTION_BLOCK_COUNT};
+
+/// The light levels of one section, two blocks per byte.
+pub(crate) type LightArray = FixedArray<u8, { SECTION_BLOCK_COUNT / 2 }>;
+
+const FULL_LIGHT: LightArray = FixedArray([0xff; SECTION_BLOCK_COUNT / 2]);
+const NO_LIGHT: LightArray = FixedArray([0; SECTION_BLOCK_COUNT / 2]);
+
+/// The brightest light level.
+const MAX_LIGHT: u8 = 15;
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+enum LightKind {
+    Sky,
+    Block,
+}
+
+const LIGHT_KINDS: [LightKind; 2] = [LightKind::Sky, LightKind::Block];
+
+/// The light stored in a [`Chunk`].
+#[derive(Clone, Default, Debug)]
+pub(crate) struct ChunkLight {
+    sky: Vec<LightArray>,
+    block: Vec<LightArray>,
+    /// The sections whose light changed since the last [`LightUpdateS2c`] was
+    /// sent.
+    changed_sections: BTreeSet<u32>,
+    /// Whether the light has to be computed from scratch.
+    stale: bool,
+}
+
+impl ChunkLight {
+    pub(super) fn set_section_count(&mut self, section_count: usize) {
+        self.sky.resize(section_count, NO_LIGHT);
+        self.block.resize(section_count, NO_LIGHT);
+        self.changed_sections
+            .retain(|&sect_y| (sect_y as usize) < section_count);
+        self.stale = true;
+    }
+
+    /// Marks the light as needing to be computed from scratch. Viewers are
+    /// sent the whole chunk instead of the changes.
+    pub(super) fn reset(&mut self) {
+        self.changed_sections.clear();
+        self.stale = true;
+    }
+
+    fn arrays(&self, kind: LightKind) -> &[LightArray] {
+        match kind {
+            LightKind::Sky => &self.sky,
+            LightKind::Block => &self.block,
+        }
+    }
+
+    fn arrays_mut(&mut self, kind: LightKind) -> &mut [LightArray] {
+        match kind {
+            LightKind::Sky => &mut self.sky,
+            LightKind::Block => &mut self.block,
+        }
+    }
+
+    fn get(&self, kind: LightKind, x: u32, y: u32, z: u32) -> u8 {
+        let idx = light_idx(x, y, z);
+        self.arrays(kind)[y as usize / 16].0[idx / 2] >> (idx % 2 * 4) & 0xf
+    }
+
+    /// Sets a light level and returns whether it changed.
+    fn set(&mut self, kind: LightKind, x: u32, y: u32, z: u32, level: u8) -> bool {
+        let idx = light_idx(x, y, z);
+        let shift = idx % 2 * 4;
+        let byte = &mut self.arrays_mut(kind)[y as usize / 16].0[idx / 2];
+
+        if *byte >> shift & 0xf == level {
+            return false;
+        }
+
+        *byte = *byte & !(0xf << shift) | level << shift;
+        self.changed_sections.insert(y / 16);
+        true
+    }
+
+    /// Builds the light masks and arrays of a light packet for the sections in
+    /// `sections`, in ascending order. Light sections are offset by one from
+    /// block sections: the game also expects light for the section below and
+    /// the section above the chunk, which are included if `with_borders` is
+    /// set. Sections without any light are put in the empty masks instead of
+    /// being sent.
+    fn packet_data(
+        &self,
+        sections: impl IntoIterator<Item = u32>,
+        with_borders: bool,
+        full_bright: bool,
+    ) -> LightPacketData {
+        let section_count = self.sky.len() as u32;
+        let mut data = LightPacketData::new(section_count as usize + 2);
+
+        let inner = sections.into_iter().map(|sect_y| sect_y + 1);
+        let light_sections: Vec<u32> = if with_borders {
+            [0].into_iter()
+                .chain(inner)
+                .chain([section_count + 1])
+                .collect()
+        } else {
+            inner.collect()
+        };
+
+        for light_y in light_sections {
+            let in_chunk = (1..=section_count).contains(&light_y);
+
+            let sky = if full_bright || light_y == section_count + 1 {
+                Some(&FULL_LIGHT)
+            } else if in_chunk {
+                Some(&self.sky[light_y as usize - 1])
+            } else {
+                None
+            };
+
+            let block = if in_chunk && !full_bright {
+                Some(&self.block[light_y as usize - 1])
+            } else {
+                None
+            };
+
+            match sky.filter(|array| **array != NO_LIGHT) {
+                Some(array) => {
+                    set_bit(&mut data.sky_light_mask, light_y);
+                    data.sky_light_arrays.push(*array);
+                }
+                None => set_bit(&mut data.empty_sky_light_mask, light_y),
+            }
+
+            match block.filter(|array| **array != NO_LIGHT) {
+                Some(array) => {
+                    set_bit(&mut data.block_light_mask, light_y);
+                    data.block_light_arrays.push(*array);
+                }
+                None => set_bit(&mut data.empty_block_light_mask, light_y),
+            }
+        }
+
+        data
+    }
+}
+
+/// The light fields shared by [`ChunkDataS2c`](super::ChunkDataS2c) and
+/// [`LightUpdateS2c`].
+#[derive(Debug)]
+pub(crate) struct LightPacketData {
+    pub(crate) sky_light_mask: Vec<u64>,
+    pub(crate) block_light_mask: Vec<u64>,
+    pub(crate) empty_sky_light_mask: Vec<u64>,
+    pub(crate) empty_block_light_mask: Vec<u64>,
+    pub(crate) sky_light_arrays: Vec<LightArray>,
+    pub(crate) block_light_arrays: Vec<LightArray>,
+}
+
+impl LightPacketData {
+    fn new(light_section_count: usize) -> Self {
+        let words = light_section_count.div_ceil(64);
+
+        Self {
+            sky_light_mask: vec![0; words],
+            block_light_mask: vec![0; words],
+            empty_sky_light_mask: vec![0; words],
+            empty_block_light_mask: vec![0; words],
+            sky_light_arrays: vec![],
+            block_light_arrays: vec![],
+        }
+    }
+}
+
+fn set_bit(mask: &mut [u64], bit: u32) {
+    mask[bit as usize / 64] |= 1 << (bit % 64);
+}
+
+impl Chunk {
+    /// The sky light level at the given offsets. Always zero until the chunk
+    /// has been lit in a layer.
+    pub fn sky_light(&self, x: u32, y: u32, z: u32) -> u8 {
+        assert!(
+            x < 16 && y < self.height() && z < 16,
+            "chunk block offsets of ({x}, {y}, {z}) are out of bounds"
+        );
+
+        self.light.get(LightKind::Sky, x, y, z)
+    }
+
+    /// The block light level at the given offsets. Always zero until the
+    /// chunk has been lit in a layer.
+    pub fn block_light(&self, x: u32, y: u32, z: u32) -> u8 {
+        assert!(
+            x < 16 && y < self.height() && z < 16,
+            "chunk block offsets of ({x}, {y}, {z}) are out of bounds"
+        );
+
+        self.light.get(LightKind::Block, x, y, z)
+    }
+
+    fn set_light(&mut self, kind: LightKind, x: u32, y: u32, z: u32, level: u8) {
+        if self.light.set(kind, x, y, z, level) {
+            // Light isn't saved, so the chunk isn't modified.
+            self.clear_cached_init_packet();
+        }
+    }
+
+    /// The light of the whole chunk for its [`ChunkDataS2c`](super::ChunkDataS2c).
+    pub(super) fn light_packet_data(&self, full_bright: bool) -> LightPacketData {
+        self.light
+            .packet_data(0..self.sections.len() as u32, true, full_bright)
+    }
+
+    /// Writes a [`LightUpdateS2c`] for the sections whose light changed since
+    /// the last call and clears the changes.
+    pub(super) fn write_light_updates(&mut self, writer: &mut impl WritePacket, pos: ChunkPos) {
+        if self.light.changed_sections.is_empty() {
+            return;
+        }
+
+        let sections = std::mem::take(&mut self.light.changed_sections);
+        let data = self.light.packet_data(sections, false, false);
+
+        writer.write_packet(&LightUpdateS2c {
+            chunk_x: VarInt(pos.x),
+            chunk_z: VarInt(pos.z),
+            sky_light_mask: Cow::Owned(data.sky_light_mask),
+            block_light_mask: Cow::Owned(data.block_light_mask),
+            empty_sky_light_mask: Cow::Owned(data.empty_sky_light_mask),
+            empty_block_light_mask: Cow::Owned(data.empty_block_light_mask),
+            sky_light_arrays: Cow::Owned(data.sky_light_arrays),
+            block_light_arrays: Cow::Owned(data.block_light_arrays),
+        });
+    }
+
+    /// The offset of the top of the highest section with blocks in it, or
+    /// zero if the chunk is empty.
+    fn top_of_blocks(&self) -> u32 {
+        self.sections
+            .iter()
+            .rposition(|section| section.non_air_count > 0)
+            .map_or(0, |sect_y| (sect_y as u32 + 1) * 16)
+    }
+}
+
+impl ChunkLayer {
+    /// Whether light is computed for the layer. See
+    /// [`ChunkLayer::set_full_bright`].
+    pub fn is_full_bright(&self) -> bool {
+        self.info.full_bright
+    }
+
+    /// Turns the light engine off and sends every block as fully lit instead,
+    /// or turns it back on. Only affects chunks sent to clients afterwards,
+    /// so this is best set before anyone joins.
+    pub fn set_full_bright(&mut self, full_bright: bool) {
+        if full_bright == self.info.full_bright {
+            return;
+        }
+
+        self.info.full_bright = full_bright;
+
+        for chunk in self.chunks.values_mut() {
+            // The light wasn't kept up to date while the layer was full bright.
+            chunk.light.reset();
+            chunk.clear_cached_init_packet();
+        }
+    }
+
+    /// Lights the chunks inserted since the last call and relights the blocks
+    /// changed since then.
+    pub(crate) fn update_light(&mut self) {
+        if self.info.full_bright {
+            return;
+        }
+
+        let mut removals = Jobs::default();
+        let mut spreads = Jobs::default();
+        let mut stale = vec![];
+        let mut filled = vec![];
+
+        // Darken the light around the changed blocks. Filled sections relight
+        // their whole chunk rather than every block in them.
+        for (&pos, chunk) in &mut self.chunks {
+            if chunk.light.stale {
+                stale.push(pos);
+                continue;
+            }
+
+            if chunk.sections.iter().any(|section| section.filled) {
+                filled.push(pos);
+                continue;
+            }
+
+            let changed: Vec<_> = chunk
+                .sections
+                .iter()
+                .enumerate()
+                .flat_map(|(sect_y, section)| {
+                    section.changed_blocks.iter().map(move |idx| {
+                        let idx = idx as u32;
+                        (idx % 16, sect_y as u32 * 16 + idx / 256, idx / 16 % 16)
+                    })
+                })
+                .collect();
+
+            for (x, y, z) in changed {
+                let cell = Cell::new(x, y, z);
+
+                for kind in LIGHT_KINDS {
+                    let old = chunk.light.get(kind, x, y, z);
+                    chunk.set_light(kind, x, y, z, 0);
+
+                    for (neighbor, down) in cell.neighbors(chunk.height()) {
+                        removals.push(pos, Job::Remove(kind, neighbor, old, down));
+                    }
+                    // The neighbors that weren't darkened light the block back
+                    // up, or the sky if it's at the top.
+                    spreads.push(pos, Job::Spread(kind, cell));
+                    for (neighbor, _) in cell.neighbors(chunk.height()) {
+                        spreads.push(pos, Job::Spread(kind, neighbor));
+                    }
+                }
+
+                if y + 1 == chunk.height() {
+                    spreads.push(pos, Job::Offer(LightKind::Sky, cell, MAX_LIGHT, true));
+                }
+
+                let luminance = chunk.block_state(x, y, z).luminance();
+                if luminance > 0 {
+                    chunk.set_light(LightKind::Block, x, y, z, luminance);
+                }
+            }
+        }
+
+        run_jobs(&mut self.chunks, removals, &mut spreads);
+
+        // The light a filled chunk and the chunks around it gave each other
+        // may be gone, so they are all darkened first and lit from scratch.
+        let relit: BTreeSet<ChunkPos> = filled
+            .iter()
+            .flat_map(|&pos| {
+                (-1..=1).flat_map(move |dz| {
+                    (-1..=1).map(move |dx| ChunkPos::new(pos.x + dx, pos.z + dz))
+                })
+            })
+            .filter(|pos| self.chunks.get(pos).is_some_and(|chunk| !chunk.light.stale))
+            .collect();
+
+        // The light viewers of the relit chunks were last sent, or the
+        // sections that changed since.
+        let mut sent_light = vec![];
+        for &pos in &relit {
+            let chunk = self.chunks.get_mut(&pos).unwrap();
+            sent_light.push((pos, chunk.light.clone()));
+            chunk.light.sky.fill(NO_LIGHT);
+            chunk.light.block.fill(NO_LIGHT);
+        }
+
+        for &pos in stale.iter().chain(&relit) {
+            light_from_scratch(&mut self.chunks, pos, &mut spreads);
+        }
+
+        run_jobs(&mut self.chunks, spreads, &mut Jobs::default());
+
+        // Viewers of chunks inserted since the last call are sent the whole
+        // chunk.
+        for pos in stale {
+            if let Some(chunk) = self.chunks.get_mut(&pos) {
+                chunk.light.changed_sections.clear();
+            }
+        }
+
+        // Viewers of relit chunks are sent the sections that differ from what
+        // they have.
+        for (pos, old) in sent_light {
+            let light = &mut self.chunks.get_mut(&pos).unwrap().light;
+
+            light.changed_sections = old.changed_sections;
+            for sect_y in 0..light.sky.len() {
+                if light.sky[sect_y] != old.sky[sect_y] || light.block[sect_y] != old.block[sect_y]
+                {
+                    light.changed_sections.insert(sect_y as u32);
+                }
+            }
+        }
+    }
+}
+
+/// Updates the light of every layer after the blocks changed during the tick
+/// and before the changes are sent.
+pub fn update_light(_: Receiver<PostUpdate>, mut layers: Fetcher<&mut ChunkLayer>) {
+    for layer in layers.iter_mut() {
+        layer.update_light();
+    }
+}
+
+/// Clears the light of the chunk at `pos` and queues the jobs that light it
+/// again, along with the neighboring chunks it lights.
+fn light_from_scratch(chunks: &mut HashMap<ChunkPos, Chunk>, pos: ChunkPos, jobs: &mut Jobs) {
+    let neighbors =
+        [(-1, 0), (1, 0), (0, -1), (0, 1)].map(|(dx, dz)| ChunkPos::new(pos.x + dx, pos.z + dz));
+
+    // Everything above the blocks of this chunk and its neighbors is lit by
+    // the sky at full strength, so there's nothing left to spread up there.
+    let top = neighbors
+        .iter()
+        .chain([&pos])
+        .filter_map(|pos| chunks.get(pos))
+        .map(Chunk::top_of_blocks)
+        .max()
+        .unwrap_or(0);
+
+    // Light shining in from the edges of the neighbors.
+    let mut offers = vec![];
+    for (i, neighbor) in neighbors.iter().enumerate() {
+        let Some(neighbor) = chunks.get(neighbor) else {
+            continue;
+        };
+
+        for y in 0..neighbor.height() {
+            for i_edge in 0..16 {
+                // The edge of the neighbor and the edge of this chunk touching it.
+                let ((nx, nz), (x, z)) = match i {
+                    0 => ((15, i_edge), (0, i_edge)),
+                    1 => ((0, i_edge), (15, i_edge)),
+                    2 => ((i_edge, 15), (i_edge, 0)),
+                    _ => ((i_edge, 0), (i_edge, 15)),
+                };
+
+                for kind in LIGHT_KINDS {
+                    let level = neighbor.light.get(kind, nx, y, nz);
+                    if level > 1 {
+                        offers.push(Job::Offer(kind, Cell::new(x, y, z), level, false));
+                    }
+                }
+            }
+        }
+    }
+
+    let Some(chunk) = chunks.get_mut(&pos) else {
+        return;
+    };
+
+    chunk.light.reset();
+    chunk.light.stale = false;
+    chunk.clear_cached_init_packet();
+
+    let height = chunk.height();
+    let top = top.min(height);
+
+    for (sect_y, (sky, block)) in chunk
+        .light
+        .sky
+        .iter_mut()
+        .zip(&mut chunk.light.block)
+        .enumerate()
+    {
+        *sky = if sect_y as u32 * 16 >= top {
+            FULL_LIGHT
+        } else {
+            NO_LIGHT
+        };
+        *block = NO_LIGHT;
+    }
+
+    // Sky light shines straight down each column until something dims it.
+    for z in 0..16 {
+        for x in 0..16 {
+            let mut level = MAX_LIGHT;
+
+            for y in (0..top).rev() {
+                level = next_level(
+                    LightKind::Sky,
+                    level,
+                    opacity(chunk.block_state(x, y, z)),
+                    true,
+                );
+                if level == 0 {
+                    break;
+                }
+                chunk.light.set(LightKind::Sky, x, y, z, level);
+            }
+        }
+    }
+
+    for y in 0..top {
+        for z in 0..16 {
+            for x in 0..16 {
+                if chunk.light.get(LightKind::Sky, x, y, z) > 1 {
+                    jobs.push(pos, Job::Spread(LightKind::Sky, Cell::new(x, y, z)));
+                }
+            }
+        }
+    }
+
+    for (sect_y, section) in chunk.sections.iter().enumerate() {
+        if section.non_air_count == 0 {
+            continue;
+        }
+
+        for idx in 0..SECTION_BLOCK_COUNT as u32 {
+            let luminance = section.block_states.get(idx as usize).luminance();
+
+            if luminance > 0 {
+                let (x, y, z) = (idx % 16, sect_y as u32 * 16 + idx / 256, idx / 16 % 16);
+                chunk.light.set(LightKind::Block, x, y, z, luminance);
+                jobs.push(pos, Job::Spread(LightKind::Block, Cell::new(x, y, z)));
+            }
+        }
+    }
+
+    for offer in offers {
+        jobs.push(pos, offer);
+    }
+}
+
+/// A block in a chunk. `x` and `z` are outside of `0..16` for blocks in the
+/// neighboring chunks.
+#[derive(Copy, Clone, Debug)]
+struct Cell {
+    x: i32,
+    y: u32,
+    z: i32,
+}
+
+impl Cell {
+    fn new(x: u32, y: u32, z: u32) -> Self {
+        Self {
+            x: x as i32,
+            y,
+            z: z as i32,
+        }
+    }
+
+    /// The neighbors of the cell in a chunk `height` blocks tall, along with
+    /// whether each is below the cell.
+    fn neighbors(self, height: u32) -> impl Iterator<Item = (Cell, bool)> {
+        let Cell { x, y, z } = self;
+
+        [
+            (y.checked_sub(1).map(|y| Cell { x, y, z }), true),
+            ((y + 1 < height).then_some(Cell { x, y: y + 1, z }), false),
+            (Some(Cell { x: x - 1, y, z }), false),
+            (Some(Cell { x: x + 1, y, z }), false),
+            (Some(Cell { x, y, z: z - 1 }), false),
+            (Some(Cell { x, y, z: z + 1 }), false),
+        ]
+        .into_iter()
+        .filter_map(|(cell, down)| Some((cell?, down)))
+    }
+
+    /// The chunk the cell is in, relative to the chunk at `pos`, and the cell
+    /// within that chunk.
+    fn locate(self, pos: ChunkPos) -> (ChunkPos, Cell) {
+        (
+            ChunkPos::new(pos.x + self.x.div_euclid(16), pos.z + self.z.div_euclid(16)),
+            Cell {
+                x: self.x.rem_euclid(16),
+                y: self.y,
+                z: self.z.rem_euclid(16),
+            },
+        )
+    }
+
+    fn offsets(self) -> (u32, u32, u32) {
+        (self.x as u32, self.y, self.z as u32)
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+enum Job {
+    /// Spread the light of the cell to its neighbors.
+    Spread(LightKind, Cell),
+    /// A neighbor with the given light level lights the cell. The flag is set
+    /// if the neighbor is above the cell.
+    Offer(LightKind, Cell, u8, bool),
+    /// A neighbor that had the given light level went dark. The flag is set
+    /// if the neighbor is above the cell.
+    Remove(LightKind, Cell, u8, bool),
+}
+
+/// Jobs grouped by the chunk they are in, so that each chunk is looked up
+/// once per batch of jobs.
+#[derive(Default)]
+struct Jobs(HashMap<ChunkPos, Vec<Job>>);
+
+impl Jobs {
+    /// Queues a job for the chunk at `pos`. The job's cell may be in a
+    /// neighboring chunk.
+    fn push(&mut self, pos: ChunkPos, job: Job) {
+        let (pos, job) = match job {
+            Job::Spread(kind, cell) => {
+                let (pos, cell) = cell.locate(pos);
+                (pos, Job::Spread(kind, cell))
+            }
+            Job::Offer(kind, cell, level, down) => {
+                let (pos, cell) = cell.locate(pos);
+                (pos, Job::Offer(kind, cell, level, down))
+            }
+            Job::Remove(kind, cell, level, down) => {
+                let (pos, cell) = cell.locate(pos);
+                (pos, Job::Remove(kind, cell, level, down))
+            }
+        };
+
+        self.0.entry(pos).or_default().push(job);
+    }
+}
+
+/// Runs `jobs` and the jobs they lead to until none are left. Removals queue
+/// the cells that light the darkened area back up in `spreads`.
+fn run_jobs(chunks: &mut HashMap<ChunkPos, Chunk>, mut jobs: Jobs, spreads: &mut Jobs) {
+    while let Some(&pos) = jobs.0.keys().next() {
+        let mut queue = VecDeque::from(jobs.0.remove(&pos).unwrap_or_default());
+
+        // Taken out of the map so that neighboring chunks can be looked up
+        // while it's borrowed.
+        let Some(mut chunk) = chunks.remove(&pos) else {
+            // Light doesn't spread into chunks that aren't loaded.
+            continue;
+        };
+        let height = chunk.height();
+
+        // Queues a job in this chunk or the neighbor the cell is in.
+        let queue_job = |queue: &mut VecDeque<Job>, jobs: &mut Jobs, job: Job| {
+            let cell = match job {
+                Job::Spread(_, cell) | Job::Offer(_, cell, ..) | Job::Remove(_, cell, ..) => cell,
+            };
+            let (neighbor, _) = cell.locate(pos);
+
+            if neighbor == pos {
+                queue.push_back(job);
+            } else if chunks.contains_key(&neighbor) {
+                jobs.push(pos, job);
+            }
+        };
+
+        while let Some(job) = queue.pop_front() {
+            match job {
+                Job::Spread(kind, cell) => {
+                    let (x, y, z) = cell.offsets();
+                    let level = chunk.light.get(kind, x, y, z);
+
+                    if level > 1 {
+                        for (neighbor, down) in cell.neighbors(height) {
+                            queue_job(
+                                &mut queue,
+                                &mut jobs,
+                                Job::Offer(kind, neighbor, level, down),
+                            );
+                        }
+                    }
+                }
+                Job::Offer(kind, cell, level, down) => {
+                    let (x, y, z) = cell.offsets();
+                    let level = next_level(kind, level, opacity(chunk.block_state(x, y, z)), down);
+
+                    if level > chunk.light.get(kind, x, y, z) {
+                        chunk.set_light(kind, x, y, z, level);
+                        queue.push_back(Job::Spread(kind, cell));
+                    }
+                }
+                Job::Remove(kind, cell, old, down) => {
+                    let (x, y, z) = cell.offsets();
+                    let level = chunk.light.get(kind, x, y, z);
+
+                    if level == 0 {
+                        continue;
+                    }
+
+                    let lit_by_removed = level < old
+                        || (kind == LightKind::Sky && down && old == MAX_LIGHT && level == old);
+
+                    if !lit_by_removed {
+                        // Lit by something else, so it lights the darkened area
+                        // back up.
+                        spreads.push(pos, Job::Spread(kind, cell));
+                        continue;
+                    }
+
+                    chunk.set_light(kind, x, y, z, 0);
+
+                    for (neighbor, down) in cell.neighbors(height) {
+                        queue_job(
+                            &mut queue,
+                            &mut jobs,
+                            Job::Remove(kind, neighbor, level, down),
+                        );
+                    }
+
+                    if kind == LightKind::Block {
+                        let luminance = chunk.block_state(x, y, z).luminance();
+                        if luminance > 0 {
+                            chunk.set_light(kind, x, y, z, luminance);
+                            spreads.push(pos, Job::Spread(kind, cell));
+                        }
+                    }
+                }
+            }
+        }
+
+        chunks.insert(pos, chunk);
+    }
+}
+
+/// The light level of a block with `opacity` lit by a neighbor with `level`.
+/// `down` is set if the neighbor is above the block.
+fn next_level(kind: LightKind, level: u8, opacity: u8, down: bool) -> u8 {
+    if kind == LightKind::Sky && down && level == MAX_LIGHT && opacity == 0 {
+        MAX_LIGHT
+    } else {
+        level.saturating_sub(opacity.max(1))
+    }
+}
+
+/// How much a block dims light passing through it.
+fn opacity(block: BlockState) -> u8 {
+    if block.is_opaque() {
+        MAX_LIGHT
+    } else if block.is_liquid() {
+        1
+    } else {
+        0
+    }
+}
+
+fn light_idx(x: u32, y: u32, z: u32) -> usize {
+    (x + z * 16 + y % 16 * 16 * 16) as usize
+}
+
+#[cfg(test)]
+mod tests {
+    use valence_protocol::encode::PacketWriter;
+    use valence_protocol::packets::play::ChunkDataS2c;
+    use valence_protocol::{BlockPos, CompressionThreshold, Packet, PacketDecoder};
+
+    use super::*;
+    use crate::testing::floor_layer;
+
+    /// The layer of [`floor_layer`] in the chunks at (-1, 0) and (0, 0), lit
+    /// and with no pending updates.
+    fn lit_layer() -> ChunkLayer {
+        let mut layer = floor_layer([[-1, 0], [0, 0]]);
+        layer.update_light();
+        layer.take_block_updates();
+        layer
+    }
+
+    fn sky_light(layer: &ChunkLayer, pos: impl Into<BlockPos>) -> u8 {
+        let pos = pos.into();
+        let chunk = layer.chunk(pos).unwrap();
+        chunk.sky_light(
+            pos.x.rem_euclid(16) as u32,
+            (pos.y - layer.min_y()) as u32,
+            pos.z.rem_euclid(16) as u32,
+        )
+    }
+
+    fn block_light(layer: &ChunkLayer, pos: impl Into<BlockPos>) -> u8 {
+        let pos = pos.into();
+        let chunk = layer.chunk(pos).unwrap();
+        chunk.block_light(
+            pos.x.rem_euclid(16) as u32,
+            (pos.y - layer.min_y()) as u32,
+            pos.z.rem_euclid(16) as u32,
+        )
+    }
+
+    /// The light sections whose block light is in the [`LightUpdateS2c`] sent
+    /// for the chunk at `pos`, lit or empty, if one was sent.
+    fn block_light_update(updates: &[(ChunkPos, Vec<u8>)], pos: ChunkPos) -> Option<u64> {
+        let (_, bytes) = updates.iter().find(|(chunk_pos, _)| *chunk_pos == pos)?;
+
+        let mut decoder = PacketDecoder::new();
+        decoder.queue_slice(bytes);
+
+        let mut light_update = None;
+        while let Some(frame) = decoder.try_next_packet().unwrap() {
+            if frame.id == LightUpdateS2c::ID {
+                let packet = frame.decode::<LightUpdateS2c>().unwrap();
+                light_update = Some(packet.block_light_mask[0] | packet.empty_block_light_mask[0]);
+            }
+        }
+
+        light_update
+    }
+
+    #[test]
+    fn sky_light_under_a_roof() {
+        let mut layer = lit_layer();
+
+        assert_eq!(sky_light(&layer, [3, -48, 3]), 15);
+        assert_eq!(sky_light(&layer, [3, -49, 3]), 0);
+        assert_eq!(sky_light(&layer, [-5, 300, 7]), 15);
+
+        for z in 0..6 {
+            for x in 0..6 {
+                layer.set_block([x, -44, z], BlockState::STONE);
+            }
+        }
+        layer.update_light();
+
+        // Lit from the side, with the chunk at (-1, 0) closest.
+        assert_eq!(sky_light(&layer, [0, -45, 0]), 14);
+        assert_eq!(sky_light(&layer, [2, -45, 2]), 12);
+        assert_eq!(sky_light(&layer, [2, -48, 2]), 12);
+        assert_eq!(sky_light(&layer, [5, -46, 3]), 14);
+        assert_eq!(sky_light(&layer, [2, -44, 2]), 0);
+
+        // Opening a hole lets the sky straight through.
+        layer.set_block([2, -44, 2], BlockState::AIR);
+        layer.update_light();
+
+        assert_eq!(sky_light(&layer, [2, -45, 2]), 15);
+        assert_eq!(sky_light(&layer, [2, -48, 2]), 15);
+        assert_eq!(sky_light(&layer, [3, -45, 2]), 14);
+        assert_eq!(sky_light(&layer, [1, -47, 1]), 13);
+    }
+
+    #[test]
+    fn block_light_spreads_across_chunks() {
+        let mut layer = lit_layer();
+
+        layer.set_block([1, -30, 8], BlockState::GLOWSTONE);
+        layer.update_light();
+
+        assert_eq!(block_light(&layer, [1, -30, 8]), 15);
+        assert_eq!(block_light(&layer, [4, -30, 8]), 12);
+        assert_eq!(block_light(&layer, [-2, -31, 8]), 11);
+        // Stone blocks it.
+        assert_eq!(block_light(&layer, [1, -49, 8]), 0);
+
+        layer.set_block([1, -30, 8], BlockState::AIR);
+        layer.update_light();
+
+        assert_eq!(block_light(&layer, [1, -30, 8]), 0);
+        assert_eq!(block_light(&layer, [-2, -31, 8]), 0);
+
+        // Other lights are kept when one goes out.
+        layer.set_block([1, -30, 8], BlockState::GLOWSTONE);
+        layer.set_block([7, -30, 8], BlockState::GLOWSTONE);
+        layer.update_light();
+        layer.set_block([1, -30, 8], BlockState::AIR);
+        layer.update_light();
+
+        assert_eq!(block_light(&layer, [4, -30, 8]), 12);
+        assert_eq!(block_light(&layer, [1, -30, 8]), 9);
+    }
+
+    #[test]
+    fn light_updates_are_sent() {
+        let mut layer = lit_layer();
+
+        layer.set_block([8, -40, 8], BlockState::GLOWSTONE);
+        layer.update_light();
+
+        let updates = layer.take_block_updates();
+        let light_update = block_light_update(&updates, ChunkPos::new(0, 0));
+
+        // The glowstone is in the second section, which is light section 2
+        // since light sections start below the chunk. Its light reaches the
+        // section above but not the stone below.
+        assert_eq!(light_update, Some(0b1100));
+
+        // Chunks lit from scratch are sent whole.
+        let mut chunk = Chunk::with_height(layer.height());
+        chunk.fill_block_state_section(0, BlockState::STONE);
+        layer.insert_chunk([1, 0], chunk);
+        layer.update_light();
+        assert!(layer.take_block_updates().is_empty());
+    }
+
+    #[test]
+    fn filling_a_loaded_chunk_sends_its_light() {
+        let mut layer = lit_layer();
+
+        layer
+            .chunk_mut([0, 0])
+            .unwrap()
+            .fill_block_state_section(1, BlockState::GLOWSTONE);
+        layer.update_light();
+
+        assert_eq!(block_light(&layer, [0, -40, 8]), 15);
+        assert_eq!(block_light(&layer, [-1, -40, 8]), 14);
+        assert_eq!(block_light(&layer, [3, -32, 8]), 14);
+
+        // The glowstone lights its own section and the one above, and the
+        // chunk next to it.
+        let updates = layer.take_block_updates();
+        assert_eq!(
+            block_light_update(&updates, ChunkPos::new(0, 0)),
+            Some(0b1100)
+        );
+        assert_eq!(
+            block_light_update(&updates, ChunkPos::new(-1, 0)),
+            Some(0b1100)
+        );
+
+        // Filling it with air again takes the light away from the neighbor.
+        layer
+            .chunk_mut([0, 0])
+            .unwrap()
+            .fill_block_state_section(1, BlockState::AIR);
+        layer.update_light();
+
+        assert_eq!(block_light(&layer, [-1, -40, 8]), 0);
+        let updates = layer.take_block_updates();
+        assert_eq!(
+            block_light_update(&updates, ChunkPos::new(-1, 0)),
+            Some(0b1100)
+        );
+    }
+
+    #[test]
+    fn full_bright_sends_full_sky_light() {
+        let mut layer = lit_layer();
+        layer.set_full_bright(true);
+
+        let mut buf = vec![];
+        let mut writer = PacketWriter::new(&mut buf, CompressionThreshold(-1));
+        layer.write_chunk_data([0, 0], &mut writer);
+
+        let mut decoder = PacketDecoder::new();
+        decoder.queue_slice(&buf);
+        let frame = decoder.try_next_packet().unwrap().unwrap();
+        let chunk_data = frame.decode::<ChunkDataS2c>().unwrap();
+
+        // 24 sections and the sections below and above.
+        assert_eq!(*chunk_data.sky_light_mask, [(1 << 26) - 1]);
+        assert_eq!(chunk_data.sky_light_arrays.len(), 26);
+        assert!(chunk_data.sky_light_arrays.iter().all(|a| *a == FULL_LIGHT));
+        assert_eq!(*chunk_data.empty_block_light_mask, [(1 << 26) - 1]);
+        assert!(chunk_data.block_light_arrays.is_empty());
+
+        // Light isn't computed while full bright.
+        layer.set_block([8, -40, 8], BlockState::GLOWSTONE);
+        layer.update_light();
+        assert_eq!(block_light(&layer, [8, -40, 8]), 0);
+
+        layer.set_full_bright(false);
+        layer.update_light();
+        assert_eq!(block_light(&layer, [8, -40, 8]), 15);
+        assert_eq!(sky_light(&layer, [8, -49, 8]), 0);
+    }
+}
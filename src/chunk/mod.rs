@@ -0,0 +1,1102 @@
+//! Chunk storage. A [`ChunkLayer`] holds the loaded [`Chunk`] columns of one
+//! dimension and knows how to encode them into [`ChunkDataS2c`] packets.
+
+pub mod block_entity;
+pub mod light;
+mod paletted_container;
+pub mod view;
+
+use std::borrow::Cow;
+use std::collections::hash_map::Entry;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::mem;
+use std::sync::Mutex;
+
+use evenio::prelude::*;
+use valence_protocol::encode::PacketWriter;
+use valence_protocol::math::{Aabb, DVec3};
+use valence_protocol::nbt::{compound, Compound, Value};
+use valence_protocol::packets::play::chunk_data_s2c::ChunkDataBlockEntity;
+use valence_protocol::packets::play::chunk_delta_update_s2c::ChunkDeltaUpdateEntry;
+use valence_protocol::packets::play::{
+    BlockEntityUpdateS2c, BlockUpdateS2c, ChunkDataS2c, ChunkDeltaUpdateS2c,
+};
+use valence_protocol::{
+    BlockPos, BlockState, ChunkPos, ChunkSectionPos, CompressionThreshold, Encode, Ident,
+    WritePacket,
+};
+
+use self::light::ChunkLight;
+pub(crate) use self::paletted_container::bit_width;
+use self::paletted_container::PalettedContainer;
+use crate::registry::{RegistryCodec, BIOME, DIMENSION_TYPE};
+
+/// The protocol ID of a biome in the biome registry of the
+/// [`RegistryCodec`].
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Default, Debug)]
+pub struct BiomeId(pub u16);
+
+/// The chunks of a single dimension. Clients viewing the layer are sent the
+/// chunks within their view distance.
+#[derive(Component, Debug)]
+pub struct ChunkLayer {
+    chunks: HashMap<ChunkPos, Chunk>,
+    info: ChunkLayerInfo,
+    /// The number of chunks inserted where there was none. Views compare it
+    /// to find out whether there may be new chunks to send.
+    added_chunks: u64,
+    /// The chunks replaced since they were last sent to their viewers.
+    replaced_chunks: Vec<ChunkPos>,
+}
+
+/// Properties of a [`ChunkLayer`] that every chunk in it shares.
+#[derive(Clone, Debug)]
+pub struct ChunkLayerInfo {
+    dimension_type_name: Ident<String>,
+    /// The height of every chunk in blocks. Always a multiple of 16.
+    height: u32,
+    min_y: i32,
+    biome_registry_len: usize,
+    threshold: CompressionThreshold,
+    /// Whether every block is sent as fully lit instead of computing light.
+    full_bright: bool,
+}
+
+impl ChunkLayer {
+    /// Creates an empty layer using the dimension type `dimension_type_name`
+    /// from `codec`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the dimension type is missing from `codec` or has no valid
+    /// `min_y` and `height`.
+    pub fn new(
+        dimension_type_name: impl Into<Ident<String>>,
+        codec: &RegistryCodec,
+        threshold: CompressionThreshold,
+    ) -> Self {
+        let dimension_type_name = dimension_type_name.into();
+
+        let dimension_type = codec
+            .registry(DIMENSION_TYPE)
+            .iter()
+            .find(|value| value.name == dimension_type_name)
+            .unwrap_or_else(|| panic!("missing dimension type '{dimension_type_name}'"));
+
+        let (Some(Value::Int(min_y)), Some(Value::Int(height))) = (
+            dimension_type.element.get("min_y"),
+            dimension_type.element.get("height"),
+        ) else {
+            panic!("dimension type '{dimension_type_name}' has no valid min_y and height");
+        };
+
+        assert!(
+            *height > 0 && height % 16 == 0 && min_y % 16 == 0,
+            "dimension type '{dimension_type_name}' is not aligned to chunk sections"
+        );
+
+        Self {
+            chunks: HashMap::new(),
+            info: ChunkLayerInfo {
+                dimension_type_name,
+                height: *height as u32,
+                min_y: *min_y,
+                biome_registry_len: codec.registry(BIOME).len(),
+                threshold,
+                full_bright: false,
+            },
+            added_chunks: 0,
+            replaced_chunks: vec![],
+        }
+    }
+
+    pub fn info(&self) -> &ChunkLayerInfo {
+        &self.info
+    }
+
+    pub fn dimension_type_name(&self) -> &Ident<String> {
+        &self.info.dimension_type_name
+    }
+
+    /// The height of the layer in blocks.
+    pub fn height(&self) -> u32 {
+        self.info.height
+    }
+
+    /// The lowest block Y coordinate of the layer.
+    pub fn min_y(&self) -> i32 {
+        self.info.min_y
+    }
+
+    pub fn chunk(&self, pos: impl Into<ChunkPos>) -> Option<&Chunk> {
+        self.chunks.get(&pos.into())
+    }
+
+    pub fn chunk_mut(&mut self, pos: impl Into<ChunkPos>) -> Option<&mut Chunk> {
+        self.chunks.get_mut(&pos.into())
+    }
+
+    /// Inserts a chunk, returning the chunk previously at `pos`. The chunk is
+    /// resized to the height of the layer. Clients that had the previous chunk
+    /// loaded are sent the new one at the end of the tick.
+    pub fn insert_chunk(&mut self, pos: impl Into<ChunkPos>, mut chunk: Chunk) -> Option<Chunk> {
+        chunk.set_height(self.info.height);
+        // The cached packet contains the position of the chunk.
+        chunk.clear_cached_init_packet();
+        // Viewers are sent the whole chunk instead.
+        for section in &mut chunk.sections {
+            section.clear_block_updates();
+        }
+        chunk.changed_block_entities.clear();
+        chunk.light.reset();
+
+        match self.chunks.entry(pos.into()) {
+            Entry::Occupied(mut entry) => {
+                self.replaced_chunks.push(*entry.key());
+                Some(entry.insert(chunk))
+            }
+            Entry::Vacant(entry) => {
+                entry.insert(chunk);
+                self.added_chunks += 1;
+                None
+            }
+        }
+    }
+
+    /// Returns the block at `pos`, or `None` if its chunk isn't loaded or it's
+    /// above or below the layer.
+    pub fn block(&self, pos: impl Into<BlockPos>) -> Option<BlockState> {
+        let pos = pos.into();
+        let (x, y, z) = self.block_offsets(pos)?;
+
+        self.chunk(pos).map(|chunk| chunk.block_state(x, y, z))
+    }
+
+    /// Appends the collision shapes of the blocks intersecting `aabb` to
+    /// `shapes`, in world coordinates. Returns `false` if part of `aabb` is in
+    /// an unloaded chunk. Blocks above and below the layer are air.
+    pub fn collision_shapes(&self, aabb: Aabb, shapes: &mut Vec<Aabb>) -> bool {
+        let min = aabb.min().floor().as_ivec3();
+        let max = aabb.max().floor().as_ivec3();
+
+        // Fences and walls stick up into the block above, so the blocks below
+        // are checked too.
+        for y in min.y - 1..=max.y {
+            for z in min.z..=max.z {
+                for x in min.x..=max.x {
+                    let pos = BlockPos::new(x, y, z);
+
+                    if self.chunk(pos).is_none() {
+                        return false;
+                    }
+
+                    let Some(block) = self.block(pos) else {
+                        continue;
+                    };
+
+                    let offset = DVec3::new(x as f64, y as f64, z as f64);
+                    for shape in block.collision_shapes() {
+                        let shape = shape + offset;
+                        if shape.intersects(aabb) {
+                            shapes.push(shape);
+                        }
+                    }
+                }
+            }
+        }
+
+        true
+    }
+
+    /// Sets the block at `pos` and returns the previous block. Returns `None`
+    /// and does nothing if the chunk isn't loaded or `pos` is above or below
+    /// the layer.
+    ///
+    /// Clients viewing the chunk are sent the changes made to it at the end of
+    /// the tick.
+    pub fn set_block(
+        &mut self,
+        pos: impl Into<BlockPos>,
+        block: BlockState,
+    ) -> Option<BlockState> {
+        let pos = pos.into();
+        let (x, y, z) = self.block_offsets(pos)?;
+
+        self.chunk_mut(pos)
+            .map(|chunk| chunk.set_block_state(x, y, z, block))
+    }
+
+    /// Returns the block entity data at `pos`, if any.
+    pub fn block_entity(&self, pos: impl Into<BlockPos>) -> Option<&Compound> {
+        let pos = pos.into();
+        let (x, y, z) = self.block_offsets(pos)?;
+
+        self.chunk(pos)?.block_entity(x, y, z)
+    }
+
+    /// Returns the block entity data at `pos` for changing. Clients viewing
+    /// the chunk are sent the data again at the end of the tick.
+    pub fn block_entity_mut(&mut self, pos: impl Into<BlockPos>) -> Option<&mut Compound> {
+        let pos = pos.into();
+        let (x, y, z) = self.block_offsets(pos)?;
+
+        self.chunk_mut(pos)?.block_entity_mut(x, y, z)
+    }
+
+    /// Sets or removes the block entity data at `pos` and returns the previous
+    /// data. Does nothing if the chunk isn't loaded or `pos` is above or below
+    /// the layer. See [`Chunk::set_block_entity`].
+    pub fn set_block_entity(
+        &mut self,
+        pos: impl Into<BlockPos>,
+        nbt: Option<Compound>,
+    ) -> Option<Compound> {
+        let pos = pos.into();
+        let (x, y, z) = self.block_offsets(pos)?;
+
+        self.chunk_mut(pos)?.set_block_entity(x, y, z, nbt)
+    }
+
+    /// The offsets of `pos` in its chunk, or `None` if it's above or below the
+    /// layer.
+    fn block_offsets(&self, pos: BlockPos) -> Option<(u32, u32, u32)> {
+        let y = u32::try_from(pos.y - self.info.min_y)
+            .ok()
+            .filter(|&y| y < self.info.height)?;
+
+        Some((pos.x.rem_euclid(16) as u32, y, pos.z.rem_euclid(16) as u32))
+    }
+
+    pub fn remove_chunk(&mut self, pos: impl Into<ChunkPos>) -> Option<Chunk> {
+        self.chunks.remove(&pos.into())
+    }
+
+    pub fn chunks(&self) -> impl Iterator<Item = (ChunkPos, &Chunk)> + '_ {
+        self.chunks.iter().map(|(pos, chunk)| (*pos, chunk))
+    }
+
+    pub fn chunks_mut(&mut self) -> impl Iterator<Item = (ChunkPos, &mut Chunk)> + '_ {
+        self.chunks.iter_mut().map(|(pos, chunk)| (*pos, chunk))
+    }
+
+    /// Encodes the block, block entity and light changes made to each chunk
+    /// since the previous call and clears them. Returns the packets of each
+    /// changed chunk.
+    pub(crate) fn take_block_updates(&mut self) -> Vec<(ChunkPos, Vec<u8>)> {
+        let info = &self.info;
+
+        self.chunks
+            .iter_mut()
+            .filter_map(|(&pos, chunk)| {
+                let mut buf = vec![];
+                let mut writer = PacketWriter::new(&mut buf, info.threshold);
+                chunk.write_updates(&mut writer, pos, info);
+
+                (!buf.is_empty()).then_some((pos, buf))
+            })
+            .collect()
+    }
+
+    /// Takes the positions of the chunks replaced by
+    /// [`insert_chunk`](Self::insert_chunk) since the last call.
+    pub(crate) fn take_replaced_chunks(&mut self) -> Vec<ChunkPos> {
+        mem::take(&mut self.replaced_chunks)
+    }
+
+    /// Writes the [`ChunkDataS2c`] of the chunk at `pos` to `writer`. Returns
+    /// `false` if the chunk isn't loaded.
+    pub fn write_chunk_data(
+        &self,
+        pos: impl Into<ChunkPos>,
+        writer: &mut impl WritePacket,
+    ) -> bool {
+        let pos = pos.into();
+
+        match self.chunks.get(&pos) {
+            Some(chunk) => {
+                chunk.write_init_packet(writer, pos, &self.info);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+const SECTION_BLOCK_COUNT: usize = 16 * 16 * 16;
+const SECTION_BIOME_COUNT: usize = 4 * 4 * 4;
+
+/// A set of block indices in a section, iterated in ascending order.
+#[derive(Clone, Default, Debug)]
+struct BlockSet {
+    /// One bit per block, allocated on the first insert.
+    bits: Option<Box<[u64; SECTION_BLOCK_COUNT / 64]>>,
+}
+
+impl BlockSet {
+    fn insert(&mut self, idx: u16) {
+        let bits = self
+            .bits
+            .get_or_insert_with(|| Box::new([0; SECTION_BLOCK_COUNT / 64]));
+        bits[idx as usize / 64] |= 1 << (idx % 64);
+    }
+
+    fn is_empty(&self) -> bool {
+        self.iter().next().is_none()
+    }
+
+    fn iter(&self) -> impl Iterator<Item = u16> + '_ {
+        self.bits.iter().flat_map(|bits| {
+            bits.iter().enumerate().flat_map(|(i, &word)| {
+                (0..64)
+                    .filter(move |bit| word & 1 << bit != 0)
+                    .map(move |bit| (i * 64 + bit) as u16)
+            })
+        })
+    }
+
+    fn clear(&mut self) {
+        self.bits = None;
+    }
+}
+
+#[derive(Clone, Default, Debug)]
+struct Section {
+    block_states:
+        PalettedContainer<BlockState, SECTION_BLOCK_COUNT, { SECTION_BLOCK_COUNT / 2 }>,
+    biomes: PalettedContainer<BiomeId, SECTION_BIOME_COUNT, { SECTION_BIOME_COUNT / 2 }>,
+    /// The number of blocks in the section that aren't air.
+    non_air_count: u16,
+    /// The indices of the blocks that changed since the last block update was
+    /// sent.
+    changed_blocks: BlockSet,
+    /// Whether the whole section was filled since the last block update.
+    filled: bool,
+}
+
+impl Section {
+    fn set_block_state(&mut self, idx: usize, block: BlockState) -> BlockState {
+        let old = self.block_states.set(idx, block);
+
+        match (old.is_air(), block.is_air()) {
+            (true, false) => self.non_air_count += 1,
+            (false, true) => self.non_air_count -= 1,
+            _ => {}
+        }
+
+        if old != block && !self.filled {
+            self.changed_blocks.insert(idx as u16);
+        }
+
+        old
+    }
+
+    fn fill_block_states(&mut self, block: BlockState) {
+        self.block_states.fill(block);
+        self.non_air_count = if block.is_air() {
+            0
+        } else {
+            SECTION_BLOCK_COUNT as u16
+        };
+        self.filled = true;
+        self.changed_blocks.clear();
+    }
+
+    fn clear_block_updates(&mut self) {
+        self.changed_blocks.clear();
+        self.filled = false;
+    }
+}
+
+/// A column of 16×16×16 block sections. Block coordinates are relative to the
+/// chunk, with `y` counted from the bottom of the column.
+#[derive(Debug, Default)]
+pub struct Chunk {
+    sections: Vec<Section>,
+    /// The NBT of the block entities in the chunk, keyed by
+    /// [`block_entity_idx`]. The data doesn't include the `id`, `x`, `y` and
+    /// `z` fields.
+    block_entities: BTreeMap<u32, Compound>,
+    /// The block entities changed since the last [`BlockEntityUpdateS2c`]s
+    /// were sent.
+    changed_block_entities: BTreeSet<u32>,
+    /// The encoded [`ChunkDataS2c`] of this chunk. Empty when it needs to be
+    /// rebuilt.
+    cached_init_packet: Mutex<Vec<u8>>,
+    light: ChunkLight,
+    /// Whether the chunk changed since it was last saved.
+    modified: bool,
+}
+
+impl Clone for Chunk {
+    fn clone(&self) -> Self {
+        Self {
+            sections: self.sections.clone(),
+            block_entities: self.block_entities.clone(),
+            changed_block_entities: self.changed_block_entities.clone(),
+            cached_init_packet: Mutex::default(),
+            light: self.light.clone(),
+            modified: self.modified,
+        }
+    }
+}
+
+impl Chunk {
+    /// Creates an empty chunk. Chunks are resized to the height of the layer
+    /// they are inserted into.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a chunk of air `height` blocks tall.
+    pub fn with_height(height: u32) -> Self {
+        let mut chunk = Self::new();
+        chunk.set_height(height);
+        chunk
+    }
+
+    /// The height of the chunk in blocks.
+    pub fn height(&self) -> u32 {
+        self.sections.len() as u32 * 16
+    }
+
+    /// Resizes the chunk to `height` blocks, rounded up to a multiple of 16.
+    /// New sections are filled with air.
+    pub fn set_height(&mut self, height: u32) {
+        let section_count = height.div_ceil(16) as usize;
+
+        if section_count != self.sections.len() {
+            self.sections.resize_with(section_count, Section::default);
+            self.light.set_section_count(section_count);
+
+            let height = self.height();
+            self.block_entities
+                .retain(|&idx, _| block_entity_offsets(idx).1 < height);
+
+            self.invalidate();
+        }
+    }
+
+    pub fn block_state(&self, x: u32, y: u32, z: u32) -> BlockState {
+        assert!(
+            x < 16 && y < self.height() && z < 16,
+            "chunk block offsets of ({x}, {y}, {z}) are out of bounds"
+        );
+
+        self.sections[y as usize / 16]
+            .block_states
+            .get(block_idx(x, y, z))
+    }
+
+    /// Sets the block at the given offsets and returns the previous block.
+    pub fn set_block_state(&mut self, x: u32, y: u32, z: u32, block: BlockState) -> BlockState {
+        assert!(
+            x < 16 && y < self.height() && z < 16,
+            "chunk block offsets of ({x}, {y}, {z}) are out of bounds"
+        );
+
+        let old = self.sections[y as usize / 16].set_block_state(block_idx(x, y, z), block);
+
+        if old != block {
+            // The block entity belongs to the old block.
+            if old.block_entity_kind() != block.block_entity_kind() {
+                let idx = block_entity_idx(x, y, z);
+                self.block_entities.remove(&idx);
+                self.changed_block_entities.remove(&idx);
+            }
+
+            self.invalidate();
+        }
+
+        old
+    }
+
+    /// Sets every block in the section at `sect_y` to `block`.
+    pub fn fill_block_state_section(&mut self, sect_y: u32, block: BlockState) {
+        let section = &mut self.sections[sect_y as usize];
+        section.fill_block_states(block);
+
+        let in_section = |idx: &u32| block_entity_offsets(*idx).1 / 16 == sect_y;
+        self.block_entities.retain(|idx, _| !in_section(idx));
+        self.changed_block_entities.retain(|idx| !in_section(idx));
+
+        self.invalidate();
+    }
+
+    /// Sets every block in the chunk to `block`.
+    pub fn fill_block_states(&mut self, block: BlockState) {
+        for section in &mut self.sections {
+            section.fill_block_states(block);
+        }
+        self.block_entities.clear();
+        self.changed_block_entities.clear();
+        self.invalidate();
+    }
+
+    /// Returns the biome at the given offsets in units of 4×4×4 blocks.
+    pub fn biome(&self, x: u32, y: u32, z: u32) -> BiomeId {
+        assert!(
+            x < 4 && y < self.height() / 4 && z < 4,
+            "chunk biome offsets of ({x}, {y}, {z}) are out of bounds"
+        );
+
+        self.sections[y as usize / 4].biomes.get(biome_idx(x, y, z))
+    }
+
+    /// Sets the biome at the given offsets in units of 4×4×4 blocks and
+    /// returns the previous biome.
+    pub fn set_biome(&mut self, x: u32, y: u32, z: u32, biome: BiomeId) -> BiomeId {
+        assert!(
+            x < 4 && y < self.height() / 4 && z < 4,
+            "chunk biome offsets of ({x}, {y}, {z}) are out of bounds"
+        );
+
+        let old = self.sections[y as usize / 4]
+            .biomes
+            .set(biome_idx(x, y, z), biome);
+
+        if old != biome {
+            self.invalidate();
+        }
+
+        old
+    }
+
+    /// Sets every biome in the chunk to `biome`.
+    pub fn fill_biomes(&mut self, biome: BiomeId) {
+        for section in &mut self.sections {
+            section.biomes.fill(biome);
+        }
+        self.invalidate();
+    }
+
+    /// Returns the block entity data at the given offsets, if any.
+    pub fn block_entity(&self, x: u32, y: u32, z: u32) -> Option<&Compound> {
+        self.block_entities.get(&block_entity_idx(x, y, z))
+    }
+
+    /// Returns the block entity data at the given offsets for changing. Viewers
+    /// are sent the data again at the end of the tick.
+    pub fn block_entity_mut(&mut self, x: u32, y: u32, z: u32) -> Option<&mut Compound> {
+        let idx = block_entity_idx(x, y, z);
+
+        if self.block_entities.contains_key(&idx) {
+            self.changed_block_entities.insert(idx);
+            self.invalidate();
+        }
+
+        self.block_entities.get_mut(&idx)
+    }
+
+    /// Sets or removes the block entity data at the given offsets and returns
+    /// the previous data. The data is only sent to clients if the block at the
+    /// offsets has a block entity, and is dropped when the block is replaced by one
+    /// without the same kind of block entity.
+    pub fn set_block_entity(
+        &mut self,
+        x: u32,
+        y: u32,
+        z: u32,
+        nbt: Option<Compound>,
+    ) -> Option<Compound> {
+        assert!(
+            x < 16 && y < self.height() && z < 16,
+            "chunk block offsets of ({x}, {y}, {z}) are out of bounds"
+        );
+
+        let idx = block_entity_idx(x, y, z);
+        let old = match nbt {
+            Some(nbt) => {
+                self.changed_block_entities.insert(idx);
+                self.block_entities.insert(idx, nbt)
+            }
+            None => {
+                self.changed_block_entities.remove(&idx);
+                self.block_entities.remove(&idx)
+            }
+        };
+
+        self.invalidate();
+        old
+    }
+
+    /// Iterates over the block entities in the chunk as `(x, y, z, nbt)`.
+    pub fn block_entities(&self) -> impl Iterator<Item = (u32, u32, u32, &Compound)> + '_ {
+        self.block_entities.iter().map(|(&idx, nbt)| {
+            let (x, y, z) = block_entity_offsets(idx);
+            (x, y, z, nbt)
+        })
+    }
+
+    /// Shrinks the block and biome storage of every section as much as
+    /// possible.
+    pub fn optimize(&mut self) {
+        for section in &mut self.sections {
+            section.block_states.optimize();
+            section.biomes.optimize();
+        }
+    }
+
+    /// Whether the chunk changed since [`Chunk::clear_modified`] was last
+    /// called. New chunks count as modified once anything is written to them.
+    pub fn is_modified(&self) -> bool {
+        self.modified
+    }
+
+    /// Marks the chunk as saved.
+    pub fn clear_modified(&mut self) {
+        self.modified = false;
+    }
+
+    /// Marks the chunk as changed since it was last saved, e.g. after saving
+    /// it failed.
+    pub fn mark_modified(&mut self) {
+        self.modified = true;
+    }
+
+    fn invalidate(&mut self) {
+        self.modified = true;
+        self.clear_cached_init_packet();
+    }
+
+    fn clear_cached_init_packet(&mut self) {
+        self.cached_init_packet
+            .get_mut()
+            .unwrap_or_else(|e| e.into_inner())
+            .clear();
+    }
+
+    /// Writes a [`BlockUpdateS2c`] or [`ChunkDeltaUpdateS2c`] for every
+    /// section changed since the last call, followed by a
+    /// [`BlockEntityUpdateS2c`] for every changed block entity and a
+    /// `LightUpdateS2c` for the sections whose light changed, and
+    /// clears the changes.
+    fn write_updates(
+        &mut self,
+        writer: &mut impl WritePacket,
+        pos: ChunkPos,
+        info: &ChunkLayerInfo,
+    ) {
+        for (sect_y, section) in self.sections.iter_mut().enumerate() {
+            let changed_blocks: Vec<u16> = if section.filled {
+                (0..SECTION_BLOCK_COUNT as u16).collect()
+            } else if section.changed_blocks.is_empty() {
+                continue;
+            } else {
+                section.changed_blocks.iter().collect()
+            };
+
+            section.clear_block_updates();
+
+            let offsets = |idx: u16| (idx as u32 % 16, idx as u32 / 256, idx as u32 / 16 % 16);
+            let sect_y = info.min_y.div_euclid(16) + sect_y as i32;
+
+            if let [idx] = changed_blocks[..] {
+                let (x, y, z) = offsets(idx);
+
+                writer.write_packet(&BlockUpdateS2c {
+                    position: BlockPos::new(
+                        pos.x * 16 + x as i32,
+                        sect_y * 16 + y as i32,
+                        pos.z * 16 + z as i32,
+                    ),
+                    block_id: section.block_states.get(idx as usize),
+                });
+            } else {
+                let blocks: Vec<_> = changed_blocks
+                    .into_iter()
+                    .map(|idx| {
+                        let (x, y, z) = offsets(idx);
+
+                        ChunkDeltaUpdateEntry::new()
+                            .with_off_x(x as u8)
+                            .with_off_y(y as u8)
+                            .with_off_z(z as u8)
+                            .with_block_state(
+                                section.block_states.get(idx as usize).to_raw() as u32,
+                            )
+                    })
+                    .collect();
+
+                writer.write_packet(&ChunkDeltaUpdateS2c {
+                    chunk_sect_pos: ChunkSectionPos::new(pos.x, sect_y, pos.z),
+                    blocks: Cow::Owned(blocks),
+                });
+            }
+        }
+
+        for idx in mem::take(&mut self.changed_block_entities) {
+            let (x, y, z) = block_entity_offsets(idx);
+
+            let (Some(nbt), Some(kind)) = (
+                self.block_entities.get(&idx),
+                self.block_state(x, y, z).block_entity_kind(),
+            ) else {
+                continue;
+            };
+
+            writer.write_packet(&BlockEntityUpdateS2c {
+                position: BlockPos::new(
+                    pos.x * 16 + x as i32,
+                    info.min_y + y as i32,
+                    pos.z * 16 + z as i32,
+                ),
+                kind,
+                data: Cow::Borrowed(nbt),
+            });
+        }
+
+        self.write_light_updates(writer, pos);
+    }
+
+    /// Writes the [`ChunkDataS2c`] for this chunk, encoding it first if it
+    /// changed since the last call.
+    pub(crate) fn write_init_packet(
+        &self,
+        writer: &mut impl WritePacket,
+        pos: ChunkPos,
+        info: &ChunkLayerInfo,
+    ) {
+        let mut init_packet = self
+            .cached_init_packet
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+
+        if init_packet.is_empty() {
+            let mut blocks_and_biomes = vec![];
+
+            for section in &self.sections {
+                section.encode(&mut blocks_and_biomes, info);
+            }
+
+            let heightmaps = self.heightmaps();
+
+            let block_entities: Vec<_> = self
+                .block_entities()
+                .filter_map(|(x, y, z, nbt)| {
+                    Some(ChunkDataBlockEntity {
+                        packed_xz: ((x << 4) | z) as i8,
+                        y: (info.min_y + y as i32) as i16,
+                        kind: self.block_state(x, y, z).block_entity_kind()?,
+                        data: Cow::Borrowed(nbt),
+                    })
+                })
+                .collect();
+
+            let light = self.light_packet_data(info.full_bright);
+
+            PacketWriter::new(&mut *init_packet, info.threshold).write_packet(&ChunkDataS2c {
+                pos,
+                heightmaps: Cow::Owned(heightmaps),
+                blocks_and_biomes: &blocks_and_biomes,
+                block_entities: Cow::Owned(block_entities),
+                sky_light_mask: Cow::Owned(light.sky_light_mask),
+                block_light_mask: Cow::Owned(light.block_light_mask),
+                empty_sky_light_mask: Cow::Owned(light.empty_sky_light_mask),
+                empty_block_light_mask: Cow::Owned(light.empty_block_light_mask),
+                sky_light_arrays: Cow::Owned(light.sky_light_arrays),
+                block_light_arrays: Cow::Owned(light.block_light_arrays),
+            });
+        }
+
+        writer.write_packet_bytes(&init_packet);
+    }
+
+    /// Builds the `MOTION_BLOCKING` and `WORLD_SURFACE` heightmaps. Each entry
+    /// is one more than the offset of the highest matching block in the
+    /// column, or zero if there is none.
+    fn heightmaps(&self) -> Compound {
+        let mut motion_blocking = [0_u64; 256];
+        let mut world_surface = [0_u64; 256];
+
+        for z in 0..16 {
+            for x in 0..16 {
+                let idx = (z * 16 + x) as usize;
+
+                'column: for (sect_y, section) in self.sections.iter().enumerate().rev() {
+                    if section.non_air_count == 0 {
+                        continue;
+                    }
+
+                    for y in (0..16).rev() {
+                        let block = section.block_states.get(block_idx(x, y, z));
+                        let height = (sect_y * 16) as u64 + y as u64 + 1;
+
+                        if world_surface[idx] == 0 && !block.is_air() {
+                            world_surface[idx] = height;
+                        }
+
+                        if block.blocks_motion() || block.is_liquid() {
+                            motion_blocking[idx] = height;
+                            break 'column;
+                        }
+                    }
+                }
+            }
+        }
+
+        let bits = bit_width(self.height() as usize);
+
+        compound! {
+            "MOTION_BLOCKING" => pack_heightmap(&motion_blocking, bits),
+            "WORLD_SURFACE" => pack_heightmap(&world_surface, bits),
+        }
+    }
+}
+
+impl Section {
+    fn encode(&self, buf: &mut Vec<u8>, info: &ChunkLayerInfo) {
+        let _ = (self.non_air_count as i16).encode(&mut *buf);
+
+        let _ = self.block_states.encode_mc_format(
+            &mut *buf,
+            |b| b.to_raw().into(),
+            4,
+            8,
+            bit_width(BlockState::max_raw().into()),
+        );
+
+        let _ = self.biomes.encode_mc_format(
+            &mut *buf,
+            |b| b.0.into(),
+            0,
+            3,
+            bit_width(info.biome_registry_len.saturating_sub(1)),
+        );
+    }
+}
+
+/// Packs heightmap entries into longs without spanning entries across longs.
+fn pack_heightmap(heights: &[u64; 256], bits: usize) -> Vec<i64> {
+    let per_long = 64 / bits;
+
+    heights
+        .chunks(per_long)
+        .map(|chunk| {
+            chunk
+                .iter()
+                .enumerate()
+                .fold(0_u64, |acc, (i, &h)| acc | h << (i * bits)) as i64
+        })
+        .collect()
+}
+
+fn block_idx(x: u32, y: u32, z: u32) -> usize {
+    (x + z * 16 + y % 16 * 16 * 16) as usize
+}
+
+fn block_entity_idx(x: u32, y: u32, z: u32) -> u32 {
+    x + z * 16 + y * 16 * 16
+}
+
+fn block_entity_offsets(idx: u32) -> (u32, u32, u32) {
+    (idx % 16, idx / (16 * 16), idx / 16 % 16)
+}
+
+fn biome_idx(x: u32, y: u32, z: u32) -> usize {
+    (x + z * 4 + y % 4 * 4 * 4) as usize
+}
+
+#[cfg(test)]
+mod tests {
+    use valence_protocol::block::{BlockEntityKind, PropName, PropValue};
+    use valence_protocol::PacketDecoder;
+
+    use super::*;
+    use crate::testing::layer;
+
+    #[test]
+    fn insert_chunk_matches_layer_height() {
+        let mut layer = layer();
+        layer.insert_chunk([0, 0], Chunk::new());
+
+        assert_eq!(layer.chunk([0, 0]).unwrap().height(), 384);
+        assert_eq!(layer.min_y(), -64);
+    }
+
+    #[test]
+    fn set_block_state_tracks_non_air_count() {
+        let mut chunk = Chunk::with_height(32);
+
+        assert_eq!(chunk.set_block_state(1, 17, 2, BlockState::STONE), BlockState::AIR);
+        assert_eq!(chunk.block_state(1, 17, 2), BlockState::STONE);
+        assert_eq!(chunk.sections[1].non_air_count, 1);
+
+        chunk.set_block_state(1, 17, 2, BlockState::AIR);
+        assert_eq!(chunk.sections[1].non_air_count, 0);
+
+        chunk.fill_block_state_section(0, BlockState::DIRT);
+        assert_eq!(chunk.sections[0].non_air_count, 4096);
+    }
+
+    #[test]
+    fn heightmaps() {
+        let mut chunk = Chunk::with_height(32);
+        chunk.set_block_state(0, 3, 0, BlockState::STONE);
+        chunk.set_block_state(0, 20, 0, BlockState::OAK_SIGN);
+
+        let mut motion_blocking = [0; 256];
+        motion_blocking[0] = 4;
+        let mut world_surface = [0; 256];
+        world_surface[0] = 21;
+
+        assert_eq!(
+            chunk.heightmaps(),
+            compound! {
+                "MOTION_BLOCKING" => pack_heightmap(&motion_blocking, 6),
+                "WORLD_SURFACE" => pack_heightmap(&world_surface, 6),
+            }
+        );
+    }
+
+    #[test]
+    fn changed_blocks_are_recorded_once() {
+        let mut section = Section::default();
+
+        for _ in 0..3 {
+            section.set_block_state(5, BlockState::STONE);
+            section.set_block_state(5, BlockState::DIRT);
+        }
+        section.set_block_state(1, BlockState::STONE);
+
+        assert_eq!(section.changed_blocks.iter().collect::<Vec<_>>(), [1, 5]);
+
+        section.clear_block_updates();
+        assert!(section.changed_blocks.is_empty());
+    }
+
+    #[test]
+    fn block_updates_are_batched_per_section() {
+        let mut layer = layer();
+        let mut chunk = Chunk::with_height(layer.height());
+        chunk.set_block_state(0, 0, 0, BlockState::STONE);
+        layer.insert_chunk([1, -1], chunk);
+
+        // Inserted chunks are sent whole.
+        assert!(layer.take_block_updates().is_empty());
+
+        assert_eq!(layer.set_block([16, -64, -16], BlockState::DIRT), Some(BlockState::STONE));
+        assert_eq!(layer.set_block([17, -60, -16], BlockState::DIRT), Some(BlockState::AIR));
+        layer.set_block([17, -60, -16], BlockState::GLASS);
+        layer.set_block([20, 100, -10], BlockState::GLASS);
+        // Nothing changes.
+        layer.set_block([20, 101, -10], BlockState::AIR);
+
+        assert_eq!(layer.set_block([0, 0, 0], BlockState::DIRT), None);
+        assert_eq!(layer.set_block([16, 320, -16], BlockState::DIRT), None);
+        assert_eq!(layer.block([17, -60, -16]), Some(BlockState::GLASS));
+        assert_eq!(layer.block([16, -65, -16]), None);
+
+        let updates = layer.take_block_updates();
+        assert_eq!(updates.len(), 1);
+        assert_eq!(updates[0].0, ChunkPos::new(1, -1));
+
+        let mut decoder = PacketDecoder::new();
+        decoder.queue_slice(&updates[0].1);
+
+        let frame = decoder.try_next_packet().unwrap().unwrap();
+        let delta = frame.decode::<ChunkDeltaUpdateS2c>().unwrap();
+        assert_eq!(delta.chunk_sect_pos, ChunkSectionPos::new(1, -4, -1));
+        assert_eq!(delta.blocks.len(), 2);
+        assert_eq!(delta.blocks[0].block_state(), BlockState::DIRT.to_raw() as u32);
+        assert_eq!(
+            (delta.blocks[1].off_x(), delta.blocks[1].off_y(), delta.blocks[1].off_z()),
+            (1, 4, 0)
+        );
+        assert_eq!(delta.blocks[1].block_state(), BlockState::GLASS.to_raw() as u32);
+
+        let frame = decoder.try_next_packet().unwrap().unwrap();
+        let update = frame.decode::<BlockUpdateS2c>().unwrap();
+        assert_eq!(update.position, BlockPos::new(20, 100, -10));
+        assert_eq!(update.block_id, BlockState::GLASS);
+
+        assert!(decoder.try_next_packet().unwrap().is_none());
+        assert!(layer.take_block_updates().is_empty());
+
+        // Filling a section sends every block in it.
+        layer
+            .chunk_mut([1, -1])
+            .unwrap()
+            .fill_block_state_section(20, BlockState::STONE);
+
+        let updates = layer.take_block_updates();
+        decoder.queue_slice(&updates[0].1);
+        let frame = decoder.try_next_packet().unwrap().unwrap();
+        let delta = frame.decode::<ChunkDeltaUpdateS2c>().unwrap();
+        assert_eq!(delta.chunk_sect_pos, ChunkSectionPos::new(1, 16, -1));
+        assert_eq!(delta.blocks.len(), 4096);
+    }
+
+    #[test]
+    fn block_entities_are_synced() {
+        let mut layer = layer();
+        layer.insert_chunk([0, 0], Chunk::with_height(layer.height()));
+
+        layer.set_block([1, 70, 2], BlockState::CHEST);
+        let data = compound! { "CustomName" => r#"{"text":"Loot"}"# };
+        layer.set_block_entity([1, 70, 2], Some(data.clone()));
+        // There's no block entity at this block, so the data isn't sent.
+        layer.set_block_entity([3, 70, 3], Some(Compound::new()));
+
+        let mut decoder = PacketDecoder::new();
+        decoder.queue_slice(&layer.take_block_updates()[0].1);
+
+        let frame = decoder.try_next_packet().unwrap().unwrap();
+        assert_eq!(frame.decode::<BlockUpdateS2c>().unwrap().block_id, BlockState::CHEST);
+
+        let frame = decoder.try_next_packet().unwrap().unwrap();
+        let update = frame.decode::<BlockEntityUpdateS2c>().unwrap();
+        assert_eq!(update.position, BlockPos::new(1, 70, 2));
+        assert_eq!(update.kind, BlockEntityKind::Chest);
+        assert_eq!(*update.data, data);
+
+        assert!(decoder.try_next_packet().unwrap().is_none());
+
+        let mut buf = vec![];
+        let mut writer = PacketWriter::new(&mut buf, CompressionThreshold(-1));
+        assert!(layer.write_chunk_data([0, 0], &mut writer));
+
+        decoder.queue_slice(&buf);
+        let frame = decoder.try_next_packet().unwrap().unwrap();
+        let chunk_data = frame.decode::<ChunkDataS2c>().unwrap();
+        assert_eq!(
+            *chunk_data.block_entities,
+            [ChunkDataBlockEntity {
+                packed_xz: (1 << 4) | 2,
+                y: 70,
+                kind: BlockEntityKind::Chest,
+                data: Cow::Owned(data),
+            }]
+        );
+
+        // Turning the chest doesn't replace the block entity, but removing it
+        // does.
+        layer.set_block(
+            [1, 70, 2],
+            BlockState::CHEST.set(PropName::Facing, PropValue::East),
+        );
+        assert!(layer.block_entity([1, 70, 2]).is_some());
+        layer.set_block([1, 70, 2], BlockState::STONE);
+        assert!(layer.block_entity([1, 70, 2]).is_none());
+    }
+
+    #[test]
+    fn cached_init_packet_is_invalidated() {
+        let layer = layer();
+        let mut chunk = Chunk::with_height(layer.height());
+
+        let encode = |chunk: &Chunk| {
+            let mut buf = vec![];
+            let mut writer = PacketWriter::new(&mut buf, CompressionThreshold(-1));
+            chunk.write_init_packet(&mut writer, ChunkPos::new(0, 0), layer.info());
+            buf
+        };
+
+        let first = encode(&chunk);
+        assert!(!chunk.cached_init_packet.lock().unwrap().is_empty());
+        assert_eq!(encode(&chunk), first);
+
+        chunk.set_block_state(0, 0, 0, BlockState::STONE);
+        assert!(chunk.cached_init_packet.lock().unwrap().is_empty());
+        assert_ne!(encode(&chunk), first);
+    }
+}
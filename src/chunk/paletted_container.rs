@@ -0,0 +1,380 @@
+use std::array;
+use std::io::Write;
+
+use valence_protocol::{anyhow, Encode, VarInt};
+
+/// `HALF_LEN` must be equal to `ceil(LEN / 2)`.
+#[derive(Clone, Debug)]
+pub(crate) enum PalettedContainer<T, const LEN: usize, const HALF_LEN: usize> {
+    Single(T),
+    Indirect(Box<Indirect<T, LEN, HALF_LEN>>),
+    Direct(Box<[T; LEN]>),
+}
+
+/// Up to 16 distinct values with a 4-bit index per element.
+#[derive(Clone, Debug)]
+pub(crate) struct Indirect<T, const LEN: usize, const HALF_LEN: usize> {
+    /// Never empty and never longer than 16. The first entry is the value of
+    /// every element whose index was never set.
+    palette: Vec<T>,
+    /// Each byte holds two 4-bit indices into `palette`.
+    indices: [u8; HALF_LEN],
+}
+
+const INDIRECT_CAPACITY: usize = 16;
+
+impl<T: Copy + Eq + Default, const LEN: usize, const HALF_LEN: usize>
+    PalettedContainer<T, LEN, HALF_LEN>
+{
+    pub(crate) fn new() -> Self {
+        debug_assert!(LEN > 0);
+        debug_assert_eq!(LEN.div_ceil(2), HALF_LEN);
+
+        Self::Single(T::default())
+    }
+
+    pub(crate) fn fill(&mut self, val: T) {
+        *self = Self::Single(val)
+    }
+
+    pub(crate) fn get(&self, idx: usize) -> T {
+        debug_assert!(idx < LEN);
+
+        match self {
+            Self::Single(elem) => *elem,
+            Self::Indirect(ind) => ind.get(idx),
+            Self::Direct(elems) => elems[idx],
+        }
+    }
+
+    /// Sets the element at `idx` and returns the previous value.
+    pub(crate) fn set(&mut self, idx: usize, val: T) -> T {
+        debug_assert!(idx < LEN);
+
+        match self {
+            Self::Single(old_val) => {
+                if *old_val == val {
+                    *old_val
+                } else {
+                    // Upgrade to indirect.
+                    let old = *old_val;
+                    let mut ind = Box::new(Indirect {
+                        palette: vec![old, val],
+                        // All indices are 0 (the old value).
+                        indices: [0; HALF_LEN],
+                    });
+
+                    ind.set_index(idx, 1);
+                    *self = Self::Indirect(ind);
+                    old
+                }
+            }
+            Self::Indirect(ind) => {
+                if let Some(old) = ind.set(idx, val) {
+                    old
+                } else {
+                    // Upgrade to direct.
+                    let mut direct = Box::new(array::from_fn(|i| ind.get(i)));
+                    let old = direct[idx];
+                    direct[idx] = val;
+                    *self = Self::Direct(direct);
+                    old
+                }
+            }
+            Self::Direct(elems) => {
+                let old = elems[idx];
+                elems[idx] = val;
+                old
+            }
+        }
+    }
+
+    /// Converts the container to the smallest representation that can hold
+    /// its current contents.
+    pub(crate) fn optimize(&mut self) {
+        match self {
+            Self::Single(_) => {}
+            Self::Indirect(ind) => {
+                let mut new_ind = Indirect {
+                    palette: vec![ind.get(0)],
+                    indices: [0; HALF_LEN],
+                };
+
+                for i in 0..LEN {
+                    new_ind.set(i, ind.get(i));
+                }
+
+                if new_ind.palette.len() == 1 {
+                    *self = Self::Single(new_ind.palette[0]);
+                } else {
+                    **ind = new_ind;
+                }
+            }
+            Self::Direct(elems) => {
+                let mut ind = Indirect {
+                    palette: vec![elems[0]],
+                    indices: [0; HALF_LEN],
+                };
+
+                for (i, elem) in elems.iter().enumerate() {
+                    if ind.set(i, *elem).is_none() {
+                        return;
+                    }
+                }
+
+                *self = if ind.palette.len() == 1 {
+                    Self::Single(ind.palette[0])
+                } else {
+                    Self::Indirect(Box::new(ind))
+                };
+            }
+        }
+    }
+
+    /// Encodes the paletted container in the format that Minecraft expects.
+    ///
+    /// - **`writer`**: The [`Write`] instance to write the paletted container
+    ///   to.
+    /// - **`to_bits`**: A function to convert the element type to bits. The
+    ///   output must be less than two to the power of `direct_bits`.
+    /// - **`min_indirect_bits`**: The minimum number of bits used to represent
+    ///   the element type in the indirect representation. If the bits per
+    ///   index is lower, it will be rounded up to this.
+    /// - **`max_indirect_bits`**: The maximum number of bits per element
+    ///   allowed in the indirect representation. Any higher than this will
+    ///   force conversion to the direct representation while encoding.
+    /// - **`direct_bits`**: The minimum number of bits required to represent
+    ///   all instances of the element type. If `N` is the total number of
+    ///   possible values, then `DIRECT_BITS` is `floor(log2(N - 1)) + 1`.
+    pub(crate) fn encode_mc_format<W, F>(
+        &self,
+        mut writer: W,
+        mut to_bits: F,
+        min_indirect_bits: usize,
+        max_indirect_bits: usize,
+        direct_bits: usize,
+    ) -> anyhow::Result<()>
+    where
+        W: Write,
+        F: FnMut(T) -> u64,
+    {
+        debug_assert!(min_indirect_bits <= 4);
+        debug_assert!(min_indirect_bits <= max_indirect_bits);
+        debug_assert!(max_indirect_bits <= 64);
+        debug_assert!(direct_bits <= 64);
+
+        match self {
+            Self::Single(val) => {
+                // Bits per entry
+                0_u8.encode(&mut writer)?;
+                // Palette
+                VarInt(to_bits(*val) as i32).encode(&mut writer)?;
+                // Number of longs
+                VarInt(0).encode(writer)?;
+            }
+            Self::Indirect(ind) => {
+                let bits_per_entry = min_indirect_bits.max(bit_width(ind.palette.len() - 1));
+
+                if bits_per_entry > max_indirect_bits {
+                    // Encode as direct.
+                    (direct_bits as u8).encode(&mut writer)?;
+
+                    encode_compact_u64s(
+                        writer,
+                        (0..LEN).map(|i| to_bits(ind.get(i))),
+                        LEN,
+                        direct_bits,
+                    )?;
+                } else {
+                    (bits_per_entry as u8).encode(&mut writer)?;
+
+                    VarInt(ind.palette.len() as i32).encode(&mut writer)?;
+                    for &val in &ind.palette {
+                        VarInt(to_bits(val) as i32).encode(&mut writer)?;
+                    }
+
+                    encode_compact_u64s(
+                        writer,
+                        ind.indices
+                            .iter()
+                            .copied()
+                            .flat_map(|byte| [byte & 0b1111, byte >> 4])
+                            .map(u64::from)
+                            .take(LEN),
+                        LEN,
+                        bits_per_entry,
+                    )?;
+                }
+            }
+            Self::Direct(elems) => {
+                (direct_bits as u8).encode(&mut writer)?;
+
+                encode_compact_u64s(
+                    writer,
+                    elems.iter().copied().map(to_bits),
+                    LEN,
+                    direct_bits,
+                )?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl<T: Copy + Eq + Default, const LEN: usize, const HALF_LEN: usize> Default
+    for PalettedContainer<T, LEN, HALF_LEN>
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: Copy + Eq + Default, const LEN: usize, const HALF_LEN: usize> Indirect<T, LEN, HALF_LEN> {
+    fn get(&self, idx: usize) -> T {
+        let palette_idx = (self.indices[idx / 2] >> (idx % 2 * 4)) & 0b1111;
+        self.palette[palette_idx as usize]
+    }
+
+    fn set_index(&mut self, idx: usize, palette_idx: u8) {
+        let shift = idx % 2 * 4;
+        let byte = &mut self.indices[idx / 2];
+        *byte = (*byte & !(0b1111 << shift)) | (palette_idx << shift);
+    }
+
+    /// Returns the previous value, or `None` if the palette is full and `val`
+    /// isn't in it.
+    fn set(&mut self, idx: usize, val: T) -> Option<T> {
+        let palette_idx = match self.palette.iter().position(|&v| v == val) {
+            Some(i) => i,
+            None if self.palette.len() < INDIRECT_CAPACITY => {
+                self.palette.push(val);
+                self.palette.len() - 1
+            }
+            None => return None,
+        };
+
+        let old = self.get(idx);
+        self.set_index(idx, palette_idx as u8);
+        Some(old)
+    }
+}
+
+/// The number of bits needed to represent `n`.
+pub(crate) const fn bit_width(n: usize) -> usize {
+    (usize::BITS - n.leading_zeros()) as _
+}
+
+/// Writes the length of the long array followed by the `len` values of `vals`
+/// packed `bits_per_val` bits at a time. Values never span two longs.
+pub(crate) fn encode_compact_u64s(
+    mut w: impl Write,
+    mut vals: impl Iterator<Item = u64>,
+    len: usize,
+    bits_per_val: usize,
+) -> anyhow::Result<()> {
+    debug_assert!(bits_per_val > 0 && bits_per_val <= 64);
+
+    let vals_per_u64 = 64 / bits_per_val;
+
+    VarInt(len.div_ceil(vals_per_u64) as i32).encode(&mut w)?;
+
+    loop {
+        let mut n = 0;
+        for i in 0..vals_per_u64 {
+            match vals.next() {
+                Some(val) => {
+                    debug_assert!(val < 2_u128.pow(bits_per_val as _) as _);
+                    n |= val << (i * bits_per_val);
+                }
+                None if i > 0 => return n.encode(&mut w),
+                None => return Ok(()),
+            }
+        }
+        n.encode(&mut w)?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::Rng;
+
+    use super::*;
+
+    fn check<T: Copy + Eq + Default + std::fmt::Debug, const LEN: usize, const HALF_LEN: usize>(
+        p: &PalettedContainer<T, LEN, HALF_LEN>,
+        s: &[T],
+    ) -> bool {
+        assert_eq!(s.len(), LEN);
+        (0..LEN).all(|i| p.get(i) == s[i])
+    }
+
+    #[test]
+    fn random_assignments() {
+        const LEN: usize = 100;
+        let range = 0..64;
+
+        let mut rng = rand::thread_rng();
+
+        for _ in 0..20 {
+            let mut p = PalettedContainer::<u32, LEN, { LEN / 2 }>::new();
+
+            let init = rng.gen_range(range.clone());
+
+            p.fill(init);
+            let mut a = [init; LEN];
+
+            assert!(check(&p, &a));
+
+            let mut rng = rand::thread_rng();
+
+            for _ in 0..LEN * 10 {
+                let idx = rng.gen_range(0..LEN);
+                let val = rng.gen_range(range.clone());
+
+                assert_eq!(p.get(idx), p.set(idx, val));
+                assert_eq!(val, p.get(idx));
+                a[idx] = val;
+
+                p.optimize();
+
+                assert!(check(&p, &a));
+            }
+        }
+    }
+
+    #[test]
+    fn optimize_shrinks_representation() {
+        let mut p = PalettedContainer::<u32, 64, 32>::new();
+
+        for i in 0..64 {
+            p.set(i, i as u32);
+        }
+        assert!(matches!(p, PalettedContainer::Direct(_)));
+
+        for i in 0..64 {
+            p.set(i, (i % 3) as u32);
+        }
+        p.optimize();
+        assert!(matches!(p, PalettedContainer::Indirect(_)));
+
+        for i in 0..64 {
+            p.set(i, 7);
+        }
+        p.optimize();
+        assert!(matches!(p, PalettedContainer::Single(7)));
+    }
+
+    #[test]
+    fn compact_u64s_do_not_span_longs() {
+        let mut buf = vec![];
+        // 5 bits per value means 12 values per long, so 13 values take 2 longs.
+        encode_compact_u64s(&mut buf, [31_u64; 13].into_iter(), 13, 5).unwrap();
+
+        assert_eq!(buf[0], 2);
+        let first = u64::from_be_bytes(buf[1..9].try_into().unwrap());
+        let second = u64::from_be_bytes(buf[9..17].try_into().unwrap());
+        assert_eq!(first, (1 << 60) - 1);
+        assert_eq!(second, 31);
+    }
+}
@@ -0,0 +1,371 @@
+//! Streams the chunks of a [`ChunkLayer`] to the clients viewing it.
+//!
+//! Each tick the chunks around a client are sent closest first, in a spiral
+//! around the chunk the client is standing in. At most
+//! `max_chunks_per_tick` chunks are sent to a client per tick so that joining
+//! a world doesn't queue one huge burst of packets. Chunks that leave the view
+//! are unloaded.
+//!
+//! Blocks changed in loaded chunks during a tick are sent at the end of the
+//! tick, batched into one packet per changed section. Loaded chunks that were
+//! replaced during the tick are sent again whole.
+//!
+//! Once every chunk in a client's view that exists has been sent, the view
+//! isn't searched again until it moves or the layer gains a chunk, so a view
+//! reaching past the edge of the world costs nothing while it stands still.
+
+use std::collections::HashSet;
+
+use derive_more::Deref;
+use evenio::prelude::*;
+use valence_entity::Position;
+use valence_protocol::packets::play::{
+    ChunkLoadDistanceS2c, ChunkRenderDistanceCenterS2c, UnloadChunkS2c,
+};
+use valence_protocol::{ChunkPos, VarInt, WritePacket};
+use valence_server_common::PostUpdate;
+
+use super::ChunkLayer;
+use crate::client::{Client, ViewDistance};
+use crate::event_loop::ClientSettingsEvent;
+use crate::SharedServer;
+
+/// The [`ChunkLayer`] entity whose chunks are sent to the client.
+#[derive(Component, Copy, Clone, PartialEq, Eq, Debug, Deref)]
+pub struct VisibleChunkLayer(pub EntityId);
+
+impl Default for VisibleChunkLayer {
+    fn default() -> Self {
+        Self(EntityId::NULL)
+    }
+}
+
+/// The view distance the client asked for in its settings, or `None` if it
+/// hasn't sent its settings yet. The chunks sent are limited by both this and
+/// [`ViewDistance`].
+#[derive(Component, Copy, Clone, PartialEq, Eq, Default, Debug, Deref)]
+pub struct RequestedViewDistance(pub Option<u8>);
+
+/// The chunks the client currently has loaded.
+#[derive(Component, Debug)]
+pub struct ChunkView {
+    layer: EntityId,
+    center: Option<ChunkPos>,
+    view_distance: u8,
+    loaded: HashSet<ChunkPos>,
+    /// The `added_chunks` of the layer when every chunk in view was last found
+    /// sent or missing from the layer, or `None` if the view changed since.
+    complete_at: Option<u64>,
+}
+
+impl Default for ChunkView {
+    fn default() -> Self {
+        Self {
+            layer: EntityId::NULL,
+            center: None,
+            view_distance: 0,
+            loaded: HashSet::new(),
+            complete_at: None,
+        }
+    }
+}
+
+impl ChunkView {
+    /// The chunk the view is centered on, or `None` before the first chunks
+    /// are sent.
+    pub fn center(&self) -> Option<ChunkPos> {
+        self.center
+    }
+
+    /// The view distance chunks are currently sent with.
+    pub fn view_distance(&self) -> u8 {
+        self.view_distance
+    }
+
+    /// Whether the chunk at `pos` has been sent to the client.
+    pub fn is_loaded(&self, pos: ChunkPos) -> bool {
+        self.loaded.contains(&pos)
+    }
+
+    /// The chunks that have been sent to the client.
+    pub fn loaded(&self) -> impl Iterator<Item = ChunkPos> + '_ {
+        self.loaded.iter().copied()
+    }
+
+    /// Whether `pos` is within the view, loaded or not.
+    pub fn contains(&self, pos: ChunkPos) -> bool {
+        self.center
+            .is_some_and(|center| in_view(center, self.view_distance, pos))
+    }
+
+    /// A view of `layer` in which the chunks at `loaded` were already sent.
+    #[cfg(test)]
+    pub(crate) fn with_loaded(layer: EntityId, loaded: impl IntoIterator<Item = ChunkPos>) -> Self {
+        Self {
+            layer,
+            loaded: loaded.into_iter().collect(),
+            ..Self::default()
+        }
+    }
+}
+
+pub fn handle_client_settings(r: Receiver<ClientSettingsEvent, &mut RequestedViewDistance>) {
+    let requested = r.query;
+    requested.0 = Some(r.event.view_distance);
+}
+
+/// Sends the block changes made during the tick, and the chunks replaced
+/// during it, to the clients that have the chunks loaded.
+pub fn broadcast_block_updates(
+    _: Receiver<PostUpdate>,
+    mut layers: Fetcher<(EntityId, &mut ChunkLayer)>,
+    mut clients: Fetcher<(&mut Client, &ChunkView)>,
+) {
+    for (layer_id, layer) in layers.iter_mut() {
+        let updates = layer.take_block_updates();
+        let replaced = layer.take_replaced_chunks();
+
+        if updates.is_empty() && replaced.is_empty() {
+            continue;
+        }
+
+        for (client, view) in clients.iter_mut() {
+            if view.layer != layer_id {
+                continue;
+            }
+
+            for (pos, bytes) in &updates {
+                if view.is_loaded(*pos) {
+                    client.write_packet_bytes(bytes);
+                }
+            }
+
+            for &pos in &replaced {
+                if view.is_loaded(pos) {
+                    // The client drops the chunk it has for the new one.
+                    layer.write_chunk_data(pos, client);
+                }
+            }
+        }
+    }
+}
+
+/// Sends newly visible chunks and unloads the ones that left the view.
+pub fn update_chunk_views(
+    _: Receiver<PostUpdate>,
+    mut clients: Fetcher<(
+        &mut Client,
+        &mut ChunkView,
+        &VisibleChunkLayer,
+        &Position,
+        &ViewDistance,
+        &RequestedViewDistance,
+    )>,
+    layers: Fetcher<&ChunkLayer>,
+    server: Single<&SharedServer>,
+) {
+    let max_chunks_per_tick = server.0.max_chunks_per_tick;
+
+    for (client, view, visible_layer, pos, max_view_distance, requested) in clients.iter_mut() {
+        let layer = layers.get(visible_layer.0).ok();
+
+        if view.layer != visible_layer.0 {
+            // The client is switching layers, so everything it has loaded is
+            // stale.
+            for pos in view.loaded.drain() {
+                client.write_packet(&UnloadChunkS2c { pos });
+            }
+            view.layer = visible_layer.0;
+            view.complete_at = None;
+        }
+
+        let Some(layer) = layer else {
+            continue;
+        };
+
+        let view_distance = requested
+            .0
+            .map_or(max_view_distance.get(), |dist| dist.min(max_view_distance.get()))
+            .max(2);
+        let center = ChunkPos::from(pos.0);
+
+        if view.center != Some(center) {
+            client.write_packet(&ChunkRenderDistanceCenterS2c {
+                chunk_x: VarInt(center.x),
+                chunk_z: VarInt(center.z),
+            });
+            view.center = Some(center);
+            view.complete_at = None;
+        }
+
+        if view.view_distance != view_distance {
+            client.write_packet(&ChunkLoadDistanceS2c {
+                view_distance: VarInt(view_distance.into()),
+            });
+            view.view_distance = view_distance;
+            view.complete_at = None;
+        }
+
+        view.loaded.retain(|&pos| {
+            let keep = in_view(center, view_distance, pos) && layer.chunk(pos).is_some();
+
+            if !keep {
+                client.write_packet(&UnloadChunkS2c { pos });
+            }
+
+            keep
+        });
+
+        // Chunks are only added by insertion, so nothing new can be in view.
+        if view.complete_at == Some(layer.added_chunks) {
+            continue;
+        }
+
+        let mut budget = max_chunks_per_tick;
+        let mut complete = true;
+
+        for pos in spiral(center, view_distance) {
+            if view.loaded.contains(&pos) {
+                continue;
+            }
+
+            if budget == 0 {
+                complete = false;
+                break;
+            }
+
+            if layer.write_chunk_data(pos, client) {
+                view.loaded.insert(pos);
+                budget -= 1;
+            }
+        }
+
+        if complete {
+            view.complete_at = Some(layer.added_chunks);
+        }
+    }
+}
+
+/// Views are square, like the client's chunk cache.
+fn in_view(center: ChunkPos, view_distance: u8, pos: ChunkPos) -> bool {
+    (pos.x - center.x).abs().max((pos.z - center.z).abs()) <= view_distance as i32
+}
+
+/// Returns every chunk within `radius` of `center`, ring by ring starting at
+/// `center`.
+pub(crate) fn spiral(center: ChunkPos, radius: u8) -> impl Iterator<Item = ChunkPos> {
+    let radius = radius as i32;
+
+    (0..=radius).flat_map(move |r| {
+        let ring: Box<dyn Iterator<Item = (i32, i32)>> = if r == 0 {
+            Box::new([(0, 0)].into_iter())
+        } else {
+            // Walk the four sides of the ring, each side excluding its last
+            // corner.
+            Box::new(
+                (-r..r)
+                    .map(move |i| (i, -r))
+                    .chain((-r..r).map(move |i| (r, i)))
+                    .chain((-r..r).map(move |i| (-i, r)))
+                    .chain((-r..r).map(move |i| (-r, -i))),
+            )
+        };
+
+        ring.map(move |(x, z)| ChunkPos::new(center.x + x, center.z + z))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use valence_protocol::math::DVec3;
+    use valence_protocol::packets::play::ChunkDataS2c;
+    use valence_protocol::Packet;
+
+    use super::*;
+    use crate::chunk::Chunk;
+    use crate::testing::{self, sent_packet_ids};
+
+    #[test]
+    fn spiral_covers_view_closest_first() {
+        let center = ChunkPos::new(3, -7);
+        let chunks: Vec<_> = spiral(center, 4).collect();
+
+        assert_eq!(chunks.len(), 9 * 9);
+        assert_eq!(chunks[0], center);
+        assert_eq!(chunks.iter().collect::<HashSet<_>>().len(), chunks.len());
+
+        let dist = |pos: &ChunkPos| (pos.x - center.x).abs().max((pos.z - center.z).abs());
+        assert!(chunks.windows(2).all(|w| dist(&w[0]) <= dist(&w[1])));
+        assert!(chunks.iter().all(|&pos| in_view(center, 4, pos)));
+    }
+
+    #[test]
+    fn replaced_chunks_are_sent_to_their_viewers() {
+        let mut world = World::new();
+        world.add_handler(broadcast_block_updates);
+
+        let layer = world.spawn();
+        let mut chunk_layer = testing::layer();
+        chunk_layer.insert_chunk([0, 0], Chunk::new());
+        chunk_layer.insert_chunk([1, 0], Chunk::new());
+        world.insert(layer, chunk_layer);
+
+        let viewer = world.spawn();
+        world.insert(viewer, testing::client());
+        world.insert(viewer, ChunkView::with_loaded(layer, [ChunkPos::new(0, 0)]));
+
+        world.send(PostUpdate);
+        assert!(sent_packet_ids(&mut world, viewer).is_empty());
+
+        let chunk_layer = world.get_mut::<ChunkLayer>(layer).unwrap();
+        chunk_layer.insert_chunk([0, 0], Chunk::new());
+        chunk_layer.insert_chunk([1, 0], Chunk::new());
+
+        world.send(PostUpdate);
+        assert_eq!(sent_packet_ids(&mut world, viewer), [ChunkDataS2c::ID]);
+
+        // Sent once.
+        world.send(PostUpdate);
+        assert!(sent_packet_ids(&mut world, viewer).is_empty());
+    }
+
+    #[test]
+    fn complete_views_are_searched_again_when_chunks_are_added() {
+        let mut world = World::new();
+        world.add_handler(update_chunk_views);
+
+        let server = world.spawn();
+        world.insert(server, testing::server());
+
+        let layer = world.spawn();
+        let mut chunk_layer = testing::layer();
+        chunk_layer.insert_chunk([0, 0], Chunk::new());
+        world.insert(layer, chunk_layer);
+
+        let client = world.spawn();
+        world.insert(client, testing::client());
+        world.insert(client, ChunkView::default());
+        world.insert(client, VisibleChunkLayer(layer));
+        world.insert(client, Position(DVec3::new(8.0, 64.0, 8.0)));
+        world.insert(client, ViewDistance::new(2));
+        world.insert(client, RequestedViewDistance::default());
+
+        // Every other chunk in view is past the edge of the world.
+        world.send(PostUpdate);
+        let view = world.get::<ChunkView>(client).unwrap();
+        assert_eq!(view.loaded().collect::<Vec<_>>(), [ChunkPos::new(0, 0)]);
+        assert_eq!(view.complete_at, Some(1));
+        assert!(sent_packet_ids(&mut world, client).contains(&ChunkDataS2c::ID));
+
+        world.send(PostUpdate);
+        assert!(sent_packet_ids(&mut world, client).is_empty());
+
+        let chunk_layer = world.get_mut::<ChunkLayer>(layer).unwrap();
+        chunk_layer.insert_chunk([-1, 2], Chunk::new());
+
+        world.send(PostUpdate);
+        assert_eq!(sent_packet_ids(&mut world, client), [ChunkDataS2c::ID]);
+        let view = world.get::<ChunkView>(client).unwrap();
+        assert!(view.is_loaded(ChunkPos::new(-1, 2)));
+    }
+}
@@ -1,19 +1,52 @@
-use std::{borrow::Cow, net::IpAddr};
+use std::{borrow::Cow, net::IpAddr, time::Instant};
 use derive_more::{Deref, DerefMut};
 
-use evenio::component::Component;
-use tokio::{io::AsyncWriteExt, net::TcpStream};
+use evenio::prelude::*;
+use valence_protocol::bytes::{Bytes, BytesMut};
 use valence_entity::{EntityStatus, Velocity};
-use valence_protocol::{anyhow, math::{DVec3, Vec3}, packets::play::{game_state_change_s2c::GameEventKind, DeathMessageS2c, EntityStatusS2c, EntityVelocityUpdateS2c, GameStateChangeS2c, ParticleS2c, PlaySoundS2c}, profile::Property, sound::{SoundCategory, SoundId}, text::IntoText, BlockPos, Encode, GameMode, Ident, Packet, PacketEncoder, Particle, Sound, VarInt, WritePacket};
+use valence_protocol::{anyhow, math::{DVec3, Vec3}, packets::play::{game_state_change_s2c::GameEventKind, DeathMessageS2c, DisconnectS2c, EntityStatusS2c, EntityVelocityUpdateS2c, GameMessageS2c, GameStateChangeS2c, ParticleS2c, PlaySoundS2c}, profile::Property, sound::{SoundCategory, SoundId}, text::IntoText, BlockPos, Encode, GameMode, Ident, ident, Packet, PacketEncoder, Particle, Sound, VarInt, WritePacket};
 
-use crate::block::BlockOn;
+use tracing::warn;
 
+use crate::event::{ClientDisconnectEvent, DisconnectReason};
+use valence_server_common::Last;
+
+/// The network connection of a client in the play state.
 #[derive(Component)]
 pub struct Client {
-    pub stream: TcpStream,
+    pub conn: Box<dyn ClientConnection>,
     pub enc: PacketEncoder,
 }
 
+/// Represents the bidirectional packet channel between the server and a client
+/// in the "play" state.
+pub trait ClientConnection: Send + Sync + 'static {
+    /// Sends encoded clientbound packet data. This function must not block and
+    /// the data should be sent as soon as possible.
+    fn try_send(&mut self, bytes: BytesMut) -> anyhow::Result<()>;
+    /// Receives the next pending serverbound packet. This must return
+    /// immediately without blocking.
+    fn try_recv(&mut self) -> anyhow::Result<Option<ReceivedPacket>>;
+    /// The number of pending packets waiting to be received via
+    /// [`Self::try_recv`].
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ReceivedPacket {
+    /// The moment in time this packet arrived. This is _not_ the instant this
+    /// packet was returned from [`ClientConnection::try_recv`].
+    pub timestamp: Instant,
+    /// This packet's ID.
+    pub id: i32,
+    /// The content of the packet, excluding the leading varint packet ID.
+    pub body: Bytes,
+}
+
 /// Writes packets into this client's packet buffer. The buffer is flushed at
 /// the end of the tick.
 impl WritePacket for Client {
@@ -30,12 +63,12 @@ impl WritePacket for Client {
 }
 
 impl Client {
-    pub fn connection(&self) -> &TcpStream {
-        &self.stream
+    pub fn connection(&self) -> &dyn ClientConnection {
+        self.conn.as_ref()
     }
 
-    pub fn connection_mut(&mut self) -> &mut TcpStream {
-        &mut self.stream
+    pub fn connection_mut(&mut self) -> &mut dyn ClientConnection {
+        self.conn.as_mut()
     }
 
     /// Flushes the packet queue to the underlying connection.
@@ -48,11 +81,27 @@ impl Client {
     pub fn flush_packets(&mut self) -> anyhow::Result<()> {
         let bytes = self.enc.take();
         if !bytes.is_empty() {
-            self.stream.write_all(&bytes).block()?;
+            self.conn.try_send(bytes)?;
         }
         Ok(())
     }
 
+    /// Writes a [`DisconnectS2c`] showing `reason` to the client. The
+    /// connection is closed once the client entity is despawned.
+    pub fn kick<'a>(&mut self, reason: impl IntoText<'a>) {
+        self.write_packet(&DisconnectS2c {
+            reason: reason.into_cow_text(),
+        });
+    }
+
+    /// Shows `message` in the chat.
+    pub fn send_chat_message<'a>(&mut self, message: impl IntoText<'a>) {
+        self.write_packet(&GameMessageS2c {
+            chat: message.into_cow_text(),
+            overlay: false,
+        });
+    }
+
     /// Kills the client and shows `message` on the death screen. If an entity
     /// killed the player, you should supply it as `killer`.
     pub fn kill<'a>(&mut self, message: impl IntoText<'a>) {
@@ -133,6 +182,24 @@ impl Client {
     }
 }
 
+/// Flushes the packet buffers of every client at the end of the tick.
+/// Clients that can't be written to are disconnected.
+pub fn flush_packets(
+    _: Receiver<Last>,
+    mut clients: Fetcher<(EntityId, &mut Client)>,
+    mut sender: Sender<ClientDisconnectEvent>,
+) {
+    for (entity, client) in clients.iter_mut() {
+        if let Err(e) = client.flush_packets() {
+            warn!("failed to flush packet queue for client {entity:?}: {e:#}");
+            sender.send(ClientDisconnectEvent {
+                entity,
+                reason: DisconnectReason::Io(e.to_string()),
+            });
+        }
+    }
+}
+
 #[derive(Component, Copy, Clone, PartialEq, Eq, Debug, Deref, DerefMut)]
 pub struct IpAddress(pub IpAddr);
 
@@ -168,7 +235,7 @@ pub struct PortalCooldown(pub i32);
 
 /// The initial previous gamemode. Used for the F3+F4 gamemode switcher.
 #[derive(Component, Copy, Clone, PartialEq, Eq, Default, Debug, Deref, DerefMut)]
-   pub struct PrevGameMode(pub Option<GameMode>);
+pub struct PrevGameMode(pub Option<GameMode>);
 
 impl Default for HasRespawnScreen {
     fn default() -> Self {
@@ -189,3 +256,72 @@ pub struct RespawnPosition {
 
 #[derive(Component, Clone, PartialEq, Eq, Default, Debug, Deref, DerefMut)]
 pub struct Properties(pub Vec<Property>);
+
+/// The dimension the client is in. Both names must be present in the
+/// [`RegistryCodec`](crate::registry::RegistryCodec).
+///
+/// [`ChunkLayer`](crate::chunk::ChunkLayer) entities have the dimension they
+/// are. Joining clients are told that the dimensions of every layer exist.
+#[derive(Component, Clone, PartialEq, Eq, Debug)]
+pub struct Dimension {
+    /// The name of the dimension, e.g. `minecraft:overworld`.
+    pub name: Ident<String>,
+    /// The dimension type the dimension uses.
+    pub dimension_type: Ident<String>,
+}
+
+impl Default for Dimension {
+    fn default() -> Self {
+        Self {
+            name: ident!("overworld").into(),
+            dimension_type: ident!("overworld").into(),
+        }
+    }
+}
+
+/// The maximum view distance in chunks the server will send to the client.
+#[derive(Component, Copy, Clone, PartialEq, Eq, Debug, Deref)]
+pub struct ViewDistance(u8);
+
+impl ViewDistance {
+    pub fn new(dist: u8) -> Self {
+        let mut new = Self(0);
+        new.set(dist);
+        new
+    }
+
+    pub fn get(&self) -> u8 {
+        self.0
+    }
+
+    /// `dist` is clamped to `2..=32`.
+    pub fn set(&mut self, dist: u8) {
+        self.0 = dist.clamp(2, 32);
+    }
+}
+
+impl Default for ViewDistance {
+    fn default() -> Self {
+        Self(8)
+    }
+}
+
+/// The flying speed of the client, in blocks per tick.
+#[derive(Component, Copy, Clone, PartialEq, Debug, Deref, DerefMut)]
+pub struct FlyingSpeed(pub f32);
+
+impl Default for FlyingSpeed {
+    fn default() -> Self {
+        Self(0.05)
+    }
+}
+
+/// Modifies the client's field of view.
+#[derive(Component, Copy, Clone, PartialEq, Debug, Deref, DerefMut)]
+pub struct FovModifier(pub f32);
+
+impl Default for FovModifier {
+    fn default() -> Self {
+        Self(0.1)
+    }
+}
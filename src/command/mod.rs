@@ -0,0 +1,817 @@
+//! Commands.
+//!
+//! Commands are registered in the [`CommandRegistry`] as trees of literals and
+//! arguments built with [`literal`] and [`argument`], the way the game's own
+//! command dispatcher does it. Every client is sent the part of the tree its
+//! [`Permissions`] let it use, so it can highlight and complete commands by
+//! itself, and is sent it again whenever the commands or its permissions
+//! change.
+//!
+//! Commands run by a player are parsed against the same tree. Errors are shown
+//! to the player in the chat. Otherwise the executor of the last node turns
+//! the [`Arguments`] into a command of some type `C`, which is sent as a
+//! [`CommandEvent<C>`] by the [`send_command_events::<C>`] handler. That
+//! handler has to be added once for every command type.
+//!
+//! Arguments can also get suggestions from the server while the player types
+//! them, see [`suggest`].
+
+pub mod parse;
+pub mod suggest;
+
+use std::any::Any;
+use std::collections::BTreeSet;
+use std::fmt;
+use std::future::Future;
+use std::marker::PhantomData;
+use std::sync::Arc;
+
+use evenio::prelude::*;
+use thiserror::Error;
+use tracing::debug;
+use valence_entity::EntityStatus;
+use valence_protocol::packets::play::command_tree_s2c::{Node, NodeData, Parser, Suggestion};
+use valence_protocol::packets::play::CommandTreeS2c;
+use valence_protocol::text::{Color, IntoText};
+use valence_protocol::{Text, VarInt, WritePacket};
+use valence_server_common::PostUpdate;
+
+use self::parse::{parse_argument, Arguments, CommandError, Reader};
+use self::suggest::{
+    CommandSuggestion, SuggestionChannel, SuggestionFuture, SuggestionProvider, SuggestionRequest,
+};
+use crate::client::Client;
+use crate::event::ClientJoinEvent;
+use crate::event_loop::CommandExecutionEvent;
+
+type Requirement = Arc<dyn Fn(&Permissions) -> bool + Send + Sync>;
+
+type Executor =
+    Arc<dyn Fn(&Arguments) -> Result<Box<dyn Any + Send + Sync>, CommandError> + Send + Sync>;
+
+/// Every command the server knows, as a tree whose roots are the command
+/// names.
+#[derive(Component, Default, Debug)]
+pub struct CommandRegistry {
+    commands: Vec<CommandNode>,
+    /// Whether the commands changed since the clients were last sent them.
+    changed: bool,
+    suggestions: SuggestionChannel,
+}
+
+#[derive(Clone)]
+struct CommandNode {
+    kind: NodeKind,
+    children: Vec<CommandNode>,
+    requirement: Option<Requirement>,
+    executor: Option<Executor>,
+}
+
+#[derive(Clone)]
+enum NodeKind {
+    Literal(String),
+    Argument {
+        name: String,
+        parser: Parser,
+        /// Suggestions the client works out by itself.
+        suggestion: Option<Suggestion>,
+        provider: Option<SuggestionProvider>,
+    },
+}
+
+impl fmt::Debug for CommandNode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut s = f.debug_struct("CommandNode");
+
+        match &self.kind {
+            NodeKind::Literal(name) => s.field("literal", name),
+            NodeKind::Argument { name, parser, .. } => {
+                s.field("argument", name).field("parser", parser)
+            }
+        };
+
+        s.field("executable", &self.executor.is_some())
+            .field("children", &self.children)
+            .finish_non_exhaustive()
+    }
+}
+
+impl CommandNode {
+    fn new(kind: NodeKind) -> Self {
+        Self {
+            kind,
+            children: vec![],
+            requirement: None,
+            executor: None,
+        }
+    }
+
+    /// Whether `self` and `other` are the same node of two trees being
+    /// merged.
+    fn same_as(&self, other: &Self) -> bool {
+        match (&self.kind, &other.kind) {
+            (NodeKind::Literal(a), NodeKind::Literal(b)) => a == b,
+            (NodeKind::Argument { name: a, .. }, NodeKind::Argument { name: b, .. }) => a == b,
+            _ => false,
+        }
+    }
+
+    fn is_allowed(&self, permissions: &Permissions) -> bool {
+        self.requirement
+            .as_ref()
+            .map_or(true, |requirement| requirement(permissions))
+    }
+
+    fn data(&self) -> NodeData {
+        match &self.kind {
+            NodeKind::Literal(name) => NodeData::Literal { name: name.clone() },
+            NodeKind::Argument {
+                name,
+                parser,
+                suggestion,
+                provider,
+            } => NodeData::Argument {
+                name: name.clone(),
+                parser: parser.clone(),
+                suggestion: if provider.is_some() {
+                    Some(Suggestion::AskServer)
+                } else {
+                    *suggestion
+                },
+            },
+        }
+    }
+}
+
+/// Adds `node` to `nodes`, or merges it into the node it has the same name as.
+/// The requirement, executor and parser of `node` win over the existing ones.
+fn merge(nodes: &mut Vec<CommandNode>, node: CommandNode) {
+    let Some(existing) = nodes.iter_mut().find(|existing| existing.same_as(&node)) else {
+        nodes.push(node);
+        return;
+    };
+
+    if node.requirement.is_some() {
+        existing.requirement = node.requirement;
+    }
+    if node.executor.is_some() {
+        existing.executor = node.executor;
+    }
+    if let NodeKind::Argument { .. } = node.kind {
+        existing.kind = node.kind;
+    }
+
+    for child in node.children {
+        merge(&mut existing.children, child);
+    }
+}
+
+/// The nodes of `nodes` a player with `permissions` can use, literals first
+/// so they win over arguments that would accept the same word.
+fn visible<'a>(
+    nodes: &'a [CommandNode],
+    permissions: &'a Permissions,
+) -> impl Iterator<Item = &'a CommandNode> + 'a {
+    let literals = nodes
+        .iter()
+        .filter(|node| matches!(node.kind, NodeKind::Literal(_)));
+    let arguments = nodes
+        .iter()
+        .filter(|node| matches!(node.kind, NodeKind::Argument { .. }));
+
+    literals
+        .chain(arguments)
+        .filter(move |node| node.is_allowed(permissions))
+}
+
+/// A node of a command being built, for commands of type `C`.
+pub struct CommandBuilder<C> {
+    node: CommandNode,
+    _marker: PhantomData<fn() -> C>,
+}
+
+/// Starts a node matching the word `name` exactly. The roots of commands are
+/// literals.
+pub fn literal<C>(name: impl Into<String>) -> CommandBuilder<C> {
+    CommandBuilder {
+        node: CommandNode::new(NodeKind::Literal(name.into())),
+        _marker: PhantomData,
+    }
+}
+
+/// Starts a node reading an argument called `name` with `parser`.
+pub fn argument<C>(name: impl Into<String>, parser: Parser) -> CommandBuilder<C> {
+    CommandBuilder {
+        node: CommandNode::new(NodeKind::Argument {
+            name: name.into(),
+            parser,
+            suggestion: None,
+            provider: None,
+        }),
+        _marker: PhantomData,
+    }
+}
+
+impl<C: Send + Sync + 'static> CommandBuilder<C> {
+    /// Adds a node that can follow this one.
+    pub fn then(mut self, child: CommandBuilder<C>) -> Self {
+        merge(&mut self.node.children, child.node);
+        self
+    }
+
+    /// Only lets players whose permissions pass `requirement` see and use
+    /// this node and the ones after it.
+    pub fn requires(
+        mut self,
+        requirement: impl Fn(&Permissions) -> bool + Send + Sync + 'static,
+    ) -> Self {
+        self.node.requirement = Some(Arc::new(requirement));
+        self
+    }
+
+    /// Lets the command end at this node. `executor` builds the command from
+    /// its arguments, or fails with an error shown to the player.
+    pub fn executes(
+        mut self,
+        executor: impl Fn(&Arguments) -> Result<C, CommandError> + Send + Sync + 'static,
+    ) -> Self {
+        self.node.executor = Some(Arc::new(move |arguments: &Arguments| {
+            executor(arguments).map(|command| Box::new(command) as Box<dyn Any + Send + Sync>)
+        }));
+        self
+    }
+
+    /// Has the client suggest values of a kind it knows for this argument.
+    /// Does nothing for literals.
+    pub fn suggestion(mut self, kind: Suggestion) -> Self {
+        if let NodeKind::Argument { suggestion, .. } = &mut self.node.kind {
+            *suggestion = Some(kind);
+        }
+        self
+    }
+
+    /// Asks `provider` for suggestions while the player types this argument.
+    /// It runs on the async runtime and only the suggestions starting with
+    /// what the player typed are kept. Does nothing for literals.
+    pub fn suggests<F, Fut>(mut self, provider: F) -> Self
+    where
+        F: Fn(SuggestionRequest) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Vec<CommandSuggestion>> + Send + 'static,
+    {
+        if let NodeKind::Argument {
+            provider: existing, ..
+        } = &mut self.node.kind
+        {
+            *existing = Some(Arc::new(move |request| {
+                Box::pin(provider(request)) as SuggestionFuture
+            }));
+        }
+        self
+    }
+}
+
+/// A [`CommandError`] and where in the command it was found.
+#[derive(Clone, PartialEq, Debug, Error)]
+#[error("{error}")]
+pub struct CommandSyntaxError {
+    pub error: CommandError,
+    /// The byte offset of the error in the command, or `None` if the command
+    /// was read but its executor failed.
+    pub cursor: Option<usize>,
+}
+
+impl CommandSyntaxError {
+    /// The messages shown to the player who ran `command`: the error, and
+    /// where in the command it is, the way the game shows it.
+    pub fn messages(&self, command: &str) -> Vec<Text> {
+        let mut messages = vec![self.error.message()];
+
+        if let Some(cursor) = self.cursor {
+            let cursor = cursor.min(command.len());
+            // The last ten characters before the error.
+            let start = command[..cursor]
+                .char_indices()
+                .rev()
+                .nth(9)
+                .map_or(0, |(idx, _)| idx);
+
+            let mut before = String::new();
+            if start > 0 {
+                before.push_str("...");
+            }
+            before.push_str(&command[start..cursor]);
+
+            messages.push(
+                before.color(Color::GRAY)
+                    + command[cursor..].to_owned().color(Color::RED).underlined()
+                    + Text::translate("command.context.here", [])
+                        .color(Color::RED)
+                        .italic(),
+            );
+        }
+
+        messages
+    }
+}
+
+impl CommandRegistry {
+    /// Adds a command, whose root should be a [`literal`]. A command with the
+    /// same name is merged with it.
+    pub fn register<C: Send + Sync + 'static>(&mut self, command: CommandBuilder<C>) {
+        merge(&mut self.commands, command.node);
+        self.changed = true;
+    }
+
+    /// Removes the command called `name`. Returns whether there was one.
+    pub fn remove(&mut self, name: &str) -> bool {
+        let len = self.commands.len();
+        self.commands
+            .retain(|node| !matches!(&node.kind, NodeKind::Literal(command) if command == name));
+        self.changed |= self.commands.len() != len;
+        self.commands.len() != len
+    }
+
+    /// The commands a player with `permissions` can use, as sent to its
+    /// client.
+    pub fn tree(&self, permissions: &Permissions) -> CommandTreeS2c {
+        let mut nodes = vec![Node {
+            data: NodeData::Root,
+            executable: false,
+            children: vec![],
+            redirect_node: None,
+        }];
+
+        let children: Vec<_> = visible(&self.commands, permissions)
+            .map(|node| add_node(node, permissions, &mut nodes))
+            .collect();
+        nodes[0].children = children;
+
+        CommandTreeS2c {
+            commands: nodes,
+            root_index: VarInt(0),
+        }
+    }
+
+    /// Parses `command`, without its leading slash, and runs the executor of
+    /// its last node.
+    pub fn execute(
+        &self,
+        command: &str,
+        permissions: &Permissions,
+    ) -> Result<Box<dyn Any + Send + Sync>, CommandSyntaxError> {
+        let mut arguments = Arguments::default();
+
+        let node = parse_nodes(
+            &self.commands,
+            Reader::new(command),
+            permissions,
+            &mut arguments,
+        )
+        .map_err(|(error, cursor)| CommandSyntaxError {
+            // Nothing matched the name of the command.
+            error: match (error, cursor) {
+                (CommandError::UnknownArgument, 0) => CommandError::UnknownCommand,
+                (error, _) => error,
+            },
+            cursor: Some(cursor),
+        })?;
+
+        let executor = node
+            .executor
+            .as_ref()
+            .expect("parsed commands end at an executor");
+        executor(&arguments).map_err(|error| CommandSyntaxError {
+            error,
+            cursor: None,
+        })
+    }
+
+    /// The start of the argument being typed at the end of `command` and the
+    /// provider of its suggestions, if it has one.
+    fn completion(
+        &self,
+        command: &str,
+        permissions: &Permissions,
+    ) -> Option<(usize, &SuggestionProvider)> {
+        complete(&self.commands, Reader::new(command), permissions)
+    }
+}
+
+/// Adds `node` and the nodes after it that a player with `permissions` can
+/// use to `nodes`. Returns the index of `node`.
+fn add_node(node: &CommandNode, permissions: &Permissions, nodes: &mut Vec<Node>) -> VarInt {
+    let idx = nodes.len();
+    nodes.push(Node {
+        data: node.data(),
+        executable: node.executor.is_some(),
+        children: vec![],
+        redirect_node: None,
+    });
+
+    let children: Vec<_> = visible(&node.children, permissions)
+        .map(|child| add_node(child, permissions, nodes))
+        .collect();
+    nodes[idx].children = children;
+
+    VarInt(idx as i32)
+}
+
+/// Parses the rest of the command starting with one of `nodes`, trying the
+/// next one when a node doesn't fit. If none does, the error found furthest
+/// into the command is returned, along with where it was found.
+fn parse_nodes<'a>(
+    nodes: &'a [CommandNode],
+    r: Reader,
+    permissions: &Permissions,
+    arguments: &mut Arguments,
+) -> Result<&'a CommandNode, (CommandError, usize)> {
+    let len = arguments.len();
+    let mut furthest: Option<(CommandError, usize)> = None;
+
+    for node in visible(nodes, permissions) {
+        match parse_node(node, &mut r.clone(), permissions, arguments) {
+            Ok(last) => return Ok(last),
+            Err((error, cursor)) => {
+                arguments.truncate(len);
+
+                if furthest
+                    .as_ref()
+                    .map_or(true, |(_, furthest)| cursor > *furthest)
+                {
+                    furthest = Some((error, cursor));
+                }
+            }
+        }
+    }
+
+    Err(furthest.unwrap_or((CommandError::UnknownArgument, r.cursor())))
+}
+
+fn parse_node<'a>(
+    node: &'a CommandNode,
+    r: &mut Reader,
+    permissions: &Permissions,
+    arguments: &mut Arguments,
+) -> Result<&'a CommandNode, (CommandError, usize)> {
+    let start = r.cursor();
+
+    match &node.kind {
+        NodeKind::Literal(name) => {
+            if r.read_word() != name {
+                return Err((CommandError::UnknownArgument, start));
+            }
+        }
+        NodeKind::Argument { name, parser, .. } => {
+            let value = parse_argument(parser, r).map_err(|error| (error, r.cursor()))?;
+            arguments.push(name, value);
+        }
+    }
+
+    if r.at_end() {
+        return match node.executor {
+            Some(_) => Ok(node),
+            None => Err((CommandError::UnknownCommand, r.cursor())),
+        };
+    }
+
+    if r.peek() != Some(' ') {
+        return Err((CommandError::ExpectedSeparator, r.cursor()));
+    }
+    r.skip();
+
+    parse_nodes(&node.children, r.clone(), permissions, arguments)
+}
+
+fn complete<'a>(
+    nodes: &'a [CommandNode],
+    r: Reader,
+    permissions: &Permissions,
+) -> Option<(usize, &'a SuggestionProvider)> {
+    for node in visible(nodes, permissions) {
+        let mut r = r.clone();
+        let start = r.cursor();
+
+        let parsed = match &node.kind {
+            NodeKind::Literal(name) => r.read_word() == name,
+            NodeKind::Argument {
+                parser, provider, ..
+            } => {
+                let parsed = parse_argument(parser, &mut r).is_ok();
+
+                // The argument is the one being typed if the command ends in
+                // it, or right before it.
+                let last = start == r.input().len() || (parsed && r.at_end());
+                if let (true, Some(provider)) = (last, provider) {
+                    return Some((start, provider));
+                }
+
+                parsed
+            }
+        };
+
+        if parsed && r.peek() == Some(' ') {
+            r.skip();
+            if let Some(found) = complete(&node.children, r, permissions) {
+                return Some(found);
+            }
+        }
+    }
+
+    None
+}
+
+/// What a player is allowed to do. Command nodes with a requirement are only
+/// sent to the player, and can only be used by it, if the requirement accepts
+/// its permissions.
+#[derive(Component, Clone, Default, Debug)]
+pub struct Permissions {
+    level: u8,
+    granted: BTreeSet<String>,
+    /// Whether the client's command tree is out of date.
+    changed: bool,
+}
+
+impl Permissions {
+    /// The operator level, from 0 for regular players to 4 for operators
+    /// who can do anything.
+    pub fn level(&self) -> u8 {
+        self.level
+    }
+
+    /// Sets the operator level, capped at 4. The client also uses it to
+    /// decide whether the game mode switcher can be opened.
+    pub fn set_level(&mut self, level: u8) {
+        let level = level.min(4);
+        if level != self.level {
+            self.level = level;
+            self.changed = true;
+        }
+    }
+
+    pub fn has(&self, permission: &str) -> bool {
+        self.granted.contains(permission)
+    }
+
+    /// Returns whether the permission wasn't granted before.
+    pub fn grant(&mut self, permission: impl Into<String>) -> bool {
+        let granted = self.granted.insert(permission.into());
+        self.changed |= granted;
+        granted
+    }
+
+    /// Returns whether the permission was granted before.
+    pub fn revoke(&mut self, permission: &str) -> bool {
+        let revoked = self.granted.remove(permission);
+        self.changed |= revoked;
+        revoked
+    }
+
+    fn status(&self) -> EntityStatus {
+        match self.level {
+            0 => EntityStatus::SetOpLevel0,
+            1 => EntityStatus::SetOpLevel1,
+            2 => EntityStatus::SetOpLevel2,
+            3 => EntityStatus::SetOpLevel3,
+            _ => EntityStatus::SetOpLevel4,
+        }
+    }
+}
+
+/// A command run by a player, as built by the executor of the command.
+#[derive(Event, Debug)]
+pub struct CommandEvent<C> {
+    #[event(target)]
+    pub client: EntityId,
+    pub command: C,
+}
+
+/// A command that was parsed, waiting for [`send_command_events`] to send it
+/// as a [`CommandEvent`] of its type.
+#[derive(Event)]
+pub struct ParsedCommandEvent {
+    client: EntityId,
+    command: Box<dyn Any + Send + Sync>,
+}
+
+/// Parses the commands run by players and runs their executors. Errors are
+/// sent to the player.
+pub fn handle_command_execution(
+    r: Receiver<CommandExecutionEvent, (&mut Client, &Permissions)>,
+    registry: Single<&CommandRegistry>,
+    mut sender: Sender<ParsedCommandEvent>,
+) {
+    let (client, permissions) = r.query;
+    let event = r.event;
+
+    match registry.0.execute(&event.command, permissions) {
+        Ok(command) => sender.send(ParsedCommandEvent {
+            client: event.client,
+            command,
+        }),
+        Err(e) => {
+            debug!(client = ?event.client, command = &*event.command, "command failed: {e}");
+
+            for message in e.messages(&event.command) {
+                client.send_chat_message(message);
+            }
+        }
+    }
+}
+
+/// Sends the parsed commands of type `C` as [`CommandEvent<C>`].
+pub fn send_command_events<C: Send + Sync + 'static>(
+    r: ReceiverMut<ParsedCommandEvent>,
+    mut sender: Sender<CommandEvent<C>>,
+) {
+    if !r.event.command.is::<C>() {
+        return;
+    }
+
+    let event = EventMut::take(r.event);
+    if let Ok(command) = event.command.downcast::<C>() {
+        sender.send(CommandEvent {
+            client: event.client,
+            command: *command,
+        });
+    }
+}
+
+/// Sends a joining client its command tree and operator level.
+pub fn init_command_tree(
+    r: Receiver<ClientJoinEvent>,
+    mut clients: Fetcher<(&mut Client, &mut Permissions)>,
+    registry: Single<&CommandRegistry>,
+) {
+    let Ok((client, permissions)) = clients.get_mut(r.event.entity) else {
+        return;
+    };
+
+    client.write_packet(&registry.0.tree(permissions));
+    client.trigger_status(permissions.status());
+    permissions.changed = false;
+}
+
+/// Sends the command tree again to the clients whose permissions changed this
+/// tick, or to every client if the commands did.
+pub fn sync_command_trees(
+    _: Receiver<PostUpdate>,
+    mut clients: Fetcher<(&mut Client, &mut Permissions)>,
+    registry: Single<&mut CommandRegistry>,
+) {
+    let registry = registry.0;
+
+    for (client, permissions) in clients.iter_mut() {
+        if permissions.changed || registry.changed {
+            client.write_packet(&registry.tree(permissions));
+        }
+
+        if permissions.changed {
+            client.trigger_status(permissions.status());
+            permissions.changed = false;
+        }
+    }
+
+    registry.changed = false;
+}
+
+#[cfg(test)]
+mod tests {
+    use valence_protocol::packets::play::EntityStatusS2c;
+    use valence_protocol::{GameMode, Packet};
+
+    use super::*;
+    use crate::testing::{self, sent_packet_ids};
+
+    #[derive(PartialEq, Debug)]
+    enum TestCommand {
+        Help,
+        GameMode(GameMode),
+    }
+
+    fn registry() -> CommandRegistry {
+        let mut registry = CommandRegistry::default();
+
+        registry.register(literal("help").executes(|_| Ok(TestCommand::Help)));
+        registry.register(
+            literal("gamemode")
+                .requires(|permissions| permissions.level() >= 2)
+                .then(
+                    argument("mode", Parser::GameMode)
+                        .executes(|args| Ok(TestCommand::GameMode(args.get("mode")?))),
+                ),
+        );
+
+        registry
+    }
+
+    fn run(registry: &CommandRegistry, command: &str) -> Result<TestCommand, CommandSyntaxError> {
+        let mut permissions = Permissions::default();
+        permissions.set_level(2);
+
+        registry
+            .execute(command, &permissions)
+            .map(|command| *command.downcast().unwrap())
+    }
+
+    #[test]
+    fn players_only_get_the_commands_they_can_use() {
+        let registry = registry();
+
+        let tree = registry.tree(&Permissions::default());
+        assert_eq!(tree.commands.len(), 2);
+        assert_eq!(
+            tree.commands[1].data,
+            NodeData::Literal {
+                name: "help".into()
+            }
+        );
+        assert!(tree.commands[1].executable);
+
+        let mut permissions = Permissions::default();
+        permissions.set_level(2);
+
+        let tree = registry.tree(&permissions);
+        assert_eq!(tree.commands.len(), 4);
+        assert_eq!(tree.commands[0].children.len(), 2);
+        assert!(!tree.commands[2].executable);
+        assert!(tree.commands[3].executable);
+
+        assert_eq!(
+            registry
+                .execute("gamemode creative", &Permissions::default())
+                .err(),
+            Some(CommandSyntaxError {
+                error: CommandError::UnknownCommand,
+                cursor: Some(0),
+            })
+        );
+    }
+
+    #[test]
+    fn commands_are_parsed_with_the_furthest_error() {
+        let registry = registry();
+
+        assert_eq!(run(&registry, "help"), Ok(TestCommand::Help));
+        assert_eq!(
+            run(&registry, "gamemode creative"),
+            Ok(TestCommand::GameMode(GameMode::Creative))
+        );
+
+        let error = |error, cursor| {
+            Err(CommandSyntaxError {
+                error,
+                cursor: Some(cursor),
+            })
+        };
+
+        assert_eq!(
+            run(&registry, "gamemod"),
+            error(CommandError::UnknownCommand, 0)
+        );
+        assert_eq!(
+            run(&registry, "gamemode"),
+            error(CommandError::UnknownCommand, 8)
+        );
+        assert_eq!(
+            run(&registry, "gamemode flying"),
+            error(CommandError::InvalidGameMode("flying".into()), 9)
+        );
+        assert_eq!(
+            run(&registry, "help me"),
+            error(CommandError::UnknownArgument, 5)
+        );
+    }
+
+    #[test]
+    fn command_trees_are_sent_again_when_they_change() {
+        let mut world = World::new();
+        world.add_handler(sync_command_trees);
+
+        let server = world.spawn();
+        world.insert(server, registry());
+
+        let client = world.spawn();
+        world.insert(client, testing::client());
+        world.insert(client, Permissions::default());
+
+        world.send(PostUpdate);
+        assert_eq!(sent_packet_ids(&mut world, client), [CommandTreeS2c::ID]);
+
+        world.send(PostUpdate);
+        assert!(sent_packet_ids(&mut world, client).is_empty());
+
+        world.get_mut::<Permissions>(client).unwrap().set_level(2);
+        world.send(PostUpdate);
+        assert_eq!(
+            sent_packet_ids(&mut world, client),
+            [CommandTreeS2c::ID, EntityStatusS2c::ID]
+        );
+
+        world
+            .get_mut::<CommandRegistry>(server)
+            .unwrap()
+            .register(literal("seed").executes(|_| Ok(TestCommand::Help)));
+        world.send(PostUpdate);
+        assert_eq!(sent_packet_ids(&mut world, client), [CommandTreeS2c::ID]);
+    }
+}
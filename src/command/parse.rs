@@ -0,0 +1,760 @@
+//! Reading command arguments.
+//!
+//! Arguments are read from the command line following the rules of the
+//! client's parsers, so anything the client accepts is accepted here too.
+//! Errors carry the translation keys the client uses for the same mistakes.
+
+use thiserror::Error;
+use valence_protocol::packets::play::command_tree_s2c::{Parser, StringArg};
+use valence_protocol::text::{Color, IntoText};
+use valence_protocol::uuid::Uuid;
+use valence_protocol::{BlockPos, GameMode, Ident, Text};
+
+/// A cursor over the command line.
+#[derive(Clone, Debug)]
+pub struct Reader<'a> {
+    input: &'a str,
+    cursor: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(input: &'a str) -> Self {
+        Self { input, cursor: 0 }
+    }
+
+    pub fn input(&self) -> &'a str {
+        self.input
+    }
+
+    /// The byte offset of the next character.
+    pub fn cursor(&self) -> usize {
+        self.cursor
+    }
+
+    pub fn remaining(&self) -> &'a str {
+        &self.input[self.cursor..]
+    }
+
+    pub fn at_end(&self) -> bool {
+        self.cursor >= self.input.len()
+    }
+
+    pub fn peek(&self) -> Option<char> {
+        self.remaining().chars().next()
+    }
+
+    pub fn skip(&mut self) {
+        if let Some(c) = self.peek() {
+            self.cursor += c.len_utf8();
+        }
+    }
+
+    /// Reads characters as long as `f` accepts them.
+    pub fn read_while(&mut self, f: impl Fn(char) -> bool) -> &'a str {
+        let start = self.cursor;
+        while self.peek().is_some_and(&f) {
+            self.skip();
+        }
+        &self.input[start..self.cursor]
+    }
+
+    /// Reads up to the next space.
+    pub fn read_word(&mut self) -> &'a str {
+        self.read_while(|c| c != ' ')
+    }
+
+    pub fn read_unquoted_string(&mut self) -> &'a str {
+        self.read_while(is_unquoted)
+    }
+
+    /// Reads a string in double or single quotes, with `\` escaping the
+    /// quote and itself.
+    pub fn read_quoted_string(&mut self) -> Result<String, CommandError> {
+        let quote = match self.peek() {
+            Some(c @ ('"' | '\'')) => c,
+            _ => return Err(CommandError::ExpectedStartOfQuote),
+        };
+        self.skip();
+
+        let mut string = String::new();
+        let mut escaped = false;
+
+        while let Some(c) = self.peek() {
+            self.skip();
+
+            if escaped {
+                if c != quote && c != '\\' {
+                    return Err(CommandError::InvalidEscape(c));
+                }
+                string.push(c);
+                escaped = false;
+            } else if c == '\\' {
+                escaped = true;
+            } else if c == quote {
+                return Ok(string);
+            } else {
+                string.push(c);
+            }
+        }
+
+        Err(CommandError::ExpectedEndOfQuote)
+    }
+
+    /// Reads a quoted string if the next character is a quote, or an
+    /// unquoted one otherwise.
+    pub fn read_string(&mut self) -> Result<String, CommandError> {
+        match self.peek() {
+            Some('"' | '\'') => self.read_quoted_string(),
+            _ => Ok(self.read_unquoted_string().to_owned()),
+        }
+    }
+
+    fn read_number<T: std::str::FromStr>(&mut self, kind: NumberKind) -> Result<T, CommandError> {
+        let start = self.cursor;
+        let number = self.read_while(|c| c.is_ascii_digit() || c == '.' || c == '-');
+
+        if number.is_empty() {
+            return Err(CommandError::ExpectedNumber(kind));
+        }
+
+        number.parse().map_err(|_| {
+            self.cursor = start;
+            CommandError::InvalidNumber(kind, number.to_owned())
+        })
+    }
+}
+
+/// Characters allowed in strings without quotes.
+fn is_unquoted(c: char) -> bool {
+    c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.' | '+')
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum NumberKind {
+    Int,
+    Long,
+    Float,
+    Double,
+}
+
+impl NumberKind {
+    /// The name used in the `parsing.*` translation keys.
+    fn parsing_key(self) -> &'static str {
+        match self {
+            Self::Int => "int",
+            Self::Long => "long",
+            Self::Float => "float",
+            Self::Double => "double",
+        }
+    }
+
+    /// The name used in the `argument.*` translation keys.
+    fn argument_key(self) -> &'static str {
+        match self {
+            Self::Int => "integer",
+            Self::Long => "long",
+            Self::Float => "float",
+            Self::Double => "double",
+        }
+    }
+}
+
+/// Why a command couldn't be run.
+#[derive(Clone, PartialEq, Debug, Error)]
+pub enum CommandError {
+    #[error("unknown or incomplete command")]
+    UnknownCommand,
+    #[error("incorrect argument for command")]
+    UnknownArgument,
+    #[error("expected whitespace to end one argument, but found trailing data")]
+    ExpectedSeparator,
+    #[error("expected {}", .0.argument_key())]
+    ExpectedNumber(NumberKind),
+    #[error("invalid {} '{}'", .0.argument_key(), .1)]
+    InvalidNumber(NumberKind, String),
+    #[error("{} must not be less than {min}, found {found}", .kind.argument_key())]
+    TooLow {
+        kind: NumberKind,
+        min: String,
+        found: String,
+    },
+    #[error("{} must not be more than {max}, found {found}", .kind.argument_key())]
+    TooHigh {
+        kind: NumberKind,
+        max: String,
+        found: String,
+    },
+    #[error("expected bool")]
+    ExpectedBool,
+    #[error("invalid bool, expected 'true' or 'false' but found '{0}'")]
+    InvalidBool(String),
+    #[error("expected quote to start a string")]
+    ExpectedStartOfQuote,
+    #[error("unclosed quoted string")]
+    ExpectedEndOfQuote,
+    #[error("invalid escape sequence '\\{0}' in quoted string")]
+    InvalidEscape(char),
+    #[error("incomplete (expected {0} coordinates)")]
+    IncompletePos(usize),
+    #[error("local coordinates aren't supported")]
+    LocalCoordinates,
+    #[error("unknown game mode: {0}")]
+    InvalidGameMode(String),
+    #[error("invalid UUID")]
+    InvalidUuid,
+    #[error("invalid ID")]
+    InvalidId,
+    #[error("invalid name or UUID")]
+    InvalidEntity,
+    #[error("unknown selector type '{0}'")]
+    UnknownSelector(String),
+    #[error("only players may be affected by this command")]
+    OnlyPlayers,
+    #[error("only one entity is allowed")]
+    TooManyEntities,
+    /// The executor asked for an argument the command doesn't have, or of
+    /// another type.
+    #[error("no argument '{0}' of the requested type")]
+    MissingArgument(String),
+    /// An error reported by an executor.
+    #[error("{0}")]
+    Custom(String),
+}
+
+impl CommandError {
+    /// The message shown to the player, in their language where the client
+    /// knows it.
+    pub fn message(&self) -> Text {
+        let translate = |key: String, args: Vec<Text>| Text::translate(key, args);
+
+        let message = match self {
+            Self::UnknownCommand => translate("command.unknown.command".into(), vec![]),
+            Self::UnknownArgument => translate("command.unknown.argument".into(), vec![]),
+            Self::ExpectedSeparator => translate("command.expected.separator".into(), vec![]),
+            Self::ExpectedNumber(kind) => {
+                translate(format!("parsing.{}.expected", kind.parsing_key()), vec![])
+            }
+            Self::InvalidNumber(kind, value) => translate(
+                format!("parsing.{}.invalid", kind.parsing_key()),
+                vec![value.clone().into_text()],
+            ),
+            Self::TooLow { kind, min, found } => translate(
+                format!("argument.{}.low", kind.argument_key()),
+                vec![min.clone().into_text(), found.clone().into_text()],
+            ),
+            Self::TooHigh { kind, max, found } => translate(
+                format!("argument.{}.big", kind.argument_key()),
+                vec![max.clone().into_text(), found.clone().into_text()],
+            ),
+            Self::ExpectedBool => translate("parsing.bool.expected".into(), vec![]),
+            Self::InvalidBool(value) => translate(
+                "parsing.bool.invalid".into(),
+                vec![value.clone().into_text()],
+            ),
+            Self::ExpectedStartOfQuote => translate("parsing.quote.expected.start".into(), vec![]),
+            Self::ExpectedEndOfQuote => translate("parsing.quote.expected.end".into(), vec![]),
+            Self::InvalidEscape(c) => translate(
+                "parsing.quote.escape".into(),
+                vec![c.to_string().into_text()],
+            ),
+            Self::IncompletePos(2) => translate("argument.pos2d.incomplete".into(), vec![]),
+            Self::IncompletePos(_) => translate("argument.pos.incomplete".into(), vec![]),
+            Self::InvalidGameMode(value) => translate(
+                "argument.gamemode.invalid".into(),
+                vec![value.clone().into_text()],
+            ),
+            Self::InvalidUuid => translate("argument.uuid.invalid".into(), vec![]),
+            Self::InvalidId => translate("argument.id.invalid".into(), vec![]),
+            Self::InvalidEntity => translate("argument.entity.invalid".into(), vec![]),
+            Self::UnknownSelector(value) => translate(
+                "argument.entity.selector.unknown".into(),
+                vec![value.clone().into_text()],
+            ),
+            Self::OnlyPlayers => translate("argument.player.entities".into(), vec![]),
+            Self::TooManyEntities => translate("argument.entity.toomany".into(), vec![]),
+            Self::LocalCoordinates | Self::MissingArgument(_) | Self::Custom(_) => {
+                self.to_string().into_text()
+            }
+        };
+
+        message.color(Color::RED)
+    }
+}
+
+/// A coordinate of a position argument, either absolute or relative to the
+/// player with `~`.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Coordinate {
+    pub value: f64,
+    pub relative: bool,
+}
+
+impl Coordinate {
+    /// The coordinate on the axis where the player is at `origin`.
+    pub fn resolve(self, origin: f64) -> f64 {
+        if self.relative {
+            origin + self.value
+        } else {
+            self.value
+        }
+    }
+}
+
+/// Who an entity argument refers to. Selectors are kept as written, since
+/// which entities they match depends on the world.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum EntitySelector {
+    Name(String),
+    Uuid(Uuid),
+    /// A selector such as `@p` or `@e[type=cow]`, including its arguments.
+    Selector(String),
+}
+
+/// The value of a parsed argument.
+#[derive(Clone, PartialEq, Debug)]
+pub enum ArgumentValue {
+    Bool(bool),
+    Float(f32),
+    Double(f64),
+    Integer(i32),
+    Long(i64),
+    String(String),
+    Entity(EntitySelector),
+    /// A block position or a column position, which has no y coordinate.
+    BlockPos(Vec<Coordinate>),
+    /// A three or two dimensional vector.
+    Vec(Vec<Coordinate>),
+    GameMode(GameMode),
+    Uuid(Uuid),
+    Id(Ident<String>),
+    /// The word given for a parser the server doesn't read.
+    Other(String),
+}
+
+/// Reads an argument for `parser`.
+///
+/// Parsers the server doesn't understand are read as a single word and given
+/// as [`ArgumentValue::Other`].
+pub fn parse_argument(parser: &Parser, r: &mut Reader) -> Result<ArgumentValue, CommandError> {
+    Ok(match parser {
+        Parser::Bool => {
+            let start = r.cursor();
+            let value = r.read_string()?;
+            match value.as_str() {
+                "true" => ArgumentValue::Bool(true),
+                "false" => ArgumentValue::Bool(false),
+                "" => return Err(CommandError::ExpectedBool),
+                _ => {
+                    r.cursor = start;
+                    return Err(CommandError::InvalidBool(value));
+                }
+            }
+        }
+        Parser::Float { min, max } => {
+            ArgumentValue::Float(read_bounded(r, NumberKind::Float, *min, *max)?)
+        }
+        Parser::Double { min, max } => {
+            ArgumentValue::Double(read_bounded(r, NumberKind::Double, *min, *max)?)
+        }
+        Parser::Integer { min, max } => {
+            ArgumentValue::Integer(read_bounded(r, NumberKind::Int, *min, *max)?)
+        }
+        Parser::Long { min, max } => {
+            ArgumentValue::Long(read_bounded(r, NumberKind::Long, *min, *max)?)
+        }
+        Parser::String(StringArg::SingleWord) => {
+            ArgumentValue::String(r.read_unquoted_string().to_owned())
+        }
+        Parser::String(StringArg::QuotablePhrase) => ArgumentValue::String(r.read_string()?),
+        Parser::String(StringArg::GreedyPhrase) | Parser::Message => {
+            let rest = r.remaining().to_owned();
+            r.cursor = r.input.len();
+            ArgumentValue::String(rest)
+        }
+        Parser::Entity {
+            single,
+            only_players,
+        } => ArgumentValue::Entity(read_entity(r, *single, *only_players)?),
+        Parser::GameProfile => ArgumentValue::Entity(read_entity(r, false, true)?),
+        Parser::BlockPos => ArgumentValue::BlockPos(read_coordinates(r, 3, true)?),
+        Parser::ColumnPos => ArgumentValue::BlockPos(read_coordinates(r, 2, true)?),
+        Parser::Vec3 => ArgumentValue::Vec(read_coordinates(r, 3, false)?),
+        Parser::Vec2 => ArgumentValue::Vec(read_coordinates(r, 2, false)?),
+        Parser::GameMode => {
+            let start = r.cursor();
+            let name = r.read_unquoted_string();
+            let mode = match name {
+                "survival" => GameMode::Survival,
+                "creative" => GameMode::Creative,
+                "adventure" => GameMode::Adventure,
+                "spectator" => GameMode::Spectator,
+                _ => {
+                    r.cursor = start;
+                    return Err(CommandError::InvalidGameMode(name.to_owned()));
+                }
+            };
+            ArgumentValue::GameMode(mode)
+        }
+        Parser::Uuid => {
+            let start = r.cursor();
+            let uuid = r.read_while(|c| c.is_ascii_hexdigit() || c == '-');
+            match Uuid::parse_str(uuid) {
+                Ok(uuid) => ArgumentValue::Uuid(uuid),
+                Err(_) => {
+                    r.cursor = start;
+                    return Err(CommandError::InvalidUuid);
+                }
+            }
+        }
+        Parser::ResourceLocation => {
+            let start = r.cursor();
+            let id = r.read_while(|c| {
+                c.is_ascii_lowercase()
+                    || c.is_ascii_digit()
+                    || matches!(c, '_' | '-' | '.' | ':' | '/')
+            });
+            match Ident::new(id.to_owned()) {
+                Ok(id) => ArgumentValue::Id(id.to_string_ident()),
+                Err(_) => {
+                    r.cursor = start;
+                    return Err(CommandError::InvalidId);
+                }
+            }
+        }
+        _ => ArgumentValue::Other(r.read_word().to_owned()),
+    })
+}
+
+/// Reads a number and checks it's within `min` and `max`.
+fn read_bounded<T>(
+    r: &mut Reader,
+    kind: NumberKind,
+    min: Option<T>,
+    max: Option<T>,
+) -> Result<T, CommandError>
+where
+    T: std::str::FromStr + PartialOrd + ToString + Copy,
+{
+    let start = r.cursor();
+    let value: T = r.read_number(kind)?;
+
+    if let Some(min) = min.filter(|&min| value < min) {
+        r.cursor = start;
+        return Err(CommandError::TooLow {
+            kind,
+            min: min.to_string(),
+            found: value.to_string(),
+        });
+    }
+
+    if let Some(max) = max.filter(|&max| value > max) {
+        r.cursor = start;
+        return Err(CommandError::TooHigh {
+            kind,
+            max: max.to_string(),
+            found: value.to_string(),
+        });
+    }
+
+    Ok(value)
+}
+
+/// Reads `count` space separated coordinates. Block coordinates have to be
+/// whole numbers unless they're relative. Whole x and z coordinates of
+/// vectors are moved to the center of the block, like the game does.
+fn read_coordinates(
+    r: &mut Reader,
+    count: usize,
+    block: bool,
+) -> Result<Vec<Coordinate>, CommandError> {
+    let mut coordinates = Vec::with_capacity(count);
+
+    for axis in 0..count {
+        if axis > 0 {
+            if r.peek() != Some(' ') {
+                return Err(CommandError::IncompletePos(count));
+            }
+            r.skip();
+        }
+
+        match r.peek() {
+            Some('^') => return Err(CommandError::LocalCoordinates),
+            Some('~') => {
+                r.skip();
+                let value = match r.peek() {
+                    None | Some(' ') => 0.0,
+                    Some(_) => r.read_number(NumberKind::Double)?,
+                };
+                coordinates.push(Coordinate {
+                    value,
+                    relative: true,
+                });
+            }
+            None => return Err(CommandError::IncompletePos(count)),
+            Some(_) if block => {
+                let value: i32 = r.read_number(NumberKind::Int)?;
+                coordinates.push(Coordinate {
+                    value: value.into(),
+                    relative: false,
+                });
+            }
+            Some(_) => {
+                let whole = !r.clone().read_word().contains('.');
+                let mut value: f64 = r.read_number(NumberKind::Double)?;
+
+                // The y coordinate of a 3D vector is the second one.
+                let horizontal = count == 2 || axis != 1;
+                if whole && horizontal {
+                    value += 0.5;
+                }
+
+                coordinates.push(Coordinate {
+                    value,
+                    relative: false,
+                });
+            }
+        }
+    }
+
+    Ok(coordinates)
+}
+
+/// Reads a player name, a UUID or a selector.
+fn read_entity(
+    r: &mut Reader,
+    single: bool,
+    only_players: bool,
+) -> Result<EntitySelector, CommandError> {
+    let start = r.cursor();
+
+    if r.peek() != Some('@') {
+        let word = r.read_word();
+
+        if let Ok(uuid) = Uuid::parse_str(word) {
+            return Ok(EntitySelector::Uuid(uuid));
+        }
+
+        if word.is_empty() || word.len() > 16 {
+            r.cursor = start;
+            return Err(CommandError::InvalidEntity);
+        }
+
+        return Ok(EntitySelector::Name(word.to_owned()));
+    }
+
+    r.skip();
+    let kind = r.peek();
+    r.skip();
+
+    if !matches!(kind, Some('p' | 'a' | 'r' | 's' | 'e')) {
+        r.cursor = start;
+        return Err(CommandError::UnknownSelector(r.read_word().to_owned()));
+    }
+
+    // The arguments are kept as written, so only the brackets are matched.
+    if r.peek() == Some('[') {
+        let mut quote = None;
+        loop {
+            let Some(c) = r.peek() else {
+                return Err(CommandError::ExpectedEndOfQuote);
+            };
+            r.skip();
+
+            match (quote, c) {
+                (Some(q), c) if c == q => quote = None,
+                (Some(_), _) => {}
+                (None, '"' | '\'') => quote = Some(c),
+                (None, ']') => break,
+                _ => {}
+            }
+        }
+    }
+
+    let selector = &r.input()[start..r.cursor()];
+
+    if only_players && kind == Some('e') && !selector.contains("type=player") {
+        r.cursor = start;
+        return Err(CommandError::OnlyPlayers);
+    }
+
+    if single && matches!(kind, Some('a' | 'e')) && !selector.contains("limit=1") {
+        r.cursor = start;
+        return Err(CommandError::TooManyEntities);
+    }
+
+    Ok(EntitySelector::Selector(selector.to_owned()))
+}
+
+/// The arguments of a command, by name.
+#[derive(Clone, Default, Debug)]
+pub struct Arguments {
+    values: Vec<(String, ArgumentValue)>,
+}
+
+impl Arguments {
+    pub(super) fn push(&mut self, name: &str, value: ArgumentValue) {
+        self.values.push((name.to_owned(), value));
+    }
+
+    pub(super) fn truncate(&mut self, len: usize) {
+        self.values.truncate(len);
+    }
+
+    pub(super) fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn value(&self, name: &str) -> Option<&ArgumentValue> {
+        self.values
+            .iter()
+            .find(|(arg, _)| arg == name)
+            .map(|(_, value)| value)
+    }
+
+    /// The argument `name` as a `T`. Fails if the command has no such
+    /// argument, or if it was parsed as another type.
+    pub fn get<T: FromArgument>(&self, name: &str) -> Result<T, CommandError> {
+        self.value(name)
+            .and_then(T::from_argument)
+            .ok_or_else(|| CommandError::MissingArgument(name.to_owned()))
+    }
+}
+
+/// Types argument values can be turned into.
+pub trait FromArgument: Sized {
+    fn from_argument(value: &ArgumentValue) -> Option<Self>;
+}
+
+macro_rules! impl_from_argument {
+    ($($ty:ty => $variant:ident),* $(,)?) => {
+        $(
+            impl FromArgument for $ty {
+                fn from_argument(value: &ArgumentValue) -> Option<Self> {
+                    match value {
+                        ArgumentValue::$variant(value) => Some(value.clone()),
+                        _ => None,
+                    }
+                }
+            }
+        )*
+    };
+}
+
+impl_from_argument!(
+    bool => Bool,
+    f32 => Float,
+    f64 => Double,
+    i32 => Integer,
+    i64 => Long,
+    EntitySelector => Entity,
+    GameMode => GameMode,
+    Uuid => Uuid,
+    Ident<String> => Id,
+);
+
+impl FromArgument for String {
+    fn from_argument(value: &ArgumentValue) -> Option<Self> {
+        match value {
+            ArgumentValue::String(value) | ArgumentValue::Other(value) => Some(value.clone()),
+            _ => None,
+        }
+    }
+}
+
+impl FromArgument for Vec<Coordinate> {
+    fn from_argument(value: &ArgumentValue) -> Option<Self> {
+        match value {
+            ArgumentValue::BlockPos(coordinates) | ArgumentValue::Vec(coordinates) => {
+                Some(coordinates.clone())
+            }
+            _ => None,
+        }
+    }
+}
+
+impl FromArgument for BlockPos {
+    /// Only absolute block positions can be turned into a [`BlockPos`]
+    /// directly. Take relative ones as a `Vec<Coordinate>` and resolve them.
+    fn from_argument(value: &ArgumentValue) -> Option<Self> {
+        match value {
+            ArgumentValue::BlockPos(c) if c.len() == 3 && c.iter().all(|c| !c.relative) => Some(
+                BlockPos::new(c[0].value as i32, c[1].value as i32, c[2].value as i32),
+            ),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(parser: Parser, input: &str) -> Result<(ArgumentValue, usize), CommandError> {
+        let mut r = Reader::new(input);
+        parse_argument(&parser, &mut r).map(|value| (value, r.cursor()))
+    }
+
+    #[test]
+    fn numbers_are_checked_against_their_bounds() {
+        let parser = Parser::Integer {
+            min: Some(0),
+            max: Some(64),
+        };
+
+        assert_eq!(
+            parse(parser.clone(), "12 rest"),
+            Ok((ArgumentValue::Integer(12), 2))
+        );
+        assert_eq!(
+            parse(parser.clone(), "65"),
+            Err(CommandError::TooHigh {
+                kind: NumberKind::Int,
+                max: "64".into(),
+                found: "65".into(),
+            })
+        );
+        assert_eq!(
+            parse(parser, "1.5"),
+            Err(CommandError::InvalidNumber(NumberKind::Int, "1.5".into()))
+        );
+    }
+
+    #[test]
+    fn strings_can_be_quoted() {
+        let quotable = Parser::String(StringArg::QuotablePhrase);
+
+        assert_eq!(
+            parse(quotable.clone(), r#""say \"hi\"" x"#),
+            Ok((ArgumentValue::String(r#"say "hi""#.into()), 12))
+        );
+        assert_eq!(
+            parse(quotable, "\"open"),
+            Err(CommandError::ExpectedEndOfQuote)
+        );
+        assert_eq!(
+            parse(Parser::String(StringArg::GreedyPhrase), "all of it"),
+            Ok((ArgumentValue::String("all of it".into()), 9))
+        );
+    }
+
+    #[test]
+    fn coordinates_can_be_relative() {
+        let (value, _) = parse(Parser::BlockPos, "~ ~-1 5").unwrap();
+        let coordinates = Vec::<Coordinate>::from_argument(&value).unwrap();
+
+        assert_eq!(coordinates[1].resolve(64.0), 63.0);
+        assert_eq!(coordinates[2].resolve(64.0), 5.0);
+        assert_eq!(BlockPos::from_argument(&value), None);
+
+        let (value, _) = parse(Parser::Vec3, "1 2 3.25").unwrap();
+        let coordinates = Vec::<Coordinate>::from_argument(&value).unwrap();
+        assert_eq!(coordinates[0].value, 1.5);
+        assert_eq!(coordinates[1].value, 2.0);
+        assert_eq!(coordinates[2].value, 3.25);
+
+        assert_eq!(
+            parse(Parser::BlockPos, "1 2"),
+            Err(CommandError::IncompletePos(3))
+        );
+    }
+}
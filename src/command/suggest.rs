@@ -0,0 +1,407 @@
+//! Suggestions for command arguments.
+//!
+//! Arguments given a provider with [`CommandBuilder::suggests`] are marked in
+//! the command tree so the client asks the server for suggestions while the
+//! player types them. Providers return a future, which is run on the async
+//! runtime so looking up suggestions never holds up the tick. The
+//! suggestions are sent to the client at the start of the tick after the
+//! future completes.
+//!
+//! Each client has at most one lookup running, as a new request cancels the
+//! one before it. A lookup that takes longer than the timeout, by default
+//! [`SUGGESTION_TIMEOUT`], or panics is answered with no suggestions.
+//!
+//! [`CommandBuilder::suggests`]: super::CommandBuilder::suggests
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::Duration;
+
+use evenio::prelude::*;
+use tokio::task::AbortHandle;
+use tracing::{debug, warn};
+use valence_protocol::packets::play::command_suggestions_s2c::CommandSuggestionsMatch;
+use valence_protocol::packets::play::CommandSuggestionsS2c;
+use valence_protocol::text::IntoText;
+use valence_protocol::{Text, VarInt, WritePacket};
+use valence_server_common::PreUpdate;
+
+use super::{CommandRegistry, Permissions};
+use crate::client::Client;
+use crate::event_loop::CommandCompletionsEvent;
+
+/// How long a suggestion provider gets before the client is answered without
+/// suggestions, unless changed with
+/// [`CommandRegistry::set_suggestion_timeout`].
+pub const SUGGESTION_TIMEOUT: Duration = Duration::from_secs(5);
+
+pub type SuggestionFuture = Pin<Box<dyn Future<Output = Vec<CommandSuggestion>> + Send>>;
+
+pub(super) type SuggestionProvider =
+    Arc<dyn Fn(SuggestionRequest) -> SuggestionFuture + Send + Sync>;
+
+/// What a suggestion provider is asked for.
+#[derive(Clone, Debug)]
+pub struct SuggestionRequest {
+    pub client: EntityId,
+    /// The whole command typed so far, without its leading slash.
+    pub command: String,
+    /// The part of the argument typed so far.
+    pub prefix: String,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct CommandSuggestion {
+    pub text: String,
+    /// Shown when the suggestion is hovered.
+    pub tooltip: Option<Text>,
+}
+
+impl CommandSuggestion {
+    pub fn new(text: impl Into<String>) -> Self {
+        Self {
+            text: text.into(),
+            tooltip: None,
+        }
+    }
+
+    pub fn with_tooltip(mut self, tooltip: impl IntoText<'static>) -> Self {
+        self.tooltip = Some(tooltip.into_text());
+        self
+    }
+}
+
+/// Where the futures of suggestion providers send their results.
+#[derive(Debug)]
+pub(super) struct SuggestionChannel {
+    sender: flume::Sender<SuggestionResponse>,
+    receiver: flume::Receiver<SuggestionResponse>,
+    /// The transaction ID of the lookup running for each client, and the
+    /// handle to cancel it with.
+    in_flight: HashMap<EntityId, (i32, AbortHandle)>,
+    timeout: Duration,
+}
+
+impl Default for SuggestionChannel {
+    fn default() -> Self {
+        let (sender, receiver) = flume::unbounded();
+        Self {
+            sender,
+            receiver,
+            in_flight: HashMap::new(),
+            timeout: SUGGESTION_TIMEOUT,
+        }
+    }
+}
+
+impl CommandRegistry {
+    /// Sets how long suggestion providers get before the client is answered
+    /// without suggestions.
+    pub fn set_suggestion_timeout(&mut self, timeout: Duration) {
+        self.suggestions.timeout = timeout;
+    }
+}
+
+#[derive(Debug)]
+struct SuggestionResponse {
+    client: EntityId,
+    transaction_id: i32,
+    /// Where the suggested text goes in the client's chat box, in UTF-16
+    /// code units.
+    start: usize,
+    length: usize,
+    suggestions: Vec<CommandSuggestion>,
+}
+
+/// Keeps the suggestions starting with `prefix`, ignoring case.
+fn matching(mut suggestions: Vec<CommandSuggestion>, prefix: &str) -> Vec<CommandSuggestion> {
+    let prefix = prefix.to_lowercase();
+    suggestions.retain(|suggestion| suggestion.text.to_lowercase().starts_with(&prefix));
+    suggestions
+}
+
+/// Asks the provider of the argument the player is typing for suggestions.
+pub fn handle_command_completions(
+    r: Receiver<CommandCompletionsEvent, &Permissions>,
+    registry: Single<&mut CommandRegistry>,
+) {
+    let permissions = r.query;
+    let event = r.event;
+
+    let text = &*event.text;
+    let command = text.strip_prefix('/').unwrap_or(text);
+    let slash = text.len() - command.len();
+
+    let Some((start, provider)) = registry.0.completion(command, permissions) else {
+        return;
+    };
+
+    let prefix = command[start..].to_owned();
+    let future = provider(SuggestionRequest {
+        client: event.client,
+        command: command.to_owned(),
+        prefix: prefix.clone(),
+    });
+
+    // The client counts in UTF-16 code units, like Java strings.
+    let mut response = SuggestionResponse {
+        client: event.client,
+        transaction_id: event.transaction_id,
+        start: text[..slash + start].encode_utf16().count(),
+        length: prefix.encode_utf16().count(),
+        suggestions: vec![],
+    };
+    let channel = &mut registry.0.suggestions;
+    let sender = channel.sender.clone();
+    let timeout = channel.timeout;
+    let mut lookup = tokio::spawn(future);
+
+    // The client only shows the answer to its latest request.
+    if let Some((_, previous)) = channel
+        .in_flight
+        .insert(event.client, (event.transaction_id, lookup.abort_handle()))
+    {
+        previous.abort();
+    }
+
+    tokio::spawn(async move {
+        response.suggestions = match tokio::time::timeout(timeout, &mut lookup).await {
+            Ok(Ok(suggestions)) => matching(suggestions, &prefix),
+            // A newer request took its place.
+            Ok(Err(e)) if e.is_cancelled() => return,
+            Ok(Err(e)) => {
+                warn!(
+                    "suggestion provider for {:?} panicked: {e}",
+                    response.client
+                );
+                vec![]
+            }
+            Err(_) => {
+                lookup.abort();
+                debug!("suggestions for {:?} timed out", response.client);
+                vec![]
+            }
+        };
+        // The registry is gone if the server stopped.
+        let _ = sender.send(response);
+    });
+}
+
+/// Sends the suggestions found since the previous tick to their clients.
+pub fn send_suggestions(
+    _: Receiver<PreUpdate>,
+    mut clients: Fetcher<&mut Client>,
+    registry: Single<&mut CommandRegistry>,
+) {
+    let channel = &mut registry.0.suggestions;
+
+    for response in channel.receiver.try_iter() {
+        // A lookup cancelled too late may still have answered, which mustn't
+        // forget the lookup that replaced it.
+        if channel
+            .in_flight
+            .get(&response.client)
+            .is_some_and(|&(id, _)| id == response.transaction_id)
+        {
+            channel.in_flight.remove(&response.client);
+        }
+
+        // The client may have left while the suggestions were looked up.
+        let Ok(client) = clients.get_mut(response.client) else {
+            continue;
+        };
+
+        client.write_packet(&CommandSuggestionsS2c {
+            id: VarInt(response.transaction_id),
+            start: VarInt(response.start as i32),
+            length: VarInt(response.length as i32),
+            matches: response
+                .suggestions
+                .iter()
+                .map(|suggestion| CommandSuggestionsMatch {
+                    suggested_match: &suggestion.text,
+                    tooltip: suggestion.tooltip.as_ref().map(Cow::Borrowed),
+                })
+                .collect(),
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use valence_protocol::packets::play::command_tree_s2c::Parser;
+
+    use super::*;
+    use crate::command::parse::EntitySelector;
+    use crate::command::{argument, literal};
+    use crate::testing::{self, sent_packets};
+
+    /// A registry with a `tp <target>` command whose provider never finishes
+    /// when the player typed `slow`.
+    fn registry() -> CommandRegistry {
+        let mut registry = CommandRegistry::default();
+        registry.register(
+            literal("tp").then(
+                argument(
+                    "target",
+                    Parser::Entity {
+                        single: true,
+                        only_players: true,
+                    },
+                )
+                .suggests(|request| async move {
+                    if request.prefix == "slow" {
+                        std::future::pending::<()>().await;
+                    }
+                    vec![CommandSuggestion::new("Alice")]
+                })
+                .executes(|args| args.get::<EntitySelector>("target")),
+            ),
+        );
+        registry
+    }
+
+    /// A world with `registry` and a client that can use every command.
+    fn world(registry: CommandRegistry) -> (World, EntityId, EntityId) {
+        let mut world = World::new();
+        world.add_handler(handle_command_completions);
+        world.add_handler(send_suggestions);
+
+        let server = world.spawn();
+        world.insert(server, registry);
+
+        let client = world.spawn();
+        world.insert(client, testing::client());
+        world.insert(client, Permissions::default());
+
+        (world, server, client)
+    }
+
+    fn request(world: &mut World, client: EntityId, transaction_id: i32, text: &str) {
+        world.send(CommandCompletionsEvent {
+            client,
+            transaction_id,
+            text: text.into(),
+        });
+    }
+
+    fn channel(world: &World, server: EntityId) -> &SuggestionChannel {
+        &world.get::<CommandRegistry>(server).unwrap().suggestions
+    }
+
+    /// Lets the spawned lookups run.
+    async fn wait() {
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+
+    /// The transaction IDs and suggestion counts sent to `client`.
+    fn sent_suggestions(world: &mut World, client: EntityId) -> Vec<(i32, usize)> {
+        sent_packets(world, client)
+            .iter()
+            .map(|frame| {
+                let packet = frame.decode::<CommandSuggestionsS2c>().unwrap();
+                (packet.id.0, packet.matches.len())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn the_argument_being_typed_is_completed() {
+        let mut registry = CommandRegistry::default();
+        registry.register(
+            literal("tp").then(
+                argument(
+                    "target",
+                    Parser::Entity {
+                        single: true,
+                        only_players: true,
+                    },
+                )
+                .suggests(|_| async { vec![CommandSuggestion::new("Alice")] })
+                .executes(|args| args.get::<EntitySelector>("target")),
+            ),
+        );
+
+        let permissions = Permissions::default();
+        let start = |command| {
+            registry
+                .completion(command, &permissions)
+                .map(|(start, _)| start)
+        };
+
+        assert_eq!(start("tp "), Some(3));
+        assert_eq!(start("tp Al"), Some(3));
+        assert_eq!(start("tp"), None);
+        assert_eq!(start("tp Alice "), None);
+
+        let suggestions = vec![
+            CommandSuggestion::new("Alice"),
+            CommandSuggestion::new("Bob"),
+        ];
+        assert_eq!(
+            matching(suggestions, "al"),
+            [CommandSuggestion::new("Alice")]
+        );
+    }
+
+    #[tokio::test]
+    async fn new_requests_abort_the_lookup_before_them() {
+        let (mut world, server, client) = world(registry());
+
+        request(&mut world, client, 1, "/tp slow");
+        let first = channel(&world, server).in_flight[&client].1.clone();
+
+        request(&mut world, client, 2, "/tp Al");
+        assert_eq!(channel(&world, server).in_flight[&client].0, 2);
+
+        wait().await;
+        assert!(first.is_finished());
+
+        world.send(PreUpdate);
+        assert_eq!(sent_suggestions(&mut world, client), [(2, 1)]);
+        assert!(channel(&world, server).in_flight.is_empty());
+    }
+
+    #[tokio::test]
+    async fn answers_to_replaced_lookups_keep_the_new_lookup() {
+        let (mut world, server, client) = world(registry());
+
+        request(&mut world, client, 2, "/tp slow");
+
+        // The lookup for request 1 answered just before it was aborted.
+        let channel_sender = channel(&world, server).sender.clone();
+        channel_sender
+            .send(SuggestionResponse {
+                client,
+                transaction_id: 1,
+                start: 4,
+                length: 0,
+                suggestions: vec![],
+            })
+            .unwrap();
+
+        world.send(PreUpdate);
+        assert_eq!(sent_suggestions(&mut world, client), [(1, 0)]);
+        assert_eq!(channel(&world, server).in_flight[&client].0, 2);
+    }
+
+    #[tokio::test]
+    async fn slow_providers_are_answered_without_suggestions() {
+        let mut registry = registry();
+        registry.set_suggestion_timeout(Duration::from_millis(10));
+        let (mut world, server, client) = world(registry);
+
+        request(&mut world, client, 1, "/tp slow");
+        let lookup = channel(&world, server).in_flight[&client].1.clone();
+
+        wait().await;
+        assert!(lookup.is_finished());
+
+        world.send(PreUpdate);
+        assert_eq!(sent_suggestions(&mut world, client), [(1, 0)]);
+        assert!(channel(&world, server).in_flight.is_empty());
+    }
+}
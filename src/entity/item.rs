@@ -0,0 +1,85 @@
+//! Item entities, which the items players drop turn into.
+
+use evenio::prelude::*;
+use valence_entity::entity::Entity;
+use valence_entity::item::{ItemEntity, Stack};
+use valence_entity::movement::TrackedMovement;
+use valence_entity::tracked_data::TrackedData;
+use valence_entity::{
+    EntityAnimations, EntityKind, EntityLayerId, EntityStatuses, HeadYaw, Look, ObjectData,
+    OldEntityLayerId, OldPosition, OnGround, Position, Velocity,
+};
+use valence_protocol::math::{DVec3, Vec3};
+use valence_protocol::uuid::Uuid;
+use valence_server_common::UniqueId;
+
+use crate::chunk::view::VisibleChunkLayer;
+use crate::inventory::DropItemStackEvent;
+
+/// How far below the eyes of a standing player dropped items appear.
+const DROP_HEIGHT: f64 = 1.62 - 0.3;
+
+/// How fast dropped items are thrown, in blocks per tick.
+const THROW_SPEED: f32 = 0.3;
+
+/// Spawns the items dropped by players in front of them, thrown the way they
+/// are looking.
+pub fn spawn_dropped_items(
+    r: Receiver<DropItemStackEvent, (&Position, &Look, &VisibleChunkLayer)>,
+    mut sender: Sender<(
+        (
+            Spawn,
+            Insert<EntityKind>,
+            Insert<UniqueId>,
+            Insert<EntityLayerId>,
+            Insert<OldEntityLayerId>,
+            Insert<Position>,
+            Insert<OldPosition>,
+            Insert<Look>,
+            Insert<HeadYaw>,
+        ),
+        (
+            Insert<OnGround>,
+            Insert<Velocity>,
+            Insert<EntityStatuses>,
+            Insert<EntityAnimations>,
+            Insert<ObjectData>,
+            Insert<TrackedData>,
+            Insert<TrackedMovement>,
+        ),
+        (Insert<Stack>, Insert<ItemEntity>, Insert<Entity>),
+    )>,
+) {
+    let (position, look, layer) = r.query;
+    let event = r.event;
+
+    if event.stack.is_empty() {
+        return;
+    }
+
+    let pos = position.0 + DVec3::Y * DROP_HEIGHT;
+    let velocity = (look.vec() * THROW_SPEED + Vec3::Y * 0.1) * 20.0;
+
+    let item = sender.spawn();
+    sender.insert(item, EntityKind::ITEM);
+    sender.insert(item, UniqueId(Uuid::new_v4()));
+    sender.insert(item, EntityLayerId(layer.0));
+    sender.insert(item, OldEntityLayerId::default());
+    sender.insert(item, Position(pos));
+    sender.insert(item, OldPosition::new(pos));
+    sender.insert(item, Look::default());
+    sender.insert(item, HeadYaw::default());
+
+    sender.insert(item, OnGround::default());
+    sender.insert(item, Velocity(velocity));
+    sender.insert(item, EntityStatuses::default());
+    sender.insert(item, EntityAnimations::default());
+    sender.insert(item, ObjectData::default());
+    sender.insert(item, TrackedData::default());
+    sender.insert(item, TrackedMovement::default());
+
+    sender.insert(item, Stack(event.stack.clone()));
+    sender.insert(item, ItemEntity);
+    // Inserted last, since the hitbox is added once the position is there.
+    sender.insert(item, Entity);
+}
@@ -0,0 +1,8 @@
+//! Entities shown to clients. Entities are regular ECS entities with the
+//! components of a `valence_entity` bundle. Each is on the entity layer named
+//! by its [`EntityLayerId`](valence_entity::EntityLayerId), and is shown to
+//! the clients viewing that layer that have its chunk loaded.
+
+pub mod item;
+pub mod physics;
+pub mod view;
@@ -0,0 +1,336 @@
+//! Moves items, falling blocks, TNT and projectiles.
+//!
+//! Every tick, entities whose [`EntityKind`] has [`Physics`] fall, move by
+//! their [`Velocity`] and slow down, stopping at the collision shapes of the
+//! blocks in their way. The steps follow the game closely enough that the
+//! client's own prediction of the entity matches, and changes to the velocity
+//! are sent to viewers with the rest of the entity's updates.
+//!
+//! Entities with [`NoGravity`] set keep moving and slowing down, but don't
+//! fall. Entities in unloaded chunks and entities without a [`HitboxShape`]
+//! don't move.
+
+use evenio::prelude::*;
+use valence_entity::entity::NoGravity;
+use valence_entity::hitbox::HitboxShape;
+use valence_entity::{EntityKind, EntityLayerId, OnGround, Position, Velocity};
+use valence_protocol::block::BlockKind;
+use valence_protocol::math::{Aabb, DVec3};
+use valence_protocol::{BlockPos, BlockState};
+use valence_server_common::Tick;
+
+use crate::chunk::ChunkLayer;
+
+/// [`Velocity`] is in blocks per second, and the physics step works in blocks
+/// per game tick.
+const TICKS_PER_SECOND: f64 = 20.0;
+
+/// How close a hitbox may get to a block before it counts as touching it.
+const COLLISION_EPSILON: f64 = 1e-7;
+
+/// How an entity falls and slows down.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Physics {
+    /// Subtracted from the vertical velocity every tick, in blocks per tick.
+    pub gravity: f64,
+    /// What the velocity is multiplied by every tick.
+    pub drag: f64,
+    /// What the horizontal velocity is also multiplied by every tick on the
+    /// ground, or `None` to use the slipperiness of the block underneath.
+    pub ground_drag: Option<f64>,
+    /// Whether the entity stops dead when it hits a block, like an arrow
+    /// sticking in the ground, instead of sliding along it.
+    pub stops_on_collision: bool,
+}
+
+impl Physics {
+    /// The physics of entities of `kind`, or `None` if they don't move on
+    /// their own.
+    pub fn of(kind: EntityKind) -> Option<Self> {
+        let (gravity, drag, ground_drag, stops_on_collision) = match kind {
+            EntityKind::ITEM => (0.04, 0.98, None, false),
+            EntityKind::EXPERIENCE_ORB => (0.03, 0.98, None, false),
+            EntityKind::FALLING_BLOCK | EntityKind::TNT => (0.04, 0.98, Some(0.7), false),
+            EntityKind::ARROW | EntityKind::SPECTRAL_ARROW | EntityKind::TRIDENT => {
+                (0.05, 0.99, None, true)
+            }
+            EntityKind::SNOWBALL | EntityKind::EGG | EntityKind::ENDER_PEARL => {
+                (0.03, 0.99, None, true)
+            }
+            EntityKind::POTION => (0.05, 0.99, None, true),
+            EntityKind::EXPERIENCE_BOTTLE => (0.07, 0.99, None, true),
+            EntityKind::LLAMA_SPIT => (0.06, 0.99, None, true),
+            EntityKind::FISHING_BOBBER => (0.03, 0.92, None, false),
+            _ => return None,
+        };
+
+        Some(Self {
+            gravity,
+            drag,
+            ground_drag,
+            stops_on_collision,
+        })
+    }
+}
+
+#[derive(Query)]
+pub struct PhysicsQuery<'a> {
+    kind: &'a EntityKind,
+    layer: &'a EntityLayerId,
+    position: &'a mut Position,
+    velocity: &'a mut Velocity,
+    on_ground: &'a mut OnGround,
+    shape: &'a HitboxShape,
+    no_gravity: Option<&'a NoGravity>,
+}
+
+/// Runs a physics step for every entity with [`Physics`].
+pub fn step_physics(
+    _: Receiver<Tick>,
+    mut entities: Fetcher<PhysicsQuery>,
+    layers: Fetcher<&ChunkLayer>,
+) {
+    for q in entities.iter_mut() {
+        let (Some(mut physics), Ok(layer)) = (Physics::of(*q.kind), layers.get(q.layer.0)) else {
+            continue;
+        };
+
+        if q.no_gravity.is_some_and(|no_gravity| no_gravity.0) {
+            physics.gravity = 0.0;
+        }
+
+        let velocity = q.velocity.0.as_dvec3() / TICKS_PER_SECOND;
+        let aabb = q.shape.get() + q.position.0;
+
+        if let Some(step) = advance(layer, physics, aabb, velocity) {
+            q.position.0 += step.offset;
+            q.velocity.0 = (step.velocity * TICKS_PER_SECOND).as_vec3();
+            q.on_ground.0 = step.on_ground;
+        }
+    }
+}
+
+/// The outcome of one physics step.
+#[derive(Copy, Clone, PartialEq, Debug)]
+struct Step {
+    /// How far the entity moved.
+    offset: DVec3,
+    /// The velocity for the next step, in blocks per tick.
+    velocity: DVec3,
+    on_ground: bool,
+}
+
+/// Moves the hitbox `aabb` by `velocity`, in blocks per tick. Returns `None`
+/// if the entity is in or would move into an unloaded chunk.
+fn advance(layer: &ChunkLayer, physics: Physics, aabb: Aabb, velocity: DVec3) -> Option<Step> {
+    let mut velocity = velocity - DVec3::Y * physics.gravity;
+
+    let offset = collide(layer, aabb, velocity)?;
+    let collided = offset.cmpne(velocity);
+    let on_ground = collided.y && velocity.y < 0.0;
+
+    if physics.stops_on_collision && collided.any() {
+        velocity = DVec3::ZERO;
+    } else {
+        velocity = DVec3::select(collided, DVec3::ZERO, velocity);
+    }
+
+    velocity *= physics.drag;
+
+    if on_ground {
+        let center = (aabb.min() + aabb.max()) / 2.0;
+        let feet = DVec3::new(center.x, aabb.min().y, center.z) + offset;
+        let ground_drag = physics
+            .ground_drag
+            .unwrap_or_else(|| slipperiness(layer, feet));
+        velocity.x *= ground_drag;
+        velocity.z *= ground_drag;
+    }
+
+    Some(Step {
+        offset,
+        velocity,
+        on_ground,
+    })
+}
+
+/// Cuts `motion` short where `aabb` would hit a block. The vertical axis is
+/// resolved first and then the horizontal axis with the most motion, like the
+/// game.
+fn collide(layer: &ChunkLayer, aabb: Aabb, motion: DVec3) -> Option<DVec3> {
+    let swept = Aabb::new(
+        aabb.min().min(aabb.min() + motion),
+        aabb.max().max(aabb.max() + motion),
+    );
+
+    let mut shapes = vec![];
+    if !layer.collision_shapes(swept, &mut shapes) {
+        return None;
+    }
+
+    let axes = if motion.x.abs() < motion.z.abs() {
+        [1, 2, 0]
+    } else {
+        [1, 0, 2]
+    };
+
+    let mut aabb = aabb;
+    let mut offset = DVec3::ZERO;
+
+    for axis in axes {
+        offset[axis] = clip_axis(&shapes, aabb, axis, motion[axis]);
+
+        let mut step = DVec3::ZERO;
+        step[axis] = offset[axis];
+        aabb = aabb + step;
+    }
+
+    Some(offset)
+}
+
+/// Shortens `motion` along `axis` so that `aabb` stops at the first shape in
+/// its way.
+fn clip_axis(shapes: &[Aabb], aabb: Aabb, axis: usize, mut motion: f64) -> f64 {
+    let others = [(axis + 1) % 3, (axis + 2) % 3];
+
+    for shape in shapes {
+        // Shapes that only touch the hitbox on another axis aren't in the way.
+        if others.iter().any(|&other| {
+            shape.max()[other] <= aabb.min()[other] + COLLISION_EPSILON
+                || shape.min()[other] >= aabb.max()[other] - COLLISION_EPSILON
+        }) {
+            continue;
+        }
+
+        if motion > 0.0 && shape.min()[axis] >= aabb.max()[axis] - COLLISION_EPSILON {
+            motion = motion.min(shape.min()[axis] - aabb.max()[axis]);
+        } else if motion < 0.0 && shape.max()[axis] <= aabb.min()[axis] + COLLISION_EPSILON {
+            motion = motion.max(shape.max()[axis] - aabb.min()[axis]);
+        }
+    }
+
+    motion
+}
+
+/// How much of its horizontal velocity an entity standing at `feet` keeps
+/// every tick.
+fn slipperiness(layer: &ChunkLayer, feet: DVec3) -> f64 {
+    let below = BlockPos::new(
+        feet.x.floor() as i32,
+        (feet.y - 0.5).floor() as i32,
+        feet.z.floor() as i32,
+    );
+
+    match layer.block(below).map(BlockState::to_kind) {
+        Some(BlockKind::Ice | BlockKind::PackedIce | BlockKind::FrostedIce) => 0.98,
+        Some(BlockKind::BlueIce) => 0.989,
+        Some(BlockKind::SlimeBlock) => 0.8,
+        _ => 0.6,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{floor_layer, FLOOR};
+
+    /// The layer of [`floor_layer`] in the chunk at (0, 0), with a stone wall
+    /// at x = 8.
+    fn wall_layer() -> ChunkLayer {
+        let mut layer = floor_layer([[0, 0]]);
+
+        for y in -48..-44 {
+            for z in 0..16 {
+                layer.set_block([8, y, z], BlockState::STONE);
+            }
+        }
+
+        layer
+    }
+
+    fn item_box(pos: DVec3) -> Aabb {
+        Aabb::from_bottom_size(pos, DVec3::splat(0.25))
+    }
+
+    fn step_into(layer: &ChunkLayer, pos: DVec3, velocity: DVec3) -> Option<Step> {
+        advance(
+            layer,
+            Physics::of(EntityKind::ITEM).unwrap(),
+            item_box(pos),
+            velocity,
+        )
+    }
+
+    #[test]
+    fn items_fall_and_land() {
+        let layer = wall_layer();
+        let physics = Physics::of(EntityKind::ITEM).unwrap();
+
+        let mut pos = DVec3::new(4.5, FLOOR + 3.0, 4.5);
+        let mut velocity = DVec3::ZERO;
+
+        for _ in 0..40 {
+            let step = advance(&layer, physics, item_box(pos), velocity).unwrap();
+            pos += step.offset;
+            velocity = step.velocity;
+        }
+
+        assert!((pos.y - FLOOR).abs() < 1e-9);
+        assert_eq!((pos.x, pos.z), (4.5, 4.5));
+        assert_eq!(velocity, DVec3::ZERO);
+
+        let step = advance(&layer, physics, item_box(pos), velocity).unwrap();
+        assert!(step.on_ground);
+        assert_eq!(step.offset, DVec3::ZERO);
+
+        // Gravity and drag match the game.
+        let step = advance(&layer, physics, item_box(pos + DVec3::Y * 2.0), DVec3::ZERO);
+        assert_eq!(step.unwrap().velocity.y, -0.04 * 0.98);
+    }
+
+    #[test]
+    fn blocks_stop_motion() {
+        let layer = wall_layer();
+        let physics = Physics::of(EntityKind::ITEM).unwrap();
+
+        // Sliding into the wall stops at it but keeps moving along it.
+        let pos = DVec3::new(7.5, FLOOR, 4.5);
+        let step = advance(&layer, physics, item_box(pos), DVec3::new(1.0, 0.0, 0.2)).unwrap();
+        assert_eq!(step.offset.x, 8.0 - 7.625);
+        assert_eq!(step.offset.z, 0.2);
+        assert_eq!(step.velocity.x, 0.0);
+        assert!(step.velocity.z > 0.0);
+        assert!(step.on_ground);
+
+        // Arrows stick where they hit.
+        let arrow = Physics::of(EntityKind::ARROW).unwrap();
+        let step = advance(&layer, arrow, item_box(pos), DVec3::new(1.0, 0.0, 0.2)).unwrap();
+        assert_eq!(step.velocity, DVec3::ZERO);
+
+        // Nothing moves into unloaded chunks.
+        let edge = DVec3::new(15.5, FLOOR, 4.5);
+        assert_eq!(step_into(&layer, edge, DVec3::X), None);
+    }
+
+    #[test]
+    fn entities_without_gravity_still_move() {
+        let layer = wall_layer();
+        let physics = Physics {
+            gravity: 0.0,
+            ..Physics::of(EntityKind::ITEM).unwrap()
+        };
+
+        let pos = DVec3::new(2.5, FLOOR + 2.0, 4.5);
+        let step = advance(&layer, physics, item_box(pos), DVec3::new(0.5, 0.0, 0.0)).unwrap();
+        assert_eq!(step.offset, DVec3::new(0.5, 0.0, 0.0));
+        assert_eq!(step.velocity, DVec3::new(0.5 * 0.98, 0.0, 0.0));
+        assert!(!step.on_ground);
+    }
+
+    #[test]
+    fn only_some_entities_have_physics() {
+        assert!(Physics::of(EntityKind::FALLING_BLOCK).is_some());
+        assert!(Physics::of(EntityKind::PLAYER).is_none());
+        assert!(Physics::of(EntityKind::ZOMBIE).is_none());
+    }
+}
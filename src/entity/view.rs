@@ -0,0 +1,221 @@
+//! Tracks which entities each client can see.
+//!
+//! At the end of every tick each client is sent the entities that came into
+//! view, a single [`EntitiesDestroyS2c`] for the ones that left it, and the
+//! movement and metadata changes of the ones that stayed. An entity is in view
+//! when it's on one of the client's [`VisibleEntityLayers`] and in a chunk the
+//! client has loaded.
+
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::mem;
+
+use derive_more::{Deref, DerefMut};
+use evenio::prelude::*;
+use valence_entity::query::{EntityInitQuery, UpdateEntityQuery};
+use valence_entity::{protocol_id, UpdateTrackedDataSet};
+use valence_protocol::encode::PacketWriter;
+use valence_protocol::packets::play::EntitiesDestroyS2c;
+use valence_protocol::{ChunkPos, WritePacket};
+
+use crate::chunk::view::ChunkView;
+use crate::client::Client;
+use crate::SharedServer;
+
+/// The entity layers whose entities are shown to the client. Any entity can
+/// be used as a layer, usually the one holding the [`ChunkLayer`] the client
+/// is in.
+///
+/// [`ChunkLayer`]: crate::chunk::ChunkLayer
+#[derive(Component, Clone, PartialEq, Eq, Default, Debug, Deref, DerefMut)]
+pub struct VisibleEntityLayers(pub BTreeSet<EntityId>);
+
+/// The entities currently spawned on the client.
+#[derive(Component, Default, Debug)]
+pub struct EntityView {
+    visible: HashSet<EntityId>,
+}
+
+impl EntityView {
+    /// Whether `entity` has been spawned on the client.
+    pub fn contains(&self, entity: EntityId) -> bool {
+        self.visible.contains(&entity)
+    }
+
+    /// Replaces the visible entities and returns the ones that left the view.
+    fn set_visible(&mut self, visible: HashSet<EntityId>) -> Vec<EntityId> {
+        let old = mem::replace(&mut self.visible, visible);
+
+        old.into_iter()
+            .filter(|entity| !self.visible.contains(entity))
+            .collect()
+    }
+}
+
+/// Spawns, despawns and updates the entities in view of every client. Runs
+/// after the tracked data of the tick is written and before the changes are
+/// cleared.
+pub fn update_entity_views(
+    _: Receiver<UpdateTrackedDataSet>,
+    mut clients: Fetcher<(
+        EntityId,
+        &mut Client,
+        &mut EntityView,
+        &ChunkView,
+        &VisibleEntityLayers,
+    )>,
+    entities: Fetcher<EntityInitQuery>,
+    mut updates: Fetcher<UpdateEntityQuery>,
+    server: Single<&SharedServer>,
+) {
+    let threshold = server.0.threshold;
+
+    // Every entity is updated, even without viewers, so that its movement is
+    // sent relative to what its future viewers are told.
+    let mut update_packets = HashMap::new();
+    // Where clients were told the entity is, which new viewers are spawned at.
+    let mut positions = HashMap::new();
+    let mut by_chunk: HashMap<(EntityId, ChunkPos), Vec<EntityId>> = HashMap::new();
+
+    for mut update in updates.iter_mut() {
+        let mut buf = vec![];
+        update.write_update_packets(PacketWriter::new(&mut buf, threshold));
+
+        let pos = update.movement.position().unwrap_or(update.pos.0);
+
+        by_chunk
+            .entry((update.loc.0, ChunkPos::from(update.pos.0)))
+            .or_default()
+            .push(update.id);
+        update_packets.insert(update.id, buf);
+        positions.insert(update.id, pos);
+    }
+
+    let by_chunk = &by_chunk;
+
+    for (client_id, client, view, chunk_view, layers) in clients.iter_mut() {
+        // Only the chunks the client has loaded are looked at.
+        let visible: HashSet<EntityId> = layers
+            .iter()
+            .flat_map(|&layer| {
+                chunk_view
+                    .loaded()
+                    .filter_map(move |pos| by_chunk.get(&(layer, pos)))
+            })
+            .flatten()
+            .copied()
+            .filter(|&entity| entity != client_id)
+            .collect();
+
+        let mut entered = vec![];
+        for &entity in &visible {
+            if !view.contains(entity) {
+                entered.push(entity);
+                continue;
+            }
+
+            if let Some(bytes) = update_packets.get(&entity) {
+                client.write_packet_bytes(bytes);
+            }
+        }
+
+        let left = view.set_visible(visible);
+        if !left.is_empty() {
+            client.write_packet(&EntitiesDestroyS2c {
+                entity_ids: left.into_iter().map(protocol_id).collect(),
+            });
+        }
+
+        for entity in entered {
+            if let (Ok(init), Some(&pos)) = (entities.get(entity), positions.get(&entity)) {
+                init.write_init_packets(pos, &mut *client);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use valence_entity::movement::TrackedMovement;
+    use valence_entity::tracked_data::TrackedData;
+    use valence_entity::{
+        EntityAnimations, EntityKind, EntityLayerId, EntityStatuses, HeadYaw, Look, ObjectData,
+        OldEntityLayerId, OldPosition, OnGround, Position, Velocity,
+    };
+    use valence_protocol::math::DVec3;
+    use valence_protocol::packets::play::EntitySpawnS2c;
+    use valence_protocol::{Packet, VarInt};
+    use valence_server_common::UniqueId;
+
+    use super::*;
+    use crate::testing::{self, sent_packets};
+
+    #[test]
+    fn set_visible_returns_entities_that_left() {
+        let mut world = World::new();
+        let [a, b, c] = [world.spawn(), world.spawn(), world.spawn()];
+
+        let mut view = EntityView::default();
+        assert!(view.set_visible(HashSet::from([a, b])).is_empty());
+        assert!(view.contains(a));
+
+        assert_eq!(view.set_visible(HashSet::from([b, c])), [a]);
+        assert!(!view.contains(a));
+        assert!(view.contains(c));
+
+        let left = view.set_visible(HashSet::new());
+        assert_eq!(left.into_iter().collect::<HashSet<_>>(), HashSet::from([b, c]));
+    }
+
+    #[test]
+    fn entities_are_destroyed_with_the_id_they_were_spawned_with() {
+        let mut world = World::new();
+        world.add_handler(update_entity_views);
+
+        // The first entity, whose index clients could mistake for their own ID.
+        let item = world.spawn();
+        assert_eq!(item.index().0, 0);
+
+        let layer = world.spawn();
+        let server = world.spawn();
+        world.insert(server, testing::server());
+
+        let pos = DVec3::new(8.0, 64.0, 8.0);
+        world.insert(item, EntityKind::ITEM);
+        world.insert(item, UniqueId::default());
+        world.insert(item, EntityLayerId(layer));
+        world.insert(item, OldEntityLayerId::default());
+        world.insert(item, Position(pos));
+        world.insert(item, OldPosition::new(pos));
+        world.insert(item, Look::default());
+        world.insert(item, HeadYaw::default());
+        world.insert(item, OnGround::default());
+        world.insert(item, Velocity::default());
+        world.insert(item, EntityStatuses::default());
+        world.insert(item, EntityAnimations::default());
+        world.insert(item, ObjectData::default());
+        world.insert(item, TrackedData::default());
+        world.insert(item, TrackedMovement::default());
+
+        let client = world.spawn();
+        world.insert(client, testing::client());
+        world.insert(client, EntityView::default());
+        world.insert(client, ChunkView::with_loaded(layer, [ChunkPos::new(0, 0)]));
+        world.insert(client, VisibleEntityLayers(BTreeSet::from([layer])));
+
+        world.send(UpdateTrackedDataSet);
+        let packets = sent_packets(&mut world, client);
+        let spawn = packets
+            .iter()
+            .find(|frame| frame.id == EntitySpawnS2c::ID)
+            .unwrap()
+            .decode::<EntitySpawnS2c>()
+            .unwrap();
+        assert_ne!(spawn.entity_id, VarInt(0));
+
+        world.despawn(item);
+        world.send(UpdateTrackedDataSet);
+        let packets = sent_packets(&mut world, client);
+        let destroy = packets[0].decode::<EntitiesDestroyS2c>().unwrap();
+        assert_eq!(*destroy.entity_ids, [spawn.entity_id]);
+    }
+}
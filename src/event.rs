@@ -1,38 +1,56 @@
-use std::net::SocketAddr;
-
 use evenio::{entity::EntityId, event::Event};
-use tokio::net::TcpStream;
+use valence_protocol::Text;
 
-use crate::{network::connect::handshake::HandshakeData, network::connect::login::ClientInfo, network::packet_io::PacketIo};
+use crate::{network::connect::login::ClientInfo, network::packet_io::PacketIo};
 
+/// Sent once a logged-in client entity has all of its components and is
+/// ready to be sent the join sequence.
 #[derive(Debug, Event)]
-pub struct ConnectionEvent {
-    pub stream: TcpStream,
-    pub remote_addr: SocketAddr,
+pub struct ClientJoinEvent {
+    pub entity: EntityId,
 }
 
+/// Sent when a client leaves the server for any reason. The client entity is
+/// despawned once every handler has seen the event.
+///
+/// Send this event yourself to kick a client with
+/// [`DisconnectReason::Kicked`].
 #[derive(Debug, Event)]
 pub struct ClientDisconnectEvent {
     pub entity: EntityId,
+    pub reason: DisconnectReason,
 }
 
-#[derive(Event)]
-pub struct StatusEvent {
-    pub packet_io: PacketIo,
-}
-
-#[derive(Event)]
-pub struct LegacyPingEvent {
-    pub packet_io: PacketIo,
+/// Why a client was disconnected.
+#[derive(Clone, Debug)]
+pub enum DisconnectReason {
+    /// The client closed the connection or it could no longer be read from.
+    ConnectionClosed,
+    /// Sending data to the client failed, e.g. because its outgoing packet
+    /// queue was full.
+    Io(String),
+    /// The client didn't answer a keepalive in time.
+    TimedOut,
+    /// The server kicked the client. The text is shown on the disconnect
+    /// screen.
+    Kicked(Text),
 }
 
-#[derive(Event)]
-pub struct LoginEvent {
-    pub handshake: HandshakeData,
-    pub packet_io: PacketIo,
-    pub remote_ip: SocketAddr,
+impl DisconnectReason {
+    /// The message shown to the client, if the client is still there to see
+    /// it.
+    pub fn message(&self) -> Option<Text> {
+        match self {
+            Self::ConnectionClosed => None,
+            Self::Io(_) => None,
+            Self::TimedOut => Some(Text::translate("disconnect.timeout", [])),
+            Self::Kicked(text) => Some(text.clone()),
+        }
+    }
 }
 
+/// Sent when a connection finishes logging in. The connection is turned into
+/// a client entity.
 #[derive(Event)]
 pub struct ClientLoginEvent {
     pub packet_io: PacketIo,
@@ -0,0 +1,361 @@
+//! Turns the serverbound packets queued on each [`Client`] into events.
+//!
+//! Every packet is first sent as a raw [`PacketEvent`], so plugins can decode
+//! packets the core doesn't know about. Packets the core does know about are
+//! then decoded and sent again as a typed `PacketEvent<P>`. Packets that borrow
+//! from their body can't be stored in an event, so they get an owned event
+//! instead (see [`ChatMessageEvent`], [`ClientSettingsEvent`],
+//! [`CommandExecutionEvent`] and [`CommandCompletionsEvent`]).
+
+use std::borrow::Cow;
+use std::time::Instant;
+
+use evenio::prelude::*;
+use tracing::debug;
+use valence_protocol::bytes::Bytes;
+use valence_protocol::packets::play::client_settings_c2s::{
+    ChatMode, DisplayedSkinParts, MainArm,
+};
+use valence_protocol::packets::play::{
+    ButtonClickC2s, ChatMessageC2s, ClickSlotC2s, ClientCommandC2s, ClientSettingsC2s,
+    ClientStatusC2s, CloseHandledScreenC2s, CommandExecutionC2s, CraftRequestC2s,
+    CreativeInventoryActionC2s, FullC2s, HandSwingC2s, KeepAliveC2s, LookAndOnGroundC2s,
+    OnGroundOnlyC2s, PickFromInventoryC2s, PlayerActionC2s, PlayerInputC2s,
+    PlayerInteractBlockC2s, PlayerInteractEntityC2s, PlayerInteractItemC2s,
+    PositionAndOnGroundC2s, QueryBlockNbtC2s, RecipeBookDataC2s, RecipeCategoryOptionsC2s,
+    RequestCommandCompletionsC2s, TeleportConfirmC2s, UpdatePlayerAbilitiesC2s,
+    UpdateSelectedSlotC2s, VehicleMoveC2s,
+};
+use valence_protocol::{Decode, Packet};
+use valence_server_common::PreUpdate;
+
+use crate::client::Client;
+use crate::event::{ClientDisconnectEvent, DisconnectReason};
+
+/// A serverbound packet, targeted at the client entity that sent it.
+///
+/// The plain `PacketEvent` carries a [`RawPacket`] and is sent for every
+/// packet received. `PacketEvent<P>` is sent afterwards for the packets
+/// decoded by [`dispatch_packets`].
+#[derive(Event, Clone, Debug)]
+pub struct PacketEvent<P = RawPacket> {
+    #[event(target)]
+    pub client: EntityId,
+    /// When the packet was read from the connection.
+    pub timestamp: Instant,
+    pub packet: P,
+}
+
+/// The ID and undecoded body of a packet.
+#[derive(Clone, Debug)]
+pub struct RawPacket {
+    pub id: i32,
+    pub body: Bytes,
+}
+
+impl PacketEvent {
+    /// Attempts to decode the packet as `P`. Returns `None` if the packet ID
+    /// doesn't match or the body is malformed.
+    pub fn decode<'a, P>(&'a self) -> Option<P>
+    where
+        P: Packet + Decode<'a>,
+    {
+        if self.packet.id != P::ID {
+            return None;
+        }
+
+        let mut r = &self.packet.body[..];
+
+        match P::decode(&mut r) {
+            Ok(packet) if r.is_empty() => Some(packet),
+            Ok(_) => {
+                debug!(
+                    client = ?self.client,
+                    "missed {} bytes while decoding '{}'",
+                    r.len(),
+                    P::NAME
+                );
+                None
+            }
+            Err(e) => {
+                debug!(client = ?self.client, "failed to decode '{}': {e:#}", P::NAME);
+                None
+            }
+        }
+    }
+}
+
+/// An owned copy of a [`ChatMessageC2s`].
+#[derive(Event, Clone, Debug)]
+pub struct ChatMessageEvent {
+    #[event(target)]
+    pub client: EntityId,
+    pub message: Box<str>,
+    pub timestamp: u64,
+}
+
+/// An owned copy of a [`ClientSettingsC2s`].
+#[derive(Event, Clone, Debug)]
+pub struct ClientSettingsEvent {
+    #[event(target)]
+    pub client: EntityId,
+    pub locale: Box<str>,
+    pub view_distance: u8,
+    pub chat_mode: ChatMode,
+    pub chat_colors: bool,
+    pub displayed_skin_parts: DisplayedSkinParts,
+    pub main_arm: MainArm,
+    pub enable_text_filtering: bool,
+    pub allow_server_listings: bool,
+}
+
+/// An owned copy of a [`CommandExecutionC2s`]. The command has no leading
+/// slash.
+#[derive(Event, Clone, Debug)]
+pub struct CommandExecutionEvent {
+    #[event(target)]
+    pub client: EntityId,
+    pub command: Box<str>,
+    pub timestamp: u64,
+}
+
+/// An owned copy of a [`RequestCommandCompletionsC2s`]. The text is what's
+/// in the chat box, including the slash.
+#[derive(Event, Clone, Debug)]
+pub struct CommandCompletionsEvent {
+    #[event(target)]
+    pub client: EntityId,
+    pub transaction_id: i32,
+    pub text: Box<str>,
+}
+
+/// Drains the packets every client sent since the previous tick and sends a
+/// [`PacketEvent`] for each of them.
+pub fn receive_packets(
+    _: Receiver<PreUpdate>,
+    mut clients: Fetcher<(EntityId, &mut Client)>,
+    mut sender: Sender<(PacketEvent, ClientDisconnectEvent)>,
+) {
+    for (entity, client) in clients.iter_mut() {
+        loop {
+            match client.connection_mut().try_recv() {
+                Ok(Some(packet)) => sender.send(PacketEvent {
+                    client: entity,
+                    timestamp: packet.timestamp,
+                    packet: RawPacket {
+                        id: packet.id,
+                        body: packet.body,
+                    },
+                }),
+                Ok(None) => break,
+                Err(e) => {
+                    debug!(client = ?entity, "error receiving packets: {e:#}");
+                    sender.send(ClientDisconnectEvent {
+                        entity,
+                        reason: DisconnectReason::ConnectionClosed,
+                    });
+                    break;
+                }
+            }
+        }
+    }
+}
+
+/// Decodes the packets the core handles and sends them as typed events.
+#[allow(clippy::type_complexity)]
+pub fn dispatch_packets(
+    r: Receiver<PacketEvent>,
+    mut sender: Sender<(
+        (
+            PacketEvent<TeleportConfirmC2s>,
+            PacketEvent<PositionAndOnGroundC2s>,
+            PacketEvent<FullC2s>,
+            PacketEvent<LookAndOnGroundC2s>,
+            PacketEvent<OnGroundOnlyC2s>,
+            PacketEvent<VehicleMoveC2s>,
+            PacketEvent<PlayerInputC2s>,
+        ),
+        (
+            PacketEvent<PlayerInteractEntityC2s>,
+            PacketEvent<PlayerInteractBlockC2s>,
+            PacketEvent<PlayerInteractItemC2s>,
+            PacketEvent<HandSwingC2s>,
+            PacketEvent<PlayerActionC2s>,
+            PacketEvent<ClientCommandC2s>,
+            PacketEvent<UpdateSelectedSlotC2s>,
+            PacketEvent<PickFromInventoryC2s>,
+        ),
+        (
+            PacketEvent<ClickSlotC2s<'static>>,
+            PacketEvent<CreativeInventoryActionC2s>,
+            PacketEvent<CloseHandledScreenC2s>,
+            PacketEvent<ButtonClickC2s>,
+            PacketEvent<RecipeCategoryOptionsC2s>,
+            PacketEvent<CraftRequestC2s<'static>>,
+            PacketEvent<RecipeBookDataC2s<'static>>,
+        ),
+        (
+            PacketEvent<KeepAliveC2s>,
+            PacketEvent<ClientStatusC2s>,
+            PacketEvent<QueryBlockNbtC2s>,
+            PacketEvent<UpdatePlayerAbilitiesC2s>,
+        ),
+        ChatMessageEvent,
+        ClientSettingsEvent,
+        CommandExecutionEvent,
+        CommandCompletionsEvent,
+    )>,
+) {
+    let event = r.event;
+    let client = event.client;
+    let timestamp = event.timestamp;
+
+    macro_rules! forward {
+        ($($packet:ty),* $(,)?) => {
+            $(
+                if event.packet.id == <$packet as Packet>::ID {
+                    if let Some(packet) = event.decode::<$packet>() {
+                        sender.send(PacketEvent {
+                            client,
+                            timestamp,
+                            packet,
+                        });
+                    }
+                    return;
+                }
+            )*
+        };
+    }
+
+    forward!(
+        TeleportConfirmC2s,
+        PositionAndOnGroundC2s,
+        FullC2s,
+        LookAndOnGroundC2s,
+        OnGroundOnlyC2s,
+        VehicleMoveC2s,
+        PlayerInputC2s,
+        PlayerInteractEntityC2s,
+        PlayerInteractBlockC2s,
+        PlayerInteractItemC2s,
+        HandSwingC2s,
+        PlayerActionC2s,
+        ClientCommandC2s,
+        UpdateSelectedSlotC2s,
+        PickFromInventoryC2s,
+        CreativeInventoryActionC2s,
+        CloseHandledScreenC2s,
+        ButtonClickC2s,
+        RecipeCategoryOptionsC2s,
+        KeepAliveC2s,
+        ClientStatusC2s,
+        QueryBlockNbtC2s,
+        UpdatePlayerAbilitiesC2s,
+    );
+
+    if let Some(pkt) = event.decode::<ClickSlotC2s>() {
+        sender.send(PacketEvent {
+            client,
+            timestamp,
+            packet: ClickSlotC2s {
+                window_id: pkt.window_id,
+                state_id: pkt.state_id,
+                slot_idx: pkt.slot_idx,
+                button: pkt.button,
+                mode: pkt.mode,
+                slot_changes: Cow::Owned(pkt.slot_changes.into_owned()),
+                carried_item: pkt.carried_item,
+            },
+        });
+    } else if let Some(pkt) = event.decode::<CraftRequestC2s>() {
+        sender.send(PacketEvent {
+            client,
+            timestamp,
+            packet: CraftRequestC2s {
+                window_id: pkt.window_id,
+                recipe: pkt.recipe.to_string_ident().into(),
+                make_all: pkt.make_all,
+            },
+        });
+    } else if let Some(pkt) = event.decode::<RecipeBookDataC2s>() {
+        sender.send(PacketEvent {
+            client,
+            timestamp,
+            packet: RecipeBookDataC2s {
+                recipe_id: pkt.recipe_id.to_string_ident().into(),
+            },
+        });
+    } else if let Some(pkt) = event.decode::<ChatMessageC2s>() {
+        sender.send(ChatMessageEvent {
+            client,
+            message: pkt.message.0.into(),
+            timestamp: pkt.timestamp,
+        });
+    } else if let Some(pkt) = event.decode::<ClientSettingsC2s>() {
+        sender.send(ClientSettingsEvent {
+            client,
+            locale: pkt.locale.into(),
+            view_distance: pkt.view_distance,
+            chat_mode: pkt.chat_mode,
+            chat_colors: pkt.chat_colors,
+            displayed_skin_parts: pkt.displayed_skin_parts,
+            main_arm: pkt.main_arm,
+            enable_text_filtering: pkt.enable_text_filtering,
+            allow_server_listings: pkt.allow_server_listings,
+        });
+    } else if let Some(pkt) = event.decode::<CommandExecutionC2s>() {
+        sender.send(CommandExecutionEvent {
+            client,
+            command: pkt.command.0.into(),
+            timestamp: pkt.timestamp,
+        });
+    } else if let Some(pkt) = event.decode::<RequestCommandCompletionsC2s>() {
+        sender.send(CommandCompletionsEvent {
+            client,
+            transaction_id: pkt.transaction_id.0,
+            text: pkt.text.0.into(),
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use valence_protocol::{Encode, VarInt};
+
+    use super::*;
+
+    fn raw_event<P: Packet + Encode>(packet: &P) -> PacketEvent {
+        let mut body = vec![];
+        packet.encode(&mut body).unwrap();
+
+        PacketEvent {
+            client: EntityId::NULL,
+            timestamp: Instant::now(),
+            packet: RawPacket {
+                id: P::ID,
+                body: body.into(),
+            },
+        }
+    }
+
+    #[test]
+    fn decode_matching_packet() {
+        let event = raw_event(&KeepAliveC2s { id: 42 });
+
+        assert_eq!(event.decode::<KeepAliveC2s>().unwrap().id, 42);
+        assert!(event.decode::<TeleportConfirmC2s>().is_none());
+    }
+
+    #[test]
+    fn decode_rejects_trailing_bytes() {
+        let mut event = raw_event(&TeleportConfirmC2s {
+            teleport_id: VarInt(3),
+        });
+
+        let mut body = event.packet.body.to_vec();
+        body.push(0);
+        event.packet.body = body.into();
+
+        assert!(event.decode::<TeleportConfirmC2s>().is_none());
+    }
+}
@@ -0,0 +1,228 @@
+use thiserror::Error;
+use valence_protocol::block::BlockKind;
+use valence_protocol::{BlockState, ChunkPos};
+
+use super::ChunkGenerator;
+use crate::chunk::{BiomeId, Chunk};
+use crate::registry::{RegistryCodec, BIOME};
+
+/// Generates a superflat world: horizontal layers of blocks stacked from the
+/// bottom of the world.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct FlatGenerator {
+    layers: Vec<FlatLayer>,
+    biome: BiomeId,
+}
+
+/// `height` blocks of `block` in a [`FlatGenerator`].
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct FlatLayer {
+    pub block: BlockState,
+    pub height: u32,
+}
+
+#[derive(Clone, Debug, Error)]
+#[non_exhaustive]
+pub enum FlatPresetError {
+    #[error("invalid layer height in '{0}'")]
+    BadLayerHeight(String),
+    #[error("unknown block '{0}'")]
+    UnknownBlock(String),
+    #[error("unknown biome '{0}'")]
+    UnknownBiome(String),
+}
+
+impl FlatGenerator {
+    /// Creates a generator with `layers` listed from the bottom up. Layers
+    /// above the top of the world are cut off.
+    pub fn new(layers: Vec<FlatLayer>, biome: BiomeId) -> Self {
+        Self { layers, biome }
+    }
+
+    /// Parses a preset in the format of the superflat customization screen,
+    /// e.g. `minecraft:bedrock,2*minecraft:dirt,minecraft:grass_block;minecraft:plains`.
+    /// Layers are listed from the bottom up and the biome after the `;` is
+    /// optional. Without it the first biome of `codec` is used.
+    pub fn from_preset(preset: &str, codec: &RegistryCodec) -> Result<Self, FlatPresetError> {
+        let (layers, biome) = match preset.split_once(';') {
+            Some((layers, biome)) => (layers, Some(biome)),
+            None => (preset, None),
+        };
+
+        let layers = layers
+            .split(',')
+            .map(str::trim)
+            .filter(|layer| !layer.is_empty())
+            .map(|layer| {
+                let (height, name) = match layer.split_once('*') {
+                    Some((height, name)) => {
+                        let height = height
+                            .trim()
+                            .parse()
+                            .map_err(|_| FlatPresetError::BadLayerHeight(layer.to_owned()))?;
+                        (height, name.trim())
+                    }
+                    None => (1, layer),
+                };
+
+                let kind = BlockKind::from_str(name.strip_prefix("minecraft:").unwrap_or(name))
+                    .ok_or_else(|| FlatPresetError::UnknownBlock(name.to_owned()))?;
+
+                Ok(FlatLayer {
+                    block: kind.to_state(),
+                    height,
+                })
+            })
+            .collect::<Result<_, _>>()?;
+
+        let biome = match biome.map(str::trim) {
+            Some(name) => {
+                let name = if name.contains(':') {
+                    name.to_owned()
+                } else {
+                    format!("minecraft:{name}")
+                };
+
+                codec
+                    .index_of(BIOME, &name)
+                    .ok_or(FlatPresetError::UnknownBiome(name))?
+            }
+            None => 0,
+        };
+
+        Ok(Self::new(layers, BiomeId(biome as u16)))
+    }
+
+    pub fn layers(&self) -> &[FlatLayer] {
+        &self.layers
+    }
+
+    pub fn biome(&self) -> BiomeId {
+        self.biome
+    }
+}
+
+impl Default for FlatGenerator {
+    /// The "Classic Flat" preset, with plains as the first biome of the
+    /// default registry codec.
+    fn default() -> Self {
+        Self::new(
+            vec![
+                FlatLayer {
+                    block: BlockState::BEDROCK,
+                    height: 1,
+                },
+                FlatLayer {
+                    block: BlockState::DIRT,
+                    height: 2,
+                },
+                FlatLayer {
+                    block: BlockState::GRASS_BLOCK,
+                    height: 1,
+                },
+            ],
+            BiomeId(0),
+        )
+    }
+}
+
+impl ChunkGenerator for FlatGenerator {
+    fn generate(&self, _pos: ChunkPos, _min_y: i32, height: u32) -> Chunk {
+        let mut chunk = Chunk::with_height(height);
+        chunk.fill_biomes(self.biome);
+
+        let blocks = self
+            .layers
+            .iter()
+            .flat_map(|layer| (0..layer.height).map(|_| layer.block));
+
+        for (y, block) in (0..height).zip(blocks) {
+            if block.is_air() {
+                continue;
+            }
+
+            for z in 0..16 {
+                for x in 0..16 {
+                    chunk.set_block_state(x, y, z, block);
+                }
+            }
+        }
+
+        chunk
+    }
+
+    fn is_flat(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use valence_protocol::ident;
+
+    use super::*;
+
+    #[test]
+    fn parse_preset() {
+        let codec = RegistryCodec::default();
+
+        let generator = FlatGenerator::from_preset(
+            "minecraft:bedrock,2*minecraft:dirt,minecraft:grass_block;minecraft:plains",
+            &codec,
+        )
+        .unwrap();
+        assert_eq!(generator, FlatGenerator::default());
+
+        let generator = FlatGenerator::from_preset("stone, 3 * sand", &codec).unwrap();
+        assert_eq!(
+            generator.layers(),
+            [
+                FlatLayer {
+                    block: BlockState::STONE,
+                    height: 1,
+                },
+                FlatLayer {
+                    block: BlockState::SAND,
+                    height: 3,
+                },
+            ]
+        );
+
+        assert!(matches!(
+            FlatGenerator::from_preset("x*minecraft:dirt", &codec),
+            Err(FlatPresetError::BadLayerHeight(_))
+        ));
+        assert!(matches!(
+            FlatGenerator::from_preset("minecraft:not_a_block", &codec),
+            Err(FlatPresetError::UnknownBlock(_))
+        ));
+        assert!(matches!(
+            FlatGenerator::from_preset("minecraft:dirt;minecraft:the_void", &codec),
+            Err(FlatPresetError::UnknownBiome(_))
+        ));
+    }
+
+    #[test]
+    fn layers_stack_from_the_bottom() {
+        let mut codec = RegistryCodec::default();
+        codec.insert(
+            BIOME,
+            ident!("desert").into(),
+            crate::registry::biome(2.0, 0.0, false, 7254527),
+        );
+
+        let generator =
+            FlatGenerator::from_preset("minecraft:bedrock,30*minecraft:sandstone;desert", &codec)
+                .unwrap();
+        let chunk = generator.generate(ChunkPos::new(5, -5), -64, 48);
+
+        assert_eq!(chunk.block_state(3, 0, 3), BlockState::BEDROCK);
+        assert_eq!(chunk.block_state(15, 30, 0), BlockState::SANDSTONE);
+        assert_eq!(chunk.block_state(0, 31, 15), BlockState::AIR);
+        assert_eq!(chunk.biome(1, 11, 2), BiomeId(1));
+
+        // Layers above the top of the world are cut off.
+        let chunk = generator.generate(ChunkPos::new(0, 0), 0, 16);
+        assert_eq!(chunk.block_state(0, 15, 0), BlockState::SANDSTONE);
+    }
+}
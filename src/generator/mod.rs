@@ -0,0 +1,243 @@
+//! Generates the chunks of a [`ChunkLayer`] that aren't loaded from disk.
+//!
+//! A [`LayerGenerator`] on a layer entity runs its [`ChunkGenerator`] on a
+//! pool of worker threads for every missing chunk in view of a client.
+//! Generated chunks are inserted into the layer at the start of the next
+//! tick. On layers that also have an [`AnvilLevel`], only the chunks missing
+//! from the world's region files are generated.
+
+mod flat;
+mod noise;
+
+use std::collections::HashSet;
+use std::fmt;
+use std::sync::Arc;
+use std::thread;
+
+use evenio::prelude::*;
+use evenio::query::Not;
+use sha2::{Digest, Sha256};
+use valence_protocol::ChunkPos;
+use valence_server_common::{PreUpdate, Tick};
+
+pub use self::flat::{FlatGenerator, FlatLayer, FlatPresetError};
+pub use self::noise::NoiseGenerator;
+use crate::anvil::{AnvilLevel, ChunkLoadEvent, ChunkLoadStatus};
+use crate::chunk::view::{spiral, ChunkView, VisibleChunkLayer};
+use crate::chunk::{BiomeId, Chunk, ChunkLayer};
+
+/// The most worker threads a single [`LayerGenerator`] uses.
+const MAX_WORKER_THREADS: usize = 4;
+
+/// Produces the contents of chunks. Generators are called from worker
+/// threads, so the same position must always produce the same chunk.
+pub trait ChunkGenerator: Send + Sync + 'static {
+    /// Generates the chunk at `pos` for a layer whose bottom is at `min_y`
+    /// and which is `height` blocks tall.
+    fn generate(&self, pos: ChunkPos, min_y: i32, height: u32) -> Chunk;
+
+    /// Whether the generated world is superflat. Clients of flat worlds draw
+    /// the horizon at the bottom of the world instead of at sea level.
+    fn is_flat(&self) -> bool {
+        false
+    }
+
+    /// The seed sent to clients, hashed with [`hash_seed`]. Clients use it
+    /// to blend biome colors.
+    fn hashed_seed(&self) -> u64 {
+        0
+    }
+}
+
+/// Generates nothing but air.
+#[derive(Copy, Clone, Default, Debug)]
+pub struct VoidGenerator {
+    pub biome: BiomeId,
+}
+
+impl ChunkGenerator for VoidGenerator {
+    fn generate(&self, _pos: ChunkPos, _min_y: i32, height: u32) -> Chunk {
+        let mut chunk = Chunk::with_height(height);
+        chunk.fill_biomes(self.biome);
+        chunk
+    }
+}
+
+/// Hashes a world seed the way the game does before sending it to clients:
+/// the first 8 bytes of the SHA-256 of the seed, both little endian.
+pub fn hash_seed(seed: i64) -> u64 {
+    let digest = Sha256::digest(seed.to_le_bytes());
+    u64::from_le_bytes(digest[..8].try_into().unwrap())
+}
+
+/// Generates the missing chunks of its [`ChunkLayer`].
+#[derive(Component)]
+pub struct LayerGenerator {
+    generator: Arc<dyn ChunkGenerator>,
+    requests: flume::Sender<ChunkPos>,
+    results: flume::Receiver<(ChunkPos, Chunk)>,
+    /// Chunks that were requested but haven't been received yet.
+    pending: HashSet<ChunkPos>,
+}
+
+impl LayerGenerator {
+    /// Starts the worker threads generating chunks for `layer`.
+    pub fn new(generator: impl ChunkGenerator, layer: &ChunkLayer) -> Self {
+        let generator: Arc<dyn ChunkGenerator> = Arc::new(generator);
+
+        let min_y = layer.min_y();
+        let height = layer.height();
+
+        let (request_sender, request_receiver) = flume::unbounded::<ChunkPos>();
+        let (result_sender, result_receiver) = flume::unbounded();
+
+        let thread_count = thread::available_parallelism()
+            .map_or(1, |count| count.get())
+            .min(MAX_WORKER_THREADS);
+
+        for _ in 0..thread_count {
+            let generator = generator.clone();
+            let request_receiver = request_receiver.clone();
+            let result_sender = result_sender.clone();
+
+            thread::spawn(move || {
+                // Stops once the generator is dropped.
+                for pos in request_receiver {
+                    let chunk = generator.generate(pos, min_y, height);
+
+                    if result_sender.send((pos, chunk)).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+
+        Self {
+            generator,
+            requests: request_sender,
+            results: result_receiver,
+            pending: HashSet::new(),
+        }
+    }
+
+    pub fn generator(&self) -> &dyn ChunkGenerator {
+        &*self.generator
+    }
+
+    /// Queues the chunk at `pos` to be generated. Does nothing if it's
+    /// already queued.
+    pub fn generate(&mut self, pos: impl Into<ChunkPos>) {
+        let pos = pos.into();
+
+        if self.pending.insert(pos) {
+            let _ = self.requests.send(pos);
+        }
+    }
+
+    /// Whether the chunk at `pos` is being generated.
+    pub fn is_pending(&self, pos: impl Into<ChunkPos>) -> bool {
+        self.pending.contains(&pos.into())
+    }
+}
+
+impl fmt::Debug for LayerGenerator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LayerGenerator")
+            .field("pending", &self.pending)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Requests the chunks in view of every client that aren't loaded yet, on
+/// layers without an [`AnvilLevel`].
+pub fn request_chunks(
+    _: Receiver<Tick>,
+    clients: Fetcher<(&ChunkView, &VisibleChunkLayer)>,
+    mut layers: Fetcher<(&ChunkLayer, &mut LayerGenerator, Not<&AnvilLevel>)>,
+) {
+    for (view, visible_layer) in clients.iter() {
+        let Some(center) = view.center() else {
+            continue;
+        };
+
+        let Ok((layer, generator, _)) = layers.get_mut(visible_layer.0) else {
+            continue;
+        };
+
+        for pos in spiral(center, view.view_distance()) {
+            if layer.chunk(pos).is_none() {
+                generator.generate(pos);
+            }
+        }
+    }
+}
+
+/// Generates the chunks that are missing from the region files of a layer.
+pub fn generate_missing_chunks(r: Receiver<ChunkLoadEvent, &mut LayerGenerator>) {
+    if let ChunkLoadStatus::Empty = r.event.status {
+        let generator = r.query;
+        generator.generate(r.event.pos);
+    }
+}
+
+/// Inserts the chunks generated since the previous tick into their layers.
+pub fn insert_generated_chunks(
+    _: Receiver<PreUpdate>,
+    mut layers: Fetcher<(&mut ChunkLayer, &mut LayerGenerator)>,
+) {
+    for (layer, generator) in layers.iter_mut() {
+        for (pos, chunk) in generator.results.try_iter() {
+            generator.pending.remove(&pos);
+
+            // Don't replace a chunk that was inserted in the meantime.
+            if layer.chunk(pos).is_none() {
+                layer.insert_chunk(pos, chunk);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use valence_protocol::{ident, BlockState, CompressionThreshold};
+
+    use super::*;
+    use crate::registry::RegistryCodec;
+
+    #[test]
+    fn generate_on_worker_threads() {
+        let layer = ChunkLayer::new(
+            ident!("overworld"),
+            &RegistryCodec::default(),
+            CompressionThreshold(-1),
+        );
+        let mut generator = LayerGenerator::new(FlatGenerator::default(), &layer);
+        assert!(generator.generator().is_flat());
+
+        for x in 0..8 {
+            generator.generate([x, 0]);
+        }
+        generator.generate([0, 0]);
+        assert!(generator.is_pending([3, 0]));
+
+        let mut chunks: Vec<_> = (0..8).map(|_| generator.results.recv().unwrap()).collect();
+        chunks.sort_by_key(|(pos, _)| pos.x);
+
+        for (x, (pos, chunk)) in chunks.into_iter().enumerate() {
+            assert_eq!(pos, ChunkPos::new(x as i32, 0));
+            assert_eq!(chunk.height(), layer.height());
+            assert_eq!(chunk.block_state(0, 0, 0), BlockState::BEDROCK);
+        }
+
+        // The duplicate request wasn't sent.
+        assert!(generator.results.try_recv().is_err());
+    }
+
+    #[test]
+    fn void_is_empty() {
+        let chunk = VoidGenerator::default().generate(ChunkPos::new(0, 0), -64, 384);
+
+        assert_eq!(chunk.height(), 384);
+        assert!((0..384).all(|y| chunk.block_state(8, y, 8).is_air()));
+    }
+}
@@ -0,0 +1,321 @@
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::SeedableRng;
+use valence_protocol::block::{PropName, PropValue};
+use valence_protocol::{BlockState, ChunkPos, Ident};
+
+use super::{hash_seed, ChunkGenerator};
+use crate::chunk::{BiomeId, Chunk};
+use crate::registry::{self, RegistryCodec, BIOME};
+
+/// The highest world Y coordinate filled with water.
+const SEA_LEVEL: i32 = 63;
+
+/// Generates rolling hills from seeded Perlin noise, with plains, forest,
+/// desert and snowy plains biomes picked by temperature and humidity noise.
+#[derive(Clone, Debug)]
+pub struct NoiseGenerator {
+    seed: i64,
+    terrain: Perlin,
+    temperature: Perlin,
+    humidity: Perlin,
+    biomes: Biomes,
+}
+
+#[derive(Copy, Clone, Debug)]
+struct Biomes {
+    plains: BiomeId,
+    forest: BiomeId,
+    desert: BiomeId,
+    snowy_plains: BiomeId,
+}
+
+impl NoiseGenerator {
+    /// Creates a generator for `seed` and adds the biomes it uses to
+    /// `codec`. The codec must be updated before the layers using the
+    /// generator are created, or their biome IDs will be out of range.
+    pub fn new(seed: i64, codec: &mut RegistryCodec) -> Self {
+        let mut biome = |name, temperature, downfall, has_precipitation, sky_color| {
+            if codec.index_of(BIOME, name).is_none() {
+                codec.insert(
+                    BIOME,
+                    Ident::new(name.to_owned()).unwrap(),
+                    registry::biome(temperature, downfall, has_precipitation, sky_color),
+                );
+            }
+            BiomeId(codec.index_of(BIOME, name).unwrap() as u16)
+        };
+
+        let biomes = Biomes {
+            plains: biome("minecraft:plains", 0.8, 0.4, true, 7907327),
+            forest: biome("minecraft:forest", 0.7, 0.8, true, 7972607),
+            desert: biome("minecraft:desert", 2.0, 0.0, false, 7254527),
+            snowy_plains: biome("minecraft:snowy_plains", 0.0, 0.5, true, 8364543),
+        };
+
+        Self {
+            seed,
+            terrain: Perlin::new(seed),
+            temperature: Perlin::new(seed.wrapping_add(1)),
+            humidity: Perlin::new(seed.wrapping_add(2)),
+            biomes,
+        }
+    }
+
+    pub fn seed(&self) -> i64 {
+        self.seed
+    }
+
+    /// The world Y coordinate of the surface block at `x`, `z`.
+    fn surface_y(&self, x: i32, z: i32) -> i32 {
+        let noise = self.terrain.fbm(x as f64 / 128.0, z as f64 / 128.0, 4);
+        SEA_LEVEL + 1 + (noise * 24.0) as i32
+    }
+
+    fn biome(&self, x: i32, z: i32) -> BiomeId {
+        let temperature = self.temperature.fbm(x as f64 / 512.0, z as f64 / 512.0, 2);
+        let humidity = self.humidity.fbm(x as f64 / 384.0, z as f64 / 384.0, 2);
+
+        if temperature > 0.3 {
+            self.biomes.desert
+        } else if temperature < -0.3 {
+            self.biomes.snowy_plains
+        } else if humidity > 0.1 {
+            self.biomes.forest
+        } else {
+            self.biomes.plains
+        }
+    }
+}
+
+impl ChunkGenerator for NoiseGenerator {
+    fn generate(&self, pos: ChunkPos, min_y: i32, height: u32) -> Chunk {
+        let mut chunk = Chunk::with_height(height);
+        let max_y = min_y + height as i32 - 1;
+
+        // Biomes are stored per 4×4×4 cell and sampled at the cell center.
+        let mut biomes = [[BiomeId::default(); 4]; 4];
+        for (cell_z, row) in biomes.iter_mut().enumerate() {
+            for (cell_x, biome) in row.iter_mut().enumerate() {
+                *biome = self.biome(
+                    pos.x * 16 + cell_x as i32 * 4 + 2,
+                    pos.z * 16 + cell_z as i32 * 4 + 2,
+                );
+
+                for cell_y in 0..height / 4 {
+                    chunk.set_biome(cell_x as u32, cell_y, cell_z as u32, *biome);
+                }
+            }
+        }
+
+        let mut surface = [[0; 16]; 16];
+        for (z, row) in surface.iter_mut().enumerate() {
+            for (x, surface_y) in row.iter_mut().enumerate() {
+                *surface_y = self
+                    .surface_y(pos.x * 16 + x as i32, pos.z * 16 + z as i32)
+                    .clamp(min_y, max_y);
+            }
+        }
+
+        // Fill the sections below the lowest dirt and sand with stone at once.
+        let lowest = surface.iter().flatten().min().copied().unwrap_or(min_y) - 4;
+        let stone_sections = ((lowest - min_y) / 16).max(0) as u32;
+        for sect_y in 0..stone_sections {
+            chunk.fill_block_state_section(sect_y, BlockState::STONE);
+        }
+
+        for z in 0..16 {
+            for x in 0..16 {
+                let surface_y = surface[z][x];
+                let biome = biomes[z / 4][x / 4];
+                let snowy = biome == self.biomes.snowy_plains;
+
+                let (top, filler) = if biome == self.biomes.desert || surface_y < SEA_LEVEL {
+                    (BlockState::SAND, BlockState::SAND)
+                } else if snowy {
+                    (
+                        BlockState::GRASS_BLOCK.set(PropName::Snowy, PropValue::True),
+                        BlockState::DIRT,
+                    )
+                } else {
+                    (BlockState::GRASS_BLOCK, BlockState::DIRT)
+                };
+
+                for world_y in min_y + stone_sections as i32 * 16..=max_y {
+                    let block = if world_y == min_y {
+                        BlockState::BEDROCK
+                    } else if world_y < surface_y - 3 {
+                        BlockState::STONE
+                    } else if world_y < surface_y {
+                        filler
+                    } else if world_y == surface_y {
+                        top
+                    } else if world_y < SEA_LEVEL {
+                        BlockState::WATER
+                    } else if world_y == SEA_LEVEL {
+                        if snowy {
+                            BlockState::ICE
+                        } else {
+                            BlockState::WATER
+                        }
+                    } else if world_y == surface_y + 1 && snowy {
+                        BlockState::SNOW
+                    } else {
+                        break;
+                    };
+
+                    chunk.set_block_state(x as u32, (world_y - min_y) as u32, z as u32, block);
+                }
+
+                if stone_sections > 0 {
+                    chunk.set_block_state(x as u32, 0, z as u32, BlockState::BEDROCK);
+                }
+            }
+        }
+
+        chunk
+    }
+
+    fn hashed_seed(&self) -> u64 {
+        hash_seed(self.seed)
+    }
+}
+
+/// Two dimensional Perlin noise with a seeded permutation table.
+#[derive(Clone)]
+struct Perlin {
+    /// A shuffled permutation of 0..256, repeated twice to avoid wrapping.
+    perm: Box<[u8; 512]>,
+}
+
+impl Perlin {
+    fn new(seed: i64) -> Self {
+        let mut table: Vec<u8> = (0..=255).collect();
+        table.shuffle(&mut StdRng::seed_from_u64(seed as u64));
+
+        let mut perm = Box::new([0; 512]);
+        for (i, p) in perm.iter_mut().enumerate() {
+            *p = table[i % 256];
+        }
+
+        Self { perm }
+    }
+
+    /// Noise at `x`, `z`, roughly in -1..1.
+    fn sample(&self, x: f64, z: f64) -> f64 {
+        let (xf, zf) = (x.floor(), z.floor());
+        let (xi, zi) = ((xf as i64 & 255) as usize, (zf as i64 & 255) as usize);
+        let (x, z) = (x - xf, z - zf);
+
+        let fade = |t: f64| t * t * t * (t * (t * 6.0 - 15.0) + 10.0);
+        let lerp = |t: f64, a: f64, b: f64| a + t * (b - a);
+        let grad = |hash: u8, x: f64, z: f64| match hash & 3 {
+            0 => x + z,
+            1 => -x + z,
+            2 => x - z,
+            _ => -x - z,
+        };
+
+        let p = &self.perm;
+        let aa = p[p[xi] as usize + zi];
+        let ab = p[p[xi] as usize + zi + 1];
+        let ba = p[p[xi + 1] as usize + zi];
+        let bb = p[p[xi + 1] as usize + zi + 1];
+
+        let (u, v) = (fade(x), fade(z));
+
+        lerp(
+            v,
+            lerp(u, grad(aa, x, z), grad(ba, x - 1.0, z)),
+            lerp(u, grad(ab, x, z - 1.0), grad(bb, x - 1.0, z - 1.0)),
+        )
+    }
+
+    /// Sums `octaves` layers of noise, each at twice the frequency and half
+    /// the amplitude of the one before.
+    fn fbm(&self, x: f64, z: f64, octaves: u32) -> f64 {
+        let mut sum = 0.0;
+        let mut amplitude = 1.0;
+        let mut frequency = 1.0;
+        let mut max = 0.0;
+
+        for _ in 0..octaves {
+            sum += self.sample(x * frequency, z * frequency) * amplitude;
+            max += amplitude;
+            amplitude /= 2.0;
+            frequency *= 2.0;
+        }
+
+        sum / max
+    }
+}
+
+impl std::fmt::Debug for Perlin {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Perlin").finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn same_seed_same_terrain() {
+        let mut codec = RegistryCodec::default();
+        let a = NoiseGenerator::new(1234, &mut codec);
+        let b = NoiseGenerator::new(1234, &mut codec);
+        let c = NoiseGenerator::new(4321, &mut codec);
+
+        // Biomes are only registered once.
+        assert_eq!(codec.registry(BIOME).len(), 4);
+
+        let pos = ChunkPos::new(-3, 10);
+        let (a, b, c) = (
+            a.generate(pos, -64, 384),
+            b.generate(pos, -64, 384),
+            c.generate(pos, -64, 384),
+        );
+
+        let column = |chunk: &Chunk, x, z| {
+            (0..384)
+                .map(|y| chunk.block_state(x, y, z))
+                .collect::<Vec<_>>()
+        };
+
+        let mut differs = false;
+        for z in 0..16 {
+            for x in 0..16 {
+                assert_eq!(column(&a, x, z), column(&b, x, z));
+                differs |= column(&a, x, z) != column(&c, x, z);
+            }
+        }
+        assert!(differs);
+    }
+
+    #[test]
+    fn terrain_is_solid_below_the_surface() {
+        let mut codec = RegistryCodec::default();
+        let generator = NoiseGenerator::new(99, &mut codec);
+        let chunk = generator.generate(ChunkPos::new(0, 0), -64, 384);
+
+        for z in 0..16 {
+            for x in 0..16 {
+                let surface_y = generator.surface_y(x as i32, z as i32);
+                assert!((SEA_LEVEL - 24..=SEA_LEVEL + 25).contains(&surface_y));
+
+                let surface = (surface_y + 64) as u32;
+                assert_eq!(chunk.block_state(x, 0, z), BlockState::BEDROCK);
+                assert_eq!(chunk.block_state(x, 1, z), BlockState::STONE);
+                assert!(!chunk.block_state(x, surface, z).is_air());
+                assert!(!chunk.block_state(x, surface, z).is_liquid());
+
+                let above = chunk.block_state(x, surface + 2, z);
+                assert!(above.is_air() || above == BlockState::WATER || above == BlockState::ICE);
+            }
+        }
+
+        let other = NoiseGenerator::new(100, &mut codec);
+        assert_ne!(generator.hashed_seed(), other.hashed_seed());
+    }
+}
@@ -0,0 +1,912 @@
+//! Works out what clicking a slot does.
+//!
+//! Clients predict the outcome of their clicks and send the slots that
+//! changed, but the server doesn't take their word for it: [`Window::click`]
+//! redoes the click on the server's copy of the slots following the game's
+//! rules, and whatever the client predicted is only compared against it.
+//!
+//! The result slot of a crafting grid is filled in after every click, and
+//! taking the result out uses up the ingredients.
+
+use std::mem;
+use std::ops::Range;
+
+use valence_protocol::packets::play::click_slot_c2s::ClickMode;
+use valence_protocol::{ItemKind, ItemStack};
+
+use super::{crafting, insert_stack, InventoryKind};
+use crate::recipe::{crafting_remainder, RecipeRegistry};
+
+/// The slot index of clicks outside the window.
+pub(crate) const OUTSIDE: i16 = -999;
+
+/// The slots of the player's main inventory and hotbar, which come after the
+/// slots of the inventory shown in every window.
+const PLAYER_MAIN_SLOTS: usize = 36;
+
+/// The slots shown in a window and the item held by the cursor.
+#[derive(Clone, Debug)]
+pub(crate) struct Window<'a> {
+    kind: InventoryKind,
+    pub(crate) slots: Vec<ItemStack>,
+    pub(crate) cursor: ItemStack,
+    /// The recipes the crafting grid is matched against, or `None` if it
+    /// doesn't craft anything.
+    recipes: Option<&'a RecipeRegistry>,
+    /// What's left of crafting ingredients that didn't fit in the player's
+    /// inventory, which is dropped.
+    pub(crate) leftovers: Vec<ItemStack>,
+}
+
+/// A click as sent by the client.
+#[derive(Copy, Clone, Debug)]
+pub(crate) struct Click {
+    pub(crate) slot: i16,
+    pub(crate) button: i8,
+    pub(crate) mode: ClickMode,
+}
+
+/// A stack thrown out of the window.
+#[derive(Clone, PartialEq, Debug)]
+pub(crate) struct Dropped {
+    /// The slot the stack came from, or `None` if it was on the cursor.
+    pub(crate) slot: Option<usize>,
+    pub(crate) stack: ItemStack,
+}
+
+/// A drag in progress, which takes one click to start, one for every slot
+/// dragged over and one to end.
+#[derive(Clone, PartialEq, Debug)]
+pub(crate) struct Drag {
+    kind: DragKind,
+    slots: Vec<usize>,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+enum DragKind {
+    /// Splits the cursor evenly between the slots.
+    Even,
+    /// Places one item in every slot.
+    One,
+    /// Fills every slot with a full stack, in creative mode.
+    Clone,
+}
+
+impl<'a> Window<'a> {
+    pub(crate) fn new(kind: InventoryKind, slots: Vec<ItemStack>, cursor: ItemStack) -> Self {
+        Self {
+            kind,
+            slots,
+            cursor,
+            recipes: None,
+            leftovers: vec![],
+        }
+    }
+
+    /// Makes the crafting grid of the window craft `recipes`.
+    pub(crate) fn with_recipes(mut self, recipes: &'a RecipeRegistry) -> Self {
+        self.recipes = Some(recipes);
+        self
+    }
+
+    /// Applies `click`, continuing or ending the `drag` in progress. Returns
+    /// the stack thrown out of the window, if any.
+    pub(crate) fn click(
+        &mut self,
+        click: Click,
+        drag: &mut Option<Drag>,
+        creative: bool,
+    ) -> Option<Dropped> {
+        if click.mode != ClickMode::Drag {
+            *drag = None;
+        }
+
+        let slot = self.slot_index(click.slot);
+
+        let dropped = match click.mode {
+            ClickMode::Click if click.slot == OUTSIDE => self.drop_cursor(click.button),
+            ClickMode::Click if matches!(click.button, 0 | 1) => {
+                if let Some(slot) = slot {
+                    self.pickup(slot, click.button == 1);
+                }
+                None
+            }
+            ClickMode::ShiftClick if matches!(click.button, 0 | 1) => {
+                if let Some(slot) = slot {
+                    self.quick_move(slot);
+                }
+                None
+            }
+            ClickMode::Hotbar => {
+                if let (Some(slot), Some(target)) = (slot, self.swap_target(click.button)) {
+                    self.swap(slot, target);
+                }
+                None
+            }
+            ClickMode::CreativeMiddleClick => {
+                if let Some(slot) = slot.filter(|_| creative && self.cursor.is_empty()) {
+                    let stack = &self.slots[slot];
+                    if !stack.is_empty() {
+                        self.cursor = stack.clone().with_count(stack.item.max_stack());
+                    }
+                }
+                None
+            }
+            ClickMode::DropKey => slot.and_then(|slot| self.throw(slot, click.button)),
+            ClickMode::Drag => {
+                self.drag(click, drag, creative);
+                None
+            }
+            ClickMode::DoubleClick => {
+                if let Some(slot) = slot {
+                    self.pickup_all(slot, click.button == 0);
+                }
+                None
+            }
+            _ => None,
+        };
+
+        self.update_result();
+        dropped
+    }
+
+    fn slot_index(&self, slot: i16) -> Option<usize> {
+        usize::try_from(slot)
+            .ok()
+            .filter(|&slot| slot < self.slots.len())
+    }
+
+    /// Where the player's main inventory starts.
+    fn player_start(&self) -> usize {
+        self.slots.len() - PLAYER_MAIN_SLOTS - usize::from(self.kind == InventoryKind::Player)
+    }
+
+    fn is_output(&self, slot: usize) -> bool {
+        slot < self.player_start() && self.kind.output_slot() == Some(slot)
+    }
+
+    /// Whether `slot` holds the result of the crafting grid.
+    fn is_result(&self, slot: usize) -> bool {
+        self.recipes.is_some() && self.kind.crafting_grid().is_some() && slot == 0
+    }
+
+    /// Puts what the crafting grid makes in its result slot.
+    fn update_result(&mut self) {
+        if let Some(recipes) = self.recipes {
+            crafting::update_result(self.kind, &mut self.slots, recipes);
+        }
+    }
+
+    /// Takes the result of the crafting grid, using up one of every
+    /// ingredient.
+    fn take_result(&mut self) -> ItemStack {
+        let result = mem::replace(&mut self.slots[0], ItemStack::EMPTY);
+        let Some((grid, _)) = self.kind.crafting_grid() else {
+            return result;
+        };
+
+        for slot in grid {
+            let stack = &mut self.slots[slot];
+            if stack.is_empty() {
+                continue;
+            }
+
+            let remainder = crafting_remainder(stack.item);
+            shrink(stack, 1);
+
+            if let Some(remainder) = remainder {
+                let remainder = ItemStack::new(remainder, 1, None);
+                if self.slots[slot].is_empty() {
+                    self.slots[slot] = remainder;
+                } else {
+                    self.give(remainder);
+                }
+            }
+        }
+
+        self.update_result();
+        result
+    }
+
+    /// Puts `stack` in the player's inventory, or drops it if it doesn't fit.
+    fn give(&mut self, stack: ItemStack) {
+        let start = self.player_start();
+        let left = insert_stack(&mut self.slots[start..start + PLAYER_MAIN_SLOTS], stack);
+
+        if !left.is_empty() {
+            self.leftovers.push(left);
+        }
+    }
+
+    fn is_armor(&self, slot: usize) -> bool {
+        self.kind == InventoryKind::Player && (5..9).contains(&slot)
+    }
+
+    /// Whether `stack` can be put in `slot`.
+    fn may_place(&self, slot: usize, stack: &ItemStack) -> bool {
+        if self.is_output(slot) {
+            return false;
+        }
+
+        !self.is_armor(slot) || armor_slot(stack.item) == Some(slot - 5)
+    }
+
+    /// The most items of `stack` that fit in `slot`.
+    fn slot_limit(&self, slot: usize, stack: &ItemStack) -> i8 {
+        if self.is_armor(slot) {
+            1
+        } else {
+            stack.item.max_stack()
+        }
+    }
+
+    fn drop_cursor(&mut self, button: i8) -> Option<Dropped> {
+        if self.cursor.is_empty() {
+            return None;
+        }
+
+        let count = match button {
+            0 => self.cursor.count,
+            1 => 1,
+            _ => return None,
+        };
+
+        Some(Dropped {
+            slot: None,
+            stack: split(&mut self.cursor, count),
+        })
+    }
+
+    /// A left or right click on a slot.
+    fn pickup(&mut self, slot: usize, right: bool) {
+        let stack = &self.slots[slot];
+
+        // The whole result is taken, as long as it fits on the cursor.
+        if self.is_result(slot) {
+            if stack.is_empty() {
+                return;
+            }
+
+            if self.cursor.is_empty() {
+                self.cursor = self.take_result();
+            } else if stacks_with(&self.cursor, stack)
+                && i32::from(self.cursor.count) + i32::from(stack.count)
+                    <= i32::from(self.cursor.item.max_stack())
+            {
+                self.cursor.count += self.take_result().count;
+            }
+            return;
+        }
+
+        if stack.is_empty() {
+            if !self.cursor.is_empty() && self.may_place(slot, &self.cursor) {
+                let count = if right { 1 } else { self.cursor.count };
+                self.place(slot, count);
+            }
+        } else if self.cursor.is_empty() {
+            // Half rounded up, without overflowing on stacks of 127.
+            let count = if right {
+                stack.count - stack.count / 2
+            } else {
+                stack.count
+            };
+            self.cursor = split(&mut self.slots[slot], count);
+        } else if self.may_place(slot, &self.cursor) {
+            if stacks_with(stack, &self.cursor) {
+                let count = if right { 1 } else { self.cursor.count };
+                self.place(slot, count);
+            } else if self.cursor.count <= self.slot_limit(slot, &self.cursor) {
+                mem::swap(&mut self.slots[slot], &mut self.cursor);
+            }
+        } else if stacks_with(stack, &self.cursor) {
+            // Output slots can still be taken from onto a matching cursor.
+            let total = i32::from(self.cursor.count) + i32::from(stack.count);
+            if total <= i32::from(self.cursor.item.max_stack()) {
+                self.cursor.count = total as i8;
+                self.slots[slot] = ItemStack::EMPTY;
+            }
+        }
+    }
+
+    /// Moves up to `count` items from the cursor into `slot`.
+    fn place(&mut self, slot: usize, count: i8) {
+        let existing = if self.slots[slot].is_empty() {
+            0
+        } else {
+            self.slots[slot].count
+        };
+        let room = self.slot_limit(slot, &self.cursor) - existing;
+        let count = count.min(self.cursor.count).min(room);
+
+        if count > 0 {
+            let placed = split(&mut self.cursor, count);
+            self.slots[slot] = placed.with_count(existing + count);
+        }
+    }
+
+    /// A shift click, which moves the stack to the other part of the window.
+    fn quick_move(&mut self, slot: usize) {
+        let stack = &self.slots[slot];
+        if stack.is_empty() {
+            return;
+        }
+
+        let main = self.player_start();
+        let hotbar = main + 27;
+        let end = main + PLAYER_MAIN_SLOTS;
+
+        if self.is_result(slot) {
+            self.craft_all(main..end);
+            return;
+        }
+
+        if self.kind != InventoryKind::Player {
+            if slot < main {
+                self.move_to(slot, main..end, true);
+            } else {
+                self.move_to(slot, 0..main, false);
+            }
+            return;
+        }
+
+        // Armor is put on if nothing is worn in its slot yet.
+        let free_armor = armor_slot(stack.item)
+            .map(|armor| armor + 5)
+            .filter(|&armor| slot >= main && self.slots[armor].is_empty());
+
+        if let Some(armor) = free_armor {
+            self.move_to(slot, armor..armor + 1, false);
+            return;
+        }
+
+        match slot {
+            0 => self.move_to(slot, main..end, true),
+            1..=8 => self.move_to(slot, main..end, false),
+            _ if slot < hotbar => self.move_to(slot, hotbar..end, false),
+            _ if slot < end => self.move_to(slot, main..hotbar, false),
+            _ => self.move_to(slot, main..end, false),
+        }
+    }
+
+    /// Crafts the result of the crafting grid into `targets` for as long as
+    /// the grid makes the same item and the whole result fits.
+    fn craft_all(&mut self, targets: Range<usize>) {
+        let item = self.slots[0].item;
+
+        while !self.slots[0].is_empty() && self.slots[0].item == item {
+            let before = self.slots.clone();
+            self.move_to(0, targets.clone(), true);
+
+            if !self.slots[0].is_empty() {
+                self.slots = before;
+                return;
+            }
+
+            self.slots[0] = before[0].clone();
+            self.take_result();
+        }
+    }
+
+    /// Moves as much of the stack in `from` as fits into `targets`, topping
+    /// up matching stacks before filling empty slots.
+    fn move_to(&mut self, from: usize, targets: Range<usize>, reverse: bool) {
+        let mut stack = mem::replace(&mut self.slots[from], ItemStack::EMPTY);
+
+        let targets: Vec<usize> = if reverse {
+            targets.rev().collect()
+        } else {
+            targets.collect()
+        };
+
+        for &slot in &targets {
+            if stack.is_empty() {
+                break;
+            }
+
+            if slot != from && stacks_with(&self.slots[slot], &stack) {
+                let room = self.slot_limit(slot, &stack) - self.slots[slot].count;
+                let count = room.min(stack.count);
+                if count > 0 {
+                    self.slots[slot].count += count;
+                    shrink(&mut stack, count);
+                }
+            }
+        }
+
+        for &slot in &targets {
+            if stack.is_empty() {
+                break;
+            }
+
+            if slot != from && self.slots[slot].is_empty() && self.may_place(slot, &stack) {
+                let count = stack.count.min(self.slot_limit(slot, &stack));
+                self.slots[slot] = split(&mut stack, count);
+            }
+        }
+
+        self.slots[from] = stack;
+    }
+
+    /// The slot swapped with by a number key, or the offhand key.
+    fn swap_target(&self, button: i8) -> Option<usize> {
+        match button {
+            0..=8 => Some(self.player_start() + 27 + button as usize),
+            40 if self.kind == InventoryKind::Player => Some(self.slots.len() - 1),
+            _ => None,
+        }
+    }
+
+    fn swap(&mut self, slot: usize, target: usize) {
+        let (stack, other) = (&self.slots[slot], &self.slots[target]);
+
+        // The result can only be swapped into an empty slot.
+        if self.is_result(slot) {
+            if !stack.is_empty() && other.is_empty() {
+                self.slots[target] = self.take_result();
+            }
+            return;
+        }
+
+        if slot == target || (stack.is_empty() && other.is_empty()) {
+            return;
+        }
+
+        if other.is_empty() {
+            self.slots.swap(slot, target);
+        } else if self.may_place(slot, other) {
+            let limit = self.slot_limit(slot, other);
+
+            if stack.is_empty() {
+                let count = other.count.min(limit);
+                self.slots[slot] = split(&mut self.slots[target], count);
+            } else if other.count <= limit {
+                self.slots.swap(slot, target);
+            }
+        }
+    }
+
+    fn throw(&mut self, slot: usize, button: i8) -> Option<Dropped> {
+        if !self.cursor.is_empty() || self.slots[slot].is_empty() {
+            return None;
+        }
+
+        let count = match button {
+            _ if self.is_result(slot) => {
+                return Some(Dropped {
+                    slot: Some(slot),
+                    stack: self.take_result(),
+                });
+            }
+            0 => 1,
+            1 => self.slots[slot].count,
+            _ => return None,
+        };
+
+        Some(Dropped {
+            slot: Some(slot),
+            stack: split(&mut self.slots[slot], count),
+        })
+    }
+
+    fn drag(&mut self, click: Click, drag: &mut Option<Drag>, creative: bool) {
+        let kind = match (click.button >> 2) & 3 {
+            0 => DragKind::Even,
+            1 => DragKind::One,
+            2 if creative => DragKind::Clone,
+            _ => {
+                *drag = None;
+                return;
+            }
+        };
+
+        match click.button & 3 {
+            0 => {
+                *drag = (!self.cursor.is_empty()).then(|| Drag {
+                    kind,
+                    slots: vec![],
+                });
+            }
+            1 => {
+                let Some(current) = drag else {
+                    return;
+                };
+
+                let Some(slot) = self.slot_index(click.slot) else {
+                    return;
+                };
+
+                let room = current.kind == DragKind::Clone
+                    || self.cursor.count as usize > current.slots.len();
+
+                if room && self.can_drag_to(slot) && !current.slots.contains(&slot) {
+                    current.slots.push(slot);
+                }
+            }
+            2 => {
+                let Some(current) = drag.take() else {
+                    return;
+                };
+
+                match *current.slots {
+                    [] => {}
+                    // Dragging over a single slot is just a click.
+                    [slot] => self.pickup(slot, current.kind != DragKind::Even),
+                    _ => self.spread(&current),
+                }
+            }
+            _ => *drag = None,
+        }
+    }
+
+    fn can_drag_to(&self, slot: usize) -> bool {
+        let stack = &self.slots[slot];
+        self.may_place(slot, &self.cursor) && (stack.is_empty() || stacks_with(stack, &self.cursor))
+    }
+
+    /// Ends a drag over several slots by spreading the cursor over them.
+    fn spread(&mut self, drag: &Drag) {
+        let cursor = self.cursor.clone();
+        let mut remaining = i32::from(cursor.count);
+
+        for &slot in &drag.slots {
+            if !self.can_drag_to(slot) {
+                continue;
+            }
+
+            let existing = if self.slots[slot].is_empty() {
+                0
+            } else {
+                i32::from(self.slots[slot].count)
+            };
+
+            let added = match drag.kind {
+                DragKind::Even => i32::from(cursor.count) / drag.slots.len() as i32,
+                DragKind::One => 1,
+                DragKind::Clone => i32::from(cursor.item.max_stack()),
+            };
+
+            // Slots already over the limit, which creative players can make,
+            // are left as they are.
+            let limit = i32::from(self.slot_limit(slot, &cursor));
+            let count = (existing + added).min(limit).max(existing);
+
+            if drag.kind != DragKind::Clone {
+                remaining -= count - existing;
+            }
+            self.slots[slot] = cursor.clone().with_count(count as i8);
+        }
+
+        self.cursor.count = remaining as i8;
+        if self.cursor.is_empty() {
+            self.cursor = ItemStack::EMPTY;
+        }
+    }
+
+    /// A double click, which gathers items matching the cursor into it. Stacks
+    /// that aren't full are taken from first.
+    fn pickup_all(&mut self, slot: usize, forward: bool) {
+        if self.cursor.is_empty() || !self.slots[slot].is_empty() {
+            return;
+        }
+
+        let max = self.cursor.item.max_stack();
+
+        let order: Vec<usize> = if forward {
+            (0..self.slots.len()).collect()
+        } else {
+            (0..self.slots.len()).rev().collect()
+        };
+
+        for full_stacks in [false, true] {
+            for &slot in &order {
+                if self.cursor.count >= max {
+                    return;
+                }
+
+                let stack = &self.slots[slot];
+                if !stacks_with(stack, &self.cursor)
+                    || self.is_output(slot)
+                    || (!full_stacks && stack.count == stack.item.max_stack())
+                {
+                    continue;
+                }
+
+                let count = stack.count.min(max - self.cursor.count);
+                self.cursor.count += count;
+                shrink(&mut self.slots[slot], count);
+            }
+        }
+    }
+}
+
+/// Whether `stack` holds the same item as `other`, so they can be merged.
+fn stacks_with(stack: &ItemStack, other: &ItemStack) -> bool {
+    !stack.is_empty() && stack.item == other.item && stack.nbt == other.nbt
+}
+
+fn shrink(stack: &mut ItemStack, count: i8) {
+    stack.count -= count;
+    if stack.count <= 0 {
+        *stack = ItemStack::EMPTY;
+    }
+}
+
+/// Takes up to `count` items off `stack`.
+fn split(stack: &mut ItemStack, count: i8) -> ItemStack {
+    let count = count.min(stack.count);
+    let taken = stack.clone().with_count(count);
+    shrink(stack, count);
+    taken
+}
+
+/// Which armor slot `item` is worn in, from the head down.
+fn armor_slot(item: ItemKind) -> Option<usize> {
+    let name = item.to_str();
+
+    if name.ends_with("_helmet")
+        || name.ends_with("_head")
+        || name.ends_with("_skull")
+        || item == ItemKind::CarvedPumpkin
+    {
+        Some(0)
+    } else if name.ends_with("_chestplate") || item == ItemKind::Elytra {
+        Some(1)
+    } else if name.ends_with("_leggings") {
+        Some(2)
+    } else if name.ends_with("_boots") {
+        Some(3)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use valence_protocol::ident;
+    use valence_protocol::packets::play::synchronize_recipes_s2c::CraftingShapedCategory;
+
+    use super::*;
+    use crate::recipe::{Ingredient, Recipe, RecipeKind};
+
+    fn stack(item: ItemKind, count: i8) -> ItemStack {
+        ItemStack::new(item, count, None)
+    }
+
+    fn player_window() -> Window<'static> {
+        Window::new(
+            InventoryKind::Player,
+            vec![ItemStack::EMPTY; InventoryKind::Player.slot_count()],
+            ItemStack::EMPTY,
+        )
+    }
+
+    fn click(window: &mut Window, slot: i16, button: i8, mode: ClickMode) -> Option<Dropped> {
+        window.click(Click { slot, button, mode }, &mut None, false)
+    }
+
+    #[test]
+    fn clicks_pick_up_place_and_swap() {
+        let mut window = player_window();
+        window.slots[9] = stack(ItemKind::Stone, 33);
+
+        // Right clicking takes half, rounded up.
+        click(&mut window, 9, 1, ClickMode::Click);
+        assert_eq!(window.cursor, stack(ItemKind::Stone, 17));
+        assert_eq!(window.slots[9], stack(ItemKind::Stone, 16));
+
+        // Right clicking an empty slot places one.
+        click(&mut window, 10, 1, ClickMode::Click);
+        assert_eq!(window.slots[10], stack(ItemKind::Stone, 1));
+
+        // Left clicking merges everything.
+        click(&mut window, 9, 0, ClickMode::Click);
+        assert_eq!(window.slots[9], stack(ItemKind::Stone, 32));
+        assert!(window.cursor.is_empty());
+
+        // Different items are swapped.
+        window.cursor = stack(ItemKind::Dirt, 5);
+        click(&mut window, 9, 0, ClickMode::Click);
+        assert_eq!(window.cursor, stack(ItemKind::Stone, 32));
+        assert_eq!(window.slots[9], stack(ItemKind::Dirt, 5));
+
+        // Only armor goes in armor slots, and nothing goes in the result slot.
+        click(&mut window, 5, 0, ClickMode::Click);
+        click(&mut window, 0, 0, ClickMode::Click);
+        assert_eq!(window.cursor, stack(ItemKind::Stone, 32));
+        assert!(window.slots[0].is_empty() && window.slots[5].is_empty());
+
+        // Clicking outside drops the cursor.
+        assert_eq!(
+            click(&mut window, OUTSIDE, 0, ClickMode::Click),
+            Some(Dropped {
+                slot: None,
+                stack: stack(ItemKind::Stone, 32),
+            })
+        );
+        assert!(window.cursor.is_empty());
+
+        // The largest stacks can be halved too.
+        window.slots[9] = stack(ItemKind::Stone, 127);
+        click(&mut window, 9, 1, ClickMode::Click);
+        assert_eq!(window.cursor, stack(ItemKind::Stone, 64));
+        assert_eq!(window.slots[9], stack(ItemKind::Stone, 63));
+    }
+
+    #[test]
+    fn shift_clicks_move_between_hotbar_and_main_inventory() {
+        let mut window = player_window();
+        window.slots[36] = stack(ItemKind::Stone, 40);
+        window.slots[20] = stack(ItemKind::Stone, 60);
+
+        // Existing stacks are topped up before empty slots are used.
+        click(&mut window, 36, 0, ClickMode::ShiftClick);
+        assert_eq!(window.slots[20], stack(ItemKind::Stone, 64));
+        assert_eq!(window.slots[9], stack(ItemKind::Stone, 36));
+        assert!(window.slots[36].is_empty());
+
+        click(&mut window, 9, 0, ClickMode::ShiftClick);
+        assert_eq!(window.slots[36], stack(ItemKind::Stone, 36));
+
+        // Armor is put on.
+        window.slots[12] = stack(ItemKind::IronBoots, 1);
+        click(&mut window, 12, 0, ClickMode::ShiftClick);
+        assert_eq!(window.slots[8], stack(ItemKind::IronBoots, 1));
+
+        // Number keys swap with the hotbar.
+        window.slots[15] = stack(ItemKind::Dirt, 3);
+        click(&mut window, 15, 0, ClickMode::Hotbar);
+        assert_eq!(window.slots[15], stack(ItemKind::Stone, 36));
+        assert_eq!(window.slots[36], stack(ItemKind::Dirt, 3));
+    }
+
+    #[test]
+    fn drags_spread_the_cursor() {
+        let mut window = player_window();
+        window.cursor = stack(ItemKind::Stone, 10);
+        window.slots[11] = stack(ItemKind::Stone, 2);
+        window.slots[12] = stack(ItemKind::Dirt, 1);
+
+        let mut drag = None;
+        for (slot, button) in [
+            (OUTSIDE, 0),
+            (9, 1),
+            (10, 1),
+            (11, 1),
+            (12, 1),
+            (OUTSIDE, 2),
+        ] {
+            window.click(
+                Click {
+                    slot,
+                    button,
+                    mode: ClickMode::Drag,
+                },
+                &mut drag,
+                false,
+            );
+        }
+
+        // The dirt slot isn't dragged over, so each of the three others gets 3.
+        assert_eq!(window.slots[9], stack(ItemKind::Stone, 3));
+        assert_eq!(window.slots[10], stack(ItemKind::Stone, 3));
+        assert_eq!(window.slots[11], stack(ItemKind::Stone, 5));
+        assert_eq!(window.slots[12], stack(ItemKind::Dirt, 1));
+        assert_eq!(window.cursor, stack(ItemKind::Stone, 1));
+        assert_eq!(drag, None);
+
+        // Slots over the limit don't give items back to the cursor.
+        window.cursor = stack(ItemKind::Stone, 10);
+        window.slots[9] = stack(ItemKind::Stone, 127);
+        window.slots[10] = ItemStack::EMPTY;
+        for (slot, button) in [(OUTSIDE, 0), (9, 1), (10, 1), (OUTSIDE, 2)] {
+            window.click(
+                Click {
+                    slot,
+                    button,
+                    mode: ClickMode::Drag,
+                },
+                &mut drag,
+                false,
+            );
+        }
+        assert_eq!(window.slots[9], stack(ItemKind::Stone, 127));
+        assert_eq!(window.slots[10], stack(ItemKind::Stone, 5));
+        assert_eq!(window.cursor, stack(ItemKind::Stone, 5));
+
+        // Another click cancels a drag.
+        window.click(
+            Click {
+                slot: OUTSIDE,
+                button: 4,
+                mode: ClickMode::Drag,
+            },
+            &mut drag,
+            false,
+        );
+        assert!(drag.is_some());
+        window.click(
+            Click {
+                slot: 9,
+                button: 0,
+                mode: ClickMode::ShiftClick,
+            },
+            &mut drag,
+            false,
+        );
+        assert_eq!(drag, None);
+    }
+
+    #[test]
+    fn double_clicks_gather_and_drop_keys_throw() {
+        let mut window = player_window();
+        window.cursor = stack(ItemKind::Stone, 1);
+        window.slots[9] = stack(ItemKind::Stone, 64);
+        window.slots[10] = stack(ItemKind::Stone, 10);
+        window.slots[11] = stack(ItemKind::Stone, 60);
+
+        // Partial stacks are emptied first.
+        click(&mut window, 12, 0, ClickMode::DoubleClick);
+        assert_eq!(window.cursor, stack(ItemKind::Stone, 64));
+        assert_eq!(window.slots[9], stack(ItemKind::Stone, 64));
+        assert!(window.slots[10].is_empty());
+        assert_eq!(window.slots[11], stack(ItemKind::Stone, 7));
+
+        // Nothing is thrown while the cursor holds something.
+        assert_eq!(click(&mut window, 9, 0, ClickMode::DropKey), None);
+
+        window.cursor = ItemStack::EMPTY;
+        assert_eq!(
+            click(&mut window, 9, 0, ClickMode::DropKey),
+            Some(Dropped {
+                slot: Some(9),
+                stack: stack(ItemKind::Stone, 1),
+            })
+        );
+        assert_eq!(
+            click(&mut window, 11, 1, ClickMode::DropKey),
+            Some(Dropped {
+                slot: Some(11),
+                stack: stack(ItemKind::Stone, 7),
+            })
+        );
+        assert!(window.slots[11].is_empty());
+    }
+
+    #[test]
+    fn taking_the_result_uses_up_the_ingredients() {
+        let mut recipes = RecipeRegistry::default();
+        recipes.insert(Recipe {
+            id: ident!("oak_planks").into(),
+            group: String::new(),
+            kind: RecipeKind::Shapeless {
+                category: CraftingShapedCategory::Building,
+                ingredients: vec![Ingredient(vec![ItemKind::OakLog])],
+                result: stack(ItemKind::OakPlanks, 4),
+            },
+        });
+
+        let mut window = player_window().with_recipes(&recipes);
+        window.cursor = stack(ItemKind::OakLog, 3);
+
+        // Placing the ingredient fills in the result.
+        click(&mut window, 4, 0, ClickMode::Click);
+        assert_eq!(window.slots[0], stack(ItemKind::OakPlanks, 4));
+
+        // Right clicking the result takes all of it.
+        click(&mut window, 0, 1, ClickMode::Click);
+        assert_eq!(window.cursor, stack(ItemKind::OakPlanks, 4));
+        assert_eq!(window.slots[4], stack(ItemKind::OakLog, 2));
+
+        // A full cursor can't take any more, even past the stack size.
+        window.cursor = stack(ItemKind::OakPlanks, 127);
+        click(&mut window, 0, 0, ClickMode::Click);
+        assert_eq!(window.cursor, stack(ItemKind::OakPlanks, 127));
+        assert_eq!(window.slots[4], stack(ItemKind::OakLog, 2));
+        window.cursor = stack(ItemKind::OakPlanks, 4);
+
+        // Shift clicking crafts as many as possible.
+        click(&mut window, 0, 0, ClickMode::ShiftClick);
+        assert_eq!(window.slots[44], stack(ItemKind::OakPlanks, 8));
+        assert!(window.slots[0].is_empty() && window.slots[4].is_empty());
+    }
+}
@@ -0,0 +1,363 @@
+//! Crafting grids.
+//!
+//! Player inventories have a 2x2 crafting grid in slots 1 to 4 and
+//! [`InventoryKind::Crafting`] inventories a 3x3 one in slots 1 to 9, with the
+//! result in slot 0. The result is worked out by the server from the
+//! [`RecipeRegistry`] whenever a click changes the grid, and taking it uses up
+//! the ingredients.
+//!
+//! Clients can also ask for a recipe of their recipe book to be laid out in
+//! the grid with a [`CraftRequestC2s`]. The ingredients are taken from the
+//! player's inventory, or the client is told which are missing. Recipes the
+//! player hasn't unlocked are ignored.
+
+use std::ops::Range;
+
+use evenio::prelude::*;
+use tracing::debug;
+use valence_protocol::packets::play::{CraftFailedResponseS2c, CraftRequestC2s};
+use valence_protocol::{ItemStack, WritePacket};
+
+use super::{insert_stack, ClientInventoryState, Inventory, InventoryKind, PLAYER_WINDOW_SLOTS};
+use crate::client::Client;
+use crate::event_loop::PacketEvent;
+use crate::recipe::book::RecipeBook;
+use crate::recipe::{Ingredient, Recipe, RecipeKind, RecipeRegistry};
+
+/// The most crafts laid out in the grid at once, when the client asks for as
+/// many as possible.
+const MAX_CRAFTS: usize = 64;
+
+impl InventoryKind {
+    /// The slots of the crafting grid of inventories of this kind and how
+    /// wide it is, if they have one.
+    pub const fn crafting_grid(self) -> Option<(Range<usize>, usize)> {
+        match self {
+            Self::Player => Some((1..5, 2)),
+            Self::Crafting => Some((1..10, 3)),
+            _ => None,
+        }
+    }
+}
+
+impl Inventory {
+    /// Puts what the crafting grid makes in its result slot. This is done
+    /// after every click, so it's only needed after setting the slots of the
+    /// grid directly.
+    pub fn update_crafting_result(&mut self, recipes: &RecipeRegistry) {
+        update_result(self.kind, &mut self.slots, recipes);
+    }
+
+    /// Empties the crafting grid and its result slot, returning the
+    /// ingredients that were in it.
+    pub(super) fn take_crafting_grid(&mut self) -> Vec<ItemStack> {
+        let Some((grid, _)) = self.kind.crafting_grid() else {
+            return vec![];
+        };
+
+        self.slots[0] = ItemStack::EMPTY;
+        self.slots[grid]
+            .iter_mut()
+            .map(|slot| std::mem::replace(slot, ItemStack::EMPTY))
+            .filter(|stack| !stack.is_empty())
+            .collect()
+    }
+}
+
+/// Puts what the crafting grid in `slots` makes in its result slot, if
+/// inventories of `kind` have a crafting grid.
+pub(super) fn update_result(
+    kind: InventoryKind,
+    slots: &mut [ItemStack],
+    recipes: &RecipeRegistry,
+) {
+    let Some((grid, width)) = kind.crafting_grid() else {
+        return;
+    };
+
+    slots[0] = recipes
+        .craft(&slots[grid], width)
+        .and_then(Recipe::crafting_result)
+        .cloned()
+        .unwrap_or(ItemStack::EMPTY);
+}
+
+/// Lays out a recipe from the recipe book in the crafting grid the client has
+/// open, once or as many times as possible.
+pub fn handle_craft_request(
+    r: Receiver<
+        PacketEvent<CraftRequestC2s<'static>>,
+        (&mut Client, &ClientInventoryState, &RecipeBook),
+    >,
+    mut inventories: Fetcher<&mut Inventory>,
+    recipes: Single<&RecipeRegistry>,
+) {
+    let (client, state, book) = r.query;
+    let event = r.event;
+    let packet = &event.packet;
+    let recipes = recipes.0;
+
+    if packet.window_id as u8 != state.window_id {
+        return;
+    }
+
+    let Some(recipe) = recipes.get(packet.recipe.as_str()) else {
+        debug!(client = ?event.client, recipe = packet.recipe.as_str(), "unknown recipe requested");
+        return;
+    };
+
+    if !book.is_unlocked(packet.recipe.as_str()) {
+        debug!(client = ?event.client, recipe = packet.recipe.as_str(), "locked recipe requested");
+        return;
+    }
+
+    let top = state.open.unwrap_or(event.client);
+    let (mut grid, width, crafts) = match inventories.get(top) {
+        Ok(inventory) if !inventory.readonly => {
+            let Some((grid, width)) = inventory.kind.crafting_grid() else {
+                return;
+            };
+            let grid = inventory.slots[grid].to_vec();
+
+            // Asking for the recipe already in the grid adds another craft.
+            let crafts = match recipes.craft(&grid, width) {
+                Some(current) if current.id == recipe.id => grid
+                    .iter()
+                    .filter(|stack| !stack.is_empty())
+                    .map(|stack| stack.count as usize)
+                    .min()
+                    .unwrap_or(0),
+                _ => 0,
+            };
+
+            (grid, width, crafts)
+        }
+        _ => return,
+    };
+
+    let Ok(player) = inventories.get(event.client) else {
+        return;
+    };
+    let mut main = player.slots[PLAYER_WINDOW_SLOTS].to_vec();
+
+    // What's in the grid goes back first, and the grid stays as it is if it
+    // doesn't fit.
+    for stack in grid.iter_mut() {
+        let stack = std::mem::replace(stack, ItemStack::EMPTY);
+        if !insert_stack(&mut main, stack).is_empty() {
+            return;
+        }
+    }
+
+    let rounds = if packet.make_all {
+        MAX_CRAFTS
+    } else {
+        crafts + 1
+    };
+
+    if !place_recipe(recipe, &mut grid, width, &mut main, rounds) {
+        client.write_packet(&CraftFailedResponseS2c {
+            window_id: state.window_id,
+            recipe: packet.recipe.clone(),
+        });
+        return;
+    }
+
+    if let Ok(inventory) = inventories.get_mut(top) {
+        if let Some((range, _)) = inventory.kind.crafting_grid() {
+            inventory.slots[range].clone_from_slice(&grid);
+            inventory.update_crafting_result(recipes);
+        }
+    }
+    if let Ok(player) = inventories.get_mut(event.client) {
+        player.slots[PLAYER_WINDOW_SLOTS].clone_from_slice(&main);
+    }
+}
+
+/// Moves the ingredients of up to `rounds` crafts of `recipe` from `main` into
+/// an empty crafting `grid` `width` slots wide. Returns whether there were
+/// enough for at least one.
+fn place_recipe(
+    recipe: &Recipe,
+    grid: &mut [ItemStack],
+    width: usize,
+    main: &mut [ItemStack],
+    rounds: usize,
+) -> bool {
+    let height = grid.len() / width;
+
+    let cells: Vec<(usize, &Ingredient)> = match &recipe.kind {
+        RecipeKind::Shaped {
+            width: pattern_width,
+            height: pattern_height,
+            ingredients,
+            ..
+        } if *pattern_width <= width && *pattern_height <= height => ingredients
+            .iter()
+            .enumerate()
+            .filter(|(_, ingredient)| !ingredient.is_empty())
+            .map(|(idx, ingredient)| {
+                let (x, y) = (idx % pattern_width, idx / pattern_width);
+                (y * width + x, ingredient)
+            })
+            .collect(),
+        RecipeKind::Shapeless { ingredients, .. } if ingredients.len() <= grid.len() => {
+            ingredients.iter().enumerate().collect()
+        }
+        _ => return false,
+    };
+
+    let mut placed = 0;
+    while placed < rounds {
+        let (grid_before, main_before) = (grid.to_vec(), main.to_vec());
+
+        if !cells
+            .iter()
+            .all(|&(cell, ingredient)| take_ingredient(ingredient, &mut grid[cell], main))
+        {
+            grid.clone_from_slice(&grid_before);
+            main.clone_from_slice(&main_before);
+            break;
+        }
+
+        placed += 1;
+    }
+
+    placed > 0
+}
+
+/// Moves one item for `ingredient` from `main` onto the stack in `cell`.
+fn take_ingredient(ingredient: &Ingredient, cell: &mut ItemStack, main: &mut [ItemStack]) -> bool {
+    let found = main.iter().position(|stack| {
+        ingredient.test(stack)
+            && (cell.is_empty()
+                || (stack.item == cell.item
+                    && stack.nbt == cell.nbt
+                    && cell.count < cell.item.max_stack()))
+    });
+
+    let Some(idx) = found else {
+        return false;
+    };
+
+    let count = if cell.is_empty() { 0 } else { cell.count };
+    *cell = main[idx].clone().with_count(count + 1);
+
+    main[idx].count -= 1;
+    if main[idx].is_empty() {
+        main[idx] = ItemStack::EMPTY;
+    }
+
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use valence_protocol::packets::play::synchronize_recipes_s2c::CraftingShapedCategory;
+    use valence_protocol::{ident, ItemKind};
+    use valence_server_common::UniqueId;
+
+    use super::*;
+    use crate::event::ClientJoinEvent;
+    use crate::recipe::book::{init_recipe_book, SavedRecipeBooks};
+    use crate::testing::{self, packet_event};
+
+    fn torch() -> Recipe {
+        Recipe {
+            id: ident!("torch").into(),
+            group: String::new(),
+            kind: RecipeKind::Shaped {
+                category: CraftingShapedCategory::Misc,
+                width: 1,
+                height: 2,
+                ingredients: vec![
+                    Ingredient(vec![ItemKind::Coal, ItemKind::Charcoal]),
+                    Ingredient(vec![ItemKind::Stick]),
+                ],
+                result: ItemStack::new(ItemKind::Torch, 4, None),
+                show_notification: true,
+            },
+        }
+    }
+
+    #[test]
+    fn recipes_are_laid_out_from_the_inventory() {
+        let mut main = vec![ItemStack::EMPTY; 36];
+        main[0] = ItemStack::new(ItemKind::Coal, 3, None);
+        main[5] = ItemStack::new(ItemKind::Stick, 2, None);
+        main[6] = ItemStack::new(ItemKind::Charcoal, 1, None);
+
+        let mut grid = vec![ItemStack::EMPTY; 9];
+        assert!(place_recipe(&torch(), &mut grid, 3, &mut main, MAX_CRAFTS));
+
+        // Coal and charcoal don't stack, so only the coal is used.
+        assert_eq!(grid[0], ItemStack::new(ItemKind::Coal, 2, None));
+        assert_eq!(grid[3], ItemStack::new(ItemKind::Stick, 2, None));
+        assert_eq!(main[0], ItemStack::new(ItemKind::Coal, 1, None));
+        assert!(main[5].is_empty());
+
+        let mut grid = vec![ItemStack::EMPTY; 4];
+        assert!(!place_recipe(&torch(), &mut grid, 2, &mut main, 1));
+        assert!(grid.iter().all(ItemStack::is_empty));
+    }
+
+    #[test]
+    fn the_result_follows_the_grid() {
+        let mut recipes = RecipeRegistry::default();
+        recipes.insert(torch());
+
+        let mut inventory = Inventory::new(InventoryKind::Player);
+        inventory.set_slot(2, ItemStack::new(ItemKind::Charcoal, 1, None));
+        inventory.set_slot(4, ItemStack::new(ItemKind::Stick, 1, None));
+        inventory.update_crafting_result(&recipes);
+        assert_eq!(*inventory.slot(0), ItemStack::new(ItemKind::Torch, 4, None));
+
+        assert_eq!(inventory.take_crafting_grid().len(), 2);
+        assert!(inventory.slot(0).is_empty());
+    }
+    #[test]
+    fn joining_players_can_craft_every_recipe() {
+        let mut world = World::new();
+        world.add_handler(init_recipe_book);
+        world.add_handler(handle_craft_request);
+
+        let mut recipes = RecipeRegistry::default();
+        recipes.insert(torch());
+
+        let server = world.spawn();
+        world.insert(server, recipes);
+        world.insert(server, SavedRecipeBooks::default());
+
+        let mut inventory = Inventory::new(InventoryKind::Player);
+        inventory.set_slot(9, ItemStack::new(ItemKind::Coal, 1, None));
+        inventory.set_slot(10, ItemStack::new(ItemKind::Stick, 1, None));
+
+        let client = world.spawn();
+        world.insert(client, testing::client());
+        world.insert(client, UniqueId::default());
+        world.insert(client, RecipeBook::default());
+        world.insert(client, ClientInventoryState::new());
+        world.insert(client, inventory);
+
+        world.send(ClientJoinEvent { entity: client });
+        assert!(world
+            .get::<RecipeBook>(client)
+            .unwrap()
+            .is_unlocked("minecraft:torch"));
+
+        world.send(packet_event(
+            client,
+            CraftRequestC2s {
+                window_id: 0,
+                recipe: ident!("torch").into(),
+                make_all: false,
+            },
+        ));
+
+        let inventory = world.get::<Inventory>(client).unwrap();
+        assert_eq!(*inventory.slot(1), ItemStack::new(ItemKind::Coal, 1, None));
+        assert_eq!(*inventory.slot(3), ItemStack::new(ItemKind::Stick, 1, None));
+        assert_eq!(*inventory.slot(0), ItemStack::new(ItemKind::Torch, 4, None));
+        assert!(inventory.slot(9).is_empty());
+    }
+}
@@ -0,0 +1,188 @@
+//! The creative mode inventory.
+//!
+//! In creative mode the client decides what is in the slots of its own
+//! inventory, and sends a [`CreativeInventoryActionC2s`] with the new stack
+//! of every slot it changes. Items taken out of the creative menu and dropped
+//! are sent the same way with a negative slot.
+//!
+//! The stacks are checked before they're accepted, so that there are no more
+//! items in a stack than the game allows and no more NBT than fits in a
+//! packet. A book with 100 full pages is still a legal item.
+
+use std::time::{Duration, Instant};
+
+use evenio::prelude::*;
+use tracing::debug;
+use valence_protocol::packets::play::CreativeInventoryActionC2s;
+use valence_protocol::{Encode, GameMode, ItemStack, MAX_PACKET_SIZE};
+
+use super::{ClientInventoryState, DropItemStackEvent, Inventory};
+use crate::event_loop::PacketEvent;
+use crate::recipe::RecipeRegistry;
+
+/// The most bytes an item stack can take up encoded, NBT included. Like the
+/// game, this is as much as a packet can hold.
+pub const MAX_ITEM_BYTES: usize = MAX_PACKET_SIZE as usize;
+
+/// How many items a client can drop from the creative menu at once, like the
+/// game.
+const DROP_BURST: u32 = 10;
+
+/// How long it takes for a client to be able to drop another item from the
+/// creative menu once it used up the burst.
+const DROP_INTERVAL: Duration = Duration::from_secs(1);
+
+/// When the client can drop items from the creative menu again.
+#[derive(Component, Debug)]
+pub struct CreativeDropLimit {
+    /// When every drop so far would have been allowed by the interval alone.
+    next_drop: Instant,
+}
+
+impl CreativeDropLimit {
+    pub(crate) fn new() -> Self {
+        Self {
+            next_drop: Instant::now(),
+        }
+    }
+
+    /// Whether a drop at `now` is allowed, counting it if it is.
+    fn try_drop(&mut self, now: Instant) -> bool {
+        let next_drop = self.next_drop.max(now);
+
+        if next_drop - now >= DROP_INTERVAL * DROP_BURST {
+            return false;
+        }
+
+        self.next_drop = next_drop + DROP_INTERVAL;
+        true
+    }
+}
+
+#[derive(Query)]
+pub struct CreativeQuery<'a> {
+    inventory: &'a mut Inventory,
+    state: &'a mut ClientInventoryState,
+    drops: &'a mut CreativeDropLimit,
+    game_mode: &'a GameMode,
+}
+
+/// Puts the stack set in the creative menu in its slot, or drops it.
+pub fn handle_creative_inventory_action(
+    r: Receiver<PacketEvent<CreativeInventoryActionC2s>, CreativeQuery>,
+    recipes: Single<&RecipeRegistry>,
+    mut sender: Sender<DropItemStackEvent>,
+) {
+    let q = r.query;
+    let event = r.event;
+    let packet = &event.packet;
+
+    if *q.game_mode != GameMode::Creative {
+        debug!(client = ?event.client, "creative inventory action outside of creative mode");
+        q.state.resync = true;
+        return;
+    }
+
+    if let Err(reason) = check_stack(&packet.clicked_item) {
+        debug!(client = ?event.client, reason, "invalid creative item");
+        q.state.resync = true;
+        return;
+    }
+
+    if packet.slot < 0 {
+        if !packet.clicked_item.is_empty() && q.drops.try_drop(event.timestamp) {
+            sender.send(DropItemStackEvent {
+                client: event.client,
+                from_slot: None,
+                stack: packet.clicked_item.clone(),
+            });
+        }
+        return;
+    }
+
+    // Slot 0 holds the result of crafting, which can't be set.
+    let slot = packet.slot as u16;
+    if slot == 0 || slot >= q.inventory.slot_count() {
+        debug!(client = ?event.client, slot, "invalid creative slot");
+        q.state.resync = true;
+        return;
+    }
+
+    q.inventory.set_slot(slot, packet.clicked_item.clone());
+
+    // The client already shows the stack if its own inventory is open.
+    if q.state.open.is_none() {
+        if let Some(client_slot) = q.state.slots.get_mut(slot as usize) {
+            *client_slot = q.inventory.slot(slot).clone();
+        }
+    }
+
+    if q.inventory
+        .kind()
+        .crafting_grid()
+        .is_some_and(|(grid, _)| grid.contains(&(slot as usize)))
+    {
+        q.inventory.update_crafting_result(recipes.0);
+    }
+}
+
+/// Checks `stack` could have come from the creative menu.
+fn check_stack(stack: &ItemStack) -> Result<(), &'static str> {
+    if stack.is_empty() {
+        return Ok(());
+    }
+
+    if stack.count > stack.item.max_stack() {
+        return Err("too many items in the stack");
+    }
+
+    let mut buf = vec![];
+    if stack.encode(&mut buf).is_err() || buf.len() > MAX_ITEM_BYTES {
+        return Err("too much NBT");
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use valence_protocol::nbt::{compound, List};
+    use valence_protocol::ItemKind;
+
+    use super::*;
+
+    #[test]
+    fn oversized_stacks_are_rejected() {
+        assert!(check_stack(&ItemStack::EMPTY).is_ok());
+        assert!(check_stack(&ItemStack::new(ItemKind::Stone, 64, None)).is_ok());
+        assert!(check_stack(&ItemStack::new(ItemKind::Stone, 65, None)).is_err());
+        assert!(check_stack(&ItemStack::new(ItemKind::DiamondSword, 2, None)).is_err());
+
+        let page = "a".repeat(1024);
+        let book = |pages: usize| {
+            let nbt = compound! {
+                "pages" => List::String(vec![page.clone(); pages]),
+            };
+            ItemStack::new(ItemKind::WritableBook, 1, Some(nbt))
+        };
+
+        // The biggest book the game lets players write is fine.
+        assert!(check_stack(&book(100)).is_ok());
+        assert!(check_stack(&book(MAX_ITEM_BYTES / 1024)).is_err());
+    }
+
+    #[test]
+    fn drops_are_limited() {
+        let start = Instant::now();
+        let mut limit = CreativeDropLimit { next_drop: start };
+
+        for _ in 0..DROP_BURST {
+            assert!(limit.try_drop(start));
+        }
+        assert!(!limit.try_drop(start));
+
+        // Another drop is allowed every interval.
+        assert!(limit.try_drop(start + DROP_INTERVAL));
+        assert!(!limit.try_drop(start + DROP_INTERVAL));
+    }
+}
@@ -0,0 +1,780 @@
+//! Player inventories.
+//!
+//! Every client has an [`Inventory`] of [`InventoryKind::Player`], the item
+//! on its cursor in [`CursorItem`] and its selected hotbar slot in
+//! [`HeldItem`]. The server is the authority on all three: clicks are redone
+//! on the server's copy of the inventory, and the outcome the client predicted
+//! is only used to tell whether the client needs to be resynced.
+//!
+//! At the end of every tick, each client is sent the slots that changed since
+//! it was last told, with a state ID the client echoes back in its clicks so
+//! clicks made before an update can be recognised.
+//!
+//! Other inventories, like chests and furnaces, are entities of their own
+//! which clients can open in a [`screen`].
+//!
+//! The crafting grids of player inventories and of [`InventoryKind::Crafting`]
+//! inventories craft the recipes of the [`RecipeRegistry`], see [`crafting`].
+
+mod click;
+pub mod crafting;
+pub mod creative;
+pub mod screen;
+
+use std::borrow::Cow;
+use std::ops::Range;
+
+use derive_more::{Deref, DerefMut};
+use evenio::prelude::*;
+use tracing::debug;
+use valence_protocol::packets::play::click_slot_c2s::{ClickMode, SlotChange};
+use valence_protocol::packets::play::{
+    ClickSlotC2s, CloseScreenS2c, InventoryS2c, ScreenHandlerPropertyUpdateS2c,
+    ScreenHandlerSlotUpdateS2c, UpdateSelectedSlotC2s, UpdateSelectedSlotS2c,
+};
+use valence_protocol::{GameMode, ItemStack, VarInt, WritePacket};
+use valence_server_common::PostUpdate;
+
+use self::click::{Click, Drag, Dropped, Window};
+use self::screen::ScreenClosedEvent;
+use crate::client::Client;
+use crate::event_loop::PacketEvent;
+use crate::recipe::RecipeRegistry;
+
+/// The first hotbar slot of a player inventory.
+pub const PLAYER_HOTBAR_START: u16 = 36;
+/// The offhand slot of a player inventory.
+pub const PLAYER_OFFHAND_SLOT: u16 = 45;
+
+/// The slots of a player inventory shown below the inventory open in a
+/// screen: the main inventory and the hotbar.
+const PLAYER_WINDOW_SLOTS: Range<usize> = 9..45;
+
+/// More changed slots than this are sent as a whole [`InventoryS2c`] rather
+/// than one [`ScreenHandlerSlotUpdateS2c`] each.
+const MAX_SLOT_UPDATES: usize = 8;
+
+/// The most slot changes a click can report.
+const MAX_SLOT_CHANGES: usize = 128;
+
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub enum InventoryKind {
+    Generic9x1,
+    Generic9x2,
+    Generic9x3,
+    Generic9x4,
+    Generic9x5,
+    Generic9x6,
+    Generic3x3,
+    Anvil,
+    Beacon,
+    BlastFurnace,
+    BrewingStand,
+    Crafting,
+    Enchantment,
+    Furnace,
+    Grindstone,
+    Hopper,
+    Lectern,
+    Loom,
+    Merchant,
+    ShulkerBox,
+    Smithing,
+    Smoker,
+    Cartography,
+    Stonecutter,
+    Player,
+}
+
+impl InventoryKind {
+    /// The number of slots in inventories of this kind.
+    pub const fn slot_count(self) -> usize {
+        match self {
+            Self::Generic9x1 | Self::Generic3x3 => 9,
+            Self::Generic9x2 => 18,
+            Self::Generic9x3 | Self::ShulkerBox => 27,
+            Self::Generic9x4 => 36,
+            Self::Generic9x5 => 45,
+            Self::Generic9x6 => 54,
+            Self::Beacon | Self::Lectern => 1,
+            Self::Enchantment | Self::Stonecutter => 2,
+            Self::Anvil
+            | Self::BlastFurnace
+            | Self::Furnace
+            | Self::Grindstone
+            | Self::Merchant
+            | Self::Smoker
+            | Self::Cartography => 3,
+            Self::Loom | Self::Smithing => 4,
+            Self::BrewingStand | Self::Hopper => 5,
+            Self::Crafting => 10,
+            Self::Player => 46,
+        }
+    }
+
+    /// The slot holding the result of crafting, smelting and so on, which
+    /// items can be taken from but not put in.
+    pub const fn output_slot(self) -> Option<usize> {
+        match self {
+            Self::Player | Self::Crafting => Some(0),
+            Self::Stonecutter => Some(1),
+            Self::Anvil
+            | Self::BlastFurnace
+            | Self::Furnace
+            | Self::Grindstone
+            | Self::Merchant
+            | Self::Smoker
+            | Self::Cartography => Some(2),
+            Self::Loom | Self::Smithing => Some(3),
+            _ => None,
+        }
+    }
+}
+
+/// The items in an inventory.
+#[derive(Component, Clone, PartialEq, Debug)]
+pub struct Inventory {
+    kind: InventoryKind,
+    slots: Box<[ItemStack]>,
+    readonly: bool,
+    properties: Vec<i16>,
+}
+
+impl Inventory {
+    /// An empty inventory of `kind`.
+    pub fn new(kind: InventoryKind) -> Self {
+        Self {
+            kind,
+            slots: vec![ItemStack::EMPTY; kind.slot_count()].into(),
+            readonly: false,
+            properties: vec![],
+        }
+    }
+
+    pub fn kind(&self) -> InventoryKind {
+        self.kind
+    }
+
+    pub fn slot_count(&self) -> u16 {
+        self.slots.len() as u16
+    }
+
+    pub fn slots(&self) -> &[ItemStack] {
+        &self.slots
+    }
+
+    /// # Panics
+    ///
+    /// Panics if `idx` is out of bounds.
+    pub fn slot(&self, idx: u16) -> &ItemStack {
+        &self.slots[idx as usize]
+    }
+
+    /// Puts `stack` in slot `idx` and returns what was there.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `idx` is out of bounds.
+    pub fn set_slot(&mut self, idx: u16, stack: ItemStack) -> ItemStack {
+        let stack = if stack.is_empty() {
+            ItemStack::EMPTY
+        } else {
+            stack
+        };
+
+        std::mem::replace(&mut self.slots[idx as usize], stack)
+    }
+
+    /// Adds `stack` to the slots in `range`, topping up stacks of the same
+    /// item before filling empty slots. Returns what didn't fit.
+    pub fn insert(&mut self, stack: ItemStack, range: Range<u16>) -> ItemStack {
+        let end = (range.end as usize).min(self.slots.len());
+        let start = (range.start as usize).min(end);
+
+        insert_stack(&mut self.slots[start..end], stack)
+    }
+
+    /// Whether clicks leave the inventory as it is, for menus made of items.
+    pub fn is_readonly(&self) -> bool {
+        self.readonly
+    }
+
+    pub fn set_readonly(&mut self, readonly: bool) {
+        self.readonly = readonly;
+    }
+
+    /// The value of a property of the screen showing the inventory, like the
+    /// progress of a furnace. Properties that were never set are 0.
+    pub fn property(&self, idx: u16) -> i16 {
+        self.properties.get(idx as usize).copied().unwrap_or(0)
+    }
+
+    /// Sets a property of the screen showing the inventory. Clients with the
+    /// inventory open are sent the new value at the end of the tick.
+    pub fn set_property(&mut self, idx: u16, value: i16) {
+        let idx = idx as usize;
+        if idx >= self.properties.len() {
+            self.properties.resize(idx + 1, 0);
+        }
+
+        self.properties[idx] = value;
+    }
+
+    /// The first empty slot in `range`, if any.
+    pub fn first_empty_slot_in(&self, range: std::ops::Range<u16>) -> Option<u16> {
+        range.into_iter().find(|&idx| {
+            self.slots
+                .get(idx as usize)
+                .is_some_and(ItemStack::is_empty)
+        })
+    }
+}
+
+/// Adds `stack` to `slots`, topping up stacks of the same item before filling
+/// empty slots. Returns what didn't fit.
+fn insert_stack(slots: &mut [ItemStack], mut stack: ItemStack) -> ItemStack {
+    let max = stack.item.max_stack();
+
+    for slot in slots.iter_mut() {
+        if stack.is_empty() {
+            break;
+        }
+
+        if !slot.is_empty() && slot.item == stack.item && slot.nbt == stack.nbt {
+            let count = stack.count.min(max - slot.count).max(0);
+            slot.count += count;
+            stack.count -= count;
+        }
+    }
+
+    for slot in slots.iter_mut() {
+        if stack.is_empty() {
+            break;
+        }
+
+        if slot.is_empty() {
+            let count = stack.count.min(max);
+            *slot = stack.clone().with_count(count);
+            stack.count -= count;
+        }
+    }
+
+    if stack.is_empty() {
+        ItemStack::EMPTY
+    } else {
+        stack
+    }
+}
+
+/// The item a client holds with its cursor while its inventory is open.
+#[derive(Component, Clone, PartialEq, Default, Debug, Deref, DerefMut)]
+pub struct CursorItem(pub ItemStack);
+
+/// The hotbar slot a client has selected.
+#[derive(Component, Copy, Clone, PartialEq, Eq, Default, Debug)]
+pub struct HeldItem {
+    hotbar_idx: u8,
+}
+
+impl HeldItem {
+    /// The selected hotbar slot, from 0 to 8.
+    pub fn hotbar_idx(self) -> u8 {
+        self.hotbar_idx
+    }
+
+    /// Selects hotbar slot `idx`. The client is told at the end of the tick.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `idx` isn't from 0 to 8.
+    pub fn set_hotbar_idx(&mut self, idx: u8) {
+        assert!(idx < 9, "hotbar slot {idx} is out of bounds");
+        self.hotbar_idx = idx;
+    }
+
+    /// The slot of the player's [`Inventory`] holding the selected item.
+    pub fn slot(self) -> u16 {
+        PLAYER_HOTBAR_START + u16::from(self.hotbar_idx)
+    }
+}
+
+/// What a client was last told about its inventory.
+#[derive(Component, Debug)]
+pub struct ClientInventoryState {
+    window_id: u8,
+    /// The inventory open in a screen.
+    open: Option<EntityId>,
+    state_id: i32,
+    slots: Vec<ItemStack>,
+    cursor: ItemStack,
+    held: u8,
+    /// Whether the whole window is sent at the end of the tick, because the
+    /// client's idea of it can't be trusted.
+    resync: bool,
+    drag: Option<Drag>,
+    /// The properties of the screen the client was sent.
+    properties: Vec<i16>,
+}
+
+impl ClientInventoryState {
+    pub(crate) fn new() -> Self {
+        Self {
+            window_id: 0,
+            open: None,
+            state_id: 0,
+            slots: vec![ItemStack::EMPTY; InventoryKind::Player.slot_count()],
+            cursor: ItemStack::EMPTY,
+            held: 0,
+            // Clients are sent their whole inventory when they join.
+            resync: true,
+            drag: None,
+            properties: vec![],
+        }
+    }
+
+    /// The ID of the window the client has open, which is 0 for its own
+    /// inventory.
+    pub fn window_id(&self) -> u8 {
+        self.window_id
+    }
+
+    /// The inventory the client has open in a screen, if any.
+    pub fn open_inventory(&self) -> Option<EntityId> {
+        self.open
+    }
+
+    /// The state ID of the last inventory update sent to the client.
+    pub fn state_id(&self) -> i32 {
+        self.state_id
+    }
+
+    /// Sends the whole window at the end of the tick.
+    pub fn resync(&mut self) {
+        self.resync = true;
+    }
+
+    fn next_state_id(&mut self) -> VarInt {
+        self.state_id = (self.state_id + 1) & 0x7fff;
+        VarInt(self.state_id)
+    }
+
+    /// Sends the client the properties of its screen that changed.
+    fn sync_properties(&mut self, client: &mut impl WritePacket, properties: &[i16]) {
+        for (idx, &value) in properties.iter().enumerate() {
+            if self.properties.get(idx) == Some(&value) {
+                continue;
+            }
+
+            client.write_packet(&ScreenHandlerPropertyUpdateS2c {
+                window_id: self.window_id,
+                property: idx as i16,
+                value,
+            });
+        }
+
+        self.properties = properties.to_vec();
+    }
+
+    /// Sends the client the slots and cursor item that differ from what it
+    /// was last told.
+    fn sync(&mut self, client: &mut impl WritePacket, slots: &[ItemStack], cursor: &ItemStack) {
+        let changed: Vec<usize> = (0..slots.len())
+            .filter(|&idx| self.slots.get(idx) != Some(&slots[idx]))
+            .collect();
+
+        if self.resync || changed.len() > MAX_SLOT_UPDATES || self.slots.len() != slots.len() {
+            let state_id = self.next_state_id();
+            client.write_packet(&InventoryS2c {
+                window_id: self.window_id,
+                state_id,
+                slots: Cow::Borrowed(slots),
+                carried_item: Cow::Borrowed(cursor),
+            });
+
+            self.slots = slots.to_vec();
+            self.cursor = cursor.clone();
+            self.resync = false;
+            return;
+        }
+
+        for idx in changed {
+            let state_id = self.next_state_id();
+            client.write_packet(&ScreenHandlerSlotUpdateS2c {
+                window_id: self.window_id as i8,
+                state_id,
+                slot_idx: idx as i16,
+                slot_data: Cow::Borrowed(&slots[idx]),
+            });
+
+            self.slots[idx] = slots[idx].clone();
+        }
+
+        if self.cursor != *cursor {
+            let state_id = self.next_state_id();
+            client.write_packet(&ScreenHandlerSlotUpdateS2c {
+                window_id: -1,
+                state_id,
+                slot_idx: -1,
+                slot_data: Cow::Borrowed(cursor),
+            });
+
+            self.cursor = cursor.clone();
+        }
+    }
+}
+
+/// Sent when a client throws items out of its inventory, after they were
+/// taken out of it. The items are spawned in front of the player by
+/// [`spawn_dropped_items`] unless a handler takes the event first.
+///
+/// [`spawn_dropped_items`]: crate::entity::item::spawn_dropped_items
+#[derive(Event, Clone, Debug)]
+pub struct DropItemStackEvent {
+    #[event(target)]
+    pub client: EntityId,
+    /// The slot the items came from, or `None` if they were on the cursor.
+    pub from_slot: Option<u16>,
+    pub stack: ItemStack,
+}
+
+/// Sent when a client clicks a slot, before the click is applied.
+///
+/// Handlers can take the event mutably and [`cancel`] it to leave the window
+/// as it was, which resyncs the client. Clicks in a
+/// [read-only](Inventory::set_readonly) inventory are sent but never applied,
+/// so menus can react to clicks without items being taken out of them.
+///
+/// [`cancel`]: Self::cancel
+#[derive(Event, Clone, Debug)]
+pub struct ClickSlotEvent {
+    #[event(target)]
+    pub client: EntityId,
+    /// The inventory open in a screen, or `None` if the client clicked in its
+    /// own inventory.
+    pub inventory: Option<EntityId>,
+    /// The slot of the window that was clicked, or -999 for clicks outside the
+    /// window.
+    pub slot: i16,
+    pub button: i8,
+    pub mode: ClickMode,
+    state_id: i32,
+    slot_changes: Vec<SlotChange>,
+    carried_item: ItemStack,
+    cancelled: bool,
+}
+
+impl ClickSlotEvent {
+    /// Leaves the window as it was before the click.
+    pub fn cancel(&mut self) {
+        self.cancelled = true;
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled
+    }
+}
+
+/// Checks the click is in the window the client has open and sends a
+/// [`ClickSlotEvent`] for it.
+pub fn handle_click_slot(
+    r: Receiver<PacketEvent<ClickSlotC2s<'static>>, &mut ClientInventoryState>,
+    mut sender: Sender<ClickSlotEvent>,
+) {
+    let state = r.query;
+    let event = r.event;
+    let packet = &event.packet;
+
+    // The click was made in a window that has been closed since.
+    if packet.window_id != state.window_id {
+        return;
+    }
+
+    let valid = packet.slot_changes.len() <= MAX_SLOT_CHANGES
+        && packet
+            .slot_changes
+            .iter()
+            .all(|change| usize::try_from(change.idx).is_ok_and(|idx| idx < state.slots.len()));
+
+    if !valid {
+        debug!(client = ?event.client, "invalid slot changes");
+        state.resync = true;
+        return;
+    }
+
+    sender.send(ClickSlotEvent {
+        client: event.client,
+        inventory: state.open,
+        slot: packet.slot_idx,
+        button: packet.button,
+        mode: packet.mode,
+        state_id: packet.state_id.0,
+        slot_changes: packet.slot_changes.to_vec(),
+        carried_item: packet.carried_item.clone(),
+        cancelled: false,
+    });
+}
+
+/// Redoes the clicks that weren't cancelled on the server's copy of the
+/// window, and resyncs the client if it predicted anything else or clicked
+/// before its last update.
+pub fn apply_click_slot(
+    r: Receiver<ClickSlotEvent, (&mut CursorItem, &mut ClientInventoryState, &GameMode)>,
+    mut inventories: Fetcher<&mut Inventory>,
+    recipes: Single<&RecipeRegistry>,
+    mut sender: Sender<DropItemStackEvent>,
+) {
+    let (cursor, state, game_mode) = r.query;
+    let event = r.event;
+
+    // A handler opened or closed a screen in the meantime.
+    if event.inventory != state.open {
+        return;
+    }
+
+    let (mut window, readonly) = {
+        let Ok(player) = inventories.get(event.client) else {
+            return;
+        };
+        let open = match event.inventory.map(|open| inventories.get(open)) {
+            Some(Ok(open)) => Some(open),
+            Some(Err(_)) => return,
+            None => None,
+        };
+
+        let top = open.unwrap_or(player);
+        let window = Window::new(top.kind, window_slots(player, open), cursor.0.clone())
+            .with_recipes(recipes.0);
+        (window, top.readonly)
+    };
+
+    if event.cancelled || readonly {
+        state.drag = None;
+        state.resync = true;
+        return;
+    }
+
+    let mut predicted = state.slots.clone();
+    for change in &event.slot_changes {
+        predicted[change.idx as usize] = change.stack.clone();
+    }
+
+    let click = Click {
+        slot: event.slot,
+        button: event.button,
+        mode: event.mode,
+    };
+    let dropped = window.click(click, &mut state.drag, *game_mode == GameMode::Creative);
+
+    if event.state_id == state.state_id
+        && window.slots == predicted
+        && window.cursor == event.carried_item
+    {
+        state.slots.clone_from(&window.slots);
+        state.cursor.clone_from(&window.cursor);
+    } else {
+        state.resync = true;
+    }
+
+    match event.inventory {
+        Some(open) => {
+            let split = window.slots.len() - PLAYER_WINDOW_SLOTS.len();
+            let (top, main) = window.slots.split_at(split);
+
+            if let Ok(open) = inventories.get_mut(open) {
+                open.slots.clone_from_slice(top);
+            }
+            if let Ok(player) = inventories.get_mut(event.client) {
+                player.slots[PLAYER_WINDOW_SLOTS].clone_from_slice(main);
+            }
+        }
+        None => {
+            if let Ok(player) = inventories.get_mut(event.client) {
+                player.slots.clone_from_slice(&window.slots);
+            }
+        }
+    }
+
+    cursor.0 = window.cursor;
+
+    if let Some(Dropped { slot, stack }) = dropped {
+        sender.send(DropItemStackEvent {
+            client: event.client,
+            from_slot: slot.map(|slot| slot as u16),
+            stack,
+        });
+    }
+
+    for stack in window.leftovers {
+        sender.send(DropItemStackEvent {
+            client: event.client,
+            from_slot: None,
+            stack,
+        });
+    }
+}
+
+/// The slots of the window showing `open` above the main inventory of
+/// `player`, or of `player`'s own inventory.
+fn window_slots(player: &Inventory, open: Option<&Inventory>) -> Vec<ItemStack> {
+    match open {
+        Some(open) => open
+            .slots
+            .iter()
+            .chain(&player.slots[PLAYER_WINDOW_SLOTS])
+            .cloned()
+            .collect(),
+        None => player.slots.to_vec(),
+    }
+}
+
+pub fn handle_update_selected_slot(
+    r: Receiver<PacketEvent<UpdateSelectedSlotC2s>, (&mut HeldItem, &mut ClientInventoryState)>,
+) {
+    let (held, state) = r.query;
+    let slot = r.event.packet.slot;
+
+    if slot > 8 {
+        debug!(client = ?r.event.client, slot, "invalid hotbar slot");
+        return;
+    }
+
+    held.hotbar_idx = slot as u8;
+    state.held = slot as u8;
+}
+
+/// Sends every client the changes to the window it has open, its cursor item
+/// and its held item. Screens whose inventory was despawned are closed.
+pub fn sync_inventories(
+    _: Receiver<PostUpdate>,
+    mut clients: Fetcher<(
+        EntityId,
+        &mut Client,
+        &mut CursorItem,
+        &HeldItem,
+        &mut ClientInventoryState,
+    )>,
+    mut inventories: Fetcher<&mut Inventory>,
+    mut sender: Sender<(ScreenClosedEvent, DropItemStackEvent)>,
+) {
+    for (id, client, cursor, held, state) in clients.iter_mut() {
+        if state
+            .open
+            .is_some_and(|open| inventories.get(open).is_err())
+        {
+            client.write_packet(&CloseScreenS2c {
+                window_id: state.window_id,
+            });
+            screen::close_window(id, state, cursor, &mut inventories, &mut sender);
+        }
+
+        let Ok(player) = inventories.get(id) else {
+            continue;
+        };
+        let open = state.open.and_then(|open| inventories.get(open).ok());
+
+        state.sync(client, &window_slots(player, open), &cursor.0);
+
+        if let Some(open) = open {
+            state.sync_properties(client, &open.properties);
+        }
+
+        if state.held != held.hotbar_idx {
+            client.write_packet(&UpdateSelectedSlotS2c {
+                slot: held.hotbar_idx,
+            });
+            state.held = held.hotbar_idx;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use valence_protocol::encode::PacketWriter;
+    use valence_protocol::{CompressionThreshold, ItemKind, Packet, PacketDecoder};
+
+    use super::*;
+
+    /// The IDs of the packets `state` sends to bring the client up to date.
+    fn sync(
+        state: &mut ClientInventoryState,
+        inventory: &Inventory,
+        cursor: &ItemStack,
+    ) -> Vec<i32> {
+        let mut buf = vec![];
+        state.sync(
+            &mut PacketWriter::new(&mut buf, CompressionThreshold(-1)),
+            inventory.slots(),
+            cursor,
+        );
+
+        let mut decoder = PacketDecoder::new();
+        decoder.queue_slice(&buf);
+        std::iter::from_fn(|| decoder.try_next_packet().unwrap())
+            .map(|frame| frame.id)
+            .collect()
+    }
+
+    #[test]
+    fn only_changes_are_sent() {
+        let mut state = ClientInventoryState::new();
+        let mut inventory = Inventory::new(InventoryKind::Player);
+
+        // Everything is sent at first.
+        assert_eq!(
+            sync(&mut state, &inventory, &ItemStack::EMPTY),
+            [InventoryS2c::ID]
+        );
+        assert!(sync(&mut state, &inventory, &ItemStack::EMPTY).is_empty());
+        assert_eq!(state.state_id(), 1);
+
+        inventory.set_slot(36, ItemStack::new(ItemKind::Stone, 1, None));
+        let cursor = ItemStack::new(ItemKind::Dirt, 1, None);
+        assert_eq!(
+            sync(&mut state, &inventory, &cursor),
+            [
+                ScreenHandlerSlotUpdateS2c::ID,
+                ScreenHandlerSlotUpdateS2c::ID
+            ]
+        );
+        assert_eq!(state.state_id(), 3);
+
+        for idx in 9..36 {
+            inventory.set_slot(idx, ItemStack::new(ItemKind::Stone, 1, None));
+        }
+        assert_eq!(sync(&mut state, &inventory, &cursor), [InventoryS2c::ID]);
+
+        state.resync();
+        assert_eq!(sync(&mut state, &inventory, &cursor), [InventoryS2c::ID]);
+    }
+
+    #[test]
+    fn screens_show_the_main_inventory_below() {
+        let mut player = Inventory::new(InventoryKind::Player);
+        let mut chest = Inventory::new(InventoryKind::Generic9x3);
+
+        let left = player.insert(ItemStack::new(ItemKind::Stone, 100, None), 9..45);
+        assert!(left.is_empty());
+        assert_eq!(player.slot(9).count, 64);
+        assert_eq!(player.slot(10).count, 36);
+
+        // Matching stacks are topped up first, and what doesn't fit is returned.
+        chest.set_slot(5, ItemStack::new(ItemKind::Stone, 60, None));
+        let left = chest.insert(ItemStack::new(ItemKind::Stone, 10, None), 5..6);
+        assert_eq!(left, ItemStack::new(ItemKind::Stone, 6, None));
+
+        let slots = window_slots(&player, Some(&chest));
+        assert_eq!(slots.len(), 27 + 36);
+        assert_eq!(slots[5].count, 64);
+        assert_eq!(slots[27].count, 64);
+        assert_eq!(window_slots(&player, None), player.slots());
+    }
+
+    #[test]
+    fn held_item_is_in_the_hotbar() {
+        let mut held = HeldItem::default();
+        assert_eq!(held.slot(), PLAYER_HOTBAR_START);
+
+        held.set_hotbar_idx(8);
+        assert_eq!(held.slot(), PLAYER_OFFHAND_SLOT - 1);
+    }
+}
@@ -0,0 +1,465 @@
+//! Screens showing another inventory above the player's own.
+//!
+//! Sending an [`OpenScreenEvent`] opens an entity's [`Inventory`] on a client,
+//! which then clicks in it like in its own inventory. Any number of clients
+//! can have the same inventory open. The screen closes when the client closes
+//! it, when a [`CloseScreenEvent`] is sent or when the inventory is despawned,
+//! and whatever was on the cursor goes back into the player's inventory. So
+//! does whatever was left in a crafting grid.
+//!
+//! Menus are read-only inventories whose slots are reacted to with
+//! [`ClickSlotEvent`] handlers, or with [`ButtonClickEvent`] handlers for the
+//! buttons of screens like the enchanting table and the stonecutter.
+//!
+//! [`ClickSlotEvent`]: super::ClickSlotEvent
+
+use std::borrow::Cow;
+
+use evenio::prelude::*;
+use tracing::debug;
+use valence_protocol::packets::play::open_screen_s2c::WindowType;
+use valence_protocol::packets::play::{
+    ButtonClickC2s, CloseHandledScreenC2s, CloseScreenS2c, OpenScreenS2c,
+};
+use valence_protocol::{ItemStack, Text, VarInt, WritePacket};
+
+use super::{ClientInventoryState, CursorItem, DropItemStackEvent, Inventory, InventoryKind};
+use crate::client::Client;
+use crate::event_loop::PacketEvent;
+
+impl InventoryKind {
+    /// The screen showing inventories of this kind, or `None` for player
+    /// inventories, which can't be opened in a screen.
+    pub const fn window_type(self) -> Option<WindowType> {
+        Some(match self {
+            Self::Generic9x1 => WindowType::Generic9x1,
+            Self::Generic9x2 => WindowType::Generic9x2,
+            Self::Generic9x3 => WindowType::Generic9x3,
+            Self::Generic9x4 => WindowType::Generic9x4,
+            Self::Generic9x5 => WindowType::Generic9x5,
+            Self::Generic9x6 => WindowType::Generic9x6,
+            Self::Generic3x3 => WindowType::Generic3x3,
+            Self::Anvil => WindowType::Anvil,
+            Self::Beacon => WindowType::Beacon,
+            Self::BlastFurnace => WindowType::BlastFurnace,
+            Self::BrewingStand => WindowType::BrewingStand,
+            Self::Crafting => WindowType::Crafting,
+            Self::Enchantment => WindowType::Enchantment,
+            Self::Furnace => WindowType::Furnace,
+            Self::Grindstone => WindowType::Grindstone,
+            Self::Hopper => WindowType::Hopper,
+            Self::Lectern => WindowType::Lectern,
+            Self::Loom => WindowType::Loom,
+            Self::Merchant => WindowType::Merchant,
+            Self::ShulkerBox => WindowType::ShulkerBox,
+            Self::Smithing => WindowType::Smithing,
+            Self::Smoker => WindowType::Smoker,
+            Self::Cartography => WindowType::Cartography,
+            Self::Stonecutter => WindowType::Stonecutter,
+            Self::Player => return None,
+        })
+    }
+}
+
+/// Opens the [`Inventory`] of `inventory` in a screen titled `title`,
+/// closing the screen the client had open.
+#[derive(Event, Clone, Debug)]
+pub struct OpenScreenEvent {
+    #[event(target)]
+    pub client: EntityId,
+    pub inventory: EntityId,
+    pub title: Text,
+}
+
+/// Closes the screen the client has open, if any.
+#[derive(Event, Clone, Debug)]
+pub struct CloseScreenEvent {
+    #[event(target)]
+    pub client: EntityId,
+}
+
+/// Sent after a screen closed, by the client or by the server.
+#[derive(Event, Clone, Debug)]
+pub struct ScreenClosedEvent {
+    #[event(target)]
+    pub client: EntityId,
+    pub inventory: EntityId,
+}
+
+/// Sent when a client clicks a button of the screen it has open, like an
+/// enchantment of the enchanting table.
+#[derive(Event, Clone, Debug)]
+pub struct ButtonClickEvent {
+    #[event(target)]
+    pub client: EntityId,
+    pub inventory: EntityId,
+    pub button: i8,
+}
+
+#[derive(Query)]
+pub struct ScreenQuery<'a> {
+    client: &'a mut Client,
+    state: &'a mut ClientInventoryState,
+    cursor: &'a mut CursorItem,
+}
+
+pub fn open_screen(
+    r: Receiver<OpenScreenEvent, ScreenQuery>,
+    mut inventories: Fetcher<&mut Inventory>,
+    mut sender: Sender<(ScreenClosedEvent, DropItemStackEvent)>,
+) {
+    let q = r.query;
+    let event = r.event;
+
+    let window_type = match inventories.get(event.inventory) {
+        Ok(inventory) => inventory.kind.window_type(),
+        Err(_) => None,
+    };
+
+    let Some(window_type) = window_type else {
+        debug!(inventory = ?event.inventory, "can't open a screen without an inventory");
+        return;
+    };
+
+    if q.state.open.is_some() {
+        close_window(
+            event.client,
+            q.state,
+            q.cursor,
+            &mut inventories,
+            &mut sender,
+        );
+    }
+
+    // Window 0 is the player's own inventory, and the game counts to 100.
+    let state = q.state;
+    state.window_id = state.window_id % 100 + 1;
+    state.open = Some(event.inventory);
+    state.slots.clear();
+    state.resync = true;
+
+    q.client.write_packet(&OpenScreenS2c {
+        window_id: VarInt(state.window_id.into()),
+        window_type,
+        window_title: Cow::Borrowed(&event.title),
+    });
+}
+
+pub fn close_screen(
+    r: Receiver<CloseScreenEvent, ScreenQuery>,
+    mut inventories: Fetcher<&mut Inventory>,
+    mut sender: Sender<(ScreenClosedEvent, DropItemStackEvent)>,
+) {
+    let q = r.query;
+
+    if q.state.open.is_none() {
+        return;
+    }
+
+    q.client.write_packet(&CloseScreenS2c {
+        window_id: q.state.window_id,
+    });
+
+    close_window(
+        r.event.client,
+        q.state,
+        q.cursor,
+        &mut inventories,
+        &mut sender,
+    );
+}
+
+/// Closes the window the client closed, which may be its own inventory.
+pub fn handle_close_screen(
+    r: Receiver<PacketEvent<CloseHandledScreenC2s>, ScreenQuery>,
+    mut inventories: Fetcher<&mut Inventory>,
+    mut sender: Sender<(ScreenClosedEvent, DropItemStackEvent)>,
+) {
+    let q = r.query;
+
+    // The client closed a screen that was already replaced.
+    if r.event.packet.window_id as u8 != q.state.window_id {
+        return;
+    }
+
+    close_window(
+        r.event.client,
+        q.state,
+        q.cursor,
+        &mut inventories,
+        &mut sender,
+    );
+}
+
+pub fn handle_button_click(
+    r: Receiver<PacketEvent<ButtonClickC2s>, &ClientInventoryState>,
+    mut sender: Sender<ButtonClickEvent>,
+) {
+    let state = r.query;
+    let packet = &r.event.packet;
+
+    if let Some(inventory) = state.open {
+        if packet.window_id as u8 == state.window_id {
+            sender.send(ButtonClickEvent {
+                client: r.event.client,
+                inventory,
+                button: packet.button_id,
+            });
+        }
+    }
+}
+
+/// Puts the client back in its own inventory, returning the cursor item and
+/// the crafting grid of the closed window to it or dropping what doesn't fit.
+pub(super) fn close_window(
+    id: EntityId,
+    state: &mut ClientInventoryState,
+    cursor: &mut CursorItem,
+    inventories: &mut Fetcher<&mut Inventory>,
+    sender: &mut Sender<(ScreenClosedEvent, DropItemStackEvent)>,
+) {
+    let mut returned = vec![std::mem::replace(&mut cursor.0, ItemStack::EMPTY)];
+
+    // Other screens leave the player's own crafting grid alone.
+    let window = state.open.unwrap_or(id);
+    let inventory = inventories.get_mut(window).ok();
+    if let Some(inventory) = inventory.filter(|inventory| !inventory.readonly) {
+        returned.extend(inventory.take_crafting_grid());
+    }
+
+    for stack in returned {
+        let left = match inventories.get_mut(id) {
+            Ok(player) => player.insert(stack, 9..45),
+            Err(_) => stack,
+        };
+
+        if !left.is_empty() {
+            sender.send(DropItemStackEvent {
+                client: id,
+                from_slot: None,
+                stack: left,
+            });
+        }
+    }
+
+    state.drag = None;
+    // Closing a screen leaves the client with an empty cursor too.
+    state.cursor = ItemStack::EMPTY;
+
+    if let Some(inventory) = state.open.take() {
+        state.window_id = 0;
+        state.properties.clear();
+        state.slots.clear();
+        state.resync = true;
+
+        sender.send(ScreenClosedEvent {
+            client: id,
+            inventory,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use valence_protocol::packets::play::click_slot_c2s::{ClickMode, SlotChange};
+    use valence_protocol::packets::play::{
+        ClickSlotC2s, InventoryS2c, ScreenHandlerPropertyUpdateS2c,
+    };
+    use valence_protocol::text::IntoText;
+    use valence_protocol::{GameMode, ItemKind, Packet};
+    use valence_server_common::PostUpdate;
+
+    use super::*;
+    use crate::inventory::{apply_click_slot, handle_click_slot, sync_inventories, HeldItem};
+    use crate::recipe::RecipeRegistry;
+    use crate::testing::{self, packet_event, sent_packet_ids, sent_packets};
+
+    /// The buttons a client clicked, as `(inventory, button)`.
+    #[derive(Component, Default, Debug)]
+    struct ButtonClicks(Vec<(EntityId, i8)>);
+
+    fn record_button_clicks(r: Receiver<ButtonClickEvent, &mut ButtonClicks>) {
+        r.query.0.push((r.event.inventory, r.event.button));
+    }
+
+    /// A world with a client that has `inventory` open in window 1, and was
+    /// already sent it.
+    fn open(inventory: Inventory) -> (World, EntityId, EntityId) {
+        let mut world = World::new();
+        world.add_handler(open_screen);
+        world.add_handler(close_screen);
+        world.add_handler(handle_close_screen);
+        world.add_handler(handle_button_click);
+        world.add_handler(handle_click_slot);
+        world.add_handler(apply_click_slot);
+        world.add_handler(sync_inventories);
+        world.add_handler(record_button_clicks);
+
+        let server = world.spawn();
+        world.insert(server, RecipeRegistry::default());
+
+        let client = world.spawn();
+        world.insert(client, testing::client());
+        world.insert(client, GameMode::Survival);
+        world.insert(client, Inventory::new(InventoryKind::Player));
+        world.insert(client, CursorItem::default());
+        world.insert(client, HeldItem::default());
+        world.insert(client, ClientInventoryState::new());
+        world.insert(client, ButtonClicks::default());
+
+        let entity = world.spawn();
+        world.insert(entity, inventory);
+
+        world.send(OpenScreenEvent {
+            client,
+            inventory: entity,
+            title: "Test".into_text(),
+        });
+        world.send(PostUpdate);
+        sent_packets(&mut world, client);
+
+        (world, client, entity)
+    }
+
+    #[test]
+    fn inventory_kinds_match_their_screens() {
+        assert_eq!(InventoryKind::Player.window_type(), None);
+        assert_eq!(
+            InventoryKind::Generic9x3.window_type(),
+            Some(WindowType::Generic9x3)
+        );
+        assert_eq!(InventoryKind::Furnace.slot_count(), 3);
+        assert_eq!(InventoryKind::Furnace.output_slot(), Some(2));
+    }
+
+    #[test]
+    fn clicks_in_read_only_menus_are_cancelled() {
+        let stone = ItemStack::new(ItemKind::Stone, 1, None);
+        let mut menu = Inventory::new(InventoryKind::Generic9x1);
+        menu.set_slot(0, stone.clone());
+        menu.set_readonly(true);
+
+        let (mut world, client, menu) = open(menu);
+        let state_id = world
+            .get::<ClientInventoryState>(client)
+            .unwrap()
+            .state_id();
+
+        // The client thinks it picked up the item.
+        world.send(packet_event(
+            client,
+            ClickSlotC2s {
+                window_id: 1,
+                state_id: VarInt(state_id),
+                slot_idx: 0,
+                button: 0,
+                mode: ClickMode::Click,
+                slot_changes: vec![SlotChange {
+                    idx: 0,
+                    stack: ItemStack::EMPTY,
+                }]
+                .into(),
+                carried_item: stone.clone(),
+            },
+        ));
+
+        assert_eq!(*world.get::<Inventory>(menu).unwrap().slot(0), stone);
+        assert!(world.get::<CursorItem>(client).unwrap().is_empty());
+
+        world.send(PostUpdate);
+        assert_eq!(sent_packet_ids(&mut world, client), [InventoryS2c::ID]);
+    }
+
+    #[test]
+    fn closing_the_screen_returns_the_cursor_item() {
+        let (mut world, client, _) = open(Inventory::new(InventoryKind::Generic9x3));
+        let stone = ItemStack::new(ItemKind::Stone, 5, None);
+        world.get_mut::<CursorItem>(client).unwrap().0 = stone.clone();
+
+        // A window that was already replaced.
+        world.send(packet_event(client, CloseHandledScreenC2s { window_id: 2 }));
+        assert_eq!(
+            *world.get::<CursorItem>(client).unwrap(),
+            CursorItem(stone.clone())
+        );
+
+        world.send(packet_event(client, CloseHandledScreenC2s { window_id: 1 }));
+        assert!(world.get::<CursorItem>(client).unwrap().is_empty());
+        assert_eq!(*world.get::<Inventory>(client).unwrap().slot(9), stone);
+
+        let state = world.get::<ClientInventoryState>(client).unwrap();
+        assert_eq!(state.open_inventory(), None);
+        assert_eq!(state.window_id(), 0);
+    }
+
+    #[test]
+    fn buttons_are_only_clicked_in_the_open_window() {
+        let (mut world, client, table) = open(Inventory::new(InventoryKind::Enchantment));
+
+        world.send(packet_event(
+            client,
+            ButtonClickC2s {
+                window_id: 1,
+                button_id: 2,
+            },
+        ));
+        world.send(packet_event(
+            client,
+            ButtonClickC2s {
+                window_id: 2,
+                button_id: 1,
+            },
+        ));
+
+        // The player's own inventory has no buttons.
+        world.send(CloseScreenEvent { client });
+        world.send(packet_event(
+            client,
+            ButtonClickC2s {
+                window_id: 0,
+                button_id: 0,
+            },
+        ));
+
+        assert_eq!(world.get::<ButtonClicks>(client).unwrap().0, [(table, 2)]);
+    }
+
+    #[test]
+    fn despawning_the_inventory_closes_the_screen() {
+        let (mut world, client, chest) = open(Inventory::new(InventoryKind::Generic9x3));
+
+        world.despawn(chest);
+        world.send(PostUpdate);
+
+        assert_eq!(
+            sent_packet_ids(&mut world, client),
+            [CloseScreenS2c::ID, InventoryS2c::ID]
+        );
+        let state = world.get::<ClientInventoryState>(client).unwrap();
+        assert_eq!(state.open_inventory(), None);
+    }
+
+    #[test]
+    fn property_changes_are_sent() {
+        let (mut world, client, furnace) = open(Inventory::new(InventoryKind::Furnace));
+
+        world
+            .get_mut::<Inventory>(furnace)
+            .unwrap()
+            .set_property(2, 150);
+        world.send(PostUpdate);
+
+        let updates: Vec<_> = sent_packets(&mut world, client)
+            .iter()
+            .map(|frame| {
+                let packet = frame.decode::<ScreenHandlerPropertyUpdateS2c>().unwrap();
+                (packet.window_id, packet.property, packet.value)
+            })
+            .collect();
+        assert_eq!(updates.last(), Some(&(1, 2, 150)));
+
+        // Only changes are sent.
+        world.send(PostUpdate);
+        assert!(sent_packet_ids(&mut world, client).is_empty());
+    }
+}
@@ -0,0 +1,185 @@
+//! Moves freshly logged-in clients into the play state.
+
+use std::borrow::Cow;
+use std::collections::BTreeSet;
+
+use evenio::prelude::*;
+use evenio::query::With;
+use tracing::warn;
+use valence_entity::{Look, Position};
+use valence_protocol::game_mode::OptGameMode;
+use valence_protocol::packets::play::player_abilities_s2c::PlayerAbilitiesFlags;
+use valence_protocol::packets::play::{GameJoinS2c, PlayerAbilitiesS2c, PlayerSpawnPositionS2c};
+use valence_protocol::{GameMode, GlobalPos, VarInt, WritePacket};
+
+use crate::brand::SetBrand;
+use crate::chunk::ChunkLayer;
+use crate::client::{
+    Client, DeathLocation, Dimension, FlyingSpeed, FovModifier, HasRespawnScreen, HashedSeed,
+    IsDebug, IsFlat, IsHardcore, PortalCooldown, PrevGameMode, ReducedDebugInfo, RespawnPosition,
+    ViewDistance,
+};
+use crate::event::ClientJoinEvent;
+use crate::position::TeleportState;
+use crate::registry::RegistryCodec;
+use crate::SharedServer;
+
+/// The brand shown in the client's F3 screen.
+pub const SERVER_BRAND: &str = "minecraft-rs";
+
+#[derive(Query)]
+pub struct JoinQuery<'a> {
+    client: &'a mut Client,
+    game_mode: &'a GameMode,
+    prev_game_mode: &'a PrevGameMode,
+    is_hardcore: &'a IsHardcore,
+    hashed_seed: &'a HashedSeed,
+    reduced_debug_info: &'a ReducedDebugInfo,
+    has_respawn_screen: &'a HasRespawnScreen,
+    is_debug: &'a IsDebug,
+    is_flat: &'a IsFlat,
+    death_location: &'a DeathLocation,
+    portal_cooldown: &'a PortalCooldown,
+    dimension: &'a Dimension,
+    view_distance: &'a ViewDistance,
+    respawn_pos: &'a RespawnPosition,
+    position: &'a Position,
+    look: &'a Look,
+    teleport: &'a mut TeleportState,
+    abilities: (&'a PlayerAbilitiesFlags, &'a FlyingSpeed, &'a FovModifier),
+}
+
+/// Sends the join sequence to a client that just finished logging in.
+pub fn init_client(
+    r: Receiver<ClientJoinEvent>,
+    mut clients: Fetcher<JoinQuery>,
+    layers: Fetcher<(&Dimension, With<&ChunkLayer>)>,
+    server: Single<&SharedServer>,
+    codec: Single<&RegistryCodec>,
+) {
+    let Ok(q) = clients.get_mut(r.event.entity) else {
+        warn!("joining client {:?} is missing components", r.event.entity);
+        return;
+    };
+
+    let server = server.0;
+    let client = q.client;
+
+    // The dimensions of every layer, which the client may be moved to.
+    let dimension_names: BTreeSet<_> = layers
+        .iter()
+        .map(|(dimension, _)| &dimension.name)
+        .chain([&q.dimension.name])
+        .map(|name| name.as_str_ident().into())
+        .collect();
+
+    client.write_packet(&GameJoinS2c {
+        // Every client is entity 0 to itself. Other entities start at 1.
+        entity_id: 0,
+        is_hardcore: q.is_hardcore.0,
+        game_mode: *q.game_mode,
+        previous_game_mode: OptGameMode(q.prev_game_mode.0),
+        dimension_names: Cow::Owned(dimension_names),
+        registry_codec: Cow::Owned(codec.0.to_compound()),
+        dimension_type_name: q.dimension.dimension_type.as_str_ident().into(),
+        dimension_name: q.dimension.name.as_str_ident().into(),
+        hashed_seed: q.hashed_seed.0 as i64,
+        max_players: VarInt(server.max_players as i32),
+        view_distance: VarInt(q.view_distance.get() as i32),
+        // Every chunk the client has loaded is simulated.
+        simulation_distance: VarInt(q.view_distance.get().into()),
+        reduced_debug_info: q.reduced_debug_info.0,
+        enable_respawn_screen: q.has_respawn_screen.0,
+        is_debug: q.is_debug.0,
+        is_flat: q.is_flat.0,
+        last_death_location: q.death_location.0.as_ref().map(|(dimension, pos)| GlobalPos {
+            dimension_name: dimension.as_str_ident().into(),
+            position: *pos,
+        }),
+        portal_cooldown: VarInt(q.portal_cooldown.0),
+    });
+
+    client.set_brand(SERVER_BRAND);
+
+    let (flags, flying_speed, fov_modifier) = q.abilities;
+
+    client.write_packet(&PlayerAbilitiesS2c {
+        flags: *flags,
+        flying_speed: flying_speed.0,
+        fov_modifier: fov_modifier.0,
+    });
+
+    client.write_packet(&PlayerSpawnPositionS2c {
+        position: q.respawn_pos.pos,
+        angle: q.respawn_pos.yaw,
+    });
+
+    q.teleport.teleport(client, q.position.0, *q.look);
+}
+
+#[cfg(test)]
+mod tests {
+    use valence_protocol::{ident, Packet};
+
+    use super::*;
+    use crate::client::PrevGameMode;
+    use crate::testing::{self, sent_packets};
+
+    #[test]
+    fn joining_clients_are_told_the_dimensions_of_every_layer() {
+        let mut world = World::new();
+        world.add_handler(init_client);
+
+        let server = world.spawn();
+        world.insert(server, testing::server());
+        world.insert(server, RegistryCodec::default());
+
+        for name in [ident!("overworld"), ident!("the_nether")] {
+            let layer = world.spawn();
+            world.insert(layer, testing::layer());
+            world.insert(
+                layer,
+                Dimension {
+                    name: name.into(),
+                    dimension_type: ident!("overworld").into(),
+                },
+            );
+        }
+
+        let client = world.spawn();
+        world.insert(client, testing::client());
+        world.insert(client, GameMode::default());
+        world.insert(client, PrevGameMode::default());
+        world.insert(client, IsHardcore::default());
+        world.insert(client, HashedSeed::default());
+        world.insert(client, ReducedDebugInfo::default());
+        world.insert(client, HasRespawnScreen::default());
+        world.insert(client, IsDebug::default());
+        world.insert(client, IsFlat::default());
+        world.insert(client, DeathLocation::default());
+        world.insert(client, PortalCooldown::default());
+        world.insert(client, Dimension::default());
+        world.insert(client, ViewDistance::new(6));
+        world.insert(client, RespawnPosition::default());
+        world.insert(client, Position::default());
+        world.insert(client, Look::default());
+        world.insert(client, TeleportState::new());
+        world.insert(client, PlayerAbilitiesFlags::default());
+        world.insert(client, FlyingSpeed::default());
+        world.insert(client, FovModifier::default());
+
+        world.send(ClientJoinEvent { entity: client });
+
+        let packets = sent_packets(&mut world, client);
+        assert_eq!(packets[0].id, GameJoinS2c::ID);
+        let join = packets[0].decode::<GameJoinS2c>().unwrap();
+
+        let names: Vec<_> = join
+            .dimension_names
+            .iter()
+            .map(|name| name.as_str())
+            .collect();
+        assert_eq!(names, ["minecraft:overworld", "minecraft:the_nether"]);
+        assert_eq!(join.simulation_distance, VarInt(6));
+    }
+}
@@ -0,0 +1,216 @@
+//! Keeps client connections alive and measures their latency.
+//!
+//! Each keepalive period a [`KeepAliveS2c`] with a random ID is sent to every
+//! client. The client must echo the ID back before the keepalive timeout
+//! elapses or it is disconnected.
+
+use std::time::Instant;
+
+use derive_more::Deref;
+use evenio::prelude::*;
+use tracing::debug;
+use valence_protocol::packets::play::{KeepAliveC2s, KeepAliveS2c};
+use valence_protocol::text::IntoText;
+use valence_protocol::WritePacket;
+use valence_server_common::Tick;
+
+use crate::client::Client;
+use crate::event::{ClientDisconnectEvent, DisconnectReason};
+use crate::event_loop::PacketEvent;
+use crate::SharedServer;
+
+/// The state of the keepalive currently in flight.
+#[derive(Component, Debug)]
+pub struct KeepaliveState {
+    got_keepalive: bool,
+    last_keepalive_id: u64,
+    last_send: Instant,
+}
+
+impl KeepaliveState {
+    pub(crate) fn new() -> Self {
+        Self {
+            got_keepalive: true,
+            last_keepalive_id: 0,
+            last_send: Instant::now(),
+        }
+    }
+
+    /// When the last keepalive was sent to the client.
+    pub fn last_send(&self) -> Instant {
+        self.last_send
+    }
+}
+
+/// The round-trip latency of the client in milliseconds, as shown in the tab
+/// list. `-1` until the first keepalive is answered.
+#[derive(Component, Copy, Clone, PartialEq, Eq, Debug, Deref)]
+pub struct Ping(pub i32);
+
+impl Default for Ping {
+    fn default() -> Self {
+        Self(-1)
+    }
+}
+
+/// Sends a new keepalive to every client that answered the previous one, and
+/// disconnects clients that haven't answered in time.
+pub fn send_keepalive(
+    _: Receiver<Tick>,
+    mut clients: Fetcher<(EntityId, &mut Client, &mut KeepaliveState)>,
+    server: Single<&SharedServer>,
+    mut sender: Sender<ClientDisconnectEvent>,
+) {
+    let now = Instant::now();
+
+    for (entity, client, state) in clients.iter_mut() {
+        let elapsed = now.duration_since(state.last_send);
+
+        if state.got_keepalive {
+            if elapsed >= server.0.keepalive_period {
+                let id = rand::random();
+                client.write_packet(&KeepAliveS2c { id });
+
+                state.got_keepalive = false;
+                state.last_keepalive_id = id;
+                state.last_send = now;
+            }
+        } else if elapsed >= server.0.keepalive_timeout {
+            debug!(client = ?entity, "keepalive timed out");
+            sender.send(ClientDisconnectEvent {
+                entity,
+                reason: DisconnectReason::TimedOut,
+            });
+        }
+    }
+}
+
+/// Checks the keepalive answered by the client and updates its [`Ping`].
+pub fn handle_keepalive(
+    r: Receiver<PacketEvent<KeepAliveC2s>, (&mut KeepaliveState, &mut Ping)>,
+    mut sender: Sender<ClientDisconnectEvent>,
+) {
+    let (state, ping) = r.query;
+    let event = r.event;
+
+    if state.got_keepalive {
+        sender.send(ClientDisconnectEvent {
+            entity: event.client,
+            reason: DisconnectReason::Kicked("Unexpected keepalive".into_text()),
+        });
+    } else if event.packet.id != state.last_keepalive_id {
+        sender.send(ClientDisconnectEvent {
+            entity: event.client,
+            reason: DisconnectReason::Kicked(
+                format!(
+                    "Keepalive IDs don't match (expected {}, got {})",
+                    state.last_keepalive_id, event.packet.id
+                )
+                .into_text(),
+            ),
+        });
+    } else {
+        state.got_keepalive = true;
+        ping.0 = event
+            .timestamp
+            .saturating_duration_since(state.last_send)
+            .as_millis() as i32;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::testing::{self, packet_event, sent_packets};
+
+    /// The reasons the client was disconnected for during a test.
+    #[derive(Component, Default, Debug)]
+    struct Disconnects(Vec<DisconnectReason>);
+
+    fn record_disconnects(
+        r: Receiver<ClientDisconnectEvent>,
+        mut clients: Fetcher<&mut Disconnects>,
+    ) {
+        if let Ok(disconnects) = clients.get_mut(r.event.entity) {
+            disconnects.0.push(r.event.reason.clone());
+        }
+    }
+
+    fn world() -> (World, EntityId) {
+        let mut world = World::new();
+        world.add_handler(send_keepalive);
+        world.add_handler(handle_keepalive);
+        world.add_handler(record_disconnects);
+
+        let server = world.spawn();
+        world.insert(server, testing::server());
+
+        let client = world.spawn();
+        world.insert(client, testing::client());
+        world.insert(client, Ping::default());
+        world.insert(client, Disconnects::default());
+
+        (world, client)
+    }
+
+    /// A keepalive sent `ago` and not answered yet.
+    fn waiting_for(id: u64, ago: Duration) -> KeepaliveState {
+        KeepaliveState {
+            got_keepalive: false,
+            last_keepalive_id: id,
+            last_send: Instant::now() - ago,
+        }
+    }
+
+    #[test]
+    fn unanswered_keepalives_time_out() {
+        let (mut world, client) = world();
+        world.insert(
+            client,
+            KeepaliveState {
+                last_send: Instant::now() - Duration::from_secs(8),
+                ..KeepaliveState::new()
+            },
+        );
+
+        world.send(Tick);
+        let packets = sent_packets(&mut world, client);
+        assert_eq!(packets.len(), 1);
+        let id = packets[0].decode::<KeepAliveS2c>().unwrap().id;
+
+        // Still within the timeout.
+        let state = world.get_mut::<KeepaliveState>(client).unwrap();
+        assert_eq!(state.last_keepalive_id, id);
+        state.last_send = Instant::now() - Duration::from_secs(14);
+        world.send(Tick);
+        assert!(world.get::<Disconnects>(client).unwrap().0.is_empty());
+
+        world.insert(client, waiting_for(id, Duration::from_secs(15)));
+        world.send(Tick);
+        let disconnects = &world.get::<Disconnects>(client).unwrap().0;
+        assert!(matches!(disconnects[..], [DisconnectReason::TimedOut]));
+    }
+
+    #[test]
+    fn keepalives_must_echo_the_id_sent() {
+        let (mut world, client) = world();
+        world.insert(client, waiting_for(7, Duration::from_millis(30)));
+
+        world.send(packet_event(client, KeepAliveC2s { id: 8 }));
+        let disconnects = &world.get::<Disconnects>(client).unwrap().0;
+        assert!(matches!(disconnects[..], [DisconnectReason::Kicked(_)]));
+        assert_eq!(world.get::<Ping>(client).unwrap().0, -1);
+
+        world.get_mut::<Disconnects>(client).unwrap().0.clear();
+        world.send(packet_event(client, KeepAliveC2s { id: 7 }));
+        assert!(world.get::<Disconnects>(client).unwrap().0.is_empty());
+        assert!(world.get::<Ping>(client).unwrap().0 >= 30);
+
+        // Answered already, so another answer is unexpected.
+        world.send(packet_event(client, KeepAliveC2s { id: 7 }));
+        let disconnects = &world.get::<Disconnects>(client).unwrap().0;
+        assert!(matches!(disconnects[..], [DisconnectReason::Kicked(_)]));
+    }
+}
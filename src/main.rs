@@ -1,25 +1,67 @@
-use std::sync::{atomic::AtomicUsize, Arc};
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
 
-use client::{Client, IpAddress, Username};
+use anvil::AnvilLevel;
+use chunk::view::{self, ChunkView, RequestedViewDistance, VisibleChunkLayer};
+use chunk::ChunkLayer;
+use client::{
+    Client, DeathLocation, Dimension, FlyingSpeed, FovModifier, HasRespawnScreen, HashedSeed,
+    IpAddress, IsDebug, IsFlat, IsHardcore, PortalCooldown, PrevGameMode, Properties,
+    ReducedDebugInfo, RespawnPosition, Username, ViewDistance,
+};
+use command::{CommandRegistry, Permissions};
+use derive_more::Deref;
+use entity::view::{EntityView, VisibleEntityLayers};
 use evenio::prelude::*;
-use event::{ClientDisconnectEvent, ClientLoginEvent, ConnectionEvent, LoginEvent, StatusEvent};
-use network::connect::handshake::connection_handler;
-use network::connect::login::login_handler;
-use status::status_handler;
+use event::{ClientDisconnectEvent, ClientJoinEvent, ClientLoginEvent};
+use evenio_plugin::WorldPluginExt;
+use generator::{FlatGenerator, LayerGenerator, NoiseGenerator};
+use inventory::creative::CreativeDropLimit;
+use inventory::{ClientInventoryState, CursorItem, HeldItem, Inventory, InventoryKind};
+use join::init_client;
+use keepalive::{KeepaliveState, Ping};
+use network::accept_connections;
+use network::connect::login::MOJANG_SESSION_SERVER;
+use position::validation::{MovementCheck, MovementValidation};
+use position::TeleportState;
+use recipe::book::{RecipeBook, SavedRecipeBooks};
+use recipe::RecipeRegistry;
+use rsa::pkcs8::EncodePublicKey;
+use rsa::RsaPrivateKey;
 use tokio::net::TcpListener;
-use tracing::{info, Level};
-use valence_protocol::CompressionThreshold;
-use valence_server_common::UniqueId;
+use tokio::time::MissedTickBehavior;
+use registry::RegistryCodec;
+use tracing::{info, warn, Level};
+use valence_entity::hitbox::HitboxPlugin;
+use valence_entity::{EntityPlugin, HeadYaw, Look, OnGround, Position};
+use valence_protocol::packets::play::player_abilities_s2c::PlayerAbilitiesFlags;
+use valence_protocol::{ident, CompressionThreshold, GameMode};
+use valence_server_common::{run_tick, ServerPlugin, ServerSettings, UniqueId};
 
-pub mod block;
+pub mod anvil;
 pub mod status;
+pub mod chunk;
 pub mod client;
+pub mod command;
+pub mod entity;
 pub mod event;
+pub mod event_loop;
+pub mod generator;
+pub mod inventory;
 pub mod network;
 pub mod position;
+pub mod recipe;
 pub mod brand;
+pub mod join;
+pub mod keepalive;
+pub mod registry;
+pub mod world_border;
+#[cfg(test)]
+mod testing;
 
-#[derive(Debug, Component)]
+#[derive(Debug)]
 pub struct Server {
     version_name: String,
     protocol_version: i32,
@@ -29,18 +71,59 @@ pub struct Server {
     favicon: String,
     connection_mode: ConnectionMode,
     threshold: CompressionThreshold,
+    /// The RSA keypair used for the online mode encryption handshake.
+    rsa_key: RsaPrivateKey,
+    /// The public half of `rsa_key` in DER form, as sent to clients.
+    public_key_der: Box<[u8]>,
+    http_client: reqwest::Client,
+    /// The maximum number of bytes of serverbound packets queued per client
+    /// before the connection stops reading from the socket.
+    incoming_byte_limit: usize,
+    /// The maximum number of bytes of clientbound packets queued per client
+    /// before the client is disconnected.
+    outgoing_byte_limit: usize,
+    /// How often a keepalive is sent to each client.
+    keepalive_period: Duration,
+    /// How long a client has to answer a keepalive before it is disconnected.
+    keepalive_timeout: Duration,
+    /// The maximum number of chunks sent to a single client per tick.
+    max_chunks_per_tick: usize,
 }
 
-#[derive(Debug, Default)]
+/// The [`ChunkLayer`] entity new clients are placed in.
+#[derive(Component, Copy, Clone, Debug, Deref)]
+pub struct SpawnLayer(pub EntityId);
+
+/// The [`Server`] shared between the world and the connection tasks.
+#[derive(Component, Clone, Debug, Deref)]
+pub struct SharedServer(pub Arc<Server>);
+
+#[derive(Debug)]
 pub enum ConnectionMode {
-    #[default]
-    Online,
+    /// Players are authenticated with the session server and the connection
+    /// is encrypted.
+    Online {
+        /// Base URL of the session server's `hasJoined` endpoint.
+        session_server: Arc<str>,
+        /// Whether the player's IP is sent to the session server, rejecting
+        /// players connecting through a proxy.
+        prevent_proxy_connections: bool,
+    },
     Offline,
     Velocity {
         secret: Arc<str>,
     }
 }
 
+impl Default for ConnectionMode {
+    fn default() -> Self {
+        Self::Online {
+            session_server: MOJANG_SESSION_SERVER.into(),
+            prevent_proxy_connections: false,
+        }
+    }
+}
+
 #[tokio::main]
 async fn main() {
     tracing_subscriber::fmt()
@@ -48,19 +131,76 @@ async fn main() {
         .init();
 
     let mut world = World::new();
-    world.add_handler(connection_handler);
-    world.add_handler(status_handler);
-    world.add_handler(login_handler);
     world.add_handler(client_login_handler);
-    world.add_handler(client_disconnect_handler);
+    world.add_handler(ignore_repeated_disconnects.high());
+    world.add_handler(client_disconnect_handler.low());
+    world.add_handler(event_loop::receive_packets);
+    world.add_handler(event_loop::dispatch_packets);
+    world.add_handler(keepalive::send_keepalive);
+    world.add_handler(keepalive::handle_keepalive);
+    world.add_handler(position::handle_teleport_confirm);
+    world.add_handler(position::handle_position);
+    world.add_handler(position::handle_full);
+    world.add_handler(position::handle_look);
+    world.add_handler(position::handle_on_ground);
+    world.add_handler(position::validation::validate_player_move);
+    world.add_handler(position::apply_player_move.low());
+    world.add_handler(inventory::handle_click_slot);
+    world.add_handler(inventory::apply_click_slot.low());
+    world.add_handler(inventory::screen::open_screen);
+    world.add_handler(inventory::screen::close_screen);
+    world.add_handler(inventory::screen::handle_close_screen);
+    world.add_handler(inventory::screen::handle_button_click);
+    world.add_handler(inventory::handle_update_selected_slot);
+    world.add_handler(inventory::creative::handle_creative_inventory_action);
+    world.add_handler(inventory::crafting::handle_craft_request);
+    world.add_handler(recipe::book::handle_recipe_book_data);
+    world.add_handler(recipe::book::handle_recipe_category_options);
+    world.add_handler(command::handle_command_execution);
+    world.add_handler(command::suggest::handle_command_completions);
+    world.add_handler(command::suggest::send_suggestions);
+    world.add_handler(entity::item::spawn_dropped_items.low());
     world.add_handler(init_client);
+    world.add_handler(world_border::init_world_border.low());
+    world.add_handler(recipe::book::init_recipe_book.low());
+    world.add_handler(command::init_command_tree.low());
+    world.add_handler(recipe::book::save_recipe_book);
+    world.add_handler(anvil::request_chunks);
+    world.add_handler(anvil::insert_loaded_chunks);
+    world.add_handler(anvil::autosave);
+    world.add_handler(generator::request_chunks);
+    world.add_handler(generator::generate_missing_chunks);
+    world.add_handler(generator::insert_generated_chunks);
+    world.add_handler(view::handle_client_settings);
+    world.add_handler(entity::physics::step_physics.low());
+    world.add_handler(chunk::light::update_light);
+    world.add_handler(view::broadcast_block_updates);
+    world.add_handler(chunk::block_entity::handle_query_block_nbt);
+    world.add_handler(view::update_chunk_views);
+    world.add_handler(entity::view::update_entity_views.low());
+    world.add_handler(inventory::sync_inventories);
+    world.add_handler(recipe::book::sync_recipe_books);
+    world.add_handler(command::sync_command_trees);
+    world.add_handler(client::flush_packets);
+
+    let settings = ServerSettings::default();
+
+    world.add_plugin(ServerPlugin {
+        settings: settings.clone(),
+    });
+    world.add_plugin(HitboxPlugin);
+    world.add_plugin(EntityPlugin);
 
-    // world.add_plugin(HitboxPlugin);
-    // world.add_plugin(EntityPlugin);
+    let rsa_key = RsaPrivateKey::new(&mut rand::thread_rng(), 1024).unwrap();
 
+    let public_key_der = rsa_key
+        .to_public_key()
+        .to_public_key_der()
+        .unwrap()
+        .into_vec()
+        .into_boxed_slice();
 
-    let server = world.spawn();
-    world.insert(server, Server {
+    let server = SharedServer(Arc::new(Server {
         version_name: "1.20.1".to_string(),
         protocol_version: 763,
         max_players: 20,
@@ -68,51 +208,329 @@ async fn main() {
         motd: "A Valence Minecraft Server".to_string(),
         favicon: "".to_string(),
         connection_mode: ConnectionMode::Offline,
-        threshold: CompressionThreshold(256),
+        threshold: settings.compression_threshold,
+        rsa_key,
+        public_key_der,
+        http_client: reqwest::Client::new(),
+        incoming_byte_limit: 2097152,
+        outgoing_byte_limit: 8388608,
+        keepalive_period: Duration::from_secs(8),
+        keepalive_timeout: Duration::from_secs(15),
+        max_chunks_per_tick: 16,
+    }));
+
+    let mut codec = RegistryCodec::default();
+
+    // Generate hills from the seed in `SEED`, if any. It adds its biomes to
+    // the codec, so it's created before the layer.
+    let noise = std::env::var("SEED").ok().and_then(|seed| match seed.parse() {
+        Ok(seed) => Some(NoiseGenerator::new(seed, &mut codec)),
+        Err(e) => {
+            warn!("invalid seed {seed}: {e}");
+            None
+        }
     });
 
+    let mut recipes = RecipeRegistry::default();
+
+    // Load the recipes of the data pack given on the command line, if any,
+    // like the `data` directory of the game's jar.
+    if let Some(data_dir) = std::env::args().nth(2) {
+        match recipes.load_data_dir(&data_dir) {
+            Ok(count) => info!("Loaded {count} recipes"),
+            Err(e) => warn!("failed to load recipes from {data_dir}: {e}"),
+        }
+    }
+
+    let layer = ChunkLayer::new(ident!("overworld"), &codec, settings.compression_threshold);
+
+    // Load the world given on the command line, if any.
+    let level = std::env::args()
+        .nth(1)
+        .map(|world_dir| AnvilLevel::new(Path::new(&world_dir).join("region"), &codec, &layer));
+
+    // Recipe books are kept next to the world, if any.
+    let saved_recipe_books = std::env::args()
+        .nth(1)
+        .map_or_else(SavedRecipeBooks::default, |world_dir| {
+            SavedRecipeBooks::new(Path::new(&world_dir).join("recipebooks"))
+        });
+
+    // Chunks missing from the world, or every chunk without a world, are
+    // generated from the seed. Without a world or a seed, a superflat world is
+    // generated.
+    let generator = match noise {
+        Some(noise) => Some(LayerGenerator::new(noise, &layer)),
+        None if level.is_none() => Some(LayerGenerator::new(FlatGenerator::default(), &layer)),
+        None => None,
+    };
+
+    let layer_entity = world.spawn();
+    if let Some(level) = level {
+        world.insert(layer_entity, level);
+    }
+    if let Some(generator) = generator {
+        world.insert(layer_entity, generator);
+    }
+    world.insert(layer_entity, layer);
+    world.insert(layer_entity, Dimension::default());
+
+    let server_entity = world.spawn();
+    world.insert(server_entity, server.clone());
+    world.insert(server_entity, codec);
+    world.insert(server_entity, SpawnLayer(layer_entity));
+    world.insert(server_entity, MovementValidation::default());
+    world.insert(server_entity, recipes);
+    world.insert(server_entity, saved_recipe_books);
+    world.insert(server_entity, CommandRegistry::default());
+
     let Ok(listener) = TcpListener::bind("127.0.0.1:25566").await else { return; };
 
+    let (new_clients_tx, new_clients_rx) = flume::unbounded();
+    tokio::spawn(accept_connections(listener, server, new_clients_tx));
+
     info!("Listening for connections");
-    loop  {
-        let Ok((stream, remote_addr)) = listener.accept().await else { continue; };
-        world.send(ConnectionEvent {
-            stream,
-            remote_addr,
-        });
+
+    let mut interval = tokio::time::interval(settings.tick_period());
+    interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+    loop {
+        interval.tick().await;
+
+        for new_client in new_clients_rx.drain() {
+            world.send(new_client);
+        }
+
+        run_tick(&mut world);
     }
 }
 
 fn client_login_handler(
-    r: ReceiverMut<ClientLoginEvent>, 
+    r: ReceiverMut<ClientLoginEvent>,
+    server: Single<(&SharedServer, &SpawnLayer)>,
+    generators: Fetcher<&LayerGenerator>,
     mut sender: Sender<(
-        Spawn,
-        Insert<Client>,
-        Insert<IpAddress>,
-        Insert<Username>,
-        Insert<UniqueId>,
+        (
+            Spawn,
+            Insert<Client>,
+            Insert<IpAddress>,
+            Insert<Username>,
+            Insert<UniqueId>,
+            Insert<Properties>,
+        ),
+        (
+            Insert<GameMode>,
+            Insert<PrevGameMode>,
+            Insert<IsHardcore>,
+            Insert<HashedSeed>,
+            Insert<ReducedDebugInfo>,
+            Insert<HasRespawnScreen>,
+            Insert<IsDebug>,
+            Insert<IsFlat>,
+            Insert<DeathLocation>,
+            Insert<PortalCooldown>,
+        ),
+        (
+            Insert<Dimension>,
+            Insert<ViewDistance>,
+            Insert<RespawnPosition>,
+            Insert<Position>,
+            Insert<Look>,
+            Insert<HeadYaw>,
+            Insert<OnGround>,
+            Insert<PlayerAbilitiesFlags>,
+            Insert<FlyingSpeed>,
+            Insert<FovModifier>,
+        ),
+        (Insert<TeleportState>, Insert<MovementCheck>, Insert<Permissions>),
+        (
+            Insert<Inventory>,
+            Insert<CursorItem>,
+            Insert<HeldItem>,
+            Insert<ClientInventoryState>,
+            Insert<CreativeDropLimit>,
+            Insert<RecipeBook>,
+        ),
+        (Insert<KeepaliveState>, Insert<Ping>),
+        (
+            Insert<VisibleChunkLayer>,
+            Insert<RequestedViewDistance>,
+            Insert<ChunkView>,
+            Insert<VisibleEntityLayers>,
+            Insert<EntityView>,
+        ),
+        ClientJoinEvent,
     )>
 ) {
+    let (server, spawn_layer) = server.0;
     let event = EventMut::take(r.event);
     let packet_io = event.packet_io;
     let info = event.info;
 
+    let generator = generators.get(spawn_layer.0).ok().map(LayerGenerator::generator);
+
     let client = sender.spawn();
-    sender.insert(client, packet_io.into_client());
+    sender.insert(client, packet_io.into_client(
+        server.incoming_byte_limit,
+        server.outgoing_byte_limit,
+    ));
     sender.insert(client, IpAddress(info.ip));
     sender.insert(client, Username(info.username));
     sender.insert(client, UniqueId(info.uuid));
+    sender.insert(client, info.properties);
+
+    sender.insert(client, GameMode::default());
+    sender.insert(client, PrevGameMode::default());
+    sender.insert(client, IsHardcore::default());
+    sender.insert(client, HashedSeed(generator.map_or(0, |generator| generator.hashed_seed())));
+    sender.insert(client, ReducedDebugInfo::default());
+    sender.insert(client, HasRespawnScreen::default());
+    sender.insert(client, IsDebug::default());
+    sender.insert(client, IsFlat(generator.is_some_and(|generator| generator.is_flat())));
+    sender.insert(client, DeathLocation::default());
+    sender.insert(client, PortalCooldown::default());
 
+    sender.insert(client, Dimension::default());
+    sender.insert(client, ViewDistance::default());
+    sender.insert(client, RespawnPosition::default());
+    sender.insert(client, Position::default());
+    sender.insert(client, Look::default());
+    sender.insert(client, HeadYaw::default());
+    sender.insert(client, OnGround::default());
+    sender.insert(client, PlayerAbilitiesFlags::default());
+    sender.insert(client, FlyingSpeed::default());
+    sender.insert(client, FovModifier::default());
+
+    sender.insert(client, TeleportState::new());
+    sender.insert(client, MovementCheck::default());
+    sender.insert(client, Permissions::default());
+
+    sender.insert(client, Inventory::new(InventoryKind::Player));
+    sender.insert(client, CursorItem::default());
+    sender.insert(client, HeldItem::default());
+    sender.insert(client, ClientInventoryState::new());
+    sender.insert(client, CreativeDropLimit::new());
+    sender.insert(client, RecipeBook::default());
+
+    sender.insert(client, KeepaliveState::new());
+    sender.insert(client, Ping::default());
+
+    sender.insert(client, VisibleChunkLayer(spawn_layer.0));
+    sender.insert(client, RequestedViewDistance::default());
+    sender.insert(client, ChunkView::default());
+    sender.insert(client, VisibleEntityLayers([spawn_layer.0].into()));
+    sender.insert(client, EntityView::default());
+
+    server.online.fetch_add(1, Ordering::Relaxed);
+
+    sender.send(ClientJoinEvent { entity: client });
 }
 
-fn init_client(r: Receiver<Insert<Client>, ()>) {
-    let entity = r.event.entity;
+/// Drops disconnect events for clients that are already gone, so every other
+/// handler sees at most one [`ClientDisconnectEvent`] per client.
+fn ignore_repeated_disconnects(r: ReceiverMut<ClientDisconnectEvent>, clients: Fetcher<&Client>) {
+    if clients.get(r.event.entity).is_err() {
+        EventMut::take(r.event);
+    }
+}
 
-    
+fn client_disconnect_handler(
+    r: Receiver<ClientDisconnectEvent>,
+    mut clients: Fetcher<(&mut Client, &Username)>,
+    server: Single<&SharedServer>,
+    mut sender: Sender<Despawn>,
+) {
+    let event = r.event;
+
+    if let Ok((client, username)) = clients.get_mut(event.entity) {
+        info!("{} left the game ({:?})", username.0, event.reason);
+
+        if let Some(message) = event.reason.message() {
+            client.kick(message);
+            // The connection is closed when the client is dropped, so this is
+            // the last chance to get the message out.
+            let _ = client.flush_packets();
+        }
+    }
 
+    server.0.online.fetch_sub(1, Ordering::Relaxed);
+    sender.despawn(event.entity);
 }
 
-fn client_disconnect_handler(r: Receiver<ClientDisconnectEvent>, mut sender: Sender<Despawn>) {
-    let entity = r.event.entity;
-    sender.despawn(entity);
+#[cfg(test)]
+mod tests {
+    use valence_protocol::packets::play::KeepAliveS2c;
+    use valence_protocol::WritePacket;
+    use valence_server_common::{Last, PreUpdate};
+
+    use super::*;
+    use crate::event::DisconnectReason;
+
+    /// A world with the disconnect handlers and a server with `online`
+    /// players.
+    fn world(online: usize) -> (World, EntityId) {
+        let mut world = World::new();
+        world.add_handler(ignore_repeated_disconnects.high());
+        world.add_handler(client_disconnect_handler.low());
+
+        let server = world.spawn();
+        let shared = testing::server();
+        shared.online.store(online, Ordering::Relaxed);
+        world.insert(server, shared);
+
+        (world, server)
+    }
+
+    fn online(world: &World, server: EntityId) -> usize {
+        world
+            .get::<SharedServer>(server)
+            .unwrap()
+            .online
+            .load(Ordering::Relaxed)
+    }
+
+    #[test]
+    fn clients_whose_connection_closed_are_removed() {
+        let (mut world, server) = world(2);
+        world.add_handler(event_loop::receive_packets);
+        world.add_handler(client::flush_packets);
+
+        let reading = world.spawn();
+        world.insert(reading, testing::closed_client());
+        world.insert(reading, Username("Alice".into()));
+
+        world.send(PreUpdate);
+        assert!(world.get::<Client>(reading).is_none());
+        assert_eq!(online(&world, server), 1);
+
+        // Nothing was read from this one, but writing to it fails.
+        let writing = world.spawn();
+        let mut closed = testing::closed_client();
+        closed.write_packet(&KeepAliveS2c { id: 1 });
+        world.insert(writing, closed);
+        world.insert(writing, Username("Bob".into()));
+
+        world.send(Last);
+        assert!(world.get::<Client>(writing).is_none());
+        assert_eq!(online(&world, server), 0);
+    }
+
+    #[test]
+    fn disconnected_clients_are_removed_once() {
+        let (mut world, server) = world(1);
+
+        let client = world.spawn();
+        world.insert(client, testing::client());
+        world.insert(client, Username("Alice".into()));
+
+        for _ in 0..2 {
+            world.send(ClientDisconnectEvent {
+                entity: client,
+                reason: DisconnectReason::TimedOut,
+            });
+        }
+
+        assert!(world.get::<Client>(client).is_none());
+        assert_eq!(online(&world, server), 0);
+    }
 }
@@ -1,10 +1,11 @@
 use std::io;
+use std::net::SocketAddr;
 
-use evenio::prelude::*;
-use tracing::warn;
+use tokio::net::TcpStream;
+use tracing::{debug, warn};
 use valence_protocol::{packets::handshaking::{handshake_c2s::HandshakeNextState, HandshakeC2s}, PacketDecoder, PacketEncoder};
 
-use crate::{block::BlockOn, event::{ConnectionEvent, LoginEvent, StatusEvent}, network::connect::legacy_ping::try_handle_legacy_ping, network::packet_io::PacketIo};
+use crate::{event::ClientLoginEvent, network::connect::legacy_ping::try_handle_legacy_ping, network::connect::login::handle_login, network::packet_io::PacketIo, status::handle_status, SharedServer};
 
 #[derive(Debug, Clone)]
 pub struct HandshakeData {
@@ -23,39 +24,59 @@ impl<'a> From<HandshakeC2s<'a>> for HandshakeData {
     }
 }
 
-pub fn connection_handler(r: ReceiverMut<ConnectionEvent>, mut sender: Sender<(StatusEvent, LoginEvent)>) {
-    let mut event = EventMut::take(r.event);
+/// Drives a freshly accepted connection through the handshake, status and
+/// login states. This runs on its own task so a slow client never holds up
+/// the game loop. Clients that finish logging in are handed to the world
+/// through `new_clients`.
+pub async fn handle_connection(
+    server: SharedServer,
+    mut stream: TcpStream,
+    remote_addr: SocketAddr,
+    new_clients: flume::Sender<ClientLoginEvent>,
+) {
+    match try_handle_legacy_ping(&mut stream).await {
+        Ok(true) => return, // Legacy ping succeeded.
+        Ok(false) => {}     // No legacy ping.
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => {}
+        Err(e) => {
+            warn!("legacy ping ended with error: {e:#}");
+        }
+    }
 
-    async {
-        match try_handle_legacy_ping(&mut event.stream).await {
-            Ok(true) => return, // Legacy ping succeeded.
-            Ok(false) => {}     // No legacy ping.
-            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => {}
-            Err(e) => {
-                warn!("legacy ping ended with error: {e:#}");
-            }
+    let mut packet_io = PacketIo::new(
+        stream,
+        PacketEncoder::new(),
+        PacketDecoder::new(),
+    );
+
+    let (next_state, handshake) = match packet_io.recv_packet::<HandshakeC2s>().await {
+        Ok(handshake) => (handshake.next_state, HandshakeData::from(handshake)),
+        Err(e) => {
+            debug!("failed to receive handshake from {remote_addr}: {e:#}");
+            return;
         }
+    };
 
-        let mut packet_io = PacketIo::new(
-            event.stream,
-            PacketEncoder::new(),
-            PacketDecoder::new(),
-        );
-
-        let Ok(handshake) = packet_io.recv_packet::<HandshakeC2s>().await else { return; };
-        match handshake.next_state {
-            HandshakeNextState::Status => {
-                sender.send(StatusEvent {
-                    packet_io,
-                });
+    match next_state {
+        HandshakeNextState::Status => {
+            if let Err(e) = handle_status(&server, &mut packet_io).await {
+                debug!("error handling status for {remote_addr}: {e:#}");
             }
-            HandshakeNextState::Login => {
-                sender.send(LoginEvent {
-                    handshake: handshake.into(),
-                    packet_io,
-                    remote_ip: event.remote_addr,
-                });
-            },
         }
-    }.block();
-}
\ No newline at end of file
+        HandshakeNextState::Login => {
+            match handle_login(&server, &mut packet_io, remote_addr, handshake).await {
+                Ok(Some(info)) => {
+                    // The receiver is only dropped when the server is shutting down.
+                    let _ = new_clients.send_async(ClientLoginEvent {
+                        packet_io,
+                        info,
+                    }).await;
+                }
+                Ok(None) => {}
+                Err(e) => {
+                    warn!("failed to log in client from {remote_addr}: {e:#}");
+                }
+            }
+        },
+    }
+}
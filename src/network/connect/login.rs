@@ -1,11 +1,18 @@
 use std::net::{IpAddr, SocketAddr};
 
-use evenio::prelude::*;
 use hmac::{Hmac, Mac};
+use num_bigint::BigInt;
+use reqwest::StatusCode;
+use rsa::Pkcs1v15Encrypt;
+use serde::Deserialize;
+use sha1::{Digest, Sha1};
 use sha2::Sha256;
-use valence_protocol::{anyhow::{self, ensure, Context}, ident, packets::login::{LoginCompressionS2c, LoginDisconnectS2c, LoginHelloC2s, LoginQueryRequestS2c, LoginQueryResponseC2s, LoginSuccessS2c}, profile::Property, text::{Color, IntoText}, uuid::Uuid, Decode, RawBytes, VarInt};
+use valence_protocol::{anyhow::{self, bail, ensure, Context}, ident, packets::login::{LoginCompressionS2c, LoginDisconnectS2c, LoginHelloC2s, LoginHelloS2c, LoginKeyC2s, LoginQueryRequestS2c, LoginQueryResponseC2s, LoginSuccessS2c}, profile::Property, text::{Color, IntoText}, uuid::Uuid, Decode, RawBytes, Text, VarInt};
 
-use crate::{block::BlockOn, client::Properties, network::packet_io::PacketIo, ClientLoginEvent, ConnectionMode, LoginEvent, Server};
+use crate::{client::Properties, network::connect::handshake::HandshakeData, network::packet_io::PacketIo, ConnectionMode, Server};
+
+/// The session server used by vanilla servers to authenticate players.
+pub const MOJANG_SESSION_SERVER: &str = "https://sessionserver.mojang.com/session/minecraft/hasJoined";
 
 #[derive(Debug, Clone)]
 pub struct ClientInfo {
@@ -15,67 +22,211 @@ pub struct ClientInfo {
     pub properties: Properties,
 }
 
-pub fn login_handler(r: ReceiverMut<LoginEvent>, server: Single<&Server>, mut sender: Sender<ClientLoginEvent>) {
-    let event = EventMut::take(r.event);
-    let mut packet_io = event.packet_io;
+/// Runs the login sequence on a connection that just completed the handshake.
+/// Returns `None` if the client was disconnected without error, for example
+/// because of a version mismatch.
+pub async fn handle_login(
+    server: &Server,
+    packet_io: &mut PacketIo,
+    remote_addr: SocketAddr,
+    handshake: HandshakeData,
+) -> anyhow::Result<Option<ClientInfo>> {
+    if handshake.protocol_version != server.protocol_version {
+        packet_io.send_packet(&LoginDisconnectS2c {
+            // TODO: use correct translation key.
+            reason: format!("Mismatched Minecraft version (server is on {})", server.version_name)
+                .color(Color::RED)
+                .into()
+        }).await?;
+
+        // Make sure client recieved disconnect packet
+        packet_io.recv_frame().await.ok();
+
+        return Ok(None);
+    }
 
-    let server = server.0;
+    let LoginHelloC2s {
+        username,
+        profile_id: _,
+    } = packet_io.recv_packet().await?;
+
+    let username = username.0.to_owned();
+
+    let info = match &server.connection_mode {
+        ConnectionMode::Online {
+            session_server,
+            prevent_proxy_connections,
+        } => {
+            let session_server = SessionServer {
+                url: session_server,
+                prevent_proxy_connections: *prevent_proxy_connections,
+            };
+            login_online(packet_io, server, &session_server, remote_addr, username).await?
+        },
+        ConnectionMode::Offline => login_offline(remote_addr, username).await,
+        ConnectionMode::Velocity { secret } => login_velocity(packet_io, username, secret).await?,
+    };
+
+    if server.threshold.0 > 0 {
+        packet_io.send_packet(&LoginCompressionS2c {
+            threshold: server.threshold.0.into(),
+        }).await?;
+
+        packet_io.set_compression(server.threshold);
+    }
 
-    let info = async {
-        if event.handshake.protocol_version != server.protocol_version {
-            packet_io.send_packet(&LoginDisconnectS2c {
-                // TODO: use correct translation key.
-                reason: format!("Mismatched Minecraft version (server is on {})", server.version_name)
-                    .color(Color::RED)
-                    .into()
-            }).await.unwrap();
+    let properties = info
+        .properties
+        .iter()
+        .map(|p| Property {
+            name: p.name.as_str(),
+            value: p.value.as_str(),
+            signature: p.signature.as_deref(),
+        })
+        .collect::<Vec<_>>();
+
+    packet_io.send_packet(&LoginSuccessS2c {
+        uuid: info.uuid,
+        username: info.username.as_str().into(),
+        properties: properties.into(),
+    }).await?;
+
+    Ok(Some(info))
+}
 
-            // Make sure client recieved disconnect packet
-            packet_io.recv_frame().await.ok();
+/// Where and how a player's session is verified in online mode.
+struct SessionServer<'a> {
+    /// Base URL of the `hasJoined` endpoint.
+    url: &'a str,
+    /// Whether the player's IP should be sent along for the session server to
+    /// compare against the IP the session was created from.
+    prevent_proxy_connections: bool,
+}
 
-            return None;
+impl SessionServer<'_> {
+    /// Builds the `hasJoined` request. The query parameters are
+    /// percent-encoded.
+    fn has_joined_request(
+        &self,
+        http_client: &reqwest::Client,
+        username: &str,
+        server_hash: &str,
+        ip: IpAddr,
+    ) -> reqwest::RequestBuilder {
+        let request = http_client
+            .get(self.url)
+            .query(&[("username", username), ("serverId", server_hash)]);
+
+        if self.prevent_proxy_connections {
+            request.query(&[("ip", ip.to_string())])
+        } else {
+            request
         }
+    }
+}
 
-        let LoginHelloC2s {
-            username,
-            profile_id: _,
-        } = packet_io.recv_packet().await.unwrap();
+async fn login_online(
+    packet_io: &mut PacketIo,
+    server: &Server,
+    session_server: &SessionServer<'_>,
+    remote_addr: SocketAddr,
+    username: String,
+) -> anyhow::Result<ClientInfo> {
+    let my_verify_token: [u8; 16] = rand::random();
 
-        let username = username.0.to_owned();
+    packet_io.send_packet(&LoginHelloS2c {
+        server_id: "".into(), // Always empty
+        public_key: &server.public_key_der,
+        verify_token: &my_verify_token,
+    }).await?;
 
-        let info = match &server.connection_mode {
-            ConnectionMode::Online => login_online(&mut packet_io).await,
-            ConnectionMode::Offline => login_offline(event.remote_ip, username).await,
-            ConnectionMode::Velocity { secret } => login_velocity(&mut packet_io, username, &secret).await.unwrap(),
-        };
+    let LoginKeyC2s {
+        shared_secret,
+        verify_token: encrypted_verify_token,
+    } = packet_io.recv_packet().await?;
 
-        if server.threshold.0 > 0 {
-            packet_io.send_packet(&LoginCompressionS2c {
-                threshold: server.threshold.0.into(),
-            }).await.unwrap();
+    let shared_secret = server
+        .rsa_key
+        .decrypt(Pkcs1v15Encrypt, shared_secret)
+        .context("failed to decrypt shared secret")?;
 
-            packet_io.set_compression(server.threshold);
-        }
+    let verify_token = server
+        .rsa_key
+        .decrypt(Pkcs1v15Encrypt, encrypted_verify_token)
+        .context("failed to decrypt verify token")?;
+
+    ensure!(
+        my_verify_token.as_slice() == verify_token,
+        "verify tokens do not match"
+    );
+
+    let crypt_key: [u8; 16] = shared_secret
+        .as_slice()
+        .try_into()
+        .context("shared secret has the wrong length")?;
+
+    packet_io.enable_encryption(&crypt_key);
+
+    let hash = Sha1::new()
+        .chain_update(&shared_secret)
+        .chain_update(&server.public_key_der)
+        .finalize();
+
+    let request = session_server.has_joined_request(
+        &server.http_client,
+        &username,
+        &auth_digest(&hash),
+        remote_addr.ip(),
+    );
+
+    let Some(profile) = fetch_profile(request).await? else {
+        packet_io.send_packet(&LoginDisconnectS2c {
+            reason: Text::translate("multiplayer.disconnect.unverified_username", []).into(),
+        }).await?;
+
+        bail!("session server could not verify username");
+    };
+
+    ensure!(profile.name == username, "usernames do not match");
+
+    Ok(ClientInfo {
+        uuid: profile.id,
+        username,
+        ip: remote_addr.ip(),
+        properties: Properties(profile.properties),
+    })
+}
+
+/// The game profile returned by the session server. The `textures` property
+/// contains the player's skin and cape.
+#[derive(Debug, Deserialize)]
+struct GameProfile {
+    id: Uuid,
+    name: String,
+    #[serde(default)]
+    properties: Vec<Property>,
+}
 
-        packet_io.send_packet(&LoginSuccessS2c {
-            uuid: info.uuid,
-            username: info.username.as_str().into(),
-            properties: Default::default(),
-        }).await.unwrap();
+/// Asks the session server whether the player has joined. Returns `None` if
+/// the session could not be verified.
+async fn fetch_profile(request: reqwest::RequestBuilder) -> anyhow::Result<Option<GameProfile>> {
+    let resp = request.send().await?;
 
-        Some(info)
-    }.block();
+    match resp.status() {
+        StatusCode::OK => {}
+        StatusCode::NO_CONTENT => return Ok(None),
+        status => bail!("session server GET request failed (status code {status})"),
+    }
 
-    let Some(info) = info else { return; };
+    let profile = resp.json().await.context("parsing game profile")?;
 
-    sender.send(ClientLoginEvent {
-        packet_io,
-        info,
-    });
+    Ok(Some(profile))
 }
 
-async fn login_online(_packet_io: &mut PacketIo) -> ClientInfo {
-    todo!()
+/// Minecraft's hex digest of a SHA-1 hash, which is interpreted as a signed
+/// two's complement number.
+fn auth_digest(bytes: &[u8]) -> String {
+    BigInt::from_signed_bytes_be(bytes).to_str_radix(16)
 }
 
 fn offline_uuid(username: &str) -> Uuid {
@@ -102,13 +253,13 @@ async fn login_velocity(
 
     let message_id = 0;
 
-    if packet_io.send_packet(&LoginQueryRequestS2c {
+    packet_io.send_packet(&LoginQueryRequestS2c {
         message_id: VarInt(message_id),
         channel: ident!("velocity:player_info").into(),
         data: RawBytes(&[VELOCITY_MIN_SUPPORTED_VERSION]).into(),
-    }).await.is_ok() {} ;
+    }).await?;
 
-    let plugin_response: LoginQueryResponseC2s = packet_io.recv_packet().await.unwrap();
+    let plugin_response: LoginQueryResponseC2s = packet_io.recv_packet().await?;
 
     ensure!(
         plugin_response.message_id.0 == message_id,
@@ -160,4 +311,114 @@ async fn login_velocity(
         ip: remote_addr,
         properties: Properties(properties)
     })
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    use super::*;
+
+    #[test]
+    fn auth_digest_matches_vanilla() {
+        let digest = |name: &str| auth_digest(&Sha1::digest(name.as_bytes()));
+
+        assert_eq!(digest("Notch"), "4ed1f46bbe04bc756bcb17c0c7ce3e4632f06a48");
+        assert_eq!(digest("jeb_"), "-7c9d5b0044c130109a5d7b5fb5c317c02b4e28c1");
+        assert_eq!(digest("simon"), "88e16a1019277b15d58faf0541e11910eb756f6");
+    }
+
+    #[test]
+    fn has_joined_url() {
+        let session_server = SessionServer {
+            url: "http://localhost/hasJoined",
+            prevent_proxy_connections: false,
+        };
+        let ip = IpAddr::from([127, 0, 0, 1]);
+        let url = |session_server: &SessionServer, username| {
+            session_server
+                .has_joined_request(&reqwest::Client::new(), username, "-7c9d", ip)
+                .build()
+                .unwrap()
+                .url()
+                .to_string()
+        };
+
+        assert_eq!(
+            url(&session_server, "Notch"),
+            "http://localhost/hasJoined?username=Notch&serverId=-7c9d"
+        );
+        assert_eq!(
+            url(&session_server, "a&b=c d"),
+            "http://localhost/hasJoined?username=a%26b%3Dc+d&serverId=-7c9d"
+        );
+
+        let session_server = SessionServer {
+            prevent_proxy_connections: true,
+            ..session_server
+        };
+
+        assert_eq!(
+            url(&session_server, "Notch"),
+            "http://localhost/hasJoined?username=Notch&serverId=-7c9d&ip=127.0.0.1"
+        );
+    }
+
+    /// Serves a single canned HTTP response and returns the base URL.
+    async fn stand_in_session_server(status: &'static str, body: &'static str) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut buf = [0; 1024];
+            let _ = stream.read(&mut buf).await.unwrap();
+
+            let resp = format!(
+                "HTTP/1.1 {status}\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{body}",
+                body.len()
+            );
+            stream.write_all(resp.as_bytes()).await.unwrap();
+        });
+
+        format!("http://{addr}/session/minecraft/hasJoined")
+    }
+
+    #[tokio::test]
+    async fn fetch_profile_with_textures() {
+        let url = stand_in_session_server(
+            "200 OK",
+            r#"{"id":"069a79f444e94726a5befca90e38aaf5","name":"Notch","properties":[{"name":"textures","value":"e30=","signature":"c2ln"}]}"#,
+        )
+        .await;
+
+        let profile = fetch_profile(reqwest::Client::new().get(&url))
+            .await
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(profile.name, "Notch");
+        assert_eq!(
+            profile.id,
+            Uuid::parse_str("069a79f4-44e9-4726-a5be-fca90e38aaf5").unwrap()
+        );
+        assert_eq!(
+            profile.properties,
+            vec![Property {
+                name: "textures".into(),
+                value: "e30=".into(),
+                signature: Some("c2ln".into()),
+            }]
+        );
+    }
+
+    #[tokio::test]
+    async fn fetch_profile_unverified() {
+        let url = stand_in_session_server("204 No Content", "").await;
+
+        let profile = fetch_profile(reqwest::Client::new().get(&url)).await.unwrap();
+
+        assert!(profile.is_none());
+    }
+}
@@ -1,3 +1,35 @@
 pub mod packet_io;
 pub mod byte_channel;
-pub mod connect;
\ No newline at end of file
+pub mod connect;
+
+use tokio::net::TcpListener;
+use tracing::warn;
+
+use crate::{event::ClientLoginEvent, network::connect::handshake::handle_connection, SharedServer};
+
+/// Accepts new connections and handles each of them on a separate task until
+/// the receiving end of `new_clients` is dropped. Clients that finish logging
+/// in are sent to the game loop through `new_clients`.
+pub async fn accept_connections(
+    listener: TcpListener,
+    server: SharedServer,
+    new_clients: flume::Sender<ClientLoginEvent>,
+) {
+    while !new_clients.is_disconnected() {
+        match listener.accept().await {
+            Ok((stream, remote_addr)) => {
+                if let Err(e) = stream.set_nodelay(true) {
+                    warn!("failed to set TCP_NODELAY: {e}");
+                }
+
+                tokio::spawn(handle_connection(
+                    server.clone(),
+                    stream,
+                    remote_addr,
+                    new_clients.clone(),
+                ));
+            }
+            Err(e) => warn!("failed to accept incoming connection: {e}"),
+        }
+    }
+}
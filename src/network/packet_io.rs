@@ -1,11 +1,12 @@
-use std::{io::{self, ErrorKind}, sync::Arc};
+use std::{io::{self, ErrorKind}, mem, sync::Arc, time::Instant};
 
 use tokio::{io::{AsyncReadExt, AsyncWriteExt}, net::TcpStream, sync::Semaphore, task::JoinHandle};
-use valence_protocol::{anyhow, bytes::BytesMut, decode::PacketFrame, CompressionThreshold, Decode, Encode, Packet, PacketDecoder, PacketEncoder};
+use tracing::{debug, warn};
+use valence_protocol::{anyhow::{self, bail}, bytes::BytesMut, decode::PacketFrame, CompressionThreshold, Decode, Encode, Packet, PacketDecoder, PacketEncoder};
 
-use crate::client::Client;
+use crate::client::{Client, ClientConnection, ReceivedPacket};
 
-use super::byte_channel::ByteSender;
+use super::byte_channel::{byte_channel, ByteSender, TrySendError};
 
 pub struct PacketIo {
     stream: TcpStream,
@@ -66,19 +67,129 @@ impl PacketIo {
         }
     }
 
+    /// Splits the connection into a reader and a writer task and wraps it in
+    /// a [`Client`]. From here on the game loop only touches the in-memory
+    /// packet queues.
+    ///
+    /// `incoming_byte_limit` and `outgoing_byte_limit` bound the amount of
+    /// memory queued in each direction.
     pub fn into_client(
         self,
-        
+        incoming_byte_limit: usize,
+        outgoing_byte_limit: usize,
     ) -> Client {
-
+        let PacketIo {
+            stream,
+            enc,
+            mut dec,
+            ..
+        } = self;
+
+        let (incoming_sender, incoming_receiver) = flume::unbounded();
+
+        let recv_sem = Arc::new(Semaphore::new(incoming_byte_limit));
+        let recv_sem_clone = recv_sem.clone();
+
+        let (mut reader, mut writer) = stream.into_split();
+
+        let reader_task = tokio::spawn(async move {
+            let mut buf = BytesMut::new();
+
+            loop {
+                let frame = match dec.try_next_packet() {
+                    Ok(Some(frame)) => frame,
+                    Ok(None) => {
+                        // Incomplete packet. Need more data.
+
+                        buf.reserve(READ_BUF_SIZE);
+                        match reader.read_buf(&mut buf).await {
+                            Ok(0) => break, // Reader is at EOF.
+                            Ok(_) => {}
+                            Err(e) => {
+                                debug!("error reading data from stream: {e}");
+                                break;
+                            }
+                        }
+
+                        dec.queue_bytes(buf.split());
+
+                        continue;
+                    }
+                    Err(e) => {
+                        warn!("error decoding packet frame: {e:#}");
+                        break;
+                    }
+                };
+
+                let timestamp = Instant::now();
+
+                // Estimate memory usage of this packet.
+                let cost = packet_cost(frame.body.len());
+
+                if cost > incoming_byte_limit {
+                    debug!(
+                        cost,
+                        incoming_byte_limit,
+                        "cost of received packet is greater than the incoming memory limit"
+                    );
+                    // We would never acquire enough permits, so we should exit instead of getting
+                    // stuck.
+                    break;
+                }
+
+                // Wait until there's enough space for this packet.
+                let Ok(permits) = recv_sem.acquire_many(cost as u32).await else {
+                    // Semaphore closed.
+                    break;
+                };
+
+                // The permits will be added back on the other side of the channel.
+                permits.forget();
+
+                let packet = ReceivedPacket {
+                    timestamp,
+                    id: frame.id,
+                    body: frame.body.freeze(),
+                };
+
+                if incoming_sender.try_send(packet).is_err() {
+                    // Channel closed.
+                    break;
+                }
+            }
+        });
+
+        let (outgoing_sender, mut outgoing_receiver) = byte_channel(outgoing_byte_limit);
+
+        let writer_task = tokio::spawn(async move {
+            loop {
+                let bytes = match outgoing_receiver.recv_async().await {
+                    Ok(bytes) => bytes,
+                    Err(e) => {
+                        debug!("error receiving packet data: {e}");
+                        break;
+                    }
+                };
+
+                if let Err(e) = writer.write_all(&bytes).await {
+                    debug!("error writing data to stream: {e}");
+                    break;
+                }
+            }
+        });
 
         Client {
-            stream: self.stream,
-            enc: self.enc,
+            conn: Box::new(RealClientConnection {
+                send: outgoing_sender,
+                recv: incoming_receiver,
+                recv_sem: recv_sem_clone,
+                reader_task,
+                _writer_task: writer_task,
+            }),
+            enc,
         }
     }
 
-    #[allow(dead_code)]
     pub fn set_compression(&mut self, threshold: CompressionThreshold) {
         self.enc.set_compression(threshold);
         self.dec.set_compression(threshold);
@@ -90,13 +201,55 @@ impl PacketIo {
     }
 }
 
+/// Estimated memory used by a queued packet with a body of `body_len` bytes.
+fn packet_cost(body_len: usize) -> usize {
+    mem::size_of::<ReceivedPacket>() + body_len
+}
+
 struct RealClientConnection {
     send: ByteSender,
     recv: flume::Receiver<ReceivedPacket>,
     /// Limits the amount of data queued in the `recv` channel. Each permit
     /// represents one byte.
     recv_sem: Arc<Semaphore>,
-    _cleanup: CleanupOnDrop,
     reader_task: JoinHandle<()>,
-    writer_task: JoinHandle<()>,
+    _writer_task: JoinHandle<()>,
+}
+
+impl Drop for RealClientConnection {
+    fn drop(&mut self) {
+        // The writer task stops on its own once the outgoing channel is
+        // drained and `send` is dropped, so queued packets (like a disconnect
+        // message) still make it out.
+        self.reader_task.abort();
+    }
+}
+
+impl ClientConnection for RealClientConnection {
+    fn try_send(&mut self, bytes: BytesMut) -> anyhow::Result<()> {
+        match self.send.try_send(bytes) {
+            Ok(()) => Ok(()),
+            Err(TrySendError::Full(_)) => bail!(
+                "reached configured outgoing limit of {} bytes",
+                self.send.limit()
+            ),
+            Err(TrySendError::Disconnected(_)) => bail!("client disconnected"),
+        }
+    }
+
+    fn try_recv(&mut self) -> anyhow::Result<Option<ReceivedPacket>> {
+        match self.recv.try_recv() {
+            Ok(packet) => {
+                self.recv_sem.add_permits(packet_cost(packet.body.len()));
+
+                Ok(Some(packet))
+            }
+            Err(flume::TryRecvError::Empty) => Ok(None),
+            Err(flume::TryRecvError::Disconnected) => bail!("client disconnected"),
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.recv.len()
+    }
 }
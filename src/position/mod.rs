@@ -0,0 +1,493 @@
+//! Applies the movement reported by clients to their player entity.
+//!
+//! Clients send their own position, look and whether they're on the ground
+//! every tick they change. Each movement packet is turned into a
+//! [`PlayerMoveEvent`], which is applied to [`Position`], [`Look`],
+//! [`HeadYaw`] and [`OnGround`] unless a handler cancels it.
+//!
+//! Teleporting a client with [`TeleportState::teleport`] makes the client
+//! ignore its own movement until it confirms the teleport, and the server
+//! ignores any movement sent before the confirmation since it was made from
+//! the old position.
+//!
+//! Moves that are too fast or go through blocks are rejected by
+//! [`validation`], and moves outside the [`WorldBorder`] kick the client.
+
+pub mod validation;
+
+use evenio::prelude::*;
+use tracing::debug;
+use valence_entity::{HeadYaw, Look, OnGround, Position};
+use valence_protocol::math::DVec3;
+use valence_protocol::packets::play::player_position_look_s2c::PlayerPositionLookFlags;
+use valence_protocol::packets::play::{
+    FullC2s, LookAndOnGroundC2s, OnGroundOnlyC2s, PlayerPositionLookS2c, PositionAndOnGroundC2s,
+    TeleportConfirmC2s,
+};
+use valence_protocol::text::IntoText;
+use valence_protocol::{Text, VarInt, WritePacket};
+
+use crate::chunk::view::VisibleChunkLayer;
+use crate::client::Client;
+use crate::event::{ClientDisconnectEvent, DisconnectReason};
+use crate::event_loop::PacketEvent;
+use crate::world_border::{border_of, WorldBorder};
+
+/// The teleports sent to a client that it hasn't confirmed yet.
+#[derive(Component, Debug)]
+pub struct TeleportState {
+    /// The number of teleports ever sent, which is the ID of the next one.
+    sent: u32,
+    pending: u32,
+}
+
+impl TeleportState {
+    pub(crate) fn new() -> Self {
+        Self {
+            sent: 0,
+            pending: 0,
+        }
+    }
+
+    /// The number of teleports the client hasn't confirmed yet. Movement from
+    /// the client is ignored while this is nonzero.
+    pub fn pending_teleports(&self) -> u32 {
+        self.pending
+    }
+
+    /// Sends a [`PlayerPositionLookS2c`] moving the client to `position`
+    /// with `look`. The client's [`Position`] and [`Look`] should be set to
+    /// the same values, since the client's movement is ignored until it
+    /// confirms.
+    pub fn teleport(&mut self, client: &mut impl WritePacket, position: DVec3, look: Look) {
+        client.write_packet(&PlayerPositionLookS2c {
+            position,
+            yaw: look.yaw,
+            pitch: look.pitch,
+            flags: PlayerPositionLookFlags::new(),
+            teleport_id: VarInt(self.sent as i32),
+        });
+
+        self.sent = self.sent.wrapping_add(1);
+        self.pending += 1;
+    }
+
+    /// The ID of the oldest unconfirmed teleport. Clients confirm teleports in
+    /// the order they were sent.
+    fn expected_id(&self) -> Option<i32> {
+        (self.pending > 0).then(|| self.sent.wrapping_sub(self.pending) as i32)
+    }
+}
+
+/// Sent when a client moves, turns or lands, before the move is applied.
+///
+/// Handlers can take the event mutably and [`cancel`] it to keep the player
+/// where it was. The client is teleported back to its current [`Position`]
+/// and [`Look`].
+///
+/// [`cancel`]: Self::cancel
+#[derive(Event, Clone, Debug)]
+pub struct PlayerMoveEvent {
+    #[event(target)]
+    pub client: EntityId,
+    pub old_position: DVec3,
+    pub position: DVec3,
+    pub old_look: Look,
+    pub look: Look,
+    pub old_on_ground: bool,
+    pub on_ground: bool,
+    cancelled: bool,
+}
+
+impl PlayerMoveEvent {
+    /// Rejects the move and puts the client back where it was.
+    pub fn cancel(&mut self) {
+        self.cancelled = true;
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled
+    }
+}
+
+/// The current movement of a client, which moves from the client are made
+/// relative to.
+#[derive(Query)]
+pub struct MoveQuery<'a> {
+    teleport: &'a TeleportState,
+    position: &'a Position,
+    look: &'a Look,
+    on_ground: &'a OnGround,
+    layer: &'a VisibleChunkLayer,
+}
+
+pub fn handle_position(
+    r: Receiver<PacketEvent<PositionAndOnGroundC2s>, MoveQuery>,
+    borders: Fetcher<&WorldBorder>,
+    mut sender: Sender<(PlayerMoveEvent, ClientDisconnectEvent)>,
+) {
+    let packet = &r.event.packet;
+    let look = *r.query.look;
+
+    send_move(
+        r.event.client,
+        &r.query,
+        border_of(&borders, r.query.layer.0),
+        packet.position,
+        look,
+        packet.on_ground,
+        &mut sender,
+    );
+}
+
+pub fn handle_full(
+    r: Receiver<PacketEvent<FullC2s>, MoveQuery>,
+    borders: Fetcher<&WorldBorder>,
+    mut sender: Sender<(PlayerMoveEvent, ClientDisconnectEvent)>,
+) {
+    let packet = &r.event.packet;
+    let look = client_look(packet.yaw, packet.pitch);
+
+    send_move(
+        r.event.client,
+        &r.query,
+        border_of(&borders, r.query.layer.0),
+        packet.position,
+        look,
+        packet.on_ground,
+        &mut sender,
+    );
+}
+
+pub fn handle_look(
+    r: Receiver<PacketEvent<LookAndOnGroundC2s>, MoveQuery>,
+    borders: Fetcher<&WorldBorder>,
+    mut sender: Sender<(PlayerMoveEvent, ClientDisconnectEvent)>,
+) {
+    let packet = &r.event.packet;
+    let position = r.query.position.0;
+    let look = client_look(packet.yaw, packet.pitch);
+
+    send_move(
+        r.event.client,
+        &r.query,
+        border_of(&borders, r.query.layer.0),
+        position,
+        look,
+        packet.on_ground,
+        &mut sender,
+    );
+}
+
+pub fn handle_on_ground(
+    r: Receiver<PacketEvent<OnGroundOnlyC2s>, MoveQuery>,
+    borders: Fetcher<&WorldBorder>,
+    mut sender: Sender<(PlayerMoveEvent, ClientDisconnectEvent)>,
+) {
+    let on_ground = r.event.packet.on_ground;
+    let (position, look) = (r.query.position.0, *r.query.look);
+
+    send_move(
+        r.event.client,
+        &r.query,
+        border_of(&borders, r.query.layer.0),
+        position,
+        look,
+        on_ground,
+        &mut sender,
+    );
+}
+
+fn send_move(
+    client: EntityId,
+    q: &MoveQuery,
+    border: WorldBorder,
+    position: DVec3,
+    look: Look,
+    on_ground: bool,
+    sender: &mut Sender<(PlayerMoveEvent, ClientDisconnectEvent)>,
+) {
+    if !position.is_finite() || !look.yaw.is_finite() || !look.pitch.is_finite() {
+        sender.send(ClientDisconnectEvent {
+            entity: client,
+            reason: DisconnectReason::Kicked(Text::translate(
+                "multiplayer.disconnect.invalid_player_movement",
+                [],
+            )),
+        });
+        return;
+    }
+
+    if !border.contains(position) {
+        sender.send(ClientDisconnectEvent {
+            entity: client,
+            reason: DisconnectReason::Kicked("Illegal position".into_text()),
+        });
+        return;
+    }
+
+    // The move was made before the client knew about the teleport.
+    if q.teleport.pending > 0 {
+        return;
+    }
+
+    sender.send(PlayerMoveEvent {
+        client,
+        old_position: q.position.0,
+        position,
+        old_look: *q.look,
+        look,
+        old_on_ground: q.on_ground.0,
+        on_ground,
+        cancelled: false,
+    });
+}
+
+/// Clients let the yaw grow without bound as the player turns around, so it
+/// is wrapped to -180..180 like the game does.
+fn client_look(yaw: f32, pitch: f32) -> Look {
+    let yaw = (yaw + 180.0).rem_euclid(360.0) - 180.0;
+    Look::new(yaw, pitch.clamp(-90.0, 90.0))
+}
+
+/// Applies the moves that weren't cancelled and teleports the clients whose
+/// moves were back.
+pub fn apply_player_move(
+    r: Receiver<
+        PlayerMoveEvent,
+        (
+            &mut Client,
+            &mut TeleportState,
+            &mut Position,
+            &mut Look,
+            &mut HeadYaw,
+            &mut OnGround,
+        ),
+    >,
+) {
+    let (client, teleport, position, look, head_yaw, on_ground) = r.query;
+    let event = r.event;
+
+    if event.cancelled {
+        teleport.teleport(client, position.0, *look);
+        return;
+    }
+
+    position.0 = event.position;
+    *look = event.look;
+    head_yaw.0 = event.look.yaw;
+    on_ground.0 = event.on_ground;
+}
+
+/// Checks the teleport confirmed by the client.
+pub fn handle_teleport_confirm(
+    r: Receiver<PacketEvent<TeleportConfirmC2s>, &mut TeleportState>,
+    mut sender: Sender<ClientDisconnectEvent>,
+) {
+    let state = r.query;
+    let event = r.event;
+    let id = event.packet.teleport_id.0;
+
+    match state.expected_id() {
+        Some(expected) if expected == id => state.pending -= 1,
+        Some(expected) => sender.send(ClientDisconnectEvent {
+            entity: event.client,
+            reason: DisconnectReason::Kicked(
+                format!("Unexpected teleport ID (expected {expected}, got {id})").into_text(),
+            ),
+        }),
+        None => {
+            debug!(client = ?event.client, id, "unexpected teleport confirmation");
+            sender.send(ClientDisconnectEvent {
+                entity: event.client,
+                reason: DisconnectReason::Kicked("Unexpected teleport confirmation".into_text()),
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use valence_protocol::encode::PacketWriter;
+    use valence_protocol::{CompressionThreshold, PacketDecoder};
+
+    use super::*;
+    use crate::testing::packet_event;
+
+    /// What happened to a player during a test.
+    #[derive(Component, Default, Debug)]
+    struct Recorded {
+        moves: Vec<DVec3>,
+        kicked: bool,
+    }
+
+    fn record_moves(r: Receiver<PlayerMoveEvent, &mut Recorded>) {
+        r.query.moves.push(r.event.position);
+    }
+
+    fn record_kicks(r: Receiver<ClientDisconnectEvent>, mut recorded: Fetcher<&mut Recorded>) {
+        if let Ok(recorded) = recorded.get_mut(r.event.entity) {
+            recorded.kicked = true;
+        }
+    }
+
+    fn world() -> World {
+        let mut world = World::new();
+        world.add_handler(handle_position);
+        world.add_handler(handle_teleport_confirm);
+        world.add_handler(record_moves);
+        world.add_handler(record_kicks);
+        world
+    }
+
+    fn spawn_player(world: &mut World, teleport: TeleportState) -> EntityId {
+        let player = world.spawn();
+        world.insert(player, teleport);
+        world.insert(player, Position::default());
+        world.insert(player, Look::default());
+        world.insert(player, OnGround::default());
+        world.insert(player, VisibleChunkLayer::default());
+        world.insert(player, Recorded::default());
+        player
+    }
+
+    fn move_to(world: &mut World, player: EntityId, position: DVec3) {
+        world.send(packet_event(
+            player,
+            PositionAndOnGroundC2s {
+                position,
+                on_ground: true,
+            },
+        ));
+    }
+
+    fn confirm(world: &mut World, player: EntityId, id: i32) {
+        world.send(packet_event(
+            player,
+            TeleportConfirmC2s {
+                teleport_id: VarInt(id),
+            },
+        ));
+    }
+
+    /// A client that was sent two teleports.
+    fn teleported_twice() -> TeleportState {
+        let mut state = TeleportState::new();
+        let mut buf = vec![];
+        let mut writer = PacketWriter::new(&mut buf, CompressionThreshold(-1));
+
+        state.teleport(&mut writer, DVec3::ZERO, Look::default());
+        state.teleport(&mut writer, DVec3::ZERO, Look::default());
+        state
+    }
+
+    #[test]
+    fn teleports_are_confirmed_in_order() {
+        let mut state = TeleportState::new();
+        assert_eq!(state.expected_id(), None);
+
+        let mut buf = vec![];
+        let mut writer = PacketWriter::new(&mut buf, CompressionThreshold(-1));
+        state.teleport(
+            &mut writer,
+            DVec3::new(0.5, 64.0, 0.5),
+            Look::new(90.0, 0.0),
+        );
+        state.teleport(&mut writer, DVec3::new(8.5, 64.0, 0.5), Look::default());
+        assert_eq!(state.pending_teleports(), 2);
+
+        let mut decoder = PacketDecoder::new();
+        decoder.queue_slice(&buf);
+        let ids: Vec<_> = std::iter::from_fn(|| decoder.try_next_packet().unwrap())
+            .map(|frame| {
+                frame
+                    .decode::<PlayerPositionLookS2c>()
+                    .unwrap()
+                    .teleport_id
+                    .0
+            })
+            .collect();
+        assert_eq!(ids, [0, 1]);
+
+        let mut world = world();
+        let player = spawn_player(&mut world, state);
+
+        // Made from before the teleports, so it's ignored.
+        move_to(&mut world, player, DVec3::new(1.0, 64.0, 1.0));
+
+        confirm(&mut world, player, 0);
+        let state = world.get::<TeleportState>(player).unwrap();
+        assert_eq!(state.expected_id(), Some(1));
+
+        confirm(&mut world, player, 1);
+        let state = world.get::<TeleportState>(player).unwrap();
+        assert_eq!(state.expected_id(), None);
+
+        move_to(&mut world, player, DVec3::new(9.0, 64.0, 1.0));
+
+        let recorded = world.get::<Recorded>(player).unwrap();
+        assert_eq!(recorded.moves, [DVec3::new(9.0, 64.0, 1.0)]);
+        assert!(!recorded.kicked);
+    }
+
+    #[test]
+    fn unexpected_teleport_confirmations_kick() {
+        let mut world = world();
+
+        let out_of_order = spawn_player(&mut world, teleported_twice());
+        confirm(&mut world, out_of_order, 1);
+
+        let wrong_id = spawn_player(&mut world, teleported_twice());
+        confirm(&mut world, wrong_id, 7);
+
+        let unexpected = spawn_player(&mut world, TeleportState::new());
+        confirm(&mut world, unexpected, 0);
+
+        for player in [out_of_order, wrong_id, unexpected] {
+            assert!(world.get::<Recorded>(player).unwrap().kicked);
+        }
+    }
+
+    #[test]
+    fn positions_outside_the_world_border_kick() {
+        let mut world = world();
+
+        // Without a layer, the border is as large as the game allows.
+        let player = spawn_player(&mut world, TeleportState::new());
+
+        move_to(&mut world, player, DVec3::new(29_999_984.0, 64.0, 0.0));
+        assert!(!world.get::<Recorded>(player).unwrap().kicked);
+
+        move_to(&mut world, player, DVec3::new(0.0, 64.0, -3.1e7));
+        let recorded = world.get::<Recorded>(player).unwrap();
+        assert!(recorded.kicked);
+        assert_eq!(recorded.moves, [DVec3::new(29_999_984.0, 64.0, 0.0)]);
+
+        let layer = world.spawn();
+        world.insert(
+            layer,
+            WorldBorder {
+                center_x: 0.0,
+                center_z: 0.0,
+                diameter: 100.0,
+            },
+        );
+
+        let player = spawn_player(&mut world, TeleportState::new());
+        world.insert(player, VisibleChunkLayer(layer));
+
+        move_to(&mut world, player, DVec3::new(-50.0, 64.0, 49.5));
+        assert!(!world.get::<Recorded>(player).unwrap().kicked);
+
+        move_to(&mut world, player, DVec3::new(-50.0, 64.0, 50.5));
+        assert!(world.get::<Recorded>(player).unwrap().kicked);
+    }
+
+    #[test]
+    fn client_look_is_wrapped() {
+        assert_eq!(client_look(90.0, 10.0), Look::new(90.0, 10.0));
+        assert_eq!(client_look(270.0, 0.0), Look::new(-90.0, 0.0));
+        assert_eq!(client_look(-540.0, 0.0), Look::new(-180.0, 0.0));
+        assert_eq!(client_look(720.0 + 45.0, 100.0), Look::new(45.0, 90.0));
+    }
+}
@@ -0,0 +1,637 @@
+//! Rejects moves a player couldn't have made without cheating.
+//!
+//! Each [`PlayerMoveEvent`] is checked against how far the player may move in
+//! a tick, how high it may rise without standing on something, and the blocks
+//! it would have to pass through. Invalid moves are cancelled, which teleports
+//! the player back, and players that keep sending them are kicked.
+//!
+//! The checks err on the side of letting moves through. Players can move
+//! freely up and down in liquids, on ladders and with levitation, bounce back
+//! up to the height they fell from on slime and beds, and move faster for a
+//! while after leaving ice. Collisions are checked with the hitbox of a
+//! crawling player, since the pose of the player isn't known. Elytra flight
+//! and knockback aren't accounted for, so servers using them should raise
+//! the limits in [`MovementValidation`].
+
+use evenio::prelude::*;
+use thiserror::Error;
+use tracing::debug;
+use valence_entity::active_status_effects::ActiveStatusEffects;
+use valence_entity::attributes::{EntityAttribute, EntityAttributes};
+use valence_protocol::block::BlockKind;
+use valence_protocol::math::{Aabb, DVec3};
+use valence_protocol::packets::play::player_abilities_s2c::PlayerAbilitiesFlags;
+use valence_protocol::status_effects::StatusEffect;
+use valence_protocol::{BlockPos, BlockState, GameMode, Text};
+
+use super::PlayerMoveEvent;
+use crate::chunk::view::VisibleChunkLayer;
+use crate::chunk::ChunkLayer;
+use crate::client::FlyingSpeed;
+use crate::event::{ClientDisconnectEvent, DisconnectReason};
+
+/// The base movement speed of players, used when a player has no
+/// [`EntityAttributes`].
+const DEFAULT_MOVEMENT_SPEED: f64 = 0.1;
+
+const PLAYER_WIDTH: f64 = 0.6;
+const PLAYER_HEIGHT: f64 = 1.8;
+/// The height of a crawling or swimming player, the smallest a player gets.
+const CRAWLING_HEIGHT: f64 = 0.6;
+
+/// How much the hitbox is shrunk before checking collisions, so that a player
+/// touching a block isn't inside it.
+const COLLISION_EPSILON: f64 = 1e-5;
+
+/// The longest step along a move at which collisions are checked. It's less
+/// than the width of a player, so a player can't skip over a block.
+const SWEEP_STEP: f64 = 0.25;
+
+/// How many ticks a player keeps its speed after leaving a slippery block.
+const SLIPPERY_TICKS: u32 = 40;
+
+/// How many valid moves it takes to forget a violation.
+const MOVES_PER_FORGIVEN_VIOLATION: u32 = 20;
+
+/// The limits moves are checked against. Insert a changed copy on the server
+/// entity to adjust them.
+#[derive(Component, Clone, PartialEq, Debug)]
+pub struct MovementValidation {
+    /// Whether moves are checked at all.
+    pub enabled: bool,
+    /// How many blocks a player may move horizontally in one tick per point
+    /// of movement speed. Sprint jumping at the base speed of 0.1 moves up to
+    /// about 0.6 blocks.
+    pub horizontal_per_speed: f64,
+    /// How many blocks a player that's allowed to fly may move horizontally
+    /// in one tick per point of [`FlyingSpeed`].
+    pub flying_horizontal_per_speed: f64,
+    /// What the horizontal limit is multiplied by shortly after standing on
+    /// ice.
+    pub slippery_multiplier: f64,
+    /// How many blocks a player may rise in one tick, which is the most of
+    /// jumping and stepping up a block.
+    pub max_rise_per_tick: f64,
+    /// How many blocks a player may rise above where it last stood.
+    pub max_jump_height: f64,
+    /// Added to every limit to allow for rounding and lag.
+    pub tolerance: f64,
+    /// Whether moves into or through blocks are rejected.
+    pub check_collisions: bool,
+    /// The number of violations at which a player is kicked, or `None` to
+    /// only ever teleport the player back.
+    pub max_violations: Option<u32>,
+}
+
+impl Default for MovementValidation {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            horizontal_per_speed: 7.0,
+            flying_horizontal_per_speed: 24.0,
+            slippery_multiplier: 2.5,
+            max_rise_per_tick: 0.6,
+            max_jump_height: 1.25,
+            tolerance: 0.1,
+            check_collisions: true,
+            max_violations: Some(20),
+        }
+    }
+}
+
+/// What the checks remember about a player between moves.
+#[derive(Component, Clone, Default, Debug)]
+pub struct MovementCheck {
+    /// Where the last valid move ended. A move starting anywhere else means
+    /// the player was moved by the server.
+    last_position: Option<DVec3>,
+    /// The Y coordinate the player may jump from.
+    ground_y: f64,
+    /// The highest Y coordinate reached since standing on a block that isn't
+    /// bouncy.
+    peak_y: f64,
+    slippery_ticks: u32,
+    violations: u32,
+    valid_moves: u32,
+}
+
+impl MovementCheck {
+    /// The number of invalid moves the player made recently.
+    pub fn violations(&self) -> u32 {
+        self.violations
+    }
+}
+
+/// Why a move was rejected.
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Error)]
+pub enum InvalidMove {
+    #[error("moved too quickly")]
+    TooFast,
+    #[error("rose without standing on anything")]
+    Flying,
+    #[error("moved into or through a block")]
+    Collision,
+    #[error("moved into an unloaded chunk")]
+    Unloaded,
+}
+
+/// The limits of a single move, worked out from the settings and the state
+/// of the player.
+#[derive(Copy, Clone, Debug)]
+struct Limits {
+    horizontal: f64,
+    slippery_multiplier: f64,
+    rise_per_tick: f64,
+    jump_height: f64,
+    check_vertical: bool,
+    check_collisions: bool,
+}
+
+impl Limits {
+    fn new(settings: &MovementValidation, player: &PlayerQuery) -> Self {
+        let effects = player.effects;
+        let amplifier = |effect| {
+            effects
+                .and_then(|effects| effects.get_current_effect(effect))
+                .map(|effect| effect.amplifier() as f64 + 1.0)
+        };
+
+        let movement_speed = player
+            .attributes
+            .and_then(|attributes| {
+                attributes.get_compute_value(EntityAttribute::GenericMovementSpeed)
+            })
+            .unwrap_or(DEFAULT_MOVEMENT_SPEED);
+        let speed_effect = amplifier(StatusEffect::Speed).map_or(1.0, |level| 1.0 + 0.2 * level);
+        let jump_boost = amplifier(StatusEffect::JumpBoost).unwrap_or(0.0);
+
+        let can_fly = player.abilities.allow_flying() || player.abilities.flying();
+        let spectator = *player.game_mode == GameMode::Spectator;
+
+        let mut horizontal = movement_speed * speed_effect * settings.horizontal_per_speed;
+        if can_fly {
+            horizontal =
+                horizontal.max(player.flying_speed.0 as f64 * settings.flying_horizontal_per_speed);
+        }
+
+        Self {
+            horizontal: horizontal + settings.tolerance,
+            slippery_multiplier: settings.slippery_multiplier,
+            rise_per_tick: settings.max_rise_per_tick + 0.1 * jump_boost + settings.tolerance,
+            jump_height: settings.max_jump_height + 0.75 * jump_boost + settings.tolerance,
+            check_vertical: !can_fly && !spectator && amplifier(StatusEffect::Levitation).is_none(),
+            check_collisions: settings.check_collisions && !spectator,
+        }
+    }
+}
+
+#[derive(Query)]
+pub struct PlayerQuery<'a> {
+    check: &'a mut MovementCheck,
+    layer: &'a VisibleChunkLayer,
+    game_mode: &'a GameMode,
+    abilities: &'a PlayerAbilitiesFlags,
+    flying_speed: &'a FlyingSpeed,
+    attributes: Option<&'a EntityAttributes>,
+    effects: Option<&'a ActiveStatusEffects>,
+}
+
+/// Cancels invalid moves and kicks players with too many violations.
+pub fn validate_player_move(
+    mut r: ReceiverMut<PlayerMoveEvent, PlayerQuery>,
+    layers: Fetcher<&ChunkLayer>,
+    settings: Single<&MovementValidation>,
+    mut sender: Sender<ClientDisconnectEvent>,
+) {
+    let settings = settings.0;
+
+    if !settings.enabled || r.event.is_cancelled() {
+        return;
+    }
+
+    let Ok(layer) = layers.get(r.query.layer.0) else {
+        return;
+    };
+
+    let limits = Limits::new(settings, &r.query);
+    let check = r.query.check;
+    let (old, new) = (r.event.old_position, r.event.position);
+
+    match check_move(layer, check, limits, old, new) {
+        Ok(()) => {
+            check.valid_moves += 1;
+            if check.valid_moves >= MOVES_PER_FORGIVEN_VIOLATION {
+                check.valid_moves = 0;
+                check.violations = check.violations.saturating_sub(1);
+            }
+        }
+        Err(e) => {
+            debug!(client = ?r.event.client, ?old, ?new, "invalid move: {e}");
+            r.event.cancel();
+
+            check.violations += 1;
+            check.valid_moves = 0;
+
+            if settings
+                .max_violations
+                .is_some_and(|max| check.violations >= max)
+            {
+                sender.send(ClientDisconnectEvent {
+                    entity: r.event.client,
+                    reason: DisconnectReason::Kicked(Text::translate(
+                        "multiplayer.disconnect.invalid_player_movement",
+                        [],
+                    )),
+                });
+            }
+        }
+    }
+}
+
+/// Checks the move from `old` to `new` and records it in `check` if it's
+/// valid.
+fn check_move(
+    layer: &ChunkLayer,
+    check: &mut MovementCheck,
+    limits: Limits,
+    old: DVec3,
+    new: DVec3,
+) -> Result<(), InvalidMove> {
+    if check.last_position != Some(old) {
+        check.ground_y = old.y;
+        check.peak_y = old.y;
+        check.slippery_ticks = 0;
+    }
+
+    if let Some(block) = supporting_block(layer, old) {
+        if !is_bouncy(block) {
+            check.peak_y = old.y;
+        }
+        check.ground_y = check.peak_y;
+
+        if is_slippery(block) {
+            check.slippery_ticks = SLIPPERY_TICKS;
+        }
+    }
+
+    let mut horizontal_limit = limits.horizontal;
+    if check.slippery_ticks > 0 {
+        horizontal_limit *= limits.slippery_multiplier;
+    }
+
+    if (new.x - old.x).hypot(new.z - old.z) > horizontal_limit {
+        return Err(InvalidMove::TooFast);
+    }
+
+    let free_vertical = !limits.check_vertical
+        || touches_block(layer, player_box(old, PLAYER_HEIGHT), frees_vertical)
+        || touches_block(layer, player_box(new, PLAYER_HEIGHT), frees_vertical);
+
+    if !free_vertical
+        && (new.y - old.y > limits.rise_per_tick || new.y - check.ground_y > limits.jump_height)
+    {
+        return Err(InvalidMove::Flying);
+    }
+
+    if limits.check_collisions {
+        check_collisions(layer, old, new)?;
+    }
+
+    check.last_position = Some(new);
+    check.peak_y = check.peak_y.max(new.y);
+    check.slippery_ticks = check.slippery_ticks.saturating_sub(1);
+
+    if free_vertical {
+        check.ground_y = new.y;
+        check.peak_y = new.y;
+    }
+
+    Ok(())
+}
+
+/// Checks that the player doesn't run into a block it wasn't already inside
+/// anywhere along the move.
+fn check_collisions(layer: &ChunkLayer, old: DVec3, new: DVec3) -> Result<(), InvalidMove> {
+    let mut inside = vec![];
+    if !layer.collision_shapes(player_box(old, CRAWLING_HEIGHT), &mut inside) {
+        return Err(InvalidMove::Unloaded);
+    }
+
+    let steps = ((new - old).length() / SWEEP_STEP).ceil().max(1.0) as u32;
+    let mut shapes = vec![];
+
+    for step in 1..=steps {
+        let pos = old.lerp(new, step as f64 / steps as f64);
+
+        shapes.clear();
+        if !layer.collision_shapes(player_box(pos, CRAWLING_HEIGHT), &mut shapes) {
+            return Err(InvalidMove::Unloaded);
+        }
+
+        if shapes.iter().any(|shape| !inside.contains(shape)) {
+            return Err(InvalidMove::Collision);
+        }
+    }
+
+    Ok(())
+}
+
+/// The hitbox of a player standing at `pos`, shrunk slightly so that touching
+/// a block doesn't count as being inside it.
+fn player_box(pos: DVec3, height: f64) -> Aabb {
+    let half_width = PLAYER_WIDTH / 2.0 - COLLISION_EPSILON;
+
+    Aabb::new(
+        pos - DVec3::new(half_width, -COLLISION_EPSILON, half_width),
+        pos + DVec3::new(half_width, height - COLLISION_EPSILON, half_width),
+    )
+}
+
+/// The positions of the blocks overlapping `aabb`, including the ones below
+/// since fences and walls stick up into the block above.
+fn blocks_in(aabb: Aabb) -> impl Iterator<Item = BlockPos> {
+    let min = aabb.min().floor().as_ivec3();
+    let max = aabb.max().floor().as_ivec3();
+
+    (min.y - 1..=max.y).flat_map(move |y| {
+        (min.z..=max.z).flat_map(move |z| (min.x..=max.x).map(move |x| BlockPos::new(x, y, z)))
+    })
+}
+
+/// The block the player at `pos` is standing on, if any.
+fn supporting_block(layer: &ChunkLayer, pos: DVec3) -> Option<BlockState> {
+    let half_width = PLAYER_WIDTH / 2.0 - COLLISION_EPSILON;
+    let feet = Aabb::new(
+        pos - DVec3::new(half_width, 0.05, half_width),
+        pos + DVec3::new(half_width, 0.0, half_width),
+    );
+
+    blocks_in(feet).find_map(|block_pos| {
+        let block = layer.block(block_pos)?;
+        let offset = DVec3::new(block_pos.x as f64, block_pos.y as f64, block_pos.z as f64);
+
+        block
+            .collision_shapes()
+            .any(|shape| (shape + offset).intersects(feet))
+            .then_some(block)
+    })
+}
+
+/// Whether any block overlapping `aabb` matches `f`.
+fn touches_block(layer: &ChunkLayer, aabb: Aabb, f: impl Fn(BlockState) -> bool) -> bool {
+    blocks_in(aabb).filter_map(|pos| layer.block(pos)).any(f)
+}
+
+/// Whether players in the block can move up and down freely.
+fn frees_vertical(block: BlockState) -> bool {
+    block.is_liquid()
+        || matches!(
+            block.to_kind(),
+            BlockKind::Ladder
+                | BlockKind::Vine
+                | BlockKind::Scaffolding
+                | BlockKind::TwistingVines
+                | BlockKind::TwistingVinesPlant
+                | BlockKind::WeepingVines
+                | BlockKind::WeepingVinesPlant
+                | BlockKind::CaveVines
+                | BlockKind::CaveVinesPlant
+                | BlockKind::BubbleColumn
+                | BlockKind::Cobweb
+                | BlockKind::PowderSnow
+        )
+}
+
+/// Whether the block bounces players back up.
+fn is_bouncy(block: BlockState) -> bool {
+    block.to_kind() == BlockKind::SlimeBlock || block.to_kind().to_str().ends_with("_bed")
+}
+
+/// Whether players slide on the block.
+fn is_slippery(block: BlockState) -> bool {
+    matches!(
+        block.to_kind(),
+        BlockKind::Ice | BlockKind::PackedIce | BlockKind::BlueIce | BlockKind::FrostedIce
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use valence_entity::{HeadYaw, Look, OnGround, Position};
+    use valence_protocol::packets::play::{PlayerPositionLookS2c, PositionAndOnGroundC2s};
+
+    use super::*;
+    use crate::position::{apply_player_move, handle_position, TeleportState};
+    use crate::testing::{self, floor_layer, packet_event, sent_packets, FLOOR};
+
+    /// The layer of [`floor_layer`] in the chunks at (0, 0) and (1, 0), with
+    /// a stone wall at x = 8.
+    fn wall_layer() -> ChunkLayer {
+        let mut layer = floor_layer([[0, 0], [1, 0]]);
+
+        for y in -48..-46 {
+            for z in 0..16 {
+                layer.set_block([8, y, z], BlockState::STONE);
+            }
+        }
+
+        layer
+    }
+
+    fn limits() -> Limits {
+        Limits {
+            horizontal: 0.7,
+            slippery_multiplier: 2.5,
+            rise_per_tick: 0.6,
+            jump_height: 1.35,
+            check_vertical: true,
+            check_collisions: true,
+        }
+    }
+
+    #[test]
+    fn walking_and_jumping_are_valid() {
+        let layer = wall_layer();
+        let mut check = MovementCheck::default();
+
+        let mut pos = DVec3::new(2.5, FLOOR, 2.5);
+        for _ in 0..10 {
+            let next = pos + DVec3::new(0.28, 0.0, 0.0);
+            assert_eq!(check_move(&layer, &mut check, limits(), pos, next), Ok(()));
+            pos = next;
+        }
+
+        // A jump rises 0.42, then less each tick.
+        for rise in [0.42, 0.33, 0.25, 0.17] {
+            let next = pos + DVec3::new(0.0, rise, 0.0);
+            assert_eq!(check_move(&layer, &mut check, limits(), pos, next), Ok(()));
+            pos = next;
+        }
+    }
+
+    #[test]
+    fn too_fast_and_flying_are_invalid() {
+        let layer = wall_layer();
+        let mut check = MovementCheck::default();
+        let pos = DVec3::new(2.5, FLOOR, 2.5);
+
+        assert_eq!(
+            check_move(&layer, &mut check, limits(), pos, pos + DVec3::X),
+            Err(InvalidMove::TooFast)
+        );
+        assert_eq!(
+            check_move(&layer, &mut check, limits(), pos, pos + DVec3::Y),
+            Err(InvalidMove::Flying)
+        );
+
+        // Rising a little each tick adds up.
+        let mut pos = pos;
+        let mut result = Ok(());
+        for _ in 0..10 {
+            let next = pos + DVec3::new(0.0, 0.3, 0.0);
+            result = check_move(&layer, &mut check, limits(), pos, next);
+            if result.is_err() {
+                break;
+            }
+            pos = next;
+        }
+        assert_eq!(result, Err(InvalidMove::Flying));
+
+        // Without the vertical check, flying is fine.
+        let limits = Limits {
+            check_vertical: false,
+            ..limits()
+        };
+        assert_eq!(
+            check_move(&layer, &mut check, limits, pos, pos + DVec3::Y * 0.5),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn moving_through_blocks_is_invalid() {
+        let layer = wall_layer();
+        let mut check = MovementCheck::default();
+
+        // Into the wall at x = 8.
+        let pos = DVec3::new(7.6, FLOOR, 2.5);
+        assert_eq!(
+            check_move(&layer, &mut check, limits(), pos, pos + DVec3::X * 0.2),
+            Err(InvalidMove::Collision)
+        );
+
+        // Touching the wall is fine.
+        assert_eq!(
+            check_move(
+                &layer,
+                &mut check,
+                limits(),
+                pos,
+                DVec3::new(7.7, FLOOR, 2.5)
+            ),
+            Ok(())
+        );
+
+        // Into the floor.
+        assert_eq!(
+            check_move(&layer, &mut check, limits(), pos, pos - DVec3::Y * 0.5),
+            Err(InvalidMove::Collision)
+        );
+
+        // Players stuck in a block can move out of it.
+        let stuck = DVec3::new(8.2, FLOOR, 2.5);
+        assert_eq!(
+            check_move(
+                &layer,
+                &mut check,
+                limits(),
+                stuck,
+                DVec3::new(8.7, FLOOR, 2.5)
+            ),
+            Ok(())
+        );
+
+        // Into an unloaded chunk.
+        let edge = DVec3::new(31.5, FLOOR, 2.5);
+        assert_eq!(
+            check_move(&layer, &mut check, limits(), edge, edge + DVec3::X * 0.5),
+            Err(InvalidMove::Unloaded)
+        );
+    }
+
+    #[test]
+    fn server_moves_reset_the_ground() {
+        let layer = wall_layer();
+        let mut check = MovementCheck::default();
+
+        let pos = DVec3::new(2.5, FLOOR, 2.5);
+        let next = pos + DVec3::X * 0.2;
+        assert_eq!(check_move(&layer, &mut check, limits(), pos, next), Ok(()));
+
+        // The player was teleported onto something out of view and jumps.
+        let teleported = DVec3::new(2.5, FLOOR + 10.0, 2.5);
+        assert_eq!(
+            check_move(
+                &layer,
+                &mut check,
+                limits(),
+                teleported,
+                teleported + DVec3::Y * 0.42
+            ),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn invalid_moves_teleport_the_player_back() {
+        let mut world = World::new();
+        world.add_handler(handle_position);
+        world.add_handler(validate_player_move);
+        world.add_handler(apply_player_move.low());
+
+        let server = world.spawn();
+        world.insert(server, MovementValidation::default());
+
+        let layer = world.spawn();
+        world.insert(layer, wall_layer());
+
+        let start = DVec3::new(7.3, FLOOR, 2.5);
+        let player = world.spawn();
+        world.insert(player, testing::client());
+        world.insert(player, TeleportState::new());
+        world.insert(player, Position(start));
+        world.insert(player, Look::default());
+        world.insert(player, HeadYaw::default());
+        world.insert(player, OnGround(true));
+        world.insert(player, VisibleChunkLayer(layer));
+        world.insert(player, MovementCheck::default());
+        world.insert(player, GameMode::Survival);
+        world.insert(player, PlayerAbilitiesFlags::default());
+        world.insert(player, FlyingSpeed::default());
+
+        let move_to = |world: &mut World, position| {
+            world.send(packet_event(
+                player,
+                PositionAndOnGroundC2s {
+                    position,
+                    on_ground: true,
+                },
+            ));
+        };
+
+        let valid = DVec3::new(7.6, FLOOR, 2.5);
+        move_to(&mut world, valid);
+        assert_eq!(world.get::<Position>(player).unwrap().0, valid);
+        assert!(sent_packets(&mut world, player).is_empty());
+
+        // Into the wall at x = 8.
+        move_to(&mut world, DVec3::new(7.9, FLOOR, 2.5));
+        assert_eq!(world.get::<Position>(player).unwrap().0, valid);
+        assert_eq!(world.get::<MovementCheck>(player).unwrap().violations(), 1);
+
+        let packets = sent_packets(&mut world, player);
+        assert_eq!(packets.len(), 1);
+        let setback = packets[0].decode::<PlayerPositionLookS2c>().unwrap();
+        assert_eq!(setback.position, valid);
+    }
+}
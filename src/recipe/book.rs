@@ -0,0 +1,414 @@
+//! The recipe book of each player.
+//!
+//! A [`RecipeBook`] holds the recipes a player has unlocked, the ones it
+//! hasn't looked at yet and whether each tab of the book is open. Clients are
+//! sent the whole book when they join and the recipes unlocked or locked since
+//! at the end of every tick.
+//!
+//! Every recipe in the [`RecipeRegistry`] is unlocked when a player joins.
+//! To hide recipes from players, lock them with [`RecipeBook::lock`] in a
+//! [`ClientJoinEvent`] handler that runs after [`init_recipe_book`]. Recipe books are saved to disk by
+//! [`SavedRecipeBooks`] when their player leaves, so they're the same when the
+//! player comes back.
+
+use std::borrow::Cow;
+use std::collections::BTreeSet;
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::path::PathBuf;
+
+use evenio::prelude::*;
+use thiserror::Error;
+use tracing::warn;
+use valence_protocol::nbt::{self, compound, Compound, List, Value};
+use valence_protocol::packets::play::recipe_category_options_c2s::RecipeBookId;
+use valence_protocol::packets::play::unlock_recipes_s2c::UpdateRecipeBookAction;
+use valence_protocol::packets::play::{
+    RecipeBookDataC2s, RecipeCategoryOptionsC2s, SynchronizeRecipesS2c, UnlockRecipesS2c,
+};
+use valence_protocol::uuid::Uuid;
+use valence_protocol::{Ident, RawBytes, WritePacket};
+use valence_server_common::{PostUpdate, UniqueId};
+
+use super::RecipeRegistry;
+use crate::client::Client;
+use crate::event::{ClientDisconnectEvent, ClientJoinEvent};
+use crate::event_loop::PacketEvent;
+
+/// The keys of whether each tab is open and filtered in the NBT of a recipe
+/// book, as in vanilla player data.
+const SETTINGS_KEYS: [(&str, &str); 4] = [
+    ("isGuiOpen", "isFilteringCraftable"),
+    ("isFurnaceGuiOpen", "isFurnaceFilteringCraftable"),
+    (
+        "isBlastingFurnaceGuiOpen",
+        "isBlastingFurnaceFilteringCraftable",
+    ),
+    ("isSmokerGuiOpen", "isSmokerFilteringCraftable"),
+];
+
+/// Whether a tab of the recipe book is open, and whether it only shows what
+/// can be made with the items at hand.
+#[derive(Copy, Clone, PartialEq, Eq, Default, Debug)]
+pub struct BookSettings {
+    pub open: bool,
+    pub filter_active: bool,
+}
+
+#[derive(Component, Clone, Default, Debug)]
+pub struct RecipeBook {
+    unlocked: BTreeSet<Ident<String>>,
+    /// Recipes shown as new until the player looks at them.
+    highlighted: BTreeSet<Ident<String>>,
+    /// The crafting, furnace, blast furnace and smoker tabs.
+    settings: [BookSettings; 4],
+    /// Recipes unlocked since the client was last told.
+    added: BTreeSet<Ident<String>>,
+    /// Recipes locked since the client was last told.
+    removed: BTreeSet<Ident<String>>,
+}
+
+impl RecipeBook {
+    pub fn is_unlocked(&self, id: &str) -> bool {
+        self.unlocked.iter().any(|unlocked| unlocked.as_str() == id)
+    }
+
+    pub fn unlocked(&self) -> impl Iterator<Item = &Ident<String>> + '_ {
+        self.unlocked.iter()
+    }
+
+    /// Adds a recipe to the book, shown as new. Returns whether it was locked
+    /// before.
+    pub fn unlock(&mut self, id: impl Into<Ident<String>>) -> bool {
+        let id = id.into();
+        if !self.unlocked.insert(id.clone()) {
+            return false;
+        }
+
+        if !self.removed.remove(&id) {
+            self.added.insert(id.clone());
+        }
+        self.highlighted.insert(id);
+        true
+    }
+
+    /// Takes a recipe out of the book. Returns whether it was unlocked before.
+    pub fn lock(&mut self, id: impl Into<Ident<String>>) -> bool {
+        let id = id.into();
+        if !self.unlocked.remove(&id) {
+            return false;
+        }
+
+        if !self.added.remove(&id) {
+            self.removed.insert(id.clone());
+        }
+        self.highlighted.remove(&id);
+        true
+    }
+
+    /// The settings of the tab `book`, as last set by the player.
+    pub fn settings(&self, book: RecipeBookId) -> BookSettings {
+        self.settings[book_index(book)]
+    }
+
+    /// Converts the book into NBT laid out like the `recipeBook` compound of
+    /// vanilla player data.
+    pub fn to_nbt(&self) -> Compound {
+        let ids = |ids: &BTreeSet<Ident<String>>| {
+            List::String(ids.iter().map(|id| id.to_string()).collect())
+        };
+
+        let mut nbt = compound! {
+            "recipes" => ids(&self.unlocked),
+            "toBeDisplayed" => ids(&self.highlighted),
+        };
+
+        for (settings, (open, filter_active)) in self.settings.iter().zip(SETTINGS_KEYS) {
+            nbt.insert(open, settings.open);
+            nbt.insert(filter_active, settings.filter_active);
+        }
+
+        nbt
+    }
+
+    /// The inverse of [`RecipeBook::to_nbt`]. Missing fields are left empty
+    /// and invalid recipe IDs are skipped.
+    pub fn from_nbt(nbt: &Compound) -> Self {
+        let ids = |key: &str| match nbt.get(key) {
+            Some(Value::List(List::String(ids))) => ids
+                .iter()
+                .filter_map(|id| Ident::new(id.clone()).ok())
+                .collect(),
+            _ => BTreeSet::new(),
+        };
+        let flag = |key: &str| matches!(nbt.get(key), Some(&Value::Byte(b)) if b != 0);
+
+        Self {
+            unlocked: ids("recipes"),
+            highlighted: ids("toBeDisplayed"),
+            settings: SETTINGS_KEYS.map(|(open, filter_active)| BookSettings {
+                open: flag(open),
+                filter_active: flag(filter_active),
+            }),
+            added: BTreeSet::new(),
+            removed: BTreeSet::new(),
+        }
+    }
+
+    fn packet<'a>(
+        &self,
+        action: UpdateRecipeBookAction<'a>,
+        recipe_ids: Vec<Ident<Cow<'a, str>>>,
+    ) -> UnlockRecipesS2c<'a> {
+        let [crafting, furnace, blast_furnace, smoker] = self.settings;
+
+        UnlockRecipesS2c {
+            action,
+            crafting_recipe_book_open: crafting.open,
+            crafting_recipe_book_filter_active: crafting.filter_active,
+            smelting_recipe_book_open: furnace.open,
+            smelting_recipe_book_filter_active: furnace.filter_active,
+            blast_furnace_recipe_book_open: blast_furnace.open,
+            blast_furnace_recipe_book_filter_active: blast_furnace.filter_active,
+            smoker_recipe_book_open: smoker.open,
+            smoker_recipe_book_filter_active: smoker.filter_active,
+            recipe_ids,
+        }
+    }
+}
+
+/// Stores the recipe books of players while they're offline, in a directory
+/// with a `<uuid>.dat` NBT file per player. The files are small, so they're
+/// read and written on the tick thread.
+#[derive(Component, Default, Debug)]
+pub struct SavedRecipeBooks {
+    /// Where the books are stored, or `None` to not keep them.
+    dir: Option<PathBuf>,
+}
+
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum SavedRecipeBookError {
+    #[error("an I/O error occurred: {0}")]
+    Io(#[from] io::Error),
+    #[error("invalid recipe book NBT: {0}")]
+    InvalidNbt(#[from] nbt::Error),
+}
+
+impl SavedRecipeBooks {
+    /// Keeps the books in `dir`, which is created when the first one is
+    /// saved.
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            dir: Some(dir.into()),
+        }
+    }
+
+    /// Reads the book of the player `uuid`. Returns `Ok(None)` if it was never
+    /// saved.
+    pub fn load(&self, uuid: Uuid) -> Result<Option<RecipeBook>, SavedRecipeBookError> {
+        let Some(dir) = &self.dir else {
+            return Ok(None);
+        };
+
+        let data = match fs::read(dir.join(format!("{uuid}.dat"))) {
+            Ok(data) => data,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+
+        let (nbt, _) = nbt::from_binary::<String>(&mut data.as_slice())?;
+        Ok(Some(RecipeBook::from_nbt(&nbt)))
+    }
+
+    /// Writes the book of the player `uuid`, replacing the saved one.
+    pub fn save(&self, uuid: Uuid, book: &RecipeBook) -> Result<(), SavedRecipeBookError> {
+        let Some(dir) = &self.dir else {
+            return Ok(());
+        };
+
+        let mut data = vec![];
+        nbt::to_binary(&book.to_nbt(), &mut data, "")?;
+
+        fs::create_dir_all(dir)?;
+        fs::write(dir.join(format!("{uuid}.dat")), data)?;
+        Ok(())
+    }
+}
+
+fn book_index(book: RecipeBookId) -> usize {
+    match book {
+        RecipeBookId::Crafting => 0,
+        RecipeBookId::Furnace => 1,
+        RecipeBookId::BlastFurnace => 2,
+        RecipeBookId::Smoker => 3,
+    }
+}
+
+/// The IDs of the recipes in `ids` the client knows about.
+fn known<'a>(
+    ids: &'a BTreeSet<Ident<String>>,
+    recipes: &RecipeRegistry,
+) -> Vec<Ident<Cow<'a, str>>> {
+    ids.iter()
+        .filter(|id| recipes.get(id.as_str()).is_some())
+        .map(|id| id.as_str_ident().into())
+        .collect()
+}
+
+/// Sends a joining client every recipe and its recipe book, as it was when
+/// the player last left, with every recipe unlocked.
+pub fn init_recipe_book(
+    r: Receiver<ClientJoinEvent>,
+    mut clients: Fetcher<(&mut Client, &UniqueId, &mut RecipeBook)>,
+    recipes: Single<&RecipeRegistry>,
+    saved: Single<&SavedRecipeBooks>,
+) {
+    let Ok((client, uuid, book)) = clients.get_mut(r.event.entity) else {
+        return;
+    };
+
+    match saved.0.load(uuid.0) {
+        Ok(Some(saved)) => *book = saved,
+        Ok(None) => {}
+        Err(e) => warn!("failed to load the recipe book of {}: {e}", uuid.0),
+    }
+
+    let recipes = recipes.0;
+
+    // Not shown as new, since that would be every recipe for new players.
+    book.unlocked
+        .extend(recipes.iter().map(|recipe| recipe.id.clone()));
+
+    match recipes.encode() {
+        Ok(buf) => client.write_packet(&SynchronizeRecipesS2c {
+            recipes: RawBytes(&buf),
+        }),
+        Err(e) => warn!("failed to encode recipes: {e:#}"),
+    }
+
+    client.write_packet(&book.packet(
+        UpdateRecipeBookAction::Init {
+            recipe_ids: known(&book.highlighted, recipes),
+        },
+        known(&book.unlocked, recipes),
+    ));
+
+    book.added.clear();
+    book.removed.clear();
+}
+
+/// Stops showing a recipe as new once the player looked at it.
+pub fn handle_recipe_book_data(
+    r: Receiver<PacketEvent<RecipeBookDataC2s<'static>>, &mut RecipeBook>,
+) {
+    let book = r.query;
+    let id = r.event.packet.recipe_id.as_str();
+
+    book.highlighted
+        .retain(|highlighted| highlighted.as_str() != id);
+}
+
+pub fn handle_recipe_category_options(
+    r: Receiver<PacketEvent<RecipeCategoryOptionsC2s>, &mut RecipeBook>,
+) {
+    let book = r.query;
+    let packet = &r.event.packet;
+
+    book.settings[book_index(packet.book_id)] = BookSettings {
+        open: packet.book_open,
+        filter_active: packet.filter_active,
+    };
+}
+
+/// Tells every client about the recipes unlocked and locked this tick.
+pub fn sync_recipe_books(
+    _: Receiver<PostUpdate>,
+    mut clients: Fetcher<(&mut Client, &mut RecipeBook)>,
+    recipes: Single<&RecipeRegistry>,
+) {
+    for (client, book) in clients.iter_mut() {
+        if !book.added.is_empty() {
+            let ids = known(&book.added, recipes.0);
+            client.write_packet(&book.packet(UpdateRecipeBookAction::Add, ids));
+        }
+
+        if !book.removed.is_empty() {
+            let ids = known(&book.removed, recipes.0);
+            client.write_packet(&book.packet(UpdateRecipeBookAction::Remove, ids));
+        }
+
+        book.added.clear();
+        book.removed.clear();
+    }
+}
+
+/// Saves the recipe book of a leaving player for when it comes back.
+pub fn save_recipe_book(
+    r: Receiver<ClientDisconnectEvent>,
+    clients: Fetcher<(&UniqueId, &RecipeBook)>,
+    saved: Single<&SavedRecipeBooks>,
+) {
+    if let Ok((uuid, book)) = clients.get(r.event.entity) {
+        if let Err(e) = saved.0.save(uuid.0, book) {
+            warn!("failed to save the recipe book of {}: {e}", uuid.0);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use valence_protocol::ident;
+
+    use super::*;
+
+    #[test]
+    fn only_changes_since_the_last_update_are_sent() {
+        let mut book = RecipeBook::default();
+
+        assert!(book.unlock(ident!("stick")));
+        assert!(!book.unlock(ident!("stick")));
+        assert!(book.unlock(ident!("torch")));
+        assert!(book.is_unlocked("minecraft:stick"));
+
+        // Locking a recipe the client wasn't told about yet tells it nothing.
+        assert!(book.lock(ident!("torch")));
+        assert!(!book.is_unlocked("minecraft:torch"));
+        assert_eq!(book.added.len(), 1);
+        assert!(book.removed.is_empty());
+
+        book.added.clear();
+        assert!(book.lock(ident!("stick")));
+        assert_eq!(book.removed.len(), 1);
+        assert!(book.highlighted.is_empty());
+    }
+
+    #[test]
+    fn books_are_saved_to_disk() {
+        let dir = std::env::temp_dir().join(format!("recipe-books-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+
+        let saved = SavedRecipeBooks::new(&dir);
+        let uuid = Uuid::from_u128(1);
+        assert!(saved.load(uuid).unwrap().is_none());
+
+        let mut book = RecipeBook::default();
+        book.unlock(ident!("stick"));
+        book.unlock(ident!("torch"));
+        book.highlighted.clear();
+        book.settings[book_index(RecipeBookId::Smoker)].filter_active = true;
+        saved.save(uuid, &book).unwrap();
+
+        let loaded = saved.load(uuid).unwrap().unwrap();
+        assert_eq!(loaded.unlocked, book.unlocked);
+        assert!(loaded.highlighted.is_empty());
+        assert_eq!(loaded.settings, book.settings);
+        assert!(loaded.added.is_empty());
+
+        // Books aren't kept without a directory.
+        SavedRecipeBooks::default().save(uuid, &book).unwrap();
+        assert!(SavedRecipeBooks::default().load(uuid).unwrap().is_none());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
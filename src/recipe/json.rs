@@ -0,0 +1,616 @@
+//! Reads recipes and item tags in the JSON format of data packs.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::{fs, io};
+
+use serde::Deserialize;
+use serde_json::Value;
+use thiserror::Error;
+use tracing::warn;
+use valence_protocol::packets::play::synchronize_recipes_s2c::CraftingShapedCategory;
+use valence_protocol::{Ident, ItemKind, ItemStack};
+
+use super::{CookingCategory, CookingKind, Ingredient, Recipe, RecipeKind, RecipeRegistry};
+
+/// How deep tags can refer to other tags.
+const MAX_TAG_DEPTH: usize = 16;
+
+#[derive(Debug, Error)]
+pub enum LoadRecipeError {
+    #[error("an I/O error occurred: {0}")]
+    Io(#[from] io::Error),
+    #[error("invalid JSON: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("invalid ID '{0}'")]
+    BadId(String),
+    #[error("unknown item '{0}'")]
+    UnknownItem(String),
+    #[error("unknown item tag '{0}'")]
+    UnknownTag(String),
+    #[error("invalid ingredient")]
+    BadIngredient,
+    #[error("invalid pattern")]
+    BadPattern,
+    #[error("in {path}: {source}")]
+    File {
+        path: PathBuf,
+        source: Box<LoadRecipeError>,
+    },
+}
+
+/// Item tags, which recipes use to accept any item of a kind, like any planks.
+#[derive(Clone, Default, Debug)]
+pub struct ItemTags {
+    /// The entries of each tag, which are items or `#` and another tag.
+    tags: BTreeMap<String, Vec<String>>,
+}
+
+impl ItemTags {
+    /// Adds a tag with entries in the format of tag files.
+    pub fn insert(&mut self, name: &str, values: Vec<String>) {
+        self.tags.insert(namespaced(name), values);
+    }
+
+    /// The items of the tag `name`, including those of the tags it refers to.
+    pub fn resolve(&self, name: &str) -> Result<Vec<ItemKind>, LoadRecipeError> {
+        let mut items = vec![];
+        self.resolve_into(&namespaced(name), &mut items, 0)?;
+        Ok(items)
+    }
+
+    fn resolve_into(
+        &self,
+        name: &str,
+        items: &mut Vec<ItemKind>,
+        depth: usize,
+    ) -> Result<(), LoadRecipeError> {
+        let values = match self.tags.get(name) {
+            Some(values) if depth < MAX_TAG_DEPTH => values,
+            _ => return Err(LoadRecipeError::UnknownTag(name.to_owned())),
+        };
+
+        for value in values {
+            match value.strip_prefix('#') {
+                Some(tag) => self.resolve_into(&namespaced(tag), items, depth + 1)?,
+                None => {
+                    let item = item_kind(value)?;
+                    if !items.contains(&item) {
+                        items.push(item);
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Loads the item tags under the `tags/items` directory of a namespace.
+    /// Tag files that can't be read are skipped.
+    fn load_dir(&mut self, namespace: &str, dir: &Path) -> Result<(), LoadRecipeError> {
+        #[derive(Deserialize)]
+        struct TagFile {
+            values: Vec<TagEntry>,
+        }
+
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum TagEntry {
+            Id(String),
+            Optional { id: String },
+        }
+
+        for (name, path) in json_files(dir)? {
+            let file = fs::read_to_string(&path)
+                .map_err(LoadRecipeError::from)
+                .and_then(|json| Ok(serde_json::from_str::<TagFile>(&json)?));
+            let file = match file {
+                Ok(file) => file,
+                Err(e) => {
+                    warn!(
+                        "skipping item tag {namespace}:{name}: {}",
+                        in_file(&path, e)
+                    );
+                    continue;
+                }
+            };
+
+            let values = file
+                .values
+                .into_iter()
+                .map(|entry| match entry {
+                    TagEntry::Id(id) | TagEntry::Optional { id } => id,
+                })
+                .collect();
+
+            self.tags.insert(format!("{namespace}:{name}"), values);
+        }
+
+        Ok(())
+    }
+}
+
+/// Loads the tags, then the recipes, of every namespace in `data`. Recipes
+/// that can't be read are skipped, so one broken file doesn't keep the rest
+/// from loading.
+pub(super) fn load_data_dir(
+    registry: &mut RecipeRegistry,
+    data: &Path,
+) -> Result<usize, LoadRecipeError> {
+    let mut namespaces = vec![];
+    for entry in fs::read_dir(data)? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            namespaces.push((
+                entry.file_name().to_string_lossy().into_owned(),
+                entry.path(),
+            ));
+        }
+    }
+
+    let mut tags = ItemTags::default();
+    for (namespace, dir) in &namespaces {
+        let dir = dir.join("tags").join("items");
+        if dir.is_dir() {
+            tags.load_dir(namespace, &dir)?;
+        }
+    }
+
+    let mut count = 0;
+    for (namespace, dir) in &namespaces {
+        let dir = dir.join("recipes");
+        if !dir.is_dir() {
+            continue;
+        }
+
+        for (name, path) in json_files(&dir)? {
+            let id = format!("{namespace}:{name}");
+            let recipe = fs::read_to_string(&path)
+                .map_err(LoadRecipeError::from)
+                .and_then(|json| parse_recipe(&id, &json, &tags));
+
+            match recipe {
+                Ok(Some(recipe)) => {
+                    registry.insert(recipe);
+                    count += 1;
+                }
+                Ok(None) => {}
+                Err(e) => warn!("skipping recipe {id}: {}", in_file(&path, e)),
+            }
+        }
+    }
+
+    Ok(count)
+}
+
+/// Parses the recipe `id` from its JSON. Returns `None` for the special
+/// recipes hardcoded in the game, like dyeing armor, which have no data.
+pub fn parse_recipe(
+    id: &str,
+    json: &str,
+    tags: &ItemTags,
+) -> Result<Option<Recipe>, LoadRecipeError> {
+    let json: Value = serde_json::from_str(json)?;
+    let kind = json.get("type").and_then(Value::as_str).unwrap_or_default();
+    let kind = kind.strip_prefix("minecraft:").unwrap_or(kind).to_owned();
+    let group = json
+        .get("group")
+        .and_then(Value::as_str)
+        .unwrap_or_default()
+        .to_owned();
+
+    let kind = match kind.as_str() {
+        "crafting_shaped" => {
+            let json: ShapedJson = serde_json::from_value(json)?;
+            parse_shaped(json, tags)?
+        }
+        "crafting_shapeless" => {
+            let json: ShapelessJson = serde_json::from_value(json)?;
+            RecipeKind::Shapeless {
+                category: crafting_category(json.category.as_deref()),
+                ingredients: json
+                    .ingredients
+                    .into_iter()
+                    .map(|ingredient| ingredient.resolve(tags))
+                    .collect::<Result<_, _>>()?,
+                result: json.result.stack()?,
+            }
+        }
+        name @ ("smelting" | "blasting" | "smoking" | "campfire_cooking") => {
+            let (kind, default_time) = match name {
+                "smelting" => (CookingKind::Smelting, 200),
+                "blasting" => (CookingKind::Blasting, 100),
+                "smoking" => (CookingKind::Smoking, 100),
+                _ => (CookingKind::CampfireCooking, 100),
+            };
+
+            let json: CookingJson = serde_json::from_value(json)?;
+            RecipeKind::Cooking {
+                kind,
+                category: match json.category.as_deref() {
+                    Some("food") => CookingCategory::Food,
+                    Some("blocks") => CookingCategory::Blocks,
+                    _ => CookingCategory::Misc,
+                },
+                ingredient: json.ingredient.resolve(tags)?,
+                result: json.result.stack()?,
+                experience: json.experience,
+                cooking_time: json.cookingtime.unwrap_or(default_time),
+            }
+        }
+        "stonecutting" => {
+            let json: StonecuttingJson = serde_json::from_value(json)?;
+            RecipeKind::Stonecutting {
+                ingredient: json.ingredient.resolve(tags)?,
+                result: ItemStack::new(item_kind(&json.result)?, json.count, None),
+            }
+        }
+        "smithing_transform" => {
+            let json: SmithingJson = serde_json::from_value(json)?;
+            let result = json.result.ok_or(LoadRecipeError::BadIngredient)?;
+            RecipeKind::SmithingTransform {
+                template: json.template.resolve(tags)?,
+                base: json.base.resolve(tags)?,
+                addition: json.addition.resolve(tags)?,
+                result: result.stack()?,
+            }
+        }
+        "smithing_trim" => {
+            let json: SmithingJson = serde_json::from_value(json)?;
+            RecipeKind::SmithingTrim {
+                template: json.template.resolve(tags)?,
+                base: json.base.resolve(tags)?,
+                addition: json.addition.resolve(tags)?,
+            }
+        }
+        _ => return Ok(None),
+    };
+
+    Ok(Some(Recipe {
+        id: parse_id(id)?,
+        group,
+        kind,
+    }))
+}
+
+fn parse_shaped(json: ShapedJson, tags: &ItemTags) -> Result<RecipeKind, LoadRecipeError> {
+    let height = json.pattern.len();
+    let width = json.pattern.first().map_or(0, |row| row.chars().count());
+
+    if !(1..=3).contains(&width)
+        || !(1..=3).contains(&height)
+        || json.pattern.iter().any(|row| row.chars().count() != width)
+    {
+        return Err(LoadRecipeError::BadPattern);
+    }
+
+    let mut keys = BTreeMap::new();
+    for (key, ingredient) in json.key {
+        let mut chars = key.chars();
+        let (Some(key), None) = (chars.next(), chars.next()) else {
+            return Err(LoadRecipeError::BadPattern);
+        };
+        keys.insert(key, ingredient.resolve(tags)?);
+    }
+
+    let ingredients = json
+        .pattern
+        .iter()
+        .flat_map(|row| row.chars())
+        .map(|key| match key {
+            ' ' => Ok(Ingredient::default()),
+            _ => keys.get(&key).cloned().ok_or(LoadRecipeError::BadPattern),
+        })
+        .collect::<Result<_, _>>()?;
+
+    Ok(RecipeKind::Shaped {
+        category: crafting_category(json.category.as_deref()),
+        width,
+        height,
+        ingredients,
+        result: json.result.stack()?,
+        show_notification: json.show_notification,
+    })
+}
+
+#[derive(Deserialize)]
+struct ShapedJson {
+    category: Option<String>,
+    pattern: Vec<String>,
+    key: BTreeMap<String, IngredientJson>,
+    result: ResultJson,
+    #[serde(default = "yes")]
+    show_notification: bool,
+}
+
+#[derive(Deserialize)]
+struct ShapelessJson {
+    category: Option<String>,
+    ingredients: Vec<IngredientJson>,
+    result: ResultJson,
+}
+
+#[derive(Deserialize)]
+struct CookingJson {
+    category: Option<String>,
+    ingredient: IngredientJson,
+    result: ResultJson,
+    #[serde(default)]
+    experience: f32,
+    cookingtime: Option<i32>,
+}
+
+#[derive(Deserialize)]
+struct StonecuttingJson {
+    ingredient: IngredientJson,
+    result: String,
+    #[serde(default = "one")]
+    count: i8,
+}
+
+#[derive(Deserialize)]
+struct SmithingJson {
+    template: IngredientJson,
+    base: IngredientJson,
+    addition: IngredientJson,
+    result: Option<ResultJson>,
+}
+
+/// An ingredient, which is an item, a tag or a list of either.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum IngredientJson {
+    One(IngredientEntry),
+    Any(Vec<IngredientEntry>),
+}
+
+#[derive(Deserialize)]
+struct IngredientEntry {
+    item: Option<String>,
+    tag: Option<String>,
+}
+
+impl IngredientJson {
+    fn resolve(self, tags: &ItemTags) -> Result<Ingredient, LoadRecipeError> {
+        let entries = match self {
+            Self::One(entry) => vec![entry],
+            Self::Any(entries) => entries,
+        };
+
+        let mut items = vec![];
+        for entry in entries {
+            match (entry.item, entry.tag) {
+                (Some(item), None) => items.push(item_kind(&item)?),
+                (None, Some(tag)) => items.extend(tags.resolve(&tag)?),
+                _ => return Err(LoadRecipeError::BadIngredient),
+            }
+        }
+
+        Ok(Ingredient(items))
+    }
+}
+
+/// The result of a recipe, which is just the item for cooking recipes.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ResultJson {
+    Item(String),
+    Stack {
+        item: String,
+        #[serde(default = "one")]
+        count: i8,
+    },
+}
+
+impl ResultJson {
+    fn stack(self) -> Result<ItemStack, LoadRecipeError> {
+        let (item, count) = match self {
+            Self::Item(item) => (item, 1),
+            Self::Stack { item, count } => (item, count),
+        };
+
+        Ok(ItemStack::new(item_kind(&item)?, count, None))
+    }
+}
+
+fn yes() -> bool {
+    true
+}
+
+fn one() -> i8 {
+    1
+}
+
+fn crafting_category(category: Option<&str>) -> CraftingShapedCategory {
+    match category {
+        Some("building") => CraftingShapedCategory::Building,
+        Some("redstone") => CraftingShapedCategory::Redstone,
+        Some("equipment") => CraftingShapedCategory::Equipment,
+        _ => CraftingShapedCategory::Misc,
+    }
+}
+
+fn item_kind(name: &str) -> Result<ItemKind, LoadRecipeError> {
+    ItemKind::from_str(name.strip_prefix("minecraft:").unwrap_or(name))
+        .ok_or_else(|| LoadRecipeError::UnknownItem(name.to_owned()))
+}
+
+fn parse_id(id: &str) -> Result<Ident<String>, LoadRecipeError> {
+    Ident::new(namespaced(id))
+        .map(|id| id.to_string_ident())
+        .map_err(|_| LoadRecipeError::BadId(id.to_owned()))
+}
+
+/// Adds the default namespace to `name` if it has none.
+fn namespaced(name: &str) -> String {
+    if name.contains(':') {
+        name.to_owned()
+    } else {
+        format!("minecraft:{name}")
+    }
+}
+
+fn in_file(path: &Path, error: LoadRecipeError) -> LoadRecipeError {
+    LoadRecipeError::File {
+        path: path.to_owned(),
+        source: Box::new(error),
+    }
+}
+
+/// The JSON files under `dir`, named by their path relative to it without the
+/// extension, like `wooden_axe` or `chests/boats`.
+fn json_files(dir: &Path) -> io::Result<Vec<(String, PathBuf)>> {
+    let mut files = vec![];
+    let mut dirs = vec![dir.to_owned()];
+
+    while let Some(current) = dirs.pop() {
+        for entry in fs::read_dir(&current)? {
+            let path = entry?.path();
+
+            if path.is_dir() {
+                dirs.push(path);
+            } else if path.extension().is_some_and(|ext| ext == "json") {
+                let name = path.strip_prefix(dir).unwrap_or(&path).with_extension("");
+                let name = name
+                    .components()
+                    .map(|part| part.as_os_str().to_string_lossy())
+                    .collect::<Vec<_>>()
+                    .join("/");
+                files.push((name, path));
+            }
+        }
+    }
+
+    files.sort();
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn planks() -> ItemTags {
+        let mut tags = ItemTags::default();
+        tags.insert(
+            "oak_logs",
+            vec!["minecraft:oak_log".into(), "minecraft:oak_wood".into()],
+        );
+        tags.insert(
+            "logs",
+            vec!["#minecraft:oak_logs".into(), "minecraft:birch_log".into()],
+        );
+        tags
+    }
+
+    #[test]
+    fn tags_refer_to_other_tags() {
+        let tags = planks();
+        assert_eq!(
+            tags.resolve("minecraft:logs").unwrap(),
+            [ItemKind::OakLog, ItemKind::OakWood, ItemKind::BirchLog]
+        );
+        assert!(matches!(
+            tags.resolve("planks"),
+            Err(LoadRecipeError::UnknownTag(_))
+        ));
+    }
+
+    #[test]
+    fn vanilla_recipes_are_parsed() {
+        let tags = planks();
+
+        let json = r##"{
+            "type": "minecraft:crafting_shaped",
+            "category": "misc",
+            "group": "sticks",
+            "key": { "#": { "tag": "minecraft:logs" } },
+            "pattern": ["#", "#"],
+            "result": { "item": "minecraft:stick", "count": 4 }
+        }"##;
+        let recipe = parse_recipe("minecraft:stick", json, &tags)
+            .unwrap()
+            .unwrap();
+        assert_eq!(recipe.group, "sticks");
+        let RecipeKind::Shaped {
+            width,
+            height,
+            ingredients,
+            result,
+            ..
+        } = recipe.kind
+        else {
+            panic!("not a shaped recipe");
+        };
+        assert_eq!((width, height), (1, 2));
+        assert_eq!(ingredients[1].0.len(), 3);
+        assert_eq!(result, ItemStack::new(ItemKind::Stick, 4, None));
+
+        let json = r#"{
+            "type": "minecraft:smelting",
+            "category": "food",
+            "ingredient": [{ "item": "minecraft:potato" }],
+            "result": "minecraft:baked_potato",
+            "experience": 0.35
+        }"#;
+        let recipe = parse_recipe("baked_potato", json, &tags).unwrap().unwrap();
+        assert_eq!(
+            recipe.kind,
+            RecipeKind::Cooking {
+                kind: CookingKind::Smelting,
+                category: CookingCategory::Food,
+                ingredient: Ingredient(vec![ItemKind::Potato]),
+                result: ItemStack::new(ItemKind::BakedPotato, 1, None),
+                experience: 0.35,
+                cooking_time: 200,
+            }
+        );
+
+        let json = r#"{ "type": "minecraft:crafting_special_armordye" }"#;
+        assert!(parse_recipe("armor_dye", json, &tags).unwrap().is_none());
+
+        let json = r#"{
+            "type": "minecraft:stonecutting",
+            "ingredient": { "item": "minecraft:not_an_item" },
+            "result": "minecraft:stone_slab",
+            "count": 2
+        }"#;
+        assert!(matches!(
+            parse_recipe("stone_slab", json, &tags),
+            Err(LoadRecipeError::UnknownItem(_))
+        ));
+    }
+    #[test]
+    fn broken_recipes_are_skipped() {
+        let data = std::env::temp_dir().join(format!("recipe-data-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&data);
+
+        let recipes = data.join("minecraft").join("recipes");
+        fs::create_dir_all(&recipes).unwrap();
+        fs::write(
+            recipes.join("baked_potato.json"),
+            r#"{
+                "type": "minecraft:smelting",
+                "ingredient": { "item": "minecraft:potato" },
+                "result": "minecraft:baked_potato"
+            }"#,
+        )
+        .unwrap();
+        fs::write(
+            recipes.join("stone_slab.json"),
+            r#"{
+                "type": "minecraft:stonecutting",
+                "ingredient": { "item": "minecraft:not_an_item" },
+                "result": "minecraft:stone_slab"
+            }"#,
+        )
+        .unwrap();
+        fs::write(recipes.join("truncated.json"), "{").unwrap();
+
+        let mut registry = RecipeRegistry::default();
+        assert_eq!(load_data_dir(&mut registry, &data).unwrap(), 1);
+        assert!(registry.get("minecraft:baked_potato").is_some());
+        assert_eq!(registry.len(), 1);
+
+        fs::remove_dir_all(data).unwrap();
+    }
+}
@@ -0,0 +1,476 @@
+//! Recipes, loaded from data packs in the game's JSON format.
+//!
+//! The [`RecipeRegistry`] on the server entity holds every recipe the server
+//! knows about. Clients are sent all of them in [`SynchronizeRecipesS2c`] when
+//! they join, so their recipe book and crafting grid know what can be made,
+//! but what a crafting grid makes is still worked out by the server. Which
+//! recipes a player can see in its recipe book is kept in its [`RecipeBook`].
+//!
+//! [`SynchronizeRecipesS2c`]: valence_protocol::packets::play::SynchronizeRecipesS2c
+//! [`RecipeBook`]: book::RecipeBook
+
+pub mod book;
+mod json;
+
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use evenio::prelude::*;
+use valence_protocol::anyhow;
+use valence_protocol::packets::play::synchronize_recipes_s2c::CraftingShapedCategory;
+use valence_protocol::{Encode, Ident, ItemKind, ItemStack, VarInt};
+
+pub use self::json::{parse_recipe, ItemTags, LoadRecipeError};
+
+/// Every recipe the server knows about, by ID.
+#[derive(Component, Clone, Default, Debug)]
+pub struct RecipeRegistry {
+    recipes: BTreeMap<String, Recipe>,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Recipe {
+    pub id: Ident<String>,
+    /// Recipes in the same group are shown together in the recipe book.
+    pub group: String,
+    pub kind: RecipeKind,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum RecipeKind {
+    Shaped {
+        category: CraftingShapedCategory,
+        width: usize,
+        height: usize,
+        /// The ingredients row by row, empty where the grid must be empty.
+        ingredients: Vec<Ingredient>,
+        result: ItemStack,
+        /// Whether a toast is shown when the recipe is unlocked.
+        show_notification: bool,
+    },
+    Shapeless {
+        category: CraftingShapedCategory,
+        ingredients: Vec<Ingredient>,
+        result: ItemStack,
+    },
+    Cooking {
+        kind: CookingKind,
+        category: CookingCategory,
+        ingredient: Ingredient,
+        result: ItemStack,
+        experience: f32,
+        /// How long cooking takes, in ticks.
+        cooking_time: i32,
+    },
+    Stonecutting {
+        ingredient: Ingredient,
+        result: ItemStack,
+    },
+    SmithingTransform {
+        template: Ingredient,
+        base: Ingredient,
+        addition: Ingredient,
+        result: ItemStack,
+    },
+    SmithingTrim {
+        template: Ingredient,
+        base: Ingredient,
+        addition: Ingredient,
+    },
+}
+
+/// What cooks the ingredient of a cooking recipe.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum CookingKind {
+    Smelting,
+    Blasting,
+    Smoking,
+    CampfireCooking,
+}
+
+/// The tab of the recipe book a cooking recipe is shown in.
+#[derive(Copy, Clone, PartialEq, Eq, Default, Debug)]
+pub enum CookingCategory {
+    Food,
+    Blocks,
+    #[default]
+    Misc,
+}
+
+/// The items that can be used for one ingredient of a recipe. An ingredient
+/// without items stands for an empty slot.
+#[derive(Clone, PartialEq, Eq, Default, Debug)]
+pub struct Ingredient(pub Vec<ItemKind>);
+
+impl Ingredient {
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Whether `stack` can be used for this ingredient.
+    pub fn test(&self, stack: &ItemStack) -> bool {
+        if self.is_empty() {
+            stack.is_empty()
+        } else {
+            !stack.is_empty() && self.0.contains(&stack.item)
+        }
+    }
+
+    fn encode(&self, w: &mut Vec<u8>) -> anyhow::Result<()> {
+        let stacks: Vec<ItemStack> = self
+            .0
+            .iter()
+            .map(|&item| ItemStack::new(item, 1, None))
+            .collect();
+
+        stacks.encode(w)
+    }
+}
+
+impl RecipeRegistry {
+    pub fn get(&self, id: &str) -> Option<&Recipe> {
+        self.recipes.get(id)
+    }
+
+    /// Adds a recipe, returning the one it replaced.
+    pub fn insert(&mut self, recipe: Recipe) -> Option<Recipe> {
+        self.recipes.insert(recipe.id.as_str().to_owned(), recipe)
+    }
+
+    pub fn remove(&mut self, id: &str) -> Option<Recipe> {
+        self.recipes.remove(id)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Recipe> + '_ {
+        self.recipes.values()
+    }
+
+    pub fn len(&self) -> usize {
+        self.recipes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.recipes.is_empty()
+    }
+
+    /// Loads the recipes of every namespace of a data pack's `data` directory,
+    /// like the one in the game's jar, resolving item tags from the same
+    /// directory. Returns how many recipes were loaded. Recipes and tags that
+    /// can't be read are skipped with a warning.
+    pub fn load_data_dir(&mut self, data: impl AsRef<Path>) -> Result<usize, LoadRecipeError> {
+        json::load_data_dir(self, data.as_ref())
+    }
+
+    /// The crafting recipe made by the items in a crafting grid `width` slots
+    /// wide, if any.
+    pub fn craft(&self, grid: &[ItemStack], width: usize) -> Option<&Recipe> {
+        if grid.iter().all(ItemStack::is_empty) {
+            return None;
+        }
+
+        self.iter().find(|recipe| recipe.matches(grid, width))
+    }
+
+    /// Encodes every recipe as the body of [`SynchronizeRecipesS2c`].
+    ///
+    /// [`SynchronizeRecipesS2c`]: valence_protocol::packets::play::SynchronizeRecipesS2c
+    pub(crate) fn encode(&self) -> anyhow::Result<Vec<u8>> {
+        let mut buf = vec![];
+        VarInt(self.recipes.len() as i32).encode(&mut buf)?;
+
+        for recipe in self.iter() {
+            recipe.encode(&mut buf)?;
+        }
+
+        Ok(buf)
+    }
+}
+
+impl Recipe {
+    /// The item made by the recipe of a crafting grid.
+    pub fn crafting_result(&self) -> Option<&ItemStack> {
+        match &self.kind {
+            RecipeKind::Shaped { result, .. } | RecipeKind::Shapeless { result, .. } => {
+                Some(result)
+            }
+            _ => None,
+        }
+    }
+
+    /// Whether this is a crafting recipe made by the items in a crafting grid
+    /// `width` slots wide.
+    pub fn matches(&self, grid: &[ItemStack], width: usize) -> bool {
+        match &self.kind {
+            RecipeKind::Shaped {
+                width: pattern_width,
+                height: pattern_height,
+                ingredients,
+                ..
+            } => {
+                let height = grid.len() / width;
+                if *pattern_width > width || *pattern_height > height {
+                    return false;
+                }
+
+                // The pattern can be anywhere in the grid, and mirrored.
+                (0..=width - pattern_width).any(|x| {
+                    (0..=height - pattern_height).any(|y| {
+                        [false, true].into_iter().any(|mirrored| {
+                            grid.iter().enumerate().all(|(idx, stack)| {
+                                let (px, py) =
+                                    ((idx % width).wrapping_sub(x), (idx / width).wrapping_sub(y));
+                                if px >= *pattern_width || py >= *pattern_height {
+                                    return stack.is_empty();
+                                }
+
+                                let px = if mirrored { pattern_width - 1 - px } else { px };
+                                ingredients[py * pattern_width + px].test(stack)
+                            })
+                        })
+                    })
+                })
+            }
+            RecipeKind::Shapeless { ingredients, .. } => {
+                let stacks: Vec<&ItemStack> =
+                    grid.iter().filter(|stack| !stack.is_empty()).collect();
+                stacks.len() == ingredients.len()
+                    && assign(ingredients, &stacks, &mut vec![false; stacks.len()])
+            }
+            _ => false,
+        }
+    }
+
+    fn encode(&self, w: &mut Vec<u8>) -> anyhow::Result<()> {
+        let kind = match &self.kind {
+            RecipeKind::Shaped { .. } => "minecraft:crafting_shaped",
+            RecipeKind::Shapeless { .. } => "minecraft:crafting_shapeless",
+            RecipeKind::Cooking { kind, .. } => match kind {
+                CookingKind::Smelting => "minecraft:smelting",
+                CookingKind::Blasting => "minecraft:blasting",
+                CookingKind::Smoking => "minecraft:smoking",
+                CookingKind::CampfireCooking => "minecraft:campfire_cooking",
+            },
+            RecipeKind::Stonecutting { .. } => "minecraft:stonecutting",
+            RecipeKind::SmithingTransform { .. } => "minecraft:smithing_transform",
+            RecipeKind::SmithingTrim { .. } => "minecraft:smithing_trim",
+        };
+
+        kind.encode(&mut *w)?;
+        self.id.as_str().encode(&mut *w)?;
+
+        match &self.kind {
+            RecipeKind::Shaped {
+                category,
+                width,
+                height,
+                ingredients,
+                result,
+                show_notification,
+            } => {
+                VarInt(*width as i32).encode(&mut *w)?;
+                VarInt(*height as i32).encode(&mut *w)?;
+                self.group.encode(&mut *w)?;
+                category.encode(&mut *w)?;
+                for ingredient in ingredients {
+                    ingredient.encode(w)?;
+                }
+                result.encode(&mut *w)?;
+                show_notification.encode(w)
+            }
+            RecipeKind::Shapeless {
+                category,
+                ingredients,
+                result,
+            } => {
+                self.group.encode(&mut *w)?;
+                category.encode(&mut *w)?;
+                VarInt(ingredients.len() as i32).encode(&mut *w)?;
+                for ingredient in ingredients {
+                    ingredient.encode(w)?;
+                }
+                result.encode(w)
+            }
+            RecipeKind::Cooking {
+                category,
+                ingredient,
+                result,
+                experience,
+                cooking_time,
+                ..
+            } => {
+                self.group.encode(&mut *w)?;
+                VarInt(*category as i32).encode(&mut *w)?;
+                ingredient.encode(w)?;
+                result.encode(&mut *w)?;
+                experience.encode(&mut *w)?;
+                VarInt(*cooking_time).encode(w)
+            }
+            RecipeKind::Stonecutting { ingredient, result } => {
+                self.group.encode(&mut *w)?;
+                ingredient.encode(w)?;
+                result.encode(w)
+            }
+            RecipeKind::SmithingTransform {
+                template,
+                base,
+                addition,
+                result,
+            } => {
+                template.encode(w)?;
+                base.encode(w)?;
+                addition.encode(w)?;
+                result.encode(w)
+            }
+            RecipeKind::SmithingTrim {
+                template,
+                base,
+                addition,
+            } => {
+                template.encode(w)?;
+                base.encode(w)?;
+                addition.encode(w)
+            }
+        }
+    }
+}
+
+/// Whether every ingredient can be given a different one of `stacks`.
+fn assign(ingredients: &[Ingredient], stacks: &[&ItemStack], used: &mut [bool]) -> bool {
+    let Some((ingredient, rest)) = ingredients.split_first() else {
+        return true;
+    };
+
+    for idx in 0..stacks.len() {
+        if !used[idx] && ingredient.test(stacks[idx]) {
+            used[idx] = true;
+            if assign(rest, stacks, used) {
+                return true;
+            }
+            used[idx] = false;
+        }
+    }
+
+    false
+}
+
+/// What's left in the crafting grid of an ingredient once it's used, like the
+/// bucket of a milk bucket.
+pub fn crafting_remainder(item: ItemKind) -> Option<ItemKind> {
+    match item {
+        ItemKind::MilkBucket
+        | ItemKind::WaterBucket
+        | ItemKind::LavaBucket
+        | ItemKind::PowderSnowBucket => Some(ItemKind::Bucket),
+        ItemKind::HoneyBottle | ItemKind::DragonBreath => Some(ItemKind::GlassBottle),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use valence_protocol::ident;
+
+    use super::*;
+
+    fn stack(item: ItemKind) -> ItemStack {
+        ItemStack::new(item, 1, None)
+    }
+
+    fn ingredient(item: ItemKind) -> Ingredient {
+        Ingredient(vec![item])
+    }
+
+    #[test]
+    fn shaped_recipes_match_anywhere_and_mirrored() {
+        // An axe, which isn't symmetric.
+        let axe = Recipe {
+            id: ident!("wooden_axe").into(),
+            group: String::new(),
+            kind: RecipeKind::Shaped {
+                category: CraftingShapedCategory::Equipment,
+                width: 2,
+                height: 3,
+                ingredients: vec![
+                    ingredient(ItemKind::OakPlanks),
+                    ingredient(ItemKind::OakPlanks),
+                    ingredient(ItemKind::OakPlanks),
+                    ingredient(ItemKind::Stick),
+                    Ingredient::default(),
+                    ingredient(ItemKind::Stick),
+                ],
+                result: stack(ItemKind::WoodenAxe),
+                show_notification: true,
+            },
+        };
+
+        let (planks, stick, empty) = (
+            stack(ItemKind::OakPlanks),
+            stack(ItemKind::Stick),
+            ItemStack::EMPTY,
+        );
+
+        #[rustfmt::skip]
+        let grid = [
+            empty.clone(), planks.clone(), planks.clone(),
+            empty.clone(), stick.clone(), planks.clone(),
+            empty.clone(), stick.clone(), empty.clone(),
+        ];
+        assert!(axe.matches(&grid, 3));
+
+        #[rustfmt::skip]
+        let mirrored = [
+            planks.clone(), planks.clone(), empty.clone(),
+            planks.clone(), stick.clone(), empty.clone(),
+            empty.clone(), stick.clone(), empty.clone(),
+        ];
+        assert!(axe.matches(&mirrored, 3));
+
+        // An extra item spoils the recipe, and it doesn't fit in a 2x2 grid.
+        let mut extra = grid.clone();
+        extra[0] = stick.clone();
+        assert!(!axe.matches(&extra, 3));
+        assert!(!axe.matches(&grid[..4], 2));
+    }
+
+    #[test]
+    fn shapeless_recipes_match_in_any_order() {
+        let mut registry = RecipeRegistry::default();
+        registry.insert(Recipe {
+            id: ident!("mushroom_stew").into(),
+            group: String::new(),
+            kind: RecipeKind::Shapeless {
+                category: CraftingShapedCategory::Misc,
+                ingredients: vec![
+                    Ingredient(vec![ItemKind::BrownMushroom, ItemKind::RedMushroom]),
+                    ingredient(ItemKind::RedMushroom),
+                    ingredient(ItemKind::Bowl),
+                ],
+                result: stack(ItemKind::MushroomStew),
+            },
+        });
+
+        let grid = [
+            stack(ItemKind::RedMushroom),
+            stack(ItemKind::Bowl),
+            ItemStack::EMPTY,
+            stack(ItemKind::RedMushroom),
+        ];
+        let recipe = registry.craft(&grid, 2).unwrap();
+        assert_eq!(
+            recipe.crafting_result(),
+            Some(&stack(ItemKind::MushroomStew))
+        );
+
+        // Two brown mushrooms can't both be used for the first ingredient.
+        let grid = [
+            stack(ItemKind::BrownMushroom),
+            stack(ItemKind::Bowl),
+            ItemStack::EMPTY,
+            stack(ItemKind::BrownMushroom),
+        ];
+        assert!(registry.craft(&grid, 2).is_none());
+        assert!(registry.craft(&[ItemStack::EMPTY; 4], 2).is_none());
+
+        assert!(!registry.encode().unwrap().is_empty());
+    }
+}
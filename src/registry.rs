@@ -0,0 +1,293 @@
+//! The registry codec sent to clients in [`GameJoinS2c`]. It describes the
+//! dimension types, biomes, chat types and damage types the client should know
+//! about.
+//!
+//! [`GameJoinS2c`]: valence_protocol::packets::play::GameJoinS2c
+
+use std::collections::BTreeMap;
+
+use evenio::prelude::*;
+use valence_protocol::ident;
+use valence_protocol::nbt::{compound, Compound, List};
+use valence_protocol::Ident;
+
+pub const DIMENSION_TYPE: &str = "minecraft:dimension_type";
+pub const BIOME: &str = "minecraft:worldgen/biome";
+pub const CHAT_TYPE: &str = "minecraft:chat_type";
+pub const DAMAGE_TYPE: &str = "minecraft:damage_type";
+
+/// Contains every registry sent to clients when they join.
+#[derive(Component, Clone, Debug)]
+pub struct RegistryCodec {
+    registries: BTreeMap<String, Vec<RegistryValue>>,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct RegistryValue {
+    pub name: Ident<String>,
+    pub element: Compound,
+}
+
+impl RegistryCodec {
+    /// Returns the entries of a registry in protocol ID order.
+    pub fn registry(&self, registry: &str) -> &[RegistryValue] {
+        self.registries
+            .get(registry)
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+
+    /// Returns the protocol ID of the entry `name` in `registry`.
+    pub fn index_of(&self, registry: &str, name: &str) -> Option<usize> {
+        self.registry(registry)
+            .iter()
+            .position(|value| value.name.as_str() == name)
+    }
+
+    /// Adds an entry to `registry`, replacing any previous entry with the same
+    /// name while keeping its protocol ID.
+    pub fn insert(&mut self, registry: &str, name: Ident<String>, element: Compound) {
+        let values = self.registries.entry(registry.to_owned()).or_default();
+
+        if let Some(value) = values.iter_mut().find(|value| value.name == name) {
+            value.element = element;
+        } else {
+            values.push(RegistryValue { name, element });
+        }
+    }
+
+    /// Builds the NBT compound sent in the join packet.
+    pub fn to_compound(&self) -> Compound {
+        let mut codec = Compound::new();
+
+        for (registry, values) in &self.registries {
+            let value = values
+                .iter()
+                .enumerate()
+                .map(|(id, value)| {
+                    compound! {
+                        "name" => value.name.as_str(),
+                        "id" => id as i32,
+                        "element" => value.element.clone(),
+                    }
+                })
+                .collect();
+
+            codec.insert(
+                registry.as_str(),
+                compound! {
+                    "type" => registry.as_str(),
+                    "value" => List::Compound(value),
+                },
+            );
+        }
+
+        codec
+    }
+}
+
+impl Default for RegistryCodec {
+    fn default() -> Self {
+        let mut codec = Self {
+            registries: BTreeMap::new(),
+        };
+
+        codec.insert(DIMENSION_TYPE, ident!("overworld").into(), overworld());
+
+        codec.insert(BIOME, ident!("plains").into(), biome(0.8, 0.4, true, 7907327));
+
+        codec.insert(
+            CHAT_TYPE,
+            ident!("chat").into(),
+            compound! {
+                "chat" => chat_decoration("chat.type.text"),
+                "narration" => chat_decoration("chat.type.text.narrate"),
+            },
+        );
+
+        for (name, message_id, exhaustion) in DAMAGE_TYPES {
+            codec.insert(
+                DAMAGE_TYPE,
+                Ident::new(name.to_string()).unwrap(),
+                compound! {
+                    "message_id" => message_id,
+                    "scaling" => "when_caused_by_living_non_player",
+                    "exhaustion" => exhaustion,
+                },
+            );
+        }
+
+        codec
+    }
+}
+
+fn overworld() -> Compound {
+    compound! {
+        "piglin_safe" => false,
+        "has_raids" => true,
+        "monster_spawn_light_level" => compound! {
+            "type" => "minecraft:uniform",
+            "value" => compound! {
+                "min_inclusive" => 0,
+                "max_inclusive" => 7,
+            },
+        },
+        "monster_spawn_block_light_limit" => 0,
+        "natural" => true,
+        "ambient_light" => 0.0f32,
+        "infiniburn" => "#minecraft:infiniburn_overworld",
+        "respawn_anchor_works" => false,
+        "has_skylight" => true,
+        "bed_works" => true,
+        "effects" => "minecraft:overworld",
+        "min_y" => -64,
+        "height" => 384,
+        "logical_height" => 384,
+        "coordinate_scale" => 1.0f64,
+        "ultrawarm" => false,
+        "has_ceiling" => false,
+    }
+}
+
+pub(crate) fn biome(temperature: f32, downfall: f32, has_precipitation: bool, sky_color: i32) -> Compound {
+    compound! {
+        "has_precipitation" => has_precipitation,
+        "temperature" => temperature,
+        "downfall" => downfall,
+        "effects" => compound! {
+            "sky_color" => sky_color,
+            "water_fog_color" => 329011,
+            "fog_color" => 12638463,
+            "water_color" => 4159204,
+            "mood_sound" => compound! {
+                "tick_delay" => 6000,
+                "offset" => 2.0f64,
+                "sound" => "minecraft:ambient.cave",
+                "block_search_extent" => 8,
+            },
+        },
+    }
+}
+
+fn chat_decoration(translation_key: &str) -> Compound {
+    compound! {
+        "translation_key" => translation_key,
+        "parameters" => List::String(vec!["sender".into(), "content".into()]),
+    }
+}
+
+/// Every vanilla damage type as `(name, message_id, exhaustion)`. The client
+/// refuses to join if any of these are missing.
+const DAMAGE_TYPES: [(&str, &str, f32); 44] = [
+    ("minecraft:arrow", "arrow", 0.1),
+    ("minecraft:bad_respawn_point", "badRespawnPoint", 0.1),
+    ("minecraft:cactus", "cactus", 0.1),
+    ("minecraft:cramming", "cramming", 0.0),
+    ("minecraft:dragon_breath", "dragonBreath", 0.0),
+    ("minecraft:drown", "drown", 0.0),
+    ("minecraft:dry_out", "dryout", 0.1),
+    ("minecraft:explosion", "explosion", 0.1),
+    ("minecraft:fall", "fall", 0.0),
+    ("minecraft:falling_anvil", "anvil", 0.1),
+    ("minecraft:falling_block", "fallingBlock", 0.1),
+    ("minecraft:falling_stalactite", "fallingStalactite", 0.1),
+    ("minecraft:fireball", "fireball", 0.1),
+    ("minecraft:fireworks", "fireworks", 0.1),
+    ("minecraft:fly_into_wall", "flyIntoWall", 0.0),
+    ("minecraft:freeze", "freeze", 0.0),
+    ("minecraft:generic", "generic", 0.0),
+    ("minecraft:generic_kill", "genericKill", 0.0),
+    ("minecraft:hot_floor", "hotFloor", 0.1),
+    ("minecraft:in_fire", "inFire", 0.1),
+    ("minecraft:in_wall", "inWall", 0.0),
+    ("minecraft:indirect_magic", "indirectMagic", 0.0),
+    ("minecraft:lava", "lava", 0.1),
+    ("minecraft:lightning_bolt", "lightningBolt", 0.1),
+    ("minecraft:magic", "magic", 0.0),
+    ("minecraft:mob_attack", "mob", 0.1),
+    ("minecraft:mob_attack_no_aggro", "mob", 0.1),
+    ("minecraft:mob_projectile", "mob", 0.1),
+    ("minecraft:on_fire", "onFire", 0.0),
+    ("minecraft:out_of_world", "outOfWorld", 0.0),
+    ("minecraft:outside_border", "outsideBorder", 0.0),
+    ("minecraft:player_attack", "player", 0.1),
+    ("minecraft:player_explosion", "explosion.player", 0.1),
+    ("minecraft:sonic_boom", "sonic_boom", 0.0),
+    ("minecraft:stalagmite", "stalagmite", 0.0),
+    ("minecraft:starve", "starve", 0.0),
+    ("minecraft:sting", "sting", 0.1),
+    ("minecraft:sweet_berry_bush", "sweetBerryBush", 0.1),
+    ("minecraft:thorns", "thorns", 0.1),
+    ("minecraft:thrown", "thrown", 0.1),
+    ("minecraft:trident", "trident", 0.1),
+    ("minecraft:unattributed_fireball", "onFire", 0.1),
+    ("minecraft:wither", "wither", 0.0),
+    ("minecraft:wither_skull", "witherSkull", 0.1),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_vanilla_damage_type_is_registered() {
+        let vanilla = [
+            "arrow",
+            "bad_respawn_point",
+            "cactus",
+            "cramming",
+            "dragon_breath",
+            "drown",
+            "dry_out",
+            "explosion",
+            "fall",
+            "falling_anvil",
+            "falling_block",
+            "falling_stalactite",
+            "fireball",
+            "fireworks",
+            "fly_into_wall",
+            "freeze",
+            "generic",
+            "generic_kill",
+            "hot_floor",
+            "in_fire",
+            "in_wall",
+            "indirect_magic",
+            "lava",
+            "lightning_bolt",
+            "magic",
+            "mob_attack",
+            "mob_attack_no_aggro",
+            "mob_projectile",
+            "on_fire",
+            "out_of_world",
+            "outside_border",
+            "player_attack",
+            "player_explosion",
+            "sonic_boom",
+            "stalagmite",
+            "starve",
+            "sting",
+            "sweet_berry_bush",
+            "thorns",
+            "thrown",
+            "trident",
+            "unattributed_fireball",
+            "wither",
+            "wither_skull",
+        ];
+
+        let codec = RegistryCodec::default();
+        assert_eq!(codec.registry(DAMAGE_TYPE).len(), vanilla.len());
+
+        for name in vanilla {
+            assert!(
+                codec
+                    .index_of(DAMAGE_TYPE, &format!("minecraft:{name}"))
+                    .is_some(),
+                "missing damage type {name}"
+            );
+        }
+    }
+}
@@ -1,43 +1,39 @@
 use std::sync::atomic::Ordering;
 
-use evenio::prelude::*;
 use serde_json::json;
-use valence_protocol::packets::status::{QueryPingC2s, QueryPongS2c, QueryRequestC2s, QueryResponseS2c};
+use valence_protocol::{anyhow, packets::status::{QueryPingC2s, QueryPongS2c, QueryRequestC2s, QueryResponseS2c}};
 
-use crate::{block::BlockOn, Server, StatusEvent};
+use crate::{network::packet_io::PacketIo, Server};
 
-pub fn status_handler(r: ReceiverMut<StatusEvent>, server: Single<&Server>) {
-    let mut packet_io = EventMut::take(r.event).packet_io;
-    let server = server.0;
+pub async fn handle_status(server: &Server, packet_io: &mut PacketIo) -> anyhow::Result<()> {
+    packet_io.recv_packet::<QueryRequestC2s>().await?;
 
-    async {
-        packet_io.recv_packet::<QueryRequestC2s>().await.unwrap();
+    let json = json!({
+        "version": {
+            "name": server.version_name,
+            "protocol": server.protocol_version,
+        },
+        "players": {
+            "max": server.max_players,
+            "online": server.online.load(Ordering::Relaxed),
+            "sample": [],
+        },
+        "description": {
+            "text": server.motd,
+        },
+        "favicon": server.favicon,
+        "enforcesSecureChat": true,
+        "previewsChat": true,
+    }).to_string();
 
-        let json = json!({
-            "version": {
-                "name": server.version_name,
-                "protocol": server.protocol_version,
-            },
-            "players": {
-                "max": server.max_players,
-                "online": server.online.load(Ordering::Relaxed),
-                "sample": [],
-            },
-            "description": {
-                "text": server.motd,
-            },
-            "favicon": server.favicon,
-            "enforcesSecureChat": true,
-            "previewsChat": true,
-        }).to_string();
-    
-        packet_io.send_packet(&QueryResponseS2c {
-            json: &json,
-        }).await.unwrap();
-    
-        let ping = packet_io.recv_packet::<QueryPingC2s>().await.unwrap();
-        packet_io.send_packet(&QueryPongS2c {
-            payload: ping.payload,
-        }).await.unwrap();
-    }.block();
-}
\ No newline at end of file
+    packet_io.send_packet(&QueryResponseS2c {
+        json: &json,
+    }).await?;
+
+    let ping = packet_io.recv_packet::<QueryPingC2s>().await?;
+    packet_io.send_packet(&QueryPongS2c {
+        payload: ping.payload,
+    }).await?;
+
+    Ok(())
+}
@@ -0,0 +1,151 @@
+//! Helpers for tests that run handlers in a [`World`].
+
+use std::sync::atomic::AtomicUsize;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use evenio::prelude::*;
+use rsa::pkcs8::EncodePublicKey;
+use rsa::RsaPrivateKey;
+use valence_protocol::anyhow;
+use valence_protocol::bytes::BytesMut;
+use valence_protocol::decode::PacketFrame;
+use valence_protocol::{ident, BlockState, CompressionThreshold, PacketDecoder, PacketEncoder};
+
+use crate::chunk::{Chunk, ChunkLayer};
+use crate::client::{Client, ClientConnection, ReceivedPacket};
+use crate::event_loop::PacketEvent;
+use crate::registry::RegistryCodec;
+use crate::{ConnectionMode, Server, SharedServer};
+
+/// A connection that never receives anything. Packets written to the client
+/// stay in its encoder until [`sent_packets`] takes them.
+struct MockConnection;
+
+impl ClientConnection for MockConnection {
+    fn try_send(&mut self, _bytes: BytesMut) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    fn try_recv(&mut self) -> anyhow::Result<Option<ReceivedPacket>> {
+        Ok(None)
+    }
+
+    fn len(&self) -> usize {
+        0
+    }
+}
+
+/// A connection the other side closed, which fails every read and write.
+struct ClosedConnection;
+
+impl ClientConnection for ClosedConnection {
+    fn try_send(&mut self, _bytes: BytesMut) -> anyhow::Result<()> {
+        anyhow::bail!("connection closed")
+    }
+
+    fn try_recv(&mut self) -> anyhow::Result<Option<ReceivedPacket>> {
+        anyhow::bail!("connection closed")
+    }
+
+    fn len(&self) -> usize {
+        0
+    }
+}
+
+pub(crate) fn client() -> Client {
+    Client {
+        conn: Box::new(MockConnection),
+        enc: PacketEncoder::new(),
+    }
+}
+
+/// A client whose connection was closed.
+pub(crate) fn closed_client() -> Client {
+    Client {
+        conn: Box::new(ClosedConnection),
+        enc: PacketEncoder::new(),
+    }
+}
+
+/// A server with the settings of `main`, without compression.
+pub(crate) fn server() -> SharedServer {
+    // Small, since nothing is encrypted with it.
+    let rsa_key = RsaPrivateKey::new(&mut rand::thread_rng(), 512).unwrap();
+    let public_key_der = rsa_key
+        .to_public_key()
+        .to_public_key_der()
+        .unwrap()
+        .into_vec()
+        .into_boxed_slice();
+
+    SharedServer(Arc::new(Server {
+        version_name: "1.20.1".to_string(),
+        protocol_version: 763,
+        max_players: 20,
+        online: AtomicUsize::new(0),
+        motd: String::new(),
+        favicon: String::new(),
+        connection_mode: ConnectionMode::Offline,
+        threshold: CompressionThreshold(-1),
+        rsa_key,
+        public_key_der,
+        http_client: reqwest::Client::new(),
+        incoming_byte_limit: 2097152,
+        outgoing_byte_limit: 8388608,
+        keepalive_period: Duration::from_secs(8),
+        keepalive_timeout: Duration::from_secs(15),
+        max_chunks_per_tick: 16,
+    }))
+}
+
+/// The top of the stone floor of [`floor_layer`].
+pub(crate) const FLOOR: f64 = -48.0;
+
+/// An empty overworld layer without compression.
+pub(crate) fn layer() -> ChunkLayer {
+    ChunkLayer::new(
+        ident!("overworld"),
+        &RegistryCodec::default(),
+        CompressionThreshold(-1),
+    )
+}
+
+/// A layer with a stone floor, the bottom section, in each chunk of `chunks`.
+pub(crate) fn floor_layer(chunks: impl IntoIterator<Item = [i32; 2]>) -> ChunkLayer {
+    let mut layer = layer();
+
+    for pos in chunks {
+        let mut chunk = Chunk::with_height(layer.height());
+        chunk.fill_block_state_section(0, BlockState::STONE);
+        layer.insert_chunk(pos, chunk);
+    }
+
+    layer
+}
+
+/// Takes the packets written to the [`Client`] of `client` so far.
+pub(crate) fn sent_packets(world: &mut World, client: EntityId) -> Vec<PacketFrame> {
+    let client = world.get_mut::<Client>(client).unwrap();
+
+    let mut decoder = PacketDecoder::new();
+    decoder.queue_bytes(client.enc.take());
+    std::iter::from_fn(|| decoder.try_next_packet().unwrap()).collect()
+}
+
+/// The IDs of the packets written to the [`Client`] of `client` so far.
+pub(crate) fn sent_packet_ids(world: &mut World, client: EntityId) -> Vec<i32> {
+    sent_packets(world, client)
+        .into_iter()
+        .map(|frame| frame.id)
+        .collect()
+}
+
+/// `packet` as if `client` just sent it.
+pub(crate) fn packet_event<P>(client: EntityId, packet: P) -> PacketEvent<P> {
+    PacketEvent {
+        client,
+        timestamp: Instant::now(),
+        packet,
+    }
+}
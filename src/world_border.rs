@@ -0,0 +1,117 @@
+//! The world border of each [`ChunkLayer`](crate::chunk::ChunkLayer).
+//!
+//! A layer entity's border is its [`WorldBorder`] component. Layers without
+//! one have the default border, which is as large as the game allows. Clients
+//! are sent the border of their layer when they join, and players that move
+//! outside of it are kicked.
+
+use evenio::prelude::*;
+use tracing::warn;
+use valence_protocol::math::DVec3;
+use valence_protocol::packets::play::WorldBorderInitializeS2c;
+use valence_protocol::{VarInt, VarLong, WritePacket};
+
+use crate::chunk::view::VisibleChunkLayer;
+use crate::client::Client;
+use crate::event::ClientJoinEvent;
+
+/// A square border centered on `center_x` and `center_z`.
+#[derive(Component, Copy, Clone, PartialEq, Debug)]
+pub struct WorldBorder {
+    pub center_x: f64,
+    pub center_z: f64,
+    /// The length of each side in blocks.
+    pub diameter: f64,
+}
+
+impl WorldBorder {
+    /// The largest border, reaching out to 29 999 984 on every side like in
+    /// vanilla.
+    pub const MAX: Self = Self {
+        center_x: 0.0,
+        center_z: 0.0,
+        diameter: 59_999_968.0,
+    };
+
+    /// Whether `pos` is inside the border. The border reaches from the bottom
+    /// to the top of the world, so `pos.y` doesn't matter.
+    pub fn contains(&self, pos: DVec3) -> bool {
+        let radius = self.diameter / 2.0;
+
+        (pos.x - self.center_x).abs() <= radius && (pos.z - self.center_z).abs() <= radius
+    }
+}
+
+impl Default for WorldBorder {
+    fn default() -> Self {
+        Self::MAX
+    }
+}
+
+/// The border of `layer`, or the default border if it has none.
+pub(crate) fn border_of(borders: &Fetcher<&WorldBorder>, layer: EntityId) -> WorldBorder {
+    borders.get(layer).copied().unwrap_or_default()
+}
+
+/// Sends the border of the layer a client joined in.
+pub fn init_world_border(
+    r: Receiver<ClientJoinEvent>,
+    mut clients: Fetcher<(&mut Client, &VisibleChunkLayer)>,
+    borders: Fetcher<&WorldBorder>,
+) {
+    let Ok((client, layer)) = clients.get_mut(r.event.entity) else {
+        warn!("joining client {:?} is missing components", r.event.entity);
+        return;
+    };
+
+    let border = border_of(&borders, layer.0);
+
+    client.write_packet(&WorldBorderInitializeS2c {
+        x: border.center_x,
+        z: border.center_z,
+        old_diameter: border.diameter,
+        new_diameter: border.diameter,
+        duration_millis: VarLong(0),
+        portal_teleport_boundary: VarInt(29_999_984),
+        warning_blocks: VarInt(5),
+        warning_time: VarInt(15),
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use valence_protocol::Packet;
+
+    use super::*;
+    use crate::testing::{self, sent_packets};
+
+    #[test]
+    fn joining_clients_are_sent_the_border_of_their_layer() {
+        let mut world = World::new();
+        world.add_handler(init_world_border);
+
+        let layer = world.spawn();
+        let border = WorldBorder {
+            center_x: 100.0,
+            center_z: -20.0,
+            diameter: 64.0,
+        };
+        world.insert(layer, border);
+
+        let client = world.spawn();
+        world.insert(client, testing::client());
+        world.insert(client, VisibleChunkLayer(layer));
+
+        world.send(ClientJoinEvent { entity: client });
+        let packets = sent_packets(&mut world, client);
+        assert_eq!(packets.len(), 1);
+        assert_eq!(packets[0].id, WorldBorderInitializeS2c::ID);
+
+        let packet = packets[0].decode::<WorldBorderInitializeS2c>().unwrap();
+        assert_eq!((packet.x, packet.z), (100.0, -20.0));
+        assert_eq!(packet.new_diameter, 64.0);
+
+        assert!(border.contains(DVec3::new(131.5, 300.0, -51.0)));
+        assert!(!border.contains(DVec3::new(132.5, 64.0, -20.0)));
+    }
+}